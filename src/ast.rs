@@ -1,24 +1,77 @@
+use crate::lexer::Span;
+
 #[derive(Clone)]
 pub enum Expr {
-    Number(i32),
+    Number(i64),
     Float(f64),
     Boolean(bool),
     StringLiteral(String),
-    Array(Vec<Box<Expr>>),
+    Char(char),
+    Array(Vec<Expr>),
+    Map(Vec<(Box<Expr>, Box<Expr>)>),
     BinaryOp {
         op: BinOp,
         left: Box<Expr>,
         right: Box<Expr>,
+        span: Span,      // Operator position, for runtime errors
+    },
+    UnaryOp {
+        op: UnaryOp,
+        operand: Box<Expr>,
+        span: Span,
     },
     Print(Box<Expr>),
-    Exit(Box<Expr>),
+    Exit {
+        code: Box<Expr>,
+        span: Span,
+    },
+    ConsoleRead(ReadKind),   // console.readLine(), readInt(), readAll()
+    ConsoleWrite {           // console.write(), console.error(), console.eprint()
+        value: Box<Expr>,
+        stream: Stream,
+        newline: bool,
+    },
+    ProcessArgs,             // process.args()
+    ProcessEnv(Box<Expr>),   // process.env("NAME")
+    Fs {                     // fs.read(path), fs.write(path, text), ...
+        op: FsOp,
+        args: Vec<Expr>,
+        span: Span,
+    },
+    Math {                   // math.sqrt(x), math.max(a, b), ...
+        function: MathFn,
+        args: Vec<Expr>,
+        span: Span,
+    },
+    Time {                   // time.now(), time.sleep(ms)
+        function: TimeFn,
+        args: Vec<Expr>,
+    },
+    Random {                 // random.int(lo, hi), random.float(), random.seed(n)
+        function: RandomFn,
+        args: Vec<Expr>,
+        span: Span,
+    },
+    CharFromCode {           // char.fromCode(n)
+        code: Box<Expr>,
+        span: Span,
+    },
+    Printf {                 // console.printf("x = {}", x)
+        format: Box<Expr>,
+        args: Vec<Expr>,
+        span: Span,
+    },
     Const {
         name: String,
         value: Box<Expr>,
+        doc: Option<String>,  // From the /// or /** */ comment above it
+        span: Span,
     },
     Let {
         name: String,
         value: Box<Expr>,
+        doc: Option<String>,
+        span: Span,
     },
     Assign {
         name: String,
@@ -27,18 +80,106 @@ pub enum Expr {
     Variable(String),
     Null,
     If {
-        condition: Box<Expr>,
-        then_branch: Vec<Box<Expr>>,
-        else_branch: Option<Vec<Box<Expr>>>,
+        branches: Vec<(Box<Expr>, Vec<Expr>)>, // if and every else if, in order
+        else_branch: Option<Vec<Expr>>,
+        spans: Vec<Span>,                           // The `if` of each branch
+    },
+    MethodCall {
+        object: Box<Expr>,
+        method: String,
+        args: Vec<Expr>,
+    },
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    IndexAssign {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    StructDecl {
+        name: String,
+        fields: Vec<(String, String)>,
+        doc: Option<String>,
+    },
+    StructLiteral {
+        name: String,
+        fields: Vec<(String, Box<Expr>)>,
+    },
+    FieldAccess {
+        object: Box<Expr>,
+        field: String,
+    },
+    FieldAssign {
+        object: Box<Expr>,
+        field: String,
+        value: Box<Expr>,
+    },
+    EnumDecl {
+        name: String,
+        variants: Vec<(String, Vec<String>)>,
+        doc: Option<String>,
+    },
+    Variant {
+        name: String,
+        args: Vec<Expr>,
+    },
+    Match {
+        value: Box<Expr>,
+        arms: Vec<(Pattern, Vec<Expr>)>,
+        span: Span,
+    },
+    MatchValue {             // let x = match (value) { pattern => expression, ... };
+        value: Box<Expr>,
+        arms: Vec<(Pattern, Box<Expr>)>,
+        span: Span,
+    },
+}
+
+#[derive(Clone)]
+pub enum Pattern {
+    Wildcard,                // _
+    Literal(Box<Expr>),      // 1, "text", 'c', true, null
+    Variant {                // Red, Blue(n), Blue(_)
+        name: String,
+        bindings: Vec<Option<String>>,
     },
 }
 
+// What a lint does when it finds something
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+// A #[allow(...)], #[warn(...)] or #[deny(...)] attribute and the source it
+// covers, from the first to the last token of the statement after it.
+// #![...] at the start of the file covers all of it.
+#[derive(Clone)]
+pub struct LintAttribute {
+    pub level: LintLevel,
+    pub lints: Vec<String>,
+    pub span: Span,      // Where the attribute is written
+    pub start: Span,
+    pub end: Span,
+}
+
 #[derive(PartialEq, Clone)]
 pub enum BinOp {
     Add,
     Sub,
     Mul,
     Div,
+    Mod,      // %
+    Pow,      // **
+    BitAnd,   // &
+    BitOr,    // |
+    BitXor,   // ^
+    Shl,      // <<
+    Shr,      // >>
     Equal,    // ==
     NotEqual, // !=
     Lt,       // <
@@ -46,3 +187,59 @@ pub enum BinOp {
     Lte,      // <=
     Gte,      // >=
 }
+
+#[derive(PartialEq, Clone)]
+pub enum UnaryOp {
+    Neg,      // -
+    BitNot,   // ~
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum ReadKind {
+    Line,     // readLine()
+    Int,      // readInt()
+    All,      // readAll()
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum FsOp {
+    Read,     // fs.read(path)
+    Write,    // fs.write(path, text)
+    Append,   // fs.append(path, text)
+    Exists,   // fs.exists(path)
+    Remove,   // fs.remove(path)
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum MathFn {
+    Sqrt,
+    Pow,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Min,
+    Max,
+    Sin,
+    Cos,
+    Log,      // Natural logarithm
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum TimeFn {
+    Now,      // Milliseconds since the Unix epoch
+    Sleep,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum RandomFn {
+    Int,      // Between lo and hi, both included
+    Float,    // In [0, 1)
+    Seed,     // A fixed seed, or a new one from getrandom without an argument
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
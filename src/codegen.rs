@@ -1,17 +1,85 @@
-use std::collections::HashMap;
-use crate::ast::{Expr, BinOp};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::ast::{Expr, BinOp, UnaryOp, Pattern, ReadKind, Stream, FsOp, MathFn, TimeFn, RandomFn};
+use crate::lexer::Span;
+use crate::runtime;
 
 // Define the ConstValue enum to store different types of constants
 #[derive(Clone)]
 pub enum ConstValue {
-    Number(i32),
+    Number(i64),
     Float(f64),
     String(String),
     Boolean(bool),
+    Char(char),
     Array(Vec<ConstValue>),
+    Map(Vec<(ConstValue, ConstValue)>),
+    Struct { name: String, fields: Vec<(String, ConstValue)> },
+    Enum { name: String, variant: String, index: usize, payload: Vec<ConstValue> },
     Null,
 }
 
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConstValue::Number(n) => write!(f, "{}", n),
+            ConstValue::Float(n) => write!(f, "{}", n),
+            ConstValue::String(s) => write!(f, "{}", s),
+            ConstValue::Boolean(b) => write!(f, "{}", b),
+            ConstValue::Char(c) => write!(f, "{}", c),
+            ConstValue::Array(arr) => {
+                write!(f, "[")?;
+                for (i, val) in arr.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", val)?;
+                }
+                write!(f, "]")
+            },
+            ConstValue::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, val)?;
+                }
+                write!(f, "}}")
+            },
+            ConstValue::Struct { name, fields } => {
+                if fields.is_empty() {
+                    return write!(f, "{} {{}}", name);
+                }
+                write!(f, "{} {{ ", name)?;
+                for (i, (field, val)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", field, val)?;
+                }
+                write!(f, " }}")
+            },
+            ConstValue::Enum { variant, payload, .. } => {
+                write!(f, "{}", variant)?;
+                if payload.is_empty() {
+                    return Ok(());
+                }
+                write!(f, "(")?;
+                for (i, val) in payload.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", val)?;
+                }
+                write!(f, ")")
+            },
+            ConstValue::Null => write!(f, "null"),
+        }
+    }
+}
+
 // Helper function to ensure consistent label naming
 fn get_var_label(name: &str, suffix: Option<&str>) -> String {
     if let Some(suffix) = suffix {
@@ -28,12 +96,130 @@ fn get_string_label(counter: &mut usize, prefix: &str) -> String {
     label
 }
 
-// Generate NASM assembly from parsed expressions
-pub fn generate_nasm(exprs: &Vec<Expr>) -> String {
-    let mut constants: HashMap<String, ConstValue> = HashMap::new();
-    let mut variables: HashMap<String, ConstValue> = HashMap::new();
-    let mut string_counter = 0;
-    
+// Command line settings that change the generated code
+pub struct CodegenOptions {
+    pub source_file: String,   // Named in runtime error messages
+    pub overflow_checks: bool, // Trap on overflowing integer arithmetic
+}
+
+// Declared structs and enums, kept apart from the constants and variables
+#[derive(Default)]
+struct Definitions {
+    structs: HashMap<String, ConstValue>,    // Every field set to its type's default
+    enums: HashMap<String, Vec<ConstValue>>, // Every variant, in declaration order
+    variants: HashMap<String, ConstValue>,   // A single variant with default payload values
+}
+
+// Compile-time types of the constants and variables a program ends up with,
+// so later passes can ask about its expressions the way code generation does
+pub struct Types {
+    constants: HashMap<String, ConstValue>,
+    definitions: Definitions,
+    variables: HashMap<String, ConstValue>,
+}
+
+// Everything code generation works on: the sections being written, the
+// string literals already in the data section and what is known so far about
+// the program's names
+struct Codegen<'a> {
+    text_section: String,
+    data_section: String,
+    bss_section: String,
+    counter: usize, // Numbers labels so they stay unique
+    string_labels: HashMap<String, String>,
+    options: &'a CodegenOptions,
+    types: Types,
+    bindings: Vec<String>,  // Match bindings of the arms being generated
+    bound: HashSet<String>, // Names a match binding reserved storage for
+}
+
+impl Types {
+    // The value of a condition that is known at compile time
+    pub fn constant_condition(&self, condition: &Expr) -> Option<bool> {
+        if !is_constant_expr(condition, self) || !self.folds(condition) {
+            return None;
+        }
+        match evaluate_constant_expr(condition, self) {
+            ConstValue::Boolean(b) => Some(b),
+            ConstValue::Number(n) => Some(n != 0),
+            _ => None,
+        }
+    }
+
+    // Whether evaluate_constant_expr can fold the constant parts of an
+    // expression. Code generation leaves conditions and operators to runtime,
+    // so comparisons of other types, division by zero and results outside 64
+    // bits are only caught here.
+    fn folds(&self, expr: &Expr) -> bool {
+        let value = |e: &Expr| evaluate_constant_expr(e, self);
+        let constant = is_constant_expr(expr, self);
+        match expr {
+            Expr::BinaryOp { op, left, right, .. } => {
+                if !self.folds(left) || !self.folds(right) {
+                    return false;
+                }
+                if !constant {
+                    return true;
+                }
+                match (op, value(left), value(right)) {
+                    (BinOp::Div | BinOp::Mod, _, ConstValue::Number(0)) => false,
+                    (BinOp::Pow, ConstValue::Number(0), ConstValue::Number(exponent)) if exponent < 0 => false,
+                    (BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod | BinOp::Pow | BinOp::Shl,
+                     ConstValue::Number(a), ConstValue::Number(b)) => fold_int(op, a, b).is_some(),
+                    (BinOp::Equal | BinOp::NotEqual | BinOp::Lt | BinOp::Gt | BinOp::Lte | BinOp::Gte, left, right) => matches!(
+                        (op, left, right),
+                        (_, ConstValue::Number(_) | ConstValue::Float(_), ConstValue::Number(_) | ConstValue::Float(_))
+                            | (_, ConstValue::Char(_), ConstValue::Char(_))
+                            | (BinOp::Equal | BinOp::NotEqual, ConstValue::String(_), ConstValue::String(_))
+                            | (BinOp::Equal | BinOp::NotEqual, ConstValue::Boolean(_), ConstValue::Boolean(_))
+                    ),
+                    _ => true,
+                }
+            },
+            Expr::UnaryOp { op, operand, .. } => {
+                self.folds(operand) && !(constant && *op == UnaryOp::Neg && matches!(value(operand), ConstValue::Number(i64::MIN)))
+            },
+            Expr::Math { function, args, .. } => {
+                args.iter().all(|arg| self.folds(arg))
+                    && !(constant && *function == MathFn::Abs && matches!(value(&args[0]), ConstValue::Number(i64::MIN)))
+            },
+            _ => true,
+        }
+    }
+
+    // Whether an expression folds to a number, float, string, bool or char
+    pub fn is_scalar_constant(&self, expr: &Expr) -> bool {
+        is_constant_expr(expr, self) && matches!(
+            evaluate_constant_expr(expr, self),
+            ConstValue::Number(_) | ConstValue::Float(_) | ConstValue::String(_) | ConstValue::Boolean(_) | ConstValue::Char(_)
+        )
+    }
+
+    // Type names of two compared values whose types differ. Values whose type
+    // is only known at runtime, null, and ints against floats are left alone.
+    pub fn mismatched_types(&self, left: &Expr, right: &Expr) -> Option<(String, String)> {
+        if !self.folds(left) || !self.folds(right) {
+            return None;
+        }
+        if is_tagged_expr(left, self) || is_tagged_expr(right, self) {
+            return None;
+        }
+        let tags = (value_tag(left, self), value_tag(right, self));
+        match tags {
+            (left, right) if left == right => None,
+            (runtime::TAG_NULL, _) | (_, runtime::TAG_NULL) => None,
+            (runtime::TAG_NUMBER, runtime::TAG_FLOAT) | (runtime::TAG_FLOAT, runtime::TAG_NUMBER) => None,
+            _ => Some((
+                type_name(&runtime_placeholder(left, self)),
+                type_name(&runtime_placeholder(right, self)),
+            )),
+        }
+    }
+}
+
+// Generate NASM assembly from parsed expressions, along with the types the
+// program's names end up with
+pub fn generate_nasm(exprs: &Vec<Expr>, options: &CodegenOptions) -> (String, Types) {
     let mut text_section = String::from("section .text\n");
     text_section.push_str("global _start\n\n");
     
@@ -44,16 +230,15 @@ pub fn generate_nasm(exprs: &Vec<Expr>) -> String {
     text_section.push_str("    pop rdi      ; Number to print\n");
     text_section.push_str("    push rax     ; Save return address\n");
     
-    text_section.push_str("    mov rsi, buffer\n");
     text_section.push_str("    mov rax, rdi\n");
     text_section.push_str("    mov rbx, 10\n");
+    text_section.push_str("    xor r8, r8    ; Sign flag\n");
     
     text_section.push_str("    ; Handle negative numbers\n");
     text_section.push_str("    test rax, rax\n");
     text_section.push_str("    jns .positive\n");
     text_section.push_str("    neg rax\n");
-    text_section.push_str("    mov byte [rsi], '-'\n");
-    text_section.push_str("    inc rsi\n");
+    text_section.push_str("    mov r8, 1\n");
     
     text_section.push_str(".positive:\n");
     text_section.push_str("    ; Convert to string (reversed)\n");
@@ -70,6 +255,13 @@ pub fn generate_nasm(exprs: &Vec<Expr>) -> String {
     text_section.push_str("    test rax, rax\n");
     text_section.push_str("    jnz .digit_loop\n");
     
+    text_section.push_str("    ; Put the sign in front of the digits\n");
+    text_section.push_str("    test r8, r8\n");
+    text_section.push_str("    jz .unsigned\n");
+    text_section.push_str("    mov byte [rcx], '-'\n");
+    text_section.push_str("    dec rcx\n");
+    text_section.push_str(".unsigned:\n");
+    
     text_section.push_str("    ; Calculate string length\n");
     text_section.push_str("    lea rsi, [rcx+1]\n");
     text_section.push_str("    mov rdx, buffer_end\n");
@@ -88,35 +280,46 @@ pub fn generate_nasm(exprs: &Vec<Expr>) -> String {
     text_section.push_str("    ; Append a string (in RSI) to buffer (in RDI)\n");
     text_section.push_str("    ; RDI is the current position in buffer\n");
     text_section.push_str("    ; RCX is the total length so far\n");
+    text_section.push_str("    push rdx\n");
+    text_section.push_str("    mov rdx, [rsi-8] ; Bytes left\n");
     text_section.push_str(".loop:\n");
-    text_section.push_str("    mov al, [rsi]\n");
-    text_section.push_str("    test al, al\n");
+    text_section.push_str("    test rdx, rdx\n");
     text_section.push_str("    jz .done\n");
+    text_section.push_str("    mov al, [rsi]\n");
     text_section.push_str("    mov [rdi], al\n");
     text_section.push_str("    inc rsi\n");
     text_section.push_str("    inc rdi\n");
     text_section.push_str("    inc rcx\n");
+    text_section.push_str("    dec rdx\n");
     text_section.push_str("    jmp .loop\n");
     text_section.push_str(".done:\n");
+    text_section.push_str("    pop rdx\n");
     text_section.push_str("    ret\n\n");
     
     text_section.push_str("append_string_without_newline:\n");
-    text_section.push_str("    ; Append a string (in RSI) to buffer (in RDI) but skip newlines\n");
+    text_section.push_str("    ; Append a string (in RSI) to buffer (in RDI) but drop the newline\n");
+    text_section.push_str("    ; that ends it, newlines inside the string are kept\n");
     text_section.push_str("    ; RDI is the current position in buffer\n");
     text_section.push_str("    ; RCX is the total length so far\n");
+    text_section.push_str("    push rdx\n");
+    text_section.push_str("    mov rdx, [rsi-8] ; Bytes left\n");
+    text_section.push_str("    test rdx, rdx\n");
+    text_section.push_str("    jz .done\n");
+    text_section.push_str("    cmp byte [rsi+rdx-1], 10 ; Check for newline\n");
+    text_section.push_str("    jne .loop\n");
+    text_section.push_str("    dec rdx\n");
     text_section.push_str(".loop:\n");
-    text_section.push_str("    mov al, [rsi]\n");
-    text_section.push_str("    test al, al\n");
+    text_section.push_str("    test rdx, rdx\n");
     text_section.push_str("    jz .done\n");
-    text_section.push_str("    cmp al, 10    ; Check for newline\n");
-    text_section.push_str("    je .skip\n");
+    text_section.push_str("    mov al, [rsi]\n");
     text_section.push_str("    mov [rdi], al\n");
     text_section.push_str("    inc rdi\n");
     text_section.push_str("    inc rcx\n");
-    text_section.push_str(".skip:\n");
     text_section.push_str("    inc rsi\n");
+    text_section.push_str("    dec rdx\n");
     text_section.push_str("    jmp .loop\n");
     text_section.push_str(".done:\n");
+    text_section.push_str("    pop rdx\n");
     text_section.push_str("    ret\n\n");
     
     text_section.push_str("append_number:\n");
@@ -180,533 +383,885 @@ pub fn generate_nasm(exprs: &Vec<Expr>) -> String {
     text_section.push_str("    pop rbx\n");
     text_section.push_str("    ret\n\n");
     
-    text_section.push_str("_start:\n");
-    
     let mut data_section = String::from("section .data\n");
-    data_section.push_str("true_str db \"true\", 10, 0\n");
-    data_section.push_str("false_str db \"false\", 10, 0\n");
-    data_section.push_str("null_str db \"null\", 10, 0\n");
-    data_section.push_str("array_open db \"[\", 0\n");
-    data_section.push_str("array_close db \"]\", 10, 0\n");
-    data_section.push_str("array_separator db \", \", 0\n");
+    data_section.push_str(&runtime::string_data("true_str", "true\n"));
+    data_section.push_str(&runtime::string_data("false_str", "false\n"));
+    data_section.push_str(&runtime::string_data("null_str", "null\n"));
+    data_section.push_str(&runtime::string_data("array_open", "["));
+    data_section.push_str(&runtime::string_data("array_close", "]\n"));
+    data_section.push_str(&runtime::string_data("array_separator", ", "));
     
     let mut bss_section = String::from("section .bss\n");
     bss_section.push_str("buffer: resb 32\n");
     bss_section.push_str("buffer_end: resb 1\n");
     bss_section.push_str("str_buffer: resb 1024\n");
     
-    let mut string_labels: HashMap<String, String> = HashMap::new();
+    // Runtime library for values computed while the program runs
+    runtime::emit_heap(&mut text_section, &mut data_section);
+    runtime::emit_string_helpers(&mut text_section, &mut data_section);
+    runtime::emit_output_helpers(&mut text_section, &mut data_section);
+    runtime::emit_array_helpers(&mut text_section, &mut data_section);
+    runtime::emit_map_helpers(&mut text_section, &mut data_section);
+    runtime::emit_struct_helpers(&mut text_section, &mut data_section);
+    runtime::emit_enum_helpers(&mut text_section, &mut data_section);
+    runtime::emit_integer_helpers(&mut text_section);
+    runtime::emit_input_helpers(&mut text_section, &mut data_section, &mut bss_section);
+    runtime::emit_process_helpers(&mut text_section, &mut data_section);
+    runtime::emit_fs_helpers(&mut text_section, &mut data_section);
+    runtime::emit_math_helpers(&mut text_section, &mut data_section);
+    runtime::emit_time_helpers(&mut text_section, &mut data_section);
+    runtime::emit_random_helpers(&mut text_section, &mut data_section);
+    runtime::emit_char_helpers(&mut text_section, &mut data_section);
+    runtime::emit_trap_helpers(&mut text_section, &mut data_section, &options.source_file);
+    
+    text_section.push_str("_start:\n");
+    text_section.push_str("    mov [process_stack], rsp  ; argc, arguments and environment\n\n");
+    
+    let mut ctx = Codegen {
+        text_section,
+        data_section,
+        bss_section,
+        counter: 0,
+        string_labels: HashMap::new(),
+        options,
+        types: Types { constants: HashMap::new(), definitions: Definitions::default(), variables: HashMap::new() },
+        bindings: Vec::new(),
+        bound: HashSet::new(),
+    };
     
     // First pass collect all string literals
     for expr in exprs {
-        collect_string_literals(expr, &mut string_counter, &mut string_labels, &mut data_section);
+        collect_string_literals(expr, &mut ctx.counter, &mut ctx.string_labels, &mut ctx.data_section);
     }
     
     // Define helper functions for code reuse
-    fn process_statements(statements: &Vec<Box<Expr>>, text_section: &mut String, data_section: &mut String, 
-                     bss_section: &mut String, constants: &mut HashMap<String, ConstValue>, 
-                     variables: &mut HashMap<String, ConstValue>, 
-                     string_labels: &HashMap<String, String>, 
-                     string_counter: &mut usize) {
+    fn process_statements(statements: &[Expr], ctx: &mut Codegen) {
 		for stmt in statements {
-			process_statement(&**stmt, text_section, data_section, bss_section, 
-							constants, variables, string_labels, string_counter);
+			process_statement(stmt, ctx);
 		}
 	}
     
-    fn process_statement(expr: &Expr, text_section: &mut String, data_section: &mut String, bss_section: &mut String, constants: &mut HashMap<String, ConstValue>, variables: &mut HashMap<String, ConstValue>, string_labels: &HashMap<String, String>, string_counter: &mut usize) {
+    fn process_statement(expr: &Expr, ctx: &mut Codegen) {
         match expr {
             Expr::Print(inner) => {
-                generate_print_code(&**inner, text_section, constants, variables, string_labels, data_section, string_counter);
+                generate_print_code(inner, ctx);
             },
-            Expr::Exit(code) => {
-                generate_exit_code(&**code, text_section, constants, variables);
+            Expr::Exit { code, .. } => {
+                generate_exit_code(code, ctx);
             },
-            Expr::Const { name, value } => {
-                generate_const_code(name, &**value, text_section, data_section, constants, variables, string_counter);
+            Expr::Const { name, value, doc, .. } => {
+                generate_doc_comment(doc, ctx);
+                generate_const_code(name, value, ctx);
             },
-            Expr::Let { name, value } => {
-                generate_let_code(name, &**value, text_section, data_section, bss_section, constants, variables, string_counter);
+            Expr::Let { name, value, doc, span } => {
+                generate_doc_comment(doc, ctx);
+                if let Expr::MatchValue { value, arms, span: match_span } = &**value {
+                    let declare = |arm: &Expr| Expr::Let { name: name.clone(), value: Box::new(arm.clone()), doc: None, span: *span };
+                    generate_match_value_code(name, value, arms, &declare, *match_span, ctx);
+                } else {
+                    generate_let_code(name, value, ctx);
+                }
             },
             Expr::Assign { name, value } => {
-                generate_assign_code(name, &**value, text_section, data_section, constants, variables, string_counter);
+                if let Expr::MatchValue { value, arms, span } = &**value {
+                    let assign = |arm: &Expr| Expr::Assign { name: name.clone(), value: Box::new(arm.clone()) };
+                    generate_match_value_code(name, value, arms, &assign, *span, ctx);
+                } else {
+                    generate_assign_code(name, value, ctx);
+                }
             },
-            Expr::If { condition, then_branch, else_branch } => {
-				let mut local_counter = *string_counter;
-				let label_end = format!("if_end_{}", local_counter);
-				local_counter += 1;
-				let label_else = if else_branch.is_some() { format!("if_else_{}", local_counter) } else { label_end.clone() };
-				if else_branch.is_some() { local_counter += 1; }
-				*string_counter = local_counter;
-				
-				text_section.push_str("    ; If-Statement (condition evaluation)\n");
-				generate_expression_code(&**condition, text_section, constants, variables);
-				text_section.push_str("    test rax, rax\n");
-				text_section.push_str(&format!("    jz {}\n", label_else));
+            Expr::IndexAssign { object, index, value } => {
+                generate_index_assign_code(object, index, value, ctx);
+            },
+            Expr::StructDecl { name, fields, doc } => {
+                generate_doc_comment(doc, ctx);
+                generate_struct_decl_code(name, fields, ctx);
+            },
+            Expr::FieldAssign { object, field, value } => {
+                generate_field_assign_code(object, field, value, ctx);
+            },
+            Expr::EnumDecl { name, variants, doc } => {
+                generate_doc_comment(doc, ctx);
+                generate_enum_decl_code(name, variants, ctx);
+            },
+            Expr::Match { value, arms, span } => {
+                generate_match_code(value, arms, None, *span, ctx);
+            },
+            Expr::MethodCall { .. } => {
+                generate_method_statement(expr, ctx);
+            },
+            Expr::ConsoleWrite { value, stream, newline } => {
+                generate_write_code(value, *stream, *newline, ctx);
+            },
+            Expr::Printf { format, args, span } => {
+                generate_printf_code(format, args, *span, ctx);
+            },
+            Expr::ConsoleRead(_) => {
+                ctx.text_section.push_str("    ; Read input and drop it\n");
+                generate_value_code(expr, ctx);
+            },
+            Expr::Fs { .. } | Expr::Time { .. } | Expr::Random { .. } => {
+                generate_value_code(expr, ctx);
+            },
+            Expr::If { branches, else_branch, .. } => {
+				// The whole else if chain shares one end label
+				let id = ctx.counter;
+				ctx.counter += 1;
+				let label_end = format!("if_end_{}", id);
 				
-				text_section.push_str("    ; Then-Branch\n");
-				process_statements(then_branch, text_section, data_section, bss_section, constants, variables, string_labels, string_counter);
+				for (i, (condition, statements)) in branches.iter().enumerate() {
+					let is_last = i + 1 == branches.len();
+					let label_next = if !is_last {
+						format!("if_next_{}_{}", id, i)
+					} else if else_branch.is_some() {
+						format!("if_else_{}", id)
+					} else {
+						label_end.clone()
+					};
+					
+					ctx.text_section.push_str(if i == 0 { "    ; If-Statement (condition evaluation)\n" } else { "    ; Else-If (condition evaluation)\n" });
+					generate_expression_code(condition, ctx);
+					ctx.text_section.push_str("    test rax, rax\n");
+					ctx.text_section.push_str(&format!("    jz {}\n", label_next));
+					
+					ctx.text_section.push_str("    ; Then-Branch\n");
+					process_statements(statements, ctx);
+					
+					if label_next != label_end {
+						ctx.text_section.push_str(&format!("    jmp {}\n", label_end));
+						ctx.text_section.push_str(&format!("{}:\n", label_next));
+					}
+				}
 				
 				if let Some(else_b) = else_branch {
-					text_section.push_str(&format!("    jmp {}\n", label_end));
-					text_section.push_str(&format!("{}:\n", label_else));
-					text_section.push_str("    ; Else-Branch\n");
-					process_statements(else_b, text_section, data_section, bss_section, constants, variables, string_labels, string_counter);
+					ctx.text_section.push_str("    ; Else-Branch\n");
+					process_statements(else_b, ctx);
 				}
 				
-				text_section.push_str(&format!("{}:\n", label_end));
+				ctx.text_section.push_str(&format!("{}:\n", label_end));
 			},
             _ => {
                 // Handle as expression if applicable
-                text_section.push_str("    ; Expression evaluation\n");
-                generate_expression_code(expr, text_section, constants, variables);
+                ctx.text_section.push_str("    ; Expression evaluation\n");
+                generate_expression_code(expr, ctx);
             }
         }
     }
     
-    fn generate_print_code(inner: &Expr, text_section: &mut String, constants: &HashMap<String, ConstValue>, variables: &HashMap<String, ConstValue>, string_labels: &HashMap<String, String>, data_section: &mut String, string_counter: &mut usize) {
+    fn generate_print_code(inner: &Expr, ctx: &mut Codegen) {
         match inner {
             Expr::StringLiteral(s) => {
-                let label = string_labels.get(s).expect("String label not found");
-                text_section.push_str(&format!("    ; Print: {}\n", s));
-                text_section.push_str("    mov rax, 1          ; sys_write\n");
-                text_section.push_str("    mov rdi, 1          ; stdout\n");
-                text_section.push_str(&format!("    mov rsi, {}\n", label));
-                text_section.push_str(&format!("    mov rdx, {}\n", s.len() + 1));
-                text_section.push_str("    syscall\n\n");
+                let label = ctx.string_labels.get(s).expect("String label not found");
+                ctx.text_section.push_str(&format!("    ; Print: {}\n", s.escape_default()));
+                ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                ctx.text_section.push_str(&format!("    mov rsi, {}\n", label));
+                ctx.text_section.push_str(&format!("    mov rdx, {}\n", s.len() + 1));
+                ctx.text_section.push_str("    syscall\n\n");
             },
             Expr::Boolean(b) => {
-                text_section.push_str(&format!("    ; Print boolean: {}\n", b));
-                text_section.push_str("    mov rax, 1          ; sys_write\n");
-                text_section.push_str("    mov rdi, 1          ; stdout\n");
+                ctx.text_section.push_str(&format!("    ; Print boolean: {}\n", b));
+                ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
                 if *b {
-                    text_section.push_str("    mov rsi, true_str\n");
-                    text_section.push_str("    mov rdx, 5       ; 'true' + newline\n");
+                    ctx.text_section.push_str("    mov rsi, true_str\n");
+                    ctx.text_section.push_str("    mov rdx, 5       ; 'true' + newline\n");
                 } else {
-                    text_section.push_str("    mov rsi, false_str\n");
-                    text_section.push_str("    mov rdx, 6       ; 'false' + newline\n");
+                    ctx.text_section.push_str("    mov rsi, false_str\n");
+                    ctx.text_section.push_str("    mov rdx, 6       ; 'false' + newline\n");
                 }
-                text_section.push_str("    syscall\n\n");
+                ctx.text_section.push_str("    syscall\n\n");
             },
             Expr::Null => {
-                text_section.push_str("    ; Print null\n");
-                text_section.push_str("    mov rax, 1          ; sys_write\n");
-                text_section.push_str("    mov rdi, 1          ; stdout\n");
-                text_section.push_str("    mov rsi, null_str\n");
-                text_section.push_str("    mov rdx, 5          ; 'null' + newline\n");
-                text_section.push_str("    syscall\n\n");
+                ctx.text_section.push_str("    ; Print null\n");
+                ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                ctx.text_section.push_str("    mov rsi, null_str\n");
+                ctx.text_section.push_str("    mov rdx, 5          ; 'null' + newline\n");
+                ctx.text_section.push_str("    syscall\n\n");
+            },
+            Expr::Array(_) if !is_constant_expr(inner, &ctx.types) => {
+                ctx.text_section.push_str("    ; Print runtime array\n");
+                generate_value_code(inner, ctx);
+                ctx.text_section.push_str("    call print_value\n\n");
             },
             Expr::Array(elements) => {
-                text_section.push_str("    ; Print array\n");
-                text_section.push_str("    mov rax, 1          ; sys_write\n");
-                text_section.push_str("    mov rdi, 1          ; stdout\n");
-                text_section.push_str("    mov rsi, array_open\n");
-                text_section.push_str("    mov rdx, 1          ; '['\n");
-                text_section.push_str("    syscall\n\n");
+                ctx.text_section.push_str("    ; Print array\n");
+                ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                ctx.text_section.push_str("    mov rsi, array_open\n");
+                ctx.text_section.push_str("    mov rdx, 1          ; '['\n");
+                ctx.text_section.push_str("    syscall\n\n");
                 
                 for (i, elem) in elements.iter().enumerate() {
-                    print_array_element(elem, text_section, constants, variables, string_labels, data_section, string_counter);
+                    print_array_element(elem, ctx);
                     
                     if i < elements.len() - 1 {
-                        text_section.push_str("    mov rax, 1          ; sys_write\n");
-                        text_section.push_str("    mov rdi, 1          ; stdout\n");
-                        text_section.push_str("    mov rsi, array_separator\n");
-                        text_section.push_str("    mov rdx, 2          ; ', '\n");
-                        text_section.push_str("    syscall\n\n");
+                        ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                        ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                        ctx.text_section.push_str("    mov rsi, array_separator\n");
+                        ctx.text_section.push_str("    mov rdx, 2          ; ', '\n");
+                        ctx.text_section.push_str("    syscall\n\n");
                     }
                 }
                 
-                text_section.push_str("    mov rax, 1          ; sys_write\n");
-                text_section.push_str("    mov rdi, 1          ; stdout\n");
-                text_section.push_str("    mov rsi, array_close\n");
-                text_section.push_str("    mov rdx, 2          ; ']' + newline\n");
-                text_section.push_str("    syscall\n\n");
+                ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                ctx.text_section.push_str("    mov rsi, array_close\n");
+                ctx.text_section.push_str("    mov rdx, 2          ; ']' + newline\n");
+                ctx.text_section.push_str("    syscall\n\n");
+            },
+            Expr::Variable(name) if is_array_expr(inner, &ctx.types) || is_map_expr(inner, &ctx.types)
+                || is_struct_expr(inner, &ctx.types) || is_enum_expr(inner, &ctx.types) => {
+                if let Some(value) = ctx.types.constants.get(name).cloned() {
+                    generate_print_const_value(&value, ctx);
+                } else {
+                    ctx.text_section.push_str(&format!("    ; Print variable: {}\n", name));
+                    generate_value_code(inner, ctx);
+                    ctx.text_section.push_str("    call print_value\n\n");
+                }
+            },
+            Expr::Variable(name) if is_tagged_var(name, &ctx.types.variables) => {
+                ctx.text_section.push_str(&format!("    ; Print variable with a runtime type: {}\n", name));
+                generate_value_code(inner, ctx);
+                ctx.text_section.push_str("    call print_value\n\n");
             },
             Expr::Variable(name) => {
-                let in_constants = constants.contains_key(name);
-                let in_variables = variables.contains_key(name);
+                let in_constants = ctx.types.constants.contains_key(name);
+                let in_variables = ctx.types.variables.contains_key(name);
                 if !in_constants && !in_variables {
                     panic!("Undefined variable: {}", name);
                 }
                 let value = if in_constants {
-                    constants.get(name).unwrap()
+                    ctx.types.constants.get(name).unwrap()
                 } else {
-                    variables.get(name).unwrap()
+                    ctx.types.variables.get(name).unwrap()
                 };
                 let var_label = get_var_label(name, None);
-                text_section.push_str(&format!("    ; Print variable: {}\n", name));
-                text_section.push_str("    mov rdi, str_buffer  ; Destination buffer\n");
-                text_section.push_str("    xor rcx, rcx         ; Reset counter\n");
-                text_section.push_str("    xor rax, rax         ; Zero for stosb\n");
-                text_section.push_str("    mov rcx, 1024        ; Buffer size\n");
-                text_section.push_str("    cld                  ; Clear direction flag\n");
-                text_section.push_str("    rep stosb           ; Fill buffer with zeros\n");
-                text_section.push_str("    mov rdi, str_buffer  ; Reset destination buffer\n");
-                text_section.push_str("    xor rcx, rcx         ; Reset counter\n\n");
+                ctx.text_section.push_str(&format!("    ; Print variable: {}\n", name));
+                ctx.text_section.push_str("    mov rdi, str_buffer  ; Destination buffer\n");
+                ctx.text_section.push_str("    xor rcx, rcx         ; Reset counter\n");
+                ctx.text_section.push_str("    xor rax, rax         ; Zero for stosb\n");
+                ctx.text_section.push_str("    mov rcx, 1024        ; Buffer size\n");
+                ctx.text_section.push_str("    cld                  ; Clear direction flag\n");
+                ctx.text_section.push_str("    rep stosb           ; Fill buffer with zeros\n");
+                ctx.text_section.push_str("    mov rdi, str_buffer  ; Reset destination buffer\n");
+                ctx.text_section.push_str("    xor rcx, rcx         ; Reset counter\n\n");
                 match value {
                     ConstValue::String(_) => {
+                        // Only the constant's label has a newline at the end
                         if in_constants {
-                            text_section.push_str(&format!("    mov rsi, {}\n", var_label));
+                            ctx.text_section.push_str(&format!("    mov rsi, {}\n", var_label));
+                            ctx.text_section.push_str("    call append_string_without_newline\n");
                         } else {
-                            text_section.push_str(&format!("    mov rsi, [var_mem_{}]\n", name));
+                            ctx.text_section.push_str(&format!("    mov rsi, [var_mem_{}]\n", name));
+                            ctx.text_section.push_str("    call append_string\n");
                         }
-                        text_section.push_str("    call append_string_without_newline\n");
                     },
                     ConstValue::Number(n) => {
                         if in_constants {
-                            text_section.push_str(&format!("    mov rax, {}\n", n));
+                            ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
                         } else {
-                            text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                            ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
                         }
-                        text_section.push_str("    call append_number\n");
+                        ctx.text_section.push_str("    call append_number\n");
                     },
                     ConstValue::Float(_) => {
                         let float_label = get_var_label(name, Some("_float"));
                         if in_constants {
-                            text_section.push_str(&format!("    mov rsi, {}\n", float_label));
+                            ctx.text_section.push_str(&format!("    mov rsi, {}\n", float_label));
                         } else {
-                            text_section.push_str(&format!("    mov rsi, [var_mem_{}_float]\n", name));
+                            ctx.text_section.push_str(&format!("    mov rsi, [var_mem_{}_float]\n", name));
                         }
-                        text_section.push_str("    call append_string_without_newline\n");
+                        ctx.text_section.push_str("    call append_string_without_newline\n");
                     },
                     ConstValue::Boolean(b) => {
                         if in_constants {
                             if *b {
-                                text_section.push_str("    mov rsi, true_str\n");
+                                ctx.text_section.push_str("    mov rsi, true_str\n");
                             } else {
-                                text_section.push_str("    mov rsi, false_str\n");
+                                ctx.text_section.push_str("    mov rsi, false_str\n");
                             }
                         } else {
-                            text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
-                            text_section.push_str("    cmp rax, 0\n");
-                            text_section.push_str(&format!("    je .false_{}\n", name));
-                            text_section.push_str("    mov rsi, true_str\n");
-                            text_section.push_str(&format!("    jmp .done_{}\n", name));
-                            text_section.push_str(&format!(".false_{}:\n", name));
-                            text_section.push_str("    mov rsi, false_str\n");
-                            text_section.push_str(&format!(".done_{}:\n", name));
+                            ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                            ctx.text_section.push_str("    cmp rax, 0\n");
+                            ctx.text_section.push_str(&format!("    je .false_{}\n", name));
+                            ctx.text_section.push_str("    mov rsi, true_str\n");
+                            ctx.text_section.push_str(&format!("    jmp .done_{}\n", name));
+                            ctx.text_section.push_str(&format!(".false_{}:\n", name));
+                            ctx.text_section.push_str("    mov rsi, false_str\n");
+                            ctx.text_section.push_str(&format!(".done_{}:\n", name));
+                        }
+                        ctx.text_section.push_str("    call append_string_without_newline\n");
+                    },
+                    ConstValue::Char(_) => {
+                        if in_constants {
+                            ctx.text_section.push_str(&format!("    mov rsi, {}\n", var_label));
+                            ctx.text_section.push_str("    call append_string_without_newline\n");
+                        } else {
+                            ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                            ctx.text_section.push_str("    call str_from_char\n");
+                            ctx.text_section.push_str("    mov rsi, rax\n");
+                            ctx.text_section.push_str("    call append_string\n");
                         }
-                        text_section.push_str("    call append_string_without_newline\n");
                     },
                     ConstValue::Null => {
-                        text_section.push_str("    mov rsi, null_str\n");
-                        text_section.push_str("    call append_string_without_newline\n");
+                        ctx.text_section.push_str("    mov rsi, null_str\n");
+                        ctx.text_section.push_str("    call append_string_without_newline\n");
                     },
-                    ConstValue::Array(_) => {
+                    ConstValue::Array(_) | ConstValue::Map(_) | ConstValue::Struct { .. } | ConstValue::Enum { .. } => {
                         let array_label = get_var_label(name, Some("_label"));
-                        text_section.push_str(&format!("    mov rsi, {}\n", array_label));
-                        text_section.push_str("    call append_string_without_newline\n");
+                        ctx.text_section.push_str(&format!("    mov rsi, {}\n", array_label));
+                        ctx.text_section.push_str("    call append_string_without_newline\n");
                     },
                 }
-                text_section.push_str("    mov byte [rdi], 10   ; Add newline\n");
-                text_section.push_str("    inc rdi\n");
-                text_section.push_str("    inc rcx              ; Count the newline\n");
-                text_section.push_str("    mov byte [rdi], 0    ; Add null terminator\n");
-                text_section.push_str("    mov rax, 1           ; sys_write\n");
-                text_section.push_str("    mov rdi, 1           ; stdout\n");
-                text_section.push_str("    mov rsi, str_buffer  ; String buffer\n");
-                text_section.push_str("    mov rdx, rcx         ; String length\n");
-                text_section.push_str("    syscall\n\n");
+                ctx.text_section.push_str("    mov byte [rdi], 10   ; Add newline\n");
+                ctx.text_section.push_str("    inc rdi\n");
+                ctx.text_section.push_str("    inc rcx              ; Count the newline\n");
+                ctx.text_section.push_str("    mov byte [rdi], 0    ; Add null terminator\n");
+                ctx.text_section.push_str("    mov rax, 1           ; sys_write\n");
+                ctx.text_section.push_str("    mov rdi, 1           ; stdout\n");
+                ctx.text_section.push_str("    mov rsi, str_buffer  ; String buffer\n");
+                ctx.text_section.push_str("    mov rdx, rcx         ; String length\n");
+                ctx.text_section.push_str("    syscall\n\n");
             },
             Expr::Number(n) => {
-                text_section.push_str(&format!("    ; Print number: {}\n", n));
-                text_section.push_str(&format!("    mov rax, {}\n", n));
-                text_section.push_str("    push rax\n");
-                text_section.push_str("    call print_number\n");
-                text_section.push_str("    add rsp, 8\n\n");
+                ctx.text_section.push_str(&format!("    ; Print number: {}\n", n));
+                ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
+                ctx.text_section.push_str("    push rax\n");
+                ctx.text_section.push_str("    call print_number\n");
+                ctx.text_section.push_str("    add rsp, 8\n\n");
             },
             Expr::Float(f) => {
-                let num_label = format!("float_{}", *string_counter);
-                *string_counter += 1;
+                let num_label = format!("float_{}", ctx.counter);
+                ctx.counter += 1;
                 let num_str = f.to_string();
-                data_section.push_str(&format!("{} db \"{}\", 10, 0\n", num_label, num_str));
-                text_section.push_str(&format!("    ; Print float: {}\n", f));
-                text_section.push_str("    mov rax, 1          ; sys_write\n");
-                text_section.push_str("    mov rdi, 1          ; stdout\n");
-                text_section.push_str(&format!("    mov rsi, {}\n", num_label));
-                text_section.push_str(&format!("    mov rdx, {}\n", num_str.len() + 1));
-                text_section.push_str("    syscall\n\n");
+                ctx.data_section.push_str(&runtime::string_data(&num_label, &format!("{}\n", num_str)));
+                ctx.text_section.push_str(&format!("    ; Print float: {}\n", f));
+                ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                ctx.text_section.push_str(&format!("    mov rsi, {}\n", num_label));
+                ctx.text_section.push_str(&format!("    mov rdx, {}\n", num_str.len() + 1));
+                ctx.text_section.push_str("    syscall\n\n");
+            },
+            Expr::BinaryOp { .. } | Expr::UnaryOp { .. } if is_float_expr(inner, &ctx.types) => {
+                // Float expressions are folded, see needs_runtime_evaluation
+                check_float_operands(inner, &ctx.types);
+                let value = evaluate_constant_expr(inner, &ctx.types);
+                generate_print_const_value(&value, ctx);
             },
-            Expr::BinaryOp { op, left, right } => {
-                if *op == BinOp::Add && (is_string_expr(left, constants, variables) || is_string_expr(right, constants, variables)) {
-                    text_section.push_str("    mov rdi, str_buffer  ; Destination buffer\n");
-                    text_section.push_str("    xor rcx, rcx         ; Reset counter\n");
-                    text_section.push_str("    xor rax, rax         ; Zero for stosb\n");
-                    text_section.push_str("    mov rcx, 1024        ; Buffer size\n");
-                    text_section.push_str("    cld                  ; Clear direction flag\n");
-                    text_section.push_str("    rep stosb           ; Fill buffer with zeros\n");
-                    text_section.push_str("    mov rdi, str_buffer  ; Reset destination buffer\n");
-                    text_section.push_str("    xor rcx, rcx         ; Reset counter\n\n");
+            Expr::BinaryOp { op: BinOp::Add, .. } if is_constant_expr(inner, &ctx.types) && is_string_expr(inner, &ctx.types) => {
+                // Constant concatenations and template strings print folded
+                let value = evaluate_constant_expr(inner, &ctx.types);
+                generate_print_const_value(&value, ctx);
+            },
+            Expr::BinaryOp { op, left, right, .. } => {
+                if *op == BinOp::Add && (is_string_expr(left, &ctx.types) || is_string_expr(right, &ctx.types)) {
+                    ctx.text_section.push_str("    mov rdi, str_buffer  ; Destination buffer\n");
+                    ctx.text_section.push_str("    xor rcx, rcx         ; Reset counter\n");
+                    ctx.text_section.push_str("    xor rax, rax         ; Zero for stosb\n");
+                    ctx.text_section.push_str("    mov rcx, 1024        ; Buffer size\n");
+                    ctx.text_section.push_str("    cld                  ; Clear direction flag\n");
+                    ctx.text_section.push_str("    rep stosb           ; Fill buffer with zeros\n");
+                    ctx.text_section.push_str("    mov rdi, str_buffer  ; Reset destination buffer\n");
+                    ctx.text_section.push_str("    xor rcx, rcx         ; Reset counter\n\n");
                     
-                    generate_string_concat(left, text_section, constants, variables, string_labels, data_section, string_counter);
+                    generate_string_concat(left, ctx);
                     
-                    generate_string_concat(right, text_section, constants, variables, string_labels, data_section, string_counter);
+                    generate_string_concat(right, ctx);
                     
-                    text_section.push_str("    mov byte [rdi], 10   ; Add newline\n");
-                    text_section.push_str("    inc rdi\n");
-                    text_section.push_str("    inc rcx              ; Count the newline\n");
-                    text_section.push_str("    mov byte [rdi], 0    ; Add null terminator\n");
-                    text_section.push_str("    mov rax, 1           ; sys_write\n");
-                    text_section.push_str("    mov rdi, 1           ; stdout\n");
-                    text_section.push_str("    mov rsi, str_buffer  ; String buffer\n");
-                    text_section.push_str("    mov rdx, rcx         ; String length\n");
-                    text_section.push_str("    syscall\n\n");
+                    ctx.text_section.push_str("    mov byte [rdi], 10   ; Add newline\n");
+                    ctx.text_section.push_str("    inc rdi\n");
+                    ctx.text_section.push_str("    inc rcx              ; Count the newline\n");
+                    ctx.text_section.push_str("    mov byte [rdi], 0    ; Add null terminator\n");
+                    ctx.text_section.push_str("    mov rax, 1           ; sys_write\n");
+                    ctx.text_section.push_str("    mov rdi, 1           ; stdout\n");
+                    ctx.text_section.push_str("    mov rsi, str_buffer  ; String buffer\n");
+                    ctx.text_section.push_str("    mov rdx, rcx         ; String length\n");
+                    ctx.text_section.push_str("    syscall\n\n");
+                } else {
+                    ctx.text_section.push_str("    ; Print numeric expression result\n");
+                    generate_expression_code(inner, ctx);
+                    ctx.text_section.push_str("    push rax\n");
+                    ctx.text_section.push_str("    call print_number\n");
+                    ctx.text_section.push_str("    add rsp, 8\n\n");
+                }
+            },
+            Expr::MethodCall { .. } | Expr::Index { .. } | Expr::Map(_) | Expr::StructLiteral { .. } | Expr::FieldAccess { .. }
+            | Expr::Variant { .. } | Expr::ConsoleRead(_) | Expr::ProcessArgs | Expr::ProcessEnv(_) | Expr::Fs { .. }
+            | Expr::Math { .. } | Expr::Time { .. } | Expr::Random { .. } | Expr::Char(_) | Expr::CharFromCode { .. } => {
+                if is_constant_expr(inner, &ctx.types) {
+                    let value = evaluate_constant_expr(inner, &ctx.types);
+                    generate_print_const_value(&value, ctx);
                 } else {
-                    text_section.push_str("    ; Print numeric expression result\n");
-                    generate_expression_code(inner, text_section, constants, variables);
-                    text_section.push_str("    push rax\n");
-                    text_section.push_str("    call print_number\n");
-                    text_section.push_str("    add rsp, 8\n\n");
+                    ctx.text_section.push_str("    ; Print runtime value\n");
+                    generate_value_code(inner, ctx);
+                    ctx.text_section.push_str("    call print_value\n\n");
                 }
             },
             _ => {
-                text_section.push_str("    ; Print expression result\n");
-                generate_expression_code(inner, text_section, constants, variables);
-                text_section.push_str("    push rax\n");
-                text_section.push_str("    call print_number\n");
-                text_section.push_str("    add rsp, 8\n\n");
+                ctx.text_section.push_str("    ; Print expression result\n");
+                generate_expression_code(inner, ctx);
+                ctx.text_section.push_str("    push rax\n");
+                ctx.text_section.push_str("    call print_number\n");
+                ctx.text_section.push_str("    add rsp, 8\n\n");
             }
         }
     }
     
-    fn generate_exit_code(code: &Expr, text_section: &mut String, constants: &HashMap<String, ConstValue>, variables: &HashMap<String, ConstValue>) {
+    fn generate_exit_code(code: &Expr, ctx: &mut Codegen) {
         match code {
             Expr::Number(n) => {
-                text_section.push_str("    ; Exit program\n");
-                text_section.push_str("    mov rax, 60         ; sys_exit\n");
-                text_section.push_str(&format!("    mov rdi, {}\n", n));
-                text_section.push_str("    syscall\n\n");
+                ctx.text_section.push_str("    ; Exit program\n");
+                ctx.text_section.push_str("    mov rax, 60         ; sys_exit\n");
+                ctx.text_section.push_str(&format!("    mov rdi, {}\n", n));
+                ctx.text_section.push_str("    syscall\n\n");
             },
             Expr::Variable(name) => {
-                if constants.contains_key(name) || variables.contains_key(name) {
-                    text_section.push_str(&format!("    ; Exit program with variable {}\n", name));
-                    text_section.push_str("    mov rax, 60         ; sys_exit\n");
+                if ctx.types.constants.contains_key(name) || ctx.types.variables.contains_key(name) {
+                    ctx.text_section.push_str(&format!("    ; Exit program with variable {}\n", name));
+                    ctx.text_section.push_str("    mov rax, 60         ; sys_exit\n");
                     
-                    if let Some(value) = constants.get(name) {
+                    if let Some(value) = ctx.types.constants.get(name) {
                         match value {
                             ConstValue::Number(n) => {
-                                text_section.push_str(&format!("    mov rdi, {}\n", n));
+                                ctx.text_section.push_str(&format!("    mov rdi, {}\n", n));
                             },
                             ConstValue::Boolean(b) => {
-                                text_section.push_str(&format!("    mov rdi, {}\n", if *b { 1 } else { 0 }));
+                                ctx.text_section.push_str(&format!("    mov rdi, {}\n", if *b { 1 } else { 0 }));
                             },
                             _ => {
-                                text_section.push_str("    mov rdi, 0      ; Non-numeric value defaults to 0\n");
+                                ctx.text_section.push_str("    mov rdi, 0      ; Non-numeric value defaults to 0\n");
                             }
                         }
-                    } else if let Some(value) = variables.get(name) {
+                    } else if let Some(value) = ctx.types.variables.get(name) {
                         match value {
                             ConstValue::Number(_) | ConstValue::Boolean(_) => {
-                                text_section.push_str(&format!("    mov rdi, [var_mem_{}]\n", name));
+                                ctx.text_section.push_str(&format!("    mov rdi, [var_mem_{}]\n", name));
                             },
                             _ => {
-                                text_section.push_str("    mov rdi, 0      ; Non-numeric value defaults to 0\n");
+                                ctx.text_section.push_str("    mov rdi, 0      ; Non-numeric value defaults to 0\n");
                             }
                         }
                     }
-                    text_section.push_str("    syscall\n\n");
+                    ctx.text_section.push_str("    syscall\n\n");
                 } else {
                     panic!("Undefined variable in exit: {}", name);
                 }
             },
             _ => {
-                text_section.push_str("    ; Exit program with expression result\n");
-                generate_expression_code(code, text_section, constants, variables);
-                text_section.push_str("    mov rdi, rax        ; Move result to exit code\n");
-                text_section.push_str("    mov rax, 60         ; sys_exit\n");
-                text_section.push_str("    syscall\n\n");
+                ctx.text_section.push_str("    ; Exit program with expression result\n");
+                generate_expression_code(code, ctx);
+                ctx.text_section.push_str("    mov rdi, rax        ; Move result to exit code\n");
+                ctx.text_section.push_str("    mov rax, 60         ; sys_exit\n");
+                ctx.text_section.push_str("    syscall\n\n");
             }
         }
     }
 
-	fn generate_const_code(name: &str, value: &Expr, text_section: &mut String, data_section: &mut String, 
-                      constants: &mut HashMap<String, ConstValue>, variables: &HashMap<String, ConstValue>,
-                      _string_counter: &mut usize) {
-		if constants.contains_key(name) {
+	fn generate_const_code(name: &str, value: &Expr, ctx: &mut Codegen) {
+		if ctx.types.constants.contains_key(name) {
 			panic!("Constant '{}' already defined", name);
 		}
+		if !is_constant_expr(value, &ctx.types) {
+			panic!("Constant '{}' can only be initialised from literals and other constants", name);
+		}
 		
-		let const_value = evaluate_constant_expr(value, constants, variables);
-		constants.insert(name.to_string(), const_value.clone());
+		let const_value = evaluate_constant_expr(value, &ctx.types);
+		ctx.types.constants.insert(name.to_string(), const_value.clone());
 		
 		match const_value {
 			ConstValue::Number(n) => {
 				let var_label = get_var_label(name, None);
 				let value_str = n.to_string();
-				data_section.push_str(&format!("{} db \"{}\", 10, 0\n", var_label, value_str));
-				text_section.push_str(&format!("    ; Constant {} = {}\n", name, n));
+				ctx.data_section.push_str(&runtime::string_data(&var_label, &format!("{}\n", value_str)));
+				ctx.text_section.push_str(&format!("    ; Constant {} = {}\n", name, n));
 			},
 			ConstValue::Float(f) => {
 				let var_label = get_var_label(name, None);
 				let float_label = get_var_label(name, Some("_float"));
 				let float_str = f.to_string();
-				data_section.push_str(&format!("{} db \"{}\", 10, 0\n", var_label, float_str));
-				data_section.push_str(&format!("{} db \"{}\", 10, 0\n", float_label, float_str));
-				text_section.push_str(&format!("    ; Constant {} = {}\n", name, f));
+				ctx.data_section.push_str(&runtime::string_data(&var_label, &format!("{}\n", float_str)));
+				ctx.data_section.push_str(&runtime::string_data(&float_label, &format!("{}\n", float_str)));
+				ctx.text_section.push_str(&format!("    ; Constant {} = {}\n", name, f));
 			},
 			ConstValue::String(s) => {
 				let var_label = get_var_label(name, None);
-				data_section.push_str(&format!("{} db \"{}\", 10, 0\n", var_label, s));
-				text_section.push_str(&format!("    ; Constant {} = \"{}\"\n", name, s));
+				ctx.data_section.push_str(&runtime::string_data(&var_label, &format!("{}\n", s)));
+				ctx.text_section.push_str(&format!("    ; Constant {} = \"{}\"\n", name, s.escape_default()));
 			},
 			ConstValue::Boolean(b) => {
 				let var_label = get_var_label(name, None);
 				let value_str = if b { "true" } else { "false" };
-				data_section.push_str(&format!("{} db \"{}\", 10, 0\n", var_label, value_str));
-				text_section.push_str(&format!("    ; Constant {} = {}\n", name, b));
+				ctx.data_section.push_str(&runtime::string_data(&var_label, &format!("{}\n", value_str)));
+				ctx.text_section.push_str(&format!("    ; Constant {} = {}\n", name, b));
+			},
+			ConstValue::Char(c) => {
+				let var_label = get_var_label(name, None);
+				ctx.data_section.push_str(&runtime::string_data(&var_label, &format!("{}\n", c)));
+				ctx.text_section.push_str(&format!("    ; Constant {} = '{}'\n", name, c.escape_default()));
 			},
 			ConstValue::Array(values) => {
 				let var_label = get_var_label(name, Some("_label"));
-				data_section.push_str(&format!("{} db \"[Array]\", 10, 0\n", var_label));
-				text_section.push_str(&format!("    ; Constant {} = [array with {} elements]\n", name, values.len()));
+				ctx.data_section.push_str(&runtime::string_data(&var_label, "[Array]\n"));
+				ctx.text_section.push_str(&format!("    ; Constant {} = [array with {} elements]\n", name, values.len()));
+			},
+			ConstValue::Map(entries) => {
+				ctx.text_section.push_str(&format!("    ; Constant {} = {{map with {} entries}}\n", name, entries.len()));
+			},
+			ConstValue::Struct { name: struct_name, .. } => {
+				ctx.text_section.push_str(&format!("    ; Constant {} = {} {{...}}\n", name, struct_name));
+			},
+			ConstValue::Enum { variant, .. } => {
+				ctx.text_section.push_str(&format!("    ; Constant {} = {}\n", name, variant));
 			},
 			ConstValue::Null => {
 				let var_label = get_var_label(name, None);
-				data_section.push_str(&format!("{} db \"null\", 10, 0\n", var_label));
-				text_section.push_str(&format!("    ; Constant {} = null\n", name));
+				ctx.data_section.push_str(&runtime::string_data(&var_label, "null\n"));
+				ctx.text_section.push_str(&format!("    ; Constant {} = null\n", name));
 			},
 		}
 	}
 
-	fn generate_let_code(name: &str, value: &Expr, text_section: &mut String, data_section: &mut String, 
-                    bss_section: &mut String, constants: &HashMap<String, ConstValue>, 
-                    variables: &mut HashMap<String, ConstValue>, _string_counter: &mut usize) {
-		if constants.contains_key(name) {
+	fn generate_let_code(name: &str, value: &Expr, ctx: &mut Codegen) {
+		if ctx.types.constants.contains_key(name) {
 			panic!("Cannot declare variable '{}', a constant with the same name already exists", name);
 		}
-		if variables.contains_key(name) {
+		if ctx.types.variables.contains_key(name) {
 			panic!("Variable '{}' already defined", name);
 		}
 		
-		let var_value = evaluate_constant_expr(value, constants, variables);
-		variables.insert(name.to_string(), var_value.clone());
+		// A match binding of the same name may have reserved it already
+		if !ctx.bound.contains(name) {
+			ctx.bss_section.push_str(&format!("var_mem_{}: resq 1  ; Memory for variable {}\n", name, name));
+		}
+		if needs_runtime_evaluation(value, &ctx.types) {
+			generate_runtime_store(name, value, ctx);
+			return;
+		}
+		
+		let var_value = evaluate_constant_expr(value, &ctx.types);
+		ctx.types.variables.insert(name.to_string(), var_value.clone());
 		
 		match var_value {
 			ConstValue::Number(n) => {
 				let var_label = get_var_label(name, None);
 				let value_str = n.to_string();
-				data_section.push_str(&format!("{} db \"{}\", 10, 0\n", var_label, value_str));
-				text_section.push_str(&format!("    ; Variable {} = {}\n", name, n));
-				bss_section.push_str(&format!("var_mem_{}: resq 1  ; Memory for variable {}\n", name, name));
-				text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, n));
+				ctx.data_section.push_str(&runtime::string_data(&var_label, &format!("{}\n", value_str)));
+				ctx.text_section.push_str(&format!("    ; Variable {} = {}\n", name, n));
+				// Through RAX, a memory store only takes a 32-bit immediate
+				ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
+				ctx.text_section.push_str(&format!("    mov [var_mem_{}], rax\n", name));
 			},
 			ConstValue::Float(f) => {
 				let var_label = get_var_label(name, None);
 				let float_label = get_var_label(name, Some("_float"));
 				let float_str = f.to_string();
-				data_section.push_str(&format!("{} db \"{}\", 10, 0\n", var_label, float_str));
-				data_section.push_str(&format!("{} db \"{}\", 10, 0\n", float_label, float_str));
-				bss_section.push_str(&format!("var_mem_{}_float: resq 1  ; String rep for float {}\n", name, name));
-				text_section.push_str(&format!("    mov qword [var_mem_{}_float], {}\n", name, float_label));
-				text_section.push_str(&format!("    ; Variable {} = {}\n", name, f));
+				ctx.data_section.push_str(&runtime::string_data(&var_label, &format!("{}\n", float_str)));
+				ctx.data_section.push_str(&runtime::string_data(&float_label, &float_str));
+				ctx.bss_section.push_str(&format!("var_mem_{}_float: resq 1  ; String rep for float {}\n", name, name));
+				ctx.text_section.push_str(&format!("    mov qword [var_mem_{}_float], {}\n", name, float_label));
+				ctx.text_section.push_str(&format!("    ; Variable {} = {}\n", name, f));
 				let int_val = (f * 100.0) as i64;
-				bss_section.push_str(&format!("var_mem_{}: resq 1  ; Memory for variable {}\n", name, name));
-				text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, int_val));
+				ctx.text_section.push_str(&format!("    mov rax, {}\n", int_val));
+				ctx.text_section.push_str(&format!("    mov [var_mem_{}], rax\n", name));
 			},
 			ConstValue::String(s) => {
 				let var_label = get_var_label(name, None);
-				data_section.push_str(&format!("{} db \"{}\", 10, 0\n", var_label, s));
-				text_section.push_str(&format!("    ; Variable {} = \"{}\"\n", name, s));
-				bss_section.push_str(&format!("var_mem_{}: resq 1  ; Memory for variable {}\n", name, name));
-				text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, var_label));
+				// Runtime string values have no newline at the end, unlike the print labels
+				ctx.data_section.push_str(&runtime::string_data(&var_label, &s));
+				ctx.text_section.push_str(&format!("    ; Variable {} = \"{}\"\n", name, s.escape_default()));
+				ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, var_label));
 			},
 			ConstValue::Boolean(b) => {
 				let var_label = get_var_label(name, None);
 				let value_str = if b { "true" } else { "false" };
-				data_section.push_str(&format!("{} db \"{}\", 10, 0\n", var_label, value_str));
-				text_section.push_str(&format!("    ; Variable {} = {}\n", name, b));
-				bss_section.push_str(&format!("var_mem_{}: resq 1  ; Memory for variable {}\n", name, name));
-				text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, if b { 1 } else { 0 }));
+				ctx.data_section.push_str(&runtime::string_data(&var_label, &format!("{}\n", value_str)));
+				ctx.text_section.push_str(&format!("    ; Variable {} = {}\n", name, b));
+				ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, if b { 1 } else { 0 }));
 			},
-			ConstValue::Array(values) => {
-				let var_label = get_var_label(name, Some("_label"));
-				data_section.push_str(&format!("{} db \"[Array]\", 10, 0\n", var_label));
-				text_section.push_str(&format!("    ; Variable {} = [array with {} elements]\n", name, values.len()));
-				bss_section.push_str(&format!("var_mem_{}: resq 1  ; Memory for variable {}\n", name, name));
-				text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, var_label));
+			ConstValue::Char(c) => {
+				ctx.text_section.push_str(&format!("    ; Variable {} = '{}'\n", name, c.escape_default()));
+				ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, c as u32));
+			},
+			ConstValue::Array(ref values) => {
+				ctx.text_section.push_str(&format!("    ; Variable {} = [array with {} elements]\n", name, values.len()));
+				generate_const_value_code(&var_value, ctx);
+				ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], rax\n", name));
+			},
+			ConstValue::Map(ref entries) => {
+				ctx.text_section.push_str(&format!("    ; Variable {} = {{map with {} entries}}\n", name, entries.len()));
+				generate_const_value_code(&var_value, ctx);
+				ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], rax\n", name));
+			},
+			ConstValue::Struct { name: ref struct_name, .. } => {
+				ctx.text_section.push_str(&format!("    ; Variable {} = {} {{...}}\n", name, struct_name));
+				generate_const_value_code(&var_value, ctx);
+				ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], rax\n", name));
+			},
+			ConstValue::Enum { ref variant, .. } => {
+				ctx.text_section.push_str(&format!("    ; Variable {} = {}\n", name, variant));
+				generate_const_value_code(&var_value, ctx);
+				ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], rax\n", name));
 			},
 			ConstValue::Null => {
 				let var_label = get_var_label(name, None);
-				data_section.push_str(&format!("{} db \"null\", 10, 0\n", var_label));
-				text_section.push_str(&format!("    ; Variable {} = null\n", name));
-				bss_section.push_str(&format!("var_mem_{}: resq 1  ; Memory for variable {}\n", name, name));
-				text_section.push_str(&format!("    mov qword [var_mem_{}], 0\n", name));
+				ctx.data_section.push_str(&runtime::string_data(&var_label, "null\n"));
+				ctx.text_section.push_str(&format!("    ; Variable {} = null\n", name));
+				ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], 0\n", name));
 			},
 		}
 	}
     
-    fn generate_assign_code(name: &str, value: &Expr, text_section: &mut String, data_section: &mut String, constants: &HashMap<String, ConstValue>, variables: &mut HashMap<String, ConstValue>, string_counter: &mut usize) {
-        if constants.contains_key(name) {
+    fn generate_assign_code(name: &str, value: &Expr, ctx: &mut Codegen) {
+        if ctx.types.constants.contains_key(name) {
             panic!("Cannot reassign constant '{}'", name);
         }
-        if !variables.contains_key(name) {
+        if !ctx.types.variables.contains_key(name) {
             panic!("Variable '{}' not defined before assignment", name);
         }
-        let new_value = evaluate_constant_expr(value, constants, variables);
-        variables.insert(name.to_string(), new_value.clone());
+        if ctx.bindings.iter().any(|binding| binding == name) {
+            panic!("Cannot assign to '{}', a match binding", name);
+        }
+        if needs_runtime_evaluation(value, &ctx.types) {
+            generate_runtime_store(name, value, ctx);
+            return;
+        }
+        let new_value = evaluate_constant_expr(value, &ctx.types);
+        ctx.types.variables.insert(name.to_string(), new_value.clone());
+        let new_tag = const_value_tag(&new_value);
         match new_value {
             ConstValue::Number(n) => {
-                text_section.push_str(&format!("    ; Assign {} = {}\n", name, n));
-                text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, n));
+                ctx.text_section.push_str(&format!("    ; Assign {} = {}\n", name, n));
+                // Through RAX, a memory store only takes a 32-bit immediate
+                ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
+                ctx.text_section.push_str(&format!("    mov [var_mem_{}], rax\n", name));
             },
             ConstValue::Float(f) => {
                 let float_label = get_var_label(name, Some("_float"));
-                let new_label = format!("{}_updated_{}", float_label, *string_counter);
-                *string_counter += 1;
+                let new_label = format!("{}_updated_{}", float_label, ctx.counter);
+                ctx.counter += 1;
                 let float_str = f.to_string();
-                data_section.push_str(&format!("{} db \"{}\", 10, 0\n", new_label, float_str));
-                text_section.push_str(&format!("    ; Assign {} = {}\n", name, f));
+                ctx.data_section.push_str(&runtime::string_data(&new_label, &float_str));
+                ctx.text_section.push_str(&format!("    ; Assign {} = {}\n", name, f));
                 let int_val = (f * 100.0) as i64;
-                text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, int_val));
-                text_section.push_str(&format!("    mov qword [var_mem_{}_float], {}\n", name, new_label));
+                ctx.text_section.push_str(&format!("    mov rax, {}\n", int_val));
+                ctx.text_section.push_str(&format!("    mov [var_mem_{}], rax\n", name));
+                ctx.text_section.push_str(&format!("    mov qword [var_mem_{}_float], {}\n", name, new_label));
             },
             ConstValue::String(s) => {
                 let var_label = get_var_label(name, None);
-                let new_label = format!("{}_updated_{}", var_label, *string_counter);
-                *string_counter += 1;
-                data_section.push_str(&format!("{} db \"{}\", 10, 0\n", new_label, s));
-                text_section.push_str(&format!("    ; Assign {} = \"{}\"\n", name, s));
-                text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, new_label));
+                let new_label = format!("{}_updated_{}", var_label, ctx.counter);
+                ctx.counter += 1;
+                ctx.data_section.push_str(&runtime::string_data(&new_label, &s));
+                ctx.text_section.push_str(&format!("    ; Assign {} = \"{}\"\n", name, s.escape_default()));
+                ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, new_label));
             },
             ConstValue::Boolean(b) => {
-                text_section.push_str(&format!("    ; Assign {} = {}\n", name, b));
-                text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, if b { 1 } else { 0 }));
+                ctx.text_section.push_str(&format!("    ; Assign {} = {}\n", name, b));
+                ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, if b { 1 } else { 0 }));
+            },
+            ConstValue::Char(c) => {
+                ctx.text_section.push_str(&format!("    ; Assign {} = '{}'\n", name, c.escape_default()));
+                ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, c as u32));
+            },
+            ConstValue::Array(ref values) => {
+                ctx.text_section.push_str(&format!("    ; Assign {} = [array with {} elements]\n", name, values.len()));
+                generate_const_value_code(&new_value, ctx);
+                ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], rax\n", name));
+            },
+            ConstValue::Map(ref entries) => {
+                ctx.text_section.push_str(&format!("    ; Assign {} = {{map with {} entries}}\n", name, entries.len()));
+                generate_const_value_code(&new_value, ctx);
+                ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], rax\n", name));
             },
-            ConstValue::Array(values) => {
-                let var_label = get_var_label(name, Some("_label"));
-                let new_label = format!("{}_updated_{}", var_label, *string_counter);
-                *string_counter += 1;
-                data_section.push_str(&format!("{} db \"[Array]\", 10, 0\n", new_label));
-                text_section.push_str(&format!("    ; Assign {} = [array with {} elements]\n", name, values.len()));
-                text_section.push_str(&format!("    mov qword [var_mem_{}], {}\n", name, new_label));
+            ConstValue::Struct { name: ref struct_name, .. } => {
+                ctx.text_section.push_str(&format!("    ; Assign {} = {} {{...}}\n", name, struct_name));
+                generate_const_value_code(&new_value, ctx);
+                ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], rax\n", name));
+            },
+            ConstValue::Enum { ref variant, .. } => {
+                ctx.text_section.push_str(&format!("    ; Assign {} = {}\n", name, variant));
+                generate_const_value_code(&new_value, ctx);
+                ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], rax\n", name));
             },
             ConstValue::Null => {
-                text_section.push_str(&format!("    ; Assign {} = null\n", name));
-                text_section.push_str(&format!("    mov qword [var_mem_{}], 0\n", name));
+                ctx.text_section.push_str(&format!("    ; Assign {} = null\n", name));
+                ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], 0\n", name));
             },
         }
+        if is_tagged_var(name, &ctx.types.variables) {
+            ctx.text_section.push_str(&format!("    mov qword [var_tag_{}], {}\n", name, new_tag));
+        }
+    }
+    
+    fn generate_struct_decl_code(name: &str, fields: &[(String, String)], ctx: &mut Codegen) {
+        if ctx.types.definitions.structs.contains_key(name) {
+            panic!("Struct '{}' already defined", name);
+        }
+        
+        let mut defaults: Vec<(String, ConstValue)> = Vec::new();
+        for (field, type_name) in fields {
+            if defaults.iter().any(|(f, _)| f == field) {
+                panic!("Duplicate field '{}' in struct '{}'", field, name);
+            }
+            defaults.push((field.clone(), default_field_value(type_name, &ctx.types.definitions)));
+        }
+        
+        // Descriptor used by the runtime to print the struct
+        ctx.data_section.push_str(&runtime::string_data(&format!("struct_{}_name", name), name));
+        let mut descriptor = format!("{} dq struct_{}_name, {}", struct_descriptor_label(name), name, fields.len());
+        for (field, _) in fields {
+            ctx.data_section.push_str(&runtime::string_data(&format!("struct_{}_field_{}", name, field), field));
+            descriptor.push_str(&format!(", struct_{}_field_{}", name, field));
+        }
+        ctx.data_section.push_str(&descriptor);
+        ctx.data_section.push('\n');
+        
+        ctx.text_section.push_str(&format!("    ; Struct {} with {} fields\n", name, fields.len()));
+        ctx.types.definitions.structs.insert(name.to_string(), ConstValue::Struct { name: name.to_string(), fields: defaults });
+    }
+    
+    fn generate_enum_decl_code(name: &str, variants: &[(String, Vec<String>)], ctx: &mut Codegen) {
+        if ctx.types.definitions.enums.contains_key(name) {
+            panic!("Enum '{}' already defined", name);
+        }
+        
+        let mut templates = Vec::new();
+        for (index, (variant, payload_types)) in variants.iter().enumerate() {
+            // Variants are used by their bare name, so they share the constants' namespace
+            if ctx.types.definitions.variants.contains_key(variant) || ctx.types.constants.contains_key(variant) || ctx.types.variables.contains_key(variant) {
+                panic!("Variant '{}' is already defined", variant);
+            }
+            let payload: Vec<ConstValue> = payload_types.iter().map(|t| default_field_value(t, &ctx.types.definitions)).collect();
+            let template = ConstValue::Enum { name: name.to_string(), variant: variant.clone(), index, payload };
+            
+            // Descriptor used by match and by the runtime to print the value
+            let label = variant_descriptor_label(name, variant);
+            ctx.data_section.push_str(&runtime::string_data(&format!("{}_name", label), variant));
+            ctx.data_section.push_str(&format!("{} dq {}_name, {}, {}\n", label, label, index, payload_types.len()));
+            if payload_types.is_empty() {
+                ctx.data_section.push_str(&format!("{}_value dq {}\n", label, label));
+                ctx.types.constants.insert(variant.clone(), template.clone());
+            }
+            ctx.types.definitions.variants.insert(variant.clone(), template.clone());
+            templates.push(template);
+        }
+        
+        ctx.text_section.push_str(&format!("    ; Enum {} with {} variants\n", name, variants.len()));
+        ctx.types.definitions.enums.insert(name.to_string(), templates);
+    }
+    
+    // A match that gives a value runs a match statement whose arms store their
+    // value in the target: the first arm with the let or assignment, the others
+    // with assignments, so the target is declared before any arm reads it
+    fn generate_match_value_code(name: &str, value: &Expr, arms: &[(Pattern, Box<Expr>)], first_store: &dyn Fn(&Expr) -> Expr, span: Span, ctx: &mut Codegen) {
+        let arms: Vec<(Pattern, Vec<Expr>)> = arms.iter().enumerate().map(|(i, (pattern, arm))| {
+            let store = if i == 0 {
+                first_store(arm)
+            } else {
+                Expr::Assign { name: name.to_string(), value: arm.clone() }
+            };
+            (pattern.clone(), vec![store])
+        }).collect();
+        generate_match_code(value, &arms, Some(name), span, ctx);
+    }
+    
+    // Match statement. With a result, every arm stores a value in that variable
+    // and the values must all have the same type.
+    fn generate_match_code(value: &Expr, arms: &[(Pattern, Vec<Expr>)], result: Option<&str>, span: Span, ctx: &mut Codegen) {
+        let id = ctx.counter;
+        ctx.counter += 1;
+        let scrutinee = runtime_placeholder(value, &ctx.types);
+        let cases = check_match_arms(&scrutinee, arms, span, &ctx.types);
+        let slot = format!("match_value_{}", id);
+        let end_label = format!("match_end_{}", id);
+        let arm_label = |i: usize| format!("match_{}_arm_{}", id, i);
+        
+        ctx.text_section.push_str("    ; Match statement\n");
+        ctx.bss_section.push_str(&format!("{}: resq 2  ; Matched value [tag, payload]\n", slot));
+        generate_value_code(value, ctx);
+        ctx.text_section.push_str(&format!("    mov [{}], rdx\n", slot));
+        ctx.text_section.push_str(&format!("    mov [{}+8], rax\n", slot));
+        
+        let wildcard = cases.iter().position(|case| matches!(case, MatchCase::Any));
+        let mut result_types: Vec<String> = Vec::new();
+        if let ConstValue::Enum { name, .. } = &scrutinee {
+            // Jump table indexed by the variant, uncovered variants go to '_'
+            let variant_count = ctx.types.definitions.enums[name].len();
+            let targets: Vec<String> = (0..variant_count).map(|variant| {
+                let arm = cases.iter().position(|case| matches!(case, MatchCase::Variant(v) if *v == variant));
+                arm_label(arm.or(wildcard).unwrap())
+            }).collect();
+            ctx.data_section.push_str(&format!("match_table_{} dq {}\n", id, targets.join(", ")));
+            ctx.text_section.push_str(&format!("    mov rax, [{}+8]\n", slot));
+            ctx.text_section.push_str("    mov rax, [rax]       ; Variant descriptor\n");
+            ctx.text_section.push_str("    mov rax, [rax+8]     ; Variant index\n");
+            ctx.text_section.push_str(&format!("    jmp [match_table_{}+rax*8]\n", id));
+        } else {
+            // Compare chain over the literal patterns
+            for (i, case) in cases.iter().enumerate() {
+                if let MatchCase::Value(pattern) = case {
+                    ctx.text_section.push_str(&format!("    ; Compare with {}\n", pattern.to_string().escape_default()));
+                    generate_const_value_code(pattern, ctx);
+                    ctx.text_section.push_str("    push rax\n");
+                    ctx.text_section.push_str("    push rdx\n");
+                    ctx.text_section.push_str("    mov rdi, rsp\n");
+                    ctx.text_section.push_str(&format!("    mov rsi, {}\n", slot));
+                    ctx.text_section.push_str("    call value_compare\n");
+                    ctx.text_section.push_str("    add rsp, 16\n");
+                    ctx.text_section.push_str("    test rax, rax\n");
+                    ctx.text_section.push_str(&format!("    jz {}\n", arm_label(i)));
+                }
+            }
+            ctx.text_section.push_str(&format!("    jmp {}\n", wildcard.map_or(end_label.clone(), arm_label)));
+        }
+        
+        for (i, (pattern, body)) in arms.iter().enumerate() {
+            ctx.text_section.push_str(&format!("{}:\n", arm_label(i)));
+            let mut shadowed = Vec::new();
+            if let Pattern::Variant { name, bindings } = pattern {
+                for (k, binding) in bindings.iter().enumerate() {
+                    if let Some(binding) = binding {
+                        let payload = variant_payload(&scrutinee, name, &ctx.types.definitions)[k].clone();
+                        shadowed.push(bind_payload(binding, k, payload, &slot, span, ctx));
+                    }
+                }
+            }
+            process_statements(body, ctx);
+            for shadowed in shadowed.into_iter().rev() {
+                unbind_payload(shadowed, ctx);
+            }
+            ctx.text_section.push_str(&format!("    jmp {}\n", end_label));
+            if let Some(result) = result {
+                let result_type = type_name(&ctx.types.variables[result]);
+                result_types.push(result_type.clone());
+                if result_types[0] != result_type {
+                    panic!("Match arm {} gives {}, the first arm gives {} at line {}, column {}",
+                           i + 1, result_type, result_types[0], span.line, span.column);
+                }
+            }
+        }
+        ctx.text_section.push_str(&format!("{}:\n", end_label));
     }
     
     // Process all expressions using the reused functions
-	let boxed_exprs: Vec<Box<Expr>> = exprs.iter().cloned().map(Box::new).collect();
-	process_statements(&boxed_exprs, &mut text_section, &mut data_section, &mut bss_section, 
-					&mut constants, &mut variables, &string_labels, &mut string_counter);
+	process_statements(exprs, &mut ctx);
     
-    format!("{}\n{}\n{}", data_section, bss_section, text_section)
+    let Codegen { text_section, data_section, bss_section, types, .. } = ctx;
+    (format!("{}\n{}\n{}", data_section, bss_section, text_section), types)
 }
 // Recursively collect all string literals in expressions
 fn collect_string_literals(expr: &Expr, counter: &mut usize, string_labels: &mut HashMap<String, String>, data_section: &mut String) {
     match expr {
-        Expr::StringLiteral(s) => {
-            if !string_labels.contains_key(s) {
-                let label = format!("str_{}", counter);
-                *counter += 1;
-                string_labels.insert(s.clone(), label.clone());
-                data_section.push_str(&format!("{} db \"{}\", 10, 0\n", label, s));
-            }
+        Expr::StringLiteral(s) if !string_labels.contains_key(s) => {
+            let label = format!("str_{}", counter);
+            *counter += 1;
+            string_labels.insert(s.clone(), label.clone());
+            data_section.push_str(&runtime::string_data(&label, &format!("{}\n", s)));
         },
         Expr::Print(inner) => collect_string_literals(inner, counter, string_labels, data_section),
-        Expr::Exit(inner) => collect_string_literals(inner, counter, string_labels, data_section),
+        Expr::Exit { code: inner, .. } => collect_string_literals(inner, counter, string_labels, data_section),
         Expr::Const { value, .. } => collect_string_literals(value, counter, string_labels, data_section),
         Expr::Let { value, .. } => collect_string_literals(value, counter, string_labels, data_section),
         Expr::Assign { value, .. } => collect_string_literals(value, counter, string_labels, data_section),
@@ -714,16 +1269,68 @@ fn collect_string_literals(expr: &Expr, counter: &mut usize, string_labels: &mut
             collect_string_literals(left, counter, string_labels, data_section);
             collect_string_literals(right, counter, string_labels, data_section);
         },
+        Expr::UnaryOp { operand, .. } => collect_string_literals(operand, counter, string_labels, data_section),
         Expr::Array(elements) => {
             for elem in elements {
                 collect_string_literals(elem, counter, string_labels, data_section);
             }
         },
-		Expr::If { condition, then_branch, else_branch } => {
-            collect_string_literals(condition, counter, string_labels, data_section);
-            
-            for stmt in then_branch {
-                collect_string_literals(stmt, counter, string_labels, data_section);
+        Expr::MethodCall { object, args, .. } => {
+            collect_string_literals(object, counter, string_labels, data_section);
+            for arg in args {
+                collect_string_literals(arg, counter, string_labels, data_section);
+            }
+        },
+        Expr::Map(entries) => {
+            for (key, value) in entries {
+                collect_string_literals(key, counter, string_labels, data_section);
+                collect_string_literals(value, counter, string_labels, data_section);
+            }
+        },
+        Expr::Index { object, index } => {
+            collect_string_literals(object, counter, string_labels, data_section);
+            collect_string_literals(index, counter, string_labels, data_section);
+        },
+        Expr::IndexAssign { object, index, value } => {
+            collect_string_literals(object, counter, string_labels, data_section);
+            collect_string_literals(index, counter, string_labels, data_section);
+            collect_string_literals(value, counter, string_labels, data_section);
+        },
+        Expr::StructLiteral { fields, .. } => {
+            for (_, value) in fields {
+                collect_string_literals(value, counter, string_labels, data_section);
+            }
+        },
+        Expr::FieldAccess { object, .. } => collect_string_literals(object, counter, string_labels, data_section),
+        Expr::FieldAssign { object, value, .. } => {
+            collect_string_literals(object, counter, string_labels, data_section);
+            collect_string_literals(value, counter, string_labels, data_section);
+        },
+        Expr::Variant { args, .. } => {
+            for arg in args {
+                collect_string_literals(arg, counter, string_labels, data_section);
+            }
+        },
+        Expr::Match { value, arms, .. } => {
+            collect_string_literals(value, counter, string_labels, data_section);
+            for (_, body) in arms {
+                for stmt in body {
+                    collect_string_literals(stmt, counter, string_labels, data_section);
+                }
+            }
+        },
+        Expr::MatchValue { value, arms, .. } => {
+            collect_string_literals(value, counter, string_labels, data_section);
+            for (_, arm) in arms {
+                collect_string_literals(arm, counter, string_labels, data_section);
+            }
+        },
+		Expr::If { branches, else_branch, .. } => {
+            for (condition, statements) in branches {
+                collect_string_literals(condition, counter, string_labels, data_section);
+                for stmt in statements {
+                    collect_string_literals(stmt, counter, string_labels, data_section);
+                }
             }
             
             if let Some(else_b) = else_branch {
@@ -738,150 +1345,159 @@ fn collect_string_literals(expr: &Expr, counter: &mut usize, string_labels: &mut
 }
 
 // Print an array element
-fn print_array_element(elem: &Expr, text_section: &mut String, constants: &HashMap<String, ConstValue>,
-                      variables: &HashMap<String, ConstValue>, string_labels: &HashMap<String, String>,
-                      data_section: &mut String, counter: &mut usize) {
+fn print_array_element(elem: &Expr, ctx: &mut Codegen) {
     match elem {
         Expr::StringLiteral(s) => {
             // Find the label for this string
-            let label = string_labels.get(s).expect("String not found in data section");
+            let label = ctx.string_labels.get(s).expect("String not found in data section");
+            
+            ctx.text_section.push_str(&format!("    ; Print array element (string): {}\n", s.escape_default()));
+            ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+            ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+            ctx.text_section.push_str(&format!("    mov rsi, {}\n", label));
+            ctx.text_section.push_str(&format!("    mov rdx, {}\n", s.len()));
+            ctx.text_section.push_str("    syscall\n\n");
+        },
+        Expr::Char(c) => {
+            let char_label = get_string_label(&mut ctx.counter, "char");
+            ctx.data_section.push_str(&runtime::string_data(&char_label, &c.to_string()));
             
-            text_section.push_str(&format!("    ; Print array element (string): {}\n", s));
-            text_section.push_str("    mov rax, 1          ; sys_write\n");
-            text_section.push_str("    mov rdi, 1          ; stdout\n");
-            text_section.push_str(&format!("    mov rsi, {}\n", label));
-            text_section.push_str(&format!("    mov rdx, {}\n", s.len()));
-            text_section.push_str("    syscall\n\n");
+            ctx.text_section.push_str(&format!("    ; Print array element (char): {}\n", c.escape_default()));
+            ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+            ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+            ctx.text_section.push_str(&format!("    mov rsi, {}\n", char_label));
+            ctx.text_section.push_str(&format!("    mov rdx, {}\n", c.len_utf8()));
+            ctx.text_section.push_str("    syscall\n\n");
         },
         Expr::Number(n) => {
             // Convert number to string and add to data section
-            let num_label = format!("num_{}", counter);
-            *counter += 1;
+            let num_label = format!("num_{}", &mut ctx.counter);
+            ctx.counter += 1;
             let num_str = n.to_string();
-            data_section.push_str(&format!("{} db \"{}\", 0\n", num_label, num_str));
+            ctx.data_section.push_str(&runtime::string_data(&num_label, &num_str));
             
-            text_section.push_str(&format!("    ; Print array element (number): {}\n", n));
-            text_section.push_str("    mov rax, 1          ; sys_write\n");
-            text_section.push_str("    mov rdi, 1          ; stdout\n");
-            text_section.push_str(&format!("    mov rsi, {}\n", num_label));
-            text_section.push_str(&format!("    mov rdx, {}\n", num_str.len()));
-            text_section.push_str("    syscall\n\n");
+            ctx.text_section.push_str(&format!("    ; Print array element (number): {}\n", n));
+            ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+            ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+            ctx.text_section.push_str(&format!("    mov rsi, {}\n", num_label));
+            ctx.text_section.push_str(&format!("    mov rdx, {}\n", num_str.len()));
+            ctx.text_section.push_str("    syscall\n\n");
         },
         Expr::Boolean(b) => {
-            text_section.push_str(&format!("    ; Print array element (boolean): {}\n", b));
-            text_section.push_str("    mov rax, 1          ; sys_write\n");
-            text_section.push_str("    mov rdi, 1          ; stdout\n");
+            ctx.text_section.push_str(&format!("    ; Print array element (boolean): {}\n", b));
+            ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+            ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
             if *b {
-                text_section.push_str("    mov rsi, true_str\n");
-                text_section.push_str("    mov rdx, 4       ; 'true'\n");
+                ctx.text_section.push_str("    mov rsi, true_str\n");
+                ctx.text_section.push_str("    mov rdx, 4       ; 'true'\n");
             } else {
-                text_section.push_str("    mov rsi, false_str\n");
-                text_section.push_str("    mov rdx, 5       ; 'false'\n");
+                ctx.text_section.push_str("    mov rsi, false_str\n");
+                ctx.text_section.push_str("    mov rdx, 5       ; 'false'\n");
             }
-            text_section.push_str("    syscall\n\n");
+            ctx.text_section.push_str("    syscall\n\n");
         },
         Expr::Null => {
-            text_section.push_str("    ; Print array element (null)\n");
-            text_section.push_str("    mov rax, 1          ; sys_write\n");
-            text_section.push_str("    mov rdi, 1          ; stdout\n");
-            text_section.push_str("    mov rsi, null_str\n");
-            text_section.push_str("    mov rdx, 4          ; 'null'\n");
-            text_section.push_str("    syscall\n\n");
+            ctx.text_section.push_str("    ; Print array element (null)\n");
+            ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+            ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+            ctx.text_section.push_str("    mov rsi, null_str\n");
+            ctx.text_section.push_str("    mov rdx, 4          ; 'null'\n");
+            ctx.text_section.push_str("    syscall\n\n");
         },
         Expr::Variable(name) => {
-            text_section.push_str(&format!("    ; Print array element (variable): {}\n", name));
+            ctx.text_section.push_str(&format!("    ; Print array element (variable): {}\n", name));
             
             // Check if it's a constant or variable
-            if let Some(value) = constants.get(name) {
+            if let Some(value) = ctx.types.constants.get(name) {
                 match value {
                     ConstValue::Number(n) => {
                         let num_str = n.to_string();
-                        let num_label = format!("array_num_{}", counter);
-                        *counter += 1;
-                        data_section.push_str(&format!("{} db \"{}\", 0\n", num_label, num_str));
+                        let num_label = format!("array_num_{}", &mut ctx.counter);
+                        ctx.counter += 1;
+                        ctx.data_section.push_str(&runtime::string_data(&num_label, &num_str));
                         
-                        text_section.push_str("    mov rax, 1          ; sys_write\n");
-                        text_section.push_str("    mov rdi, 1          ; stdout\n");
-                        text_section.push_str(&format!("    mov rsi, {}\n", num_label));
-                        text_section.push_str(&format!("    mov rdx, {}\n", num_str.len()));
-                        text_section.push_str("    syscall\n\n");
+                        ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                        ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                        ctx.text_section.push_str(&format!("    mov rsi, {}\n", num_label));
+                        ctx.text_section.push_str(&format!("    mov rdx, {}\n", num_str.len()));
+                        ctx.text_section.push_str("    syscall\n\n");
                     },
                     ConstValue::String(s) => {
                         let label = get_var_label(name, None);
                         
-                        text_section.push_str("    mov rax, 1          ; sys_write\n");
-                        text_section.push_str("    mov rdi, 1          ; stdout\n");
-                        text_section.push_str(&format!("    mov rsi, {}\n", label));
-                        text_section.push_str(&format!("    mov rdx, {}\n", s.len()));
-                        text_section.push_str("    syscall\n\n");
+                        ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                        ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                        ctx.text_section.push_str(&format!("    mov rsi, {}\n", label));
+                        ctx.text_section.push_str(&format!("    mov rdx, {}\n", s.len()));
+                        ctx.text_section.push_str("    syscall\n\n");
                     },
                     ConstValue::Boolean(b) => {
-                        text_section.push_str("    mov rax, 1          ; sys_write\n");
-                        text_section.push_str("    mov rdi, 1          ; stdout\n");
+                        ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                        ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
                         if *b {
-                            text_section.push_str("    mov rsi, true_str\n");
-                            text_section.push_str("    mov rdx, 4       ; 'true'\n");
+                            ctx.text_section.push_str("    mov rsi, true_str\n");
+                            ctx.text_section.push_str("    mov rdx, 4       ; 'true'\n");
                         } else {
-                            text_section.push_str("    mov rsi, false_str\n");
-                            text_section.push_str("    mov rdx, 5       ; 'false'\n");
+                            ctx.text_section.push_str("    mov rsi, false_str\n");
+                            ctx.text_section.push_str("    mov rdx, 5       ; 'false'\n");
                         }
-                        text_section.push_str("    syscall\n\n");
+                        ctx.text_section.push_str("    syscall\n\n");
                     },
                     _ => {
                         // For other types, use a generic approach
                         let label = get_var_label(name, None);
                         
-                        text_section.push_str("    mov rax, 1          ; sys_write\n");
-                        text_section.push_str("    mov rdi, 1          ; stdout\n");
-                        text_section.push_str(&format!("    mov rsi, {}\n", label));
-                        text_section.push_str("    mov rdx, 10         ; Assume max 10 chars\n");
-                        text_section.push_str("    syscall\n\n");
+                        ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                        ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                        ctx.text_section.push_str(&format!("    mov rsi, {}\n", label));
+                        ctx.text_section.push_str("    mov rdx, 10         ; Assume max 10 chars\n");
+                        ctx.text_section.push_str("    syscall\n\n");
                     }
                 }
-            } else if let Some(value) = variables.get(name) {
+            } else if let Some(value) = ctx.types.variables.get(name) {
                 match value {
                     ConstValue::Number(n) => {
                         let num_str = n.to_string();
-                        let num_label = format!("array_num_{}", counter);
-                        *counter += 1;
-                        data_section.push_str(&format!("{} db \"{}\", 0\n", num_label, num_str));
+                        let num_label = format!("array_num_{}", &mut ctx.counter);
+                        ctx.counter += 1;
+                        ctx.data_section.push_str(&runtime::string_data(&num_label, &num_str));
                         
-                        text_section.push_str("    mov rax, 1          ; sys_write\n");
-                        text_section.push_str("    mov rdi, 1          ; stdout\n");
-                        text_section.push_str(&format!("    mov rsi, {}\n", num_label));
-                        text_section.push_str(&format!("    mov rdx, {}\n", num_str.len()));
-                        text_section.push_str("    syscall\n\n");
+                        ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                        ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                        ctx.text_section.push_str(&format!("    mov rsi, {}\n", num_label));
+                        ctx.text_section.push_str(&format!("    mov rdx, {}\n", num_str.len()));
+                        ctx.text_section.push_str("    syscall\n\n");
                     },
                     ConstValue::String(s) => {
                         let label = get_var_label(name, None);
                         
-                        text_section.push_str("    mov rax, 1          ; sys_write\n");
-                        text_section.push_str("    mov rdi, 1          ; stdout\n");
-                        text_section.push_str(&format!("    mov rsi, {}\n", label));
-                        text_section.push_str(&format!("    mov rdx, {}\n", s.len()));
-                        text_section.push_str("    syscall\n\n");
+                        ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                        ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                        ctx.text_section.push_str(&format!("    mov rsi, {}\n", label));
+                        ctx.text_section.push_str(&format!("    mov rdx, {}\n", s.len()));
+                        ctx.text_section.push_str("    syscall\n\n");
                     },
                     ConstValue::Boolean(b) => {
-                        text_section.push_str("    mov rax, 1          ; sys_write\n");
-                        text_section.push_str("    mov rdi, 1          ; stdout\n");
+                        ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                        ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
                         if *b {
-                            text_section.push_str("    mov rsi, true_str\n");
-                            text_section.push_str("    mov rdx, 4       ; 'true'\n");
+                            ctx.text_section.push_str("    mov rsi, true_str\n");
+                            ctx.text_section.push_str("    mov rdx, 4       ; 'true'\n");
                         } else {
-                            text_section.push_str("    mov rsi, false_str\n");
-                            text_section.push_str("    mov rdx, 5       ; 'false'\n");
+                            ctx.text_section.push_str("    mov rsi, false_str\n");
+                            ctx.text_section.push_str("    mov rdx, 5       ; 'false'\n");
                         }
-                        text_section.push_str("    syscall\n\n");
+                        ctx.text_section.push_str("    syscall\n\n");
                     },
                     _ => {
                         // For other types, use a generic approach
                         let label = get_var_label(name, None);
                         
-                        text_section.push_str("    mov rax, 1          ; sys_write\n");
-                        text_section.push_str("    mov rdi, 1          ; stdout\n");
-                        text_section.push_str(&format!("    mov rsi, {}\n", label));
-                        text_section.push_str("    mov rdx, 20         ; Assume max 20 chars\n");
-                        text_section.push_str("    syscall\n\n");
+                        ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+                        ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+                        ctx.text_section.push_str(&format!("    mov rsi, {}\n", label));
+                        ctx.text_section.push_str("    mov rdx, 20         ; Assume max 20 chars\n");
+                        ctx.text_section.push_str("    syscall\n\n");
                     }
                 }
             } else {
@@ -895,225 +1511,1982 @@ fn print_array_element(elem: &Expr, text_section: &mut String, constants: &HashM
 }
 
 // Function to check if an expression will evaluate to a string
-fn is_string_expr(expr: &Expr, constants: &HashMap<String, ConstValue>, variables: &HashMap<String, ConstValue>) -> bool {
+fn is_string_expr(expr: &Expr, types: &Types) -> bool {
     match expr {
         Expr::StringLiteral(_) => true,
         Expr::Variable(name) => {
-            if let Some(value) = constants.get(name) {
+            if let Some(value) = types.constants.get(name) {
                 matches!(value, ConstValue::String(_))
-            } else if let Some(value) = variables.get(name) {
+            } else if let Some(value) = types.variables.get(name) {
                 matches!(value, ConstValue::String(_))
             } else {
                 false
             }
         },
-        Expr::BinaryOp { op, left, right } => {
-            if *op == BinOp::Add {
-                is_string_expr(left, constants, variables) || is_string_expr(right, constants, variables)
-            } else {
-                false
-            }
+        Expr::BinaryOp { op, left, right, .. } if *op == BinOp::Add => {
+            is_string_expr(left, types) || is_string_expr(right, types)
+        },
+        Expr::ConsoleRead(kind) => *kind != ReadKind::Int,
+        Expr::ProcessEnv(_) => true,
+        Expr::Fs { op, .. } => *op == FsOp::Read,
+        Expr::MethodCall { object, method, .. } if method == "pop" && is_array_expr(object, types) => {
+            matches!(element_value(object, types), ConstValue::String(_))
+        },
+        Expr::Index { object, index } => matches!(indexed_value(object, index, types), ConstValue::String(_)),
+        Expr::FieldAccess { object, field } => matches!(field_value(object, field, types), ConstValue::String(_)),
+        Expr::MethodCall { object, method, .. } => {
+            is_string_expr(object, types) && string_method_returns_string(method)
         },
         _ => false,
     }
 }
 
-// Generate code for string concatenation
-fn generate_string_concat(expr: &Expr, text_section: &mut String, 
-                        constants: &HashMap<String, ConstValue>,
-                        variables: &HashMap<String, ConstValue>,
-                        string_labels: &HashMap<String, String>,
-                        data_section: &mut String,
-                        counter: &mut usize) {
+// Function to check if an expression will evaluate to a boolean
+fn is_boolean_expr(expr: &Expr, types: &Types) -> bool {
     match expr {
-        Expr::StringLiteral(s) => {
-            // Find the label for this string
-            let label = string_labels.get(s).expect("String literal not found in labels");
-            
-            text_section.push_str(&format!("    ; Append string: {}\n", s));
-            text_section.push_str(&format!("    mov rsi, {}\n", label));
-            text_section.push_str("    call append_string_without_newline\n");
-        },
-        Expr::Number(n) => {
-            text_section.push_str(&format!("    ; Append number: {}\n", n));
-            text_section.push_str(&format!("    mov rax, {}\n", n));
-            text_section.push_str("    call append_number\n");
+        Expr::Boolean(_) => true,
+        Expr::Variable(name) => {
+            matches!(types.constants.get(name).or_else(|| types.variables.get(name)), Some(ConstValue::Boolean(_)))
         },
-        Expr::Float(f) => {
-            // For float concatenation, convert to string and store in data section
-            let float_label = format!("float_concat_{}", counter);
-            *counter += 1;
-            let float_str = f.to_string();
-            data_section.push_str(&format!("{} db \"{}\", 0\n", float_label, float_str));
-            
-            text_section.push_str(&format!("    ; Append float: {}\n", f));
-            text_section.push_str(&format!("    mov rsi, {}\n", float_label));
-            text_section.push_str("    call append_string_without_newline\n");
+        Expr::BinaryOp { op, .. } => {
+            matches!(op, BinOp::Equal | BinOp::NotEqual | BinOp::Lt | BinOp::Gt | BinOp::Lte | BinOp::Gte)
         },
-        Expr::Boolean(b) => {
-            text_section.push_str(&format!("    ; Append boolean: {}\n", b));
-            if *b {
-                text_section.push_str("    mov rsi, true_str\n");
-            } else {
-                text_section.push_str("    mov rsi, false_str\n");
-            }
-            text_section.push_str("    call append_string_without_newline\n");
+        Expr::MethodCall { object, method, .. } if method == "pop" && is_array_expr(object, types) => {
+            matches!(element_value(object, types), ConstValue::Boolean(_))
         },
-        Expr::Null => {
-            text_section.push_str("    ; Append null\n");
-            text_section.push_str("    mov rsi, null_str\n");
-            text_section.push_str("    call append_string_without_newline\n");
+        Expr::Index { object, index } => matches!(indexed_value(object, index, types), ConstValue::Boolean(_)),
+        Expr::FieldAccess { object, field } => matches!(field_value(object, field, types), ConstValue::Boolean(_)),
+        Expr::MethodCall { method, .. } => {
+            matches!(method.as_str(), "contains" | "startsWith" | "has" | "remove" | "isDigit" | "isLetter" | "isWhitespace")
         },
+        Expr::Fs { op, .. } => *op == FsOp::Exists,
+        _ => false,
+    }
+}
+
+// Function to check if an expression will evaluate to an array
+fn is_array_expr(expr: &Expr, types: &Types) -> bool {
+    match expr {
+        Expr::Array(_) | Expr::ProcessArgs => true,
         Expr::Variable(name) => {
-            let in_constants = constants.contains_key(name);
-            let value = if in_constants {
-                constants.get(name).unwrap()
-            } else {
-                variables.get(name).unwrap()
-            };
-            match value {
-                ConstValue::Number(_) => {
-                    text_section.push_str(&format!("    ; Append numeric variable: {}\n", name));
-                    text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
-                    text_section.push_str("    call append_number\n");
-                },
-                ConstValue::Float(_) => {
-                    text_section.push_str(&format!("    ; Append float variable: {}\n", name));
-                    let float_label = get_var_label(name, Some("_float"));
-                    if in_constants {
-                        text_section.push_str(&format!("    mov rsi, {}\n", float_label));
-                    } else {
-                        text_section.push_str(&format!("    mov rsi, [var_mem_{}_float]\n", name));
-                    }
-                    text_section.push_str("    call append_string_without_newline\n");
-                },
-                ConstValue::String(_) => {
-                    text_section.push_str(&format!("    ; Append string variable: {}\n", name));
-                    let var_label = get_var_label(name, None);
-                    if in_constants {
-                        text_section.push_str(&format!("    mov rsi, {}\n", var_label));
-                    } else {
-                        text_section.push_str(&format!("    mov rsi, [var_mem_{}]\n", name));
-                    }
-                    text_section.push_str("    call append_string_without_newline\n");
-                },
-                ConstValue::Boolean(_) => {
-                    text_section.push_str(&format!("    ; Append boolean variable: {}\n", name));
-                    if in_constants {
-                        if let ConstValue::Boolean(b) = value {
-                            if *b {
-                                text_section.push_str("    mov rsi, true_str\n");
-                            } else {
-                                text_section.push_str("    mov rsi, false_str\n");
-                            }
-                        }
-                    } else {
-                        text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
-                        text_section.push_str("    cmp rax, 0\n");
-                        text_section.push_str(&format!("    je .false_{}\n", name));
-                        text_section.push_str("    mov rsi, true_str\n");
-                        text_section.push_str(&format!("    jmp .done_{}\n", name));
-                        text_section.push_str(&format!(".false_{}:\n", name));
-                        text_section.push_str("    mov rsi, false_str\n");
-                        text_section.push_str(&format!(".done_{}:\n", name));
-                    }
-                    text_section.push_str("    call append_string_without_newline\n");
-                },
-                ConstValue::Array(_) => {
-                    text_section.push_str(&format!("    ; Append array constant: {}\n", name));
-                    let array_label = get_var_label(name, Some("_label"));
-                    text_section.push_str(&format!("    mov rsi, {}\n", array_label));
-                    text_section.push_str("    call append_string_without_newline\n");
-                },
-                ConstValue::Null => {
-                    text_section.push_str(&format!("    ; Append null constant: {}\n", name));
-                    text_section.push_str("    mov rsi, null_str\n");
-                    text_section.push_str("    call append_string_without_newline\n");
-                }
-            }
+            matches!(types.constants.get(name).or_else(|| types.variables.get(name)), Some(ConstValue::Array(_)))
         },
-        Expr::BinaryOp { op, left, right } => {
-            if *op == BinOp::Add && (is_string_expr(left, constants, variables) || is_string_expr(right, constants, variables)) {
-                // If this is a string concatenation, process each part separately
-                generate_string_concat(left, text_section, constants, variables, string_labels, data_section, counter);
-                generate_string_concat(right, text_section, constants, variables, string_labels, data_section, counter);
+        Expr::MethodCall { object, method, .. } if is_map_expr(object, types) => method == "keys",
+        Expr::MethodCall { object, method, .. } => {
+            if is_array_expr(object, types) {
+                match method.as_str() {
+                    "slice" | "reverse" | "sort" => true,
+                    "pop" => matches!(element_value(object, types), ConstValue::Array(_)),
+                    _ => false,
+                }
             } else {
-                text_section.push_str("    ; Append result of numeric expression\n");
-               
-                text_section.push_str("    push rbx\n");
-                text_section.push_str("    push rdx\n");
-                
-                generate_expression_code(expr, text_section, constants, variables);
-                
-                text_section.push_str("    ; Call append_number with result in RAX\n");
-                
-                text_section.push_str("    pop rdx\n");
-                text_section.push_str("    pop rbx\n");
-                
-                text_section.push_str("    call append_number\n");
+                method == "split" && is_string_expr(object, types)
             }
         },
-        _ => panic!("Unsupported expression in string concatenation"),
+        Expr::Index { object, index } => matches!(indexed_value(object, index, types), ConstValue::Array(_)),
+        Expr::FieldAccess { object, field } => matches!(field_value(object, field, types), ConstValue::Array(_)),
+        _ => false,
     }
 }
 
-// Generate code for expressions
-fn generate_expression_code(expr: &Expr, text_section: &mut String, constants: &HashMap<String, ConstValue>, variables: &HashMap<String, ConstValue>) {
+// Function to check if an expression will evaluate to a map
+fn is_map_expr(expr: &Expr, types: &Types) -> bool {
     match expr {
-        Expr::Number(n) => {
-            text_section.push_str(&format!("    ; Load number: {}\n", n));
-            text_section.push_str(&format!("    mov rax, {}\n", n));
-        },
-        Expr::Float(f) => {
-            // Floats would normally require FPU or SSE but for simplicity use integers
-            let int_val = (*f * 100.0) as i64; // Scale up by 100 to preserve some decimal places
-            text_section.push_str(&format!("    ; Load float: {} (scaled as integer)\n", f));
-            text_section.push_str(&format!("    mov rax, {}\n", int_val));
+        Expr::Map(_) => true,
+        Expr::Variable(name) => {
+            matches!(types.constants.get(name).or_else(|| types.variables.get(name)), Some(ConstValue::Map(_)))
         },
-        Expr::Boolean(b) => {
-            text_section.push_str(&format!("    ; Load boolean: {}\n", b));
-            if *b {
-                text_section.push_str("    mov rax, 1\n");
-            } else {
-                text_section.push_str("    mov rax, 0\n");
-            }
+        Expr::MethodCall { object, method, .. } if method == "pop" && is_array_expr(object, types) => {
+            matches!(element_value(object, types), ConstValue::Map(_))
         },
-        Expr::Variable(name) => {
-            if let Some(value) = constants.get(name) {
-                match value {
-                    ConstValue::Number(n) => {
-                        text_section.push_str(&format!("    ; Load numeric constant: {}\n", name));
-                        text_section.push_str(&format!("    mov rax, {}\n", n));
-                    },
+        Expr::Index { object, index } => matches!(indexed_value(object, index, types), ConstValue::Map(_)),
+        Expr::FieldAccess { object, field } => matches!(field_value(object, field, types), ConstValue::Map(_)),
+        _ => false,
+    }
+}
+
+// Function to check if an expression will evaluate to a struct
+fn is_struct_expr(expr: &Expr, types: &Types) -> bool {
+    match expr {
+        Expr::StructLiteral { .. } => true,
+        Expr::Variable(name) => {
+            matches!(types.constants.get(name).or_else(|| types.variables.get(name)), Some(ConstValue::Struct { .. }))
+        },
+        Expr::MethodCall { object, method, .. } if method == "pop" && is_array_expr(object, types) => {
+            matches!(element_value(object, types), ConstValue::Struct { .. })
+        },
+        Expr::Index { object, index } => matches!(indexed_value(object, index, types), ConstValue::Struct { .. }),
+        Expr::FieldAccess { object, field } => matches!(field_value(object, field, types), ConstValue::Struct { .. }),
+        _ => false,
+    }
+}
+
+// Function to check if an expression will evaluate to an enum value
+fn is_enum_expr(expr: &Expr, types: &Types) -> bool {
+    match expr {
+        Expr::Variant { .. } => true,
+        Expr::Variable(name) => {
+            matches!(types.constants.get(name).or_else(|| types.variables.get(name)), Some(ConstValue::Enum { .. }))
+        },
+        Expr::MethodCall { object, method, .. } if method == "pop" && is_array_expr(object, types) => {
+            matches!(element_value(object, types), ConstValue::Enum { .. })
+        },
+        Expr::Index { object, index } => matches!(indexed_value(object, index, types), ConstValue::Enum { .. }),
+        Expr::FieldAccess { object, field } => matches!(field_value(object, field, types), ConstValue::Enum { .. }),
+        _ => false,
+    }
+}
+
+// Function to check if an expression will evaluate to a float
+fn is_float_expr(expr: &Expr, types: &Types) -> bool {
+    match expr {
+        Expr::Float(_) => true,
+        Expr::Variable(name) => {
+            matches!(types.constants.get(name).or_else(|| types.variables.get(name)), Some(ConstValue::Float(_)))
+        },
+        Expr::BinaryOp { op: BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod | BinOp::Pow, left, right, .. } => {
+            !is_string_expr(expr, types)
+                && (is_float_expr(left, types) || is_float_expr(right, types))
+        },
+        Expr::UnaryOp { op: UnaryOp::Neg, operand, .. } => is_float_expr(operand, types),
+        Expr::MethodCall { object, method, .. } if method == "pop" && is_array_expr(object, types) => {
+            matches!(element_value(object, types), ConstValue::Float(_))
+        },
+        Expr::Math { function, args, .. } => {
+            // Float results of runtime arguments carry their own tag instead
+            is_known_at_compile_time(expr, types) && !math_returns_int(*function, args, types)
+        },
+        Expr::Index { object, index } => matches!(indexed_value(object, index, types), ConstValue::Float(_)),
+        Expr::FieldAccess { object, field } => matches!(field_value(object, field, types), ConstValue::Float(_)),
+        _ => false,
+    }
+}
+
+// Function to check if an expression will evaluate to a char
+fn is_char_expr(expr: &Expr, types: &Types) -> bool {
+    match expr {
+        Expr::Char(_) | Expr::CharFromCode { .. } => true,
+        Expr::Variable(name) => {
+            matches!(types.constants.get(name).or_else(|| types.variables.get(name)), Some(ConstValue::Char(_)))
+        },
+        Expr::MethodCall { object, method, .. } if method == "pop" && is_array_expr(object, types) => {
+            matches!(element_value(object, types), ConstValue::Char(_))
+        },
+        Expr::Index { object, index } => matches!(indexed_value(object, index, types), ConstValue::Char(_)),
+        Expr::FieldAccess { object, field } => matches!(field_value(object, field, types), ConstValue::Char(_)),
+        _ => false,
+    }
+}
+
+// Function to check if a float expression only reads constants and float
+// variables, whose values are always known at compile time
+fn only_constants_and_floats(expr: &Expr, types: &Types) -> bool {
+    match expr {
+        Expr::Variable(name) => {
+            types.constants.contains_key(name) || matches!(types.variables.get(name), Some(ConstValue::Float(_)))
+        },
+        Expr::BinaryOp { left, right, .. } => {
+            only_constants_and_floats(left, types) && only_constants_and_floats(right, types)
+        },
+        Expr::UnaryOp { operand, .. } => only_constants_and_floats(operand, types),
+        Expr::Math { .. } => is_known_at_compile_time(expr, types),
+        Expr::MethodCall { .. } | Expr::FieldAccess { .. } => false,
+        _ => true,
+    }
+}
+
+// Let and assignment values are folded when they only use literals and
+// constants, anything else is computed at runtime. There is no runtime float
+// arithmetic yet, so float expressions are still folded.
+fn needs_runtime_evaluation(value: &Expr, types: &Types) -> bool {
+    if is_constant_expr(value, types) {
+        return false;
+    }
+    if is_float_expr(value, types) {
+        check_float_operands(value, types);
+        return false;
+    }
+    true
+}
+
+// Float operators are only folded, so their operands must be known while
+// compiling. The error points at the operator that meets a runtime value.
+fn check_float_operands(expr: &Expr, types: &Types) {
+    let (operands, span) = match expr {
+        Expr::BinaryOp { left, right, span, .. } => (vec![left.as_ref(), right.as_ref()], *span),
+        Expr::UnaryOp { operand, span, .. } => (vec![operand.as_ref()], *span),
+        _ => return,
+    };
+    for operand in operands {
+        if matches!(operand, Expr::BinaryOp { .. } | Expr::UnaryOp { .. }) && is_float_expr(operand, types) {
+            check_float_operands(operand, types);
+        } else if !only_constants_and_floats(operand, types) {
+            panic!("Float arithmetic only works on constants and float variables, not on values computed at runtime at line {}, column {}",
+                   span.line, span.column);
+        }
+    }
+}
+
+// Compile-time stand-in for a value that is only known at runtime. Variables
+// keep it so later code knows the type of what is stored in their memory.
+fn runtime_placeholder(expr: &Expr, types: &Types) -> ConstValue {
+    if is_constant_expr(expr, types) {
+        return evaluate_constant_expr(expr, types);
+    }
+    match expr {
+        Expr::Variable(name) => {
+            types.variables.get(name).cloned().unwrap_or_else(|| panic!("Undefined variable: {}", name))
+        },
+        Expr::Array(elements) => {
+            ConstValue::Array(elements.iter().map(|e| runtime_placeholder(e, types)).collect())
+        },
+        Expr::Map(entries) => {
+            let mut values = Vec::new();
+            for (key, value) in entries {
+                let key = runtime_placeholder(key, types);
+                let value = runtime_placeholder(value, types);
+                map_insert(&mut values, key, value);
+            }
+            ConstValue::Map(values)
+        },
+        Expr::Index { object, index } => indexed_value(object, index, types),
+        Expr::StructLiteral { name, fields } => {
+            struct_literal_value(name, fields, &types.definitions, &|value| runtime_placeholder(value, types))
+        },
+        Expr::FieldAccess { object, field } => field_value(object, field, types),
+        Expr::Variant { name, args } => {
+            variant_value(name, args, &types.definitions, &|value| runtime_placeholder(value, types))
+        },
+        Expr::MethodCall { object, method, .. } if is_map_expr(object, types) => {
+            match method.as_str() {
+                "keys" => match runtime_placeholder(object, types) {
+                    ConstValue::Map(entries) => ConstValue::Array(entries.into_iter().map(|(key, _)| key).collect()),
+                    _ => unreachable!(),
+                },
+                "len" => ConstValue::Number(0),
+                "has" | "remove" => ConstValue::Boolean(false),
+                _ => panic!("Unknown map method: {}", method),
+            }
+        },
+        Expr::MethodCall { object, method, .. } if is_array_expr(object, types) => {
+            match method.as_str() {
+                "slice" | "reverse" | "sort" => runtime_placeholder(object, types),
+                "pop" => element_value(object, types),
+                "len" | "push" => ConstValue::Number(0),
+                "contains" => ConstValue::Boolean(false),
+                _ => panic!("Unknown array method: {}", method),
+            }
+        },
+        Expr::Fs { op: FsOp::Write | FsOp::Append | FsOp::Remove, .. } => ConstValue::Null,
+        Expr::Time { function: TimeFn::Sleep, .. } | Expr::Random { function: RandomFn::Seed, .. } => ConstValue::Null,
+        Expr::Math { .. } if is_known_at_compile_time(expr, types) => evaluate_constant_expr(expr, types),
+        _ if is_array_expr(expr, types) => ConstValue::Array(vec![ConstValue::String(String::new())]),
+        _ if is_string_expr(expr, types) => ConstValue::String(String::new()),
+        _ if is_char_expr(expr, types) => ConstValue::Char('\0'),
+        _ if is_boolean_expr(expr, types) => ConstValue::Boolean(false),
+        _ if is_float_expr(expr, types) => ConstValue::Float(0.0),
+        _ => ConstValue::Number(0),
+    }
+}
+
+// Stand-in for container[index]. A constant index into a container whose
+// entry is known gives that entry's type, anything else falls back to the
+// common element type.
+fn indexed_value(object: &Expr, index: &Expr, types: &Types) -> ConstValue {
+    if is_string_expr(object, types) {
+        return ConstValue::Char('\0');
+    }
+    if is_constant_expr(index, types) {
+        let key = evaluate_constant_expr(index, types);
+        match (runtime_placeholder(object, types), key) {
+            (ConstValue::Map(entries), key) => {
+                if let Some((_, value)) = entries.into_iter().find(|(k, _)| compare_const_values(k, &key) == Ordering::Equal) {
+                    return value;
+                }
+            },
+            (ConstValue::Array(values), ConstValue::Number(i)) => {
+                if let Some(value) = usize::try_from(i).ok().and_then(|i| values.get(i)) {
+                    return value.clone();
+                }
+            },
+            _ => {},
+        }
+    }
+    element_value(object, types)
+}
+
+// Stand-in for an element taken out of an array or map by pop() or
+// indexing. An empty container gives null; the element type must be known,
+// so containers mixing types are rejected.
+fn element_value(container: &Expr, types: &Types) -> ConstValue {
+    let elements: Vec<ConstValue> = match runtime_placeholder(container, types) {
+        ConstValue::Array(values) => values,
+        ConstValue::Map(entries) => entries.into_iter().map(|(_, value)| value).collect(),
+        _ => panic!("Only arrays and maps can be indexed"),
+    };
+    match elements.last() {
+        None => ConstValue::Null,
+        Some(last) => {
+            let last_tag = const_value_tag(last);
+            if elements.iter().any(|v| const_value_tag(v) != last_tag) {
+                panic!("Cannot infer the type of an element of a container with mixed element types");
+            }
+            last.clone()
+        },
+    }
+}
+
+// Runtime type tag of a compile-time value
+fn const_value_tag(value: &ConstValue) -> u8 {
+    match value {
+        ConstValue::Null => runtime::TAG_NULL,
+        ConstValue::Number(_) => runtime::TAG_NUMBER,
+        ConstValue::String(_) => runtime::TAG_STRING,
+        ConstValue::Boolean(_) => runtime::TAG_BOOLEAN,
+        ConstValue::Char(_) => runtime::TAG_CHAR,
+        ConstValue::Float(_) => runtime::TAG_FLOAT,
+        ConstValue::Array(_) => runtime::TAG_ARRAY,
+        ConstValue::Map(_) => runtime::TAG_MAP,
+        ConstValue::Struct { .. } => runtime::TAG_STRUCT,
+        ConstValue::Enum { .. } => runtime::TAG_ENUM,
+    }
+}
+
+// Runtime type tag of an expression, from its compile-time type
+fn value_tag(expr: &Expr, types: &Types) -> u8 {
+    if is_array_expr(expr, types) {
+        runtime::TAG_ARRAY
+    } else if is_map_expr(expr, types) {
+        runtime::TAG_MAP
+    } else if is_struct_expr(expr, types) {
+        runtime::TAG_STRUCT
+    } else if is_enum_expr(expr, types) {
+        runtime::TAG_ENUM
+    } else if is_string_expr(expr, types) {
+        runtime::TAG_STRING
+    } else if is_boolean_expr(expr, types) {
+        runtime::TAG_BOOLEAN
+    } else if is_float_expr(expr, types) {
+        runtime::TAG_FLOAT
+    } else if is_char_expr(expr, types) {
+        runtime::TAG_CHAR
+    } else if matches!(runtime_placeholder(expr, types), ConstValue::Null) {
+        runtime::TAG_NULL
+    } else {
+        runtime::TAG_NUMBER
+    }
+}
+
+// Function to check if an expression only depends on literals and constants,
+// so it can be folded at compile time
+fn is_constant_expr(expr: &Expr, types: &Types) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Float(_) | Expr::Boolean(_) | Expr::StringLiteral(_) | Expr::Char(_) | Expr::Null => true,
+        Expr::Variable(name) => types.constants.contains_key(name),
+        Expr::Array(elements) => elements.iter().all(|e| is_constant_expr(e, types)),
+        Expr::Map(entries) => entries.iter().all(|(k, v)| is_constant_expr(k, types) && is_constant_expr(v, types)),
+        Expr::Index { object, index } => is_constant_expr(object, types) && is_constant_expr(index, types),
+        Expr::StructLiteral { fields, .. } => fields.iter().all(|(_, v)| is_constant_expr(v, types)),
+        Expr::FieldAccess { object, .. } => is_constant_expr(object, types),
+        Expr::Variant { args, .. } => args.iter().all(|a| is_constant_expr(a, types)),
+        Expr::BinaryOp { left, right, .. } => {
+            is_constant_expr(left, types) && is_constant_expr(right, types)
+        },
+        Expr::UnaryOp { operand, .. } => is_constant_expr(operand, types),
+        Expr::Math { args, .. } => args.iter().all(|a| is_constant_expr(a, types)),
+        Expr::CharFromCode { code, .. } => is_constant_expr(code, types),
+        Expr::MethodCall { object, method, args } => {
+            // Methods that change their container always run
+            !matches!(method.as_str(), "push" | "pop" | "remove")
+                && is_constant_expr(object, types)
+                && args.iter().all(|a| is_constant_expr(a, types))
+        },
+        _ => false,
+    }
+}
+
+// String built-ins that produce a new string
+fn string_method_returns_string(method: &str) -> bool {
+    matches!(method, "substring" | "toUpper" | "toLower" | "trim" | "replace")
+}
+
+// Print a value that was folded at compile time
+fn generate_print_const_value(value: &ConstValue, ctx: &mut Codegen) {
+    let text = value.to_string();
+    let label = get_string_label(&mut ctx.counter, "folded");
+    ctx.data_section.push_str(&runtime::string_data(&label, &format!("{}\n", text)));
+    
+    ctx.text_section.push_str(&format!("    ; Print folded value: {}\n", text.escape_default()));
+    ctx.text_section.push_str("    mov rax, 1          ; sys_write\n");
+    ctx.text_section.push_str("    mov rdi, 1          ; stdout\n");
+    ctx.text_section.push_str(&format!("    mov rsi, {}\n", label));
+    ctx.text_section.push_str(&format!("    mov rdx, {}\n", text.len() + 1));
+    ctx.text_section.push_str("    syscall\n\n");
+}
+
+// console.write, console.error and console.eprint write their value as text
+fn generate_write_code(value: &Expr, stream: Stream, newline: bool, ctx: &mut Codegen) {
+    let fd = match stream {
+        Stream::Stdout => 1,
+        Stream::Stderr => 2,
+    };
+    if is_constant_expr(value, &ctx.types) {
+        let mut text = evaluate_constant_expr(value, &ctx.types).to_string();
+        if newline {
+            text.push('\n');
+        }
+        let label = get_string_label(&mut ctx.counter, "folded");
+        ctx.data_section.push_str(&runtime::string_data(&label, &text));
+        ctx.text_section.push_str(&format!("    ; Write folded value to fd {}\n", fd));
+        ctx.text_section.push_str(&format!("    mov rsi, {}\n", label));
+        ctx.text_section.push_str(&format!("    mov rdi, {}\n", fd));
+        ctx.text_section.push_str("    call write_string\n\n");
+        return;
+    }
+    ctx.text_section.push_str(&format!("    ; Write runtime value to fd {}\n", fd));
+    generate_string_code(value, ctx);
+    ctx.text_section.push_str("    mov rsi, rax\n");
+    ctx.text_section.push_str(&format!("    mov rdi, {}\n", fd));
+    ctx.text_section.push_str(if newline { "    call write_line\n\n" } else { "    call write_string\n\n" });
+}
+
+// A piece of a printf format string
+enum FormatPiece {
+    Text(String),
+    Value(Option<usize>),   // {} or {:.N}
+}
+
+// Split a printf format string into text and placeholders
+fn parse_format_string(format: &str, span: Span) -> Vec<FormatPiece> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            },
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => panic!("Unclosed '{{' in format string at line {}, column {}", span.line, span.column),
+                    }
+                }
+                let precision = if spec.is_empty() {
+                    None
+                } else {
+                    match spec.strip_prefix(":.").map(|digits| digits.parse::<usize>()) {
+                        Some(Ok(digits)) => Some(digits),
+                        _ => panic!("Unknown placeholder '{{{}}}' in format string at line {}, column {}, expected {{}} or {{:.N}}",
+                                    spec, span.line, span.column),
+                    }
+                };
+                if !text.is_empty() {
+                    pieces.push(FormatPiece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(FormatPiece::Value(precision));
+            },
+            '}' => panic!("Unmatched '}}' in format string at line {}, column {}, write '}}}}' for a brace", span.line, span.column),
+            _ => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(FormatPiece::Text(text));
+    }
+    pieces
+}
+
+// Text of a printf value folded at compile time
+fn format_const_value(value: &ConstValue, precision: Option<usize>) -> String {
+    match (value, precision) {
+        (ConstValue::Number(n), Some(digits)) => format!("{:.*}", digits, *n as f64),
+        (ConstValue::Float(f), Some(digits)) => format!("{:.*}", digits, f),
+        _ => value.to_string(),
+    }
+}
+
+// console.printf: a literal format is checked against its arguments and
+// split at compile time, any other format is filled in by str_format
+fn generate_printf_code(format: &Expr, args: &[Expr], span: Span, ctx: &mut Codegen) {
+    if !is_constant_expr(format, &ctx.types) {
+        if !is_string_expr(format, &ctx.types) && !is_tagged_expr(format, &ctx.types) {
+            panic!("console.printf needs a format string at line {}, column {}", span.line, span.column);
+        }
+        ctx.text_section.push_str("    ; printf with a runtime format\n");
+        let values = Expr::Array(args.to_vec());
+        generate_value_code(&values, ctx);
+        ctx.text_section.push_str("    push rax\n");
+        generate_string_code(format, ctx);
+        ctx.text_section.push_str("    mov rsi, rax\n");
+        ctx.text_section.push_str("    pop rdx\n");
+        ctx.text_section.push_str("    call str_format\n");
+        ctx.text_section.push_str("    mov rsi, rax\n");
+        ctx.text_section.push_str("    mov rdi, 1\n");
+        ctx.text_section.push_str("    call write_string\n\n");
+        return;
+    }
+    
+    let format = match evaluate_constant_expr(format, &ctx.types) {
+        ConstValue::String(s) => s,
+        _ => panic!("console.printf needs a format string at line {}, column {}", span.line, span.column),
+    };
+    let pieces = parse_format_string(&format, span);
+    let placeholders = pieces.iter().filter(|piece| matches!(piece, FormatPiece::Value(_))).count();
+    if placeholders != args.len() {
+        panic!("Format string at line {}, column {} has {} placeholder(s) but {} argument(s) were given",
+               span.line, span.column, placeholders, args.len());
+    }
+    
+    // Folded text is collected until a runtime value has to be written
+    let mut text = String::new();
+    let mut args = args.iter();
+    ctx.text_section.push_str(&format!("    ; printf \"{}\"\n", format.escape_default()));
+    for piece in &pieces {
+        let (arg, precision) = match piece {
+            FormatPiece::Text(t) => {
+                text.push_str(t);
+                continue;
+            },
+            FormatPiece::Value(precision) => (args.next().unwrap(), *precision),
+        };
+        if precision.is_some() && (matches!(arg, Expr::Null) || is_string_expr(arg, &ctx.types)
+            || is_boolean_expr(arg, &ctx.types) || is_array_expr(arg, &ctx.types)
+            || is_map_expr(arg, &ctx.types) || is_struct_expr(arg, &ctx.types)
+            || is_enum_expr(arg, &ctx.types) || is_char_expr(arg, &ctx.types)) {
+            panic!("Placeholder {{:.N}} needs a number in format string at line {}, column {}", span.line, span.column);
+        }
+        if is_constant_expr(arg, &ctx.types) {
+            let value = evaluate_constant_expr(arg, &ctx.types);
+            text.push_str(&format_const_value(&value, precision));
+            continue;
+        }
+        if !text.is_empty() {
+            generate_write_code(&Expr::StringLiteral(std::mem::take(&mut text)), Stream::Stdout, false, ctx);
+        }
+        generate_string_code(arg, ctx);
+        ctx.text_section.push_str("    mov rsi, rax\n");
+        if let Some(digits) = precision {
+            ctx.text_section.push_str("    push rbx\n");
+            ctx.text_section.push_str(&format!("    mov rbx, {}\n", digits));
+            ctx.text_section.push_str("    call str_fixed_decimals\n");
+            ctx.text_section.push_str("    pop rbx\n");
+            ctx.text_section.push_str("    mov rsi, rax\n");
+        }
+        ctx.text_section.push_str("    mov rdi, 1\n");
+        ctx.text_section.push_str("    call write_string\n\n");
+    }
+    if !text.is_empty() {
+        generate_write_code(&Expr::StringLiteral(text), Stream::Stdout, false, ctx);
+    }
+}
+
+// Generate code that leaves a pointer to a runtime string in RAX
+fn generate_string_code(expr: &Expr, ctx: &mut Codegen) {
+    if is_constant_expr(expr, &ctx.types) {
+        let value = evaluate_constant_expr(expr, &ctx.types);
+        let label = get_string_label(&mut ctx.counter, "strz");
+        ctx.data_section.push_str(&runtime::string_data(&label, &value.to_string()));
+        ctx.text_section.push_str(&format!("    mov rax, {}  ; \"{}\"\n", label, value.to_string().escape_default()));
+        return;
+    }
+    
+    match expr {
+        Expr::Variable(name) if is_tagged_var(name, &ctx.types.variables) => {
+            generate_value_code(expr, ctx);
+            ctx.text_section.push_str("    call value_to_string\n");
+        },
+        Expr::Variable(name) => {
+            match ctx.types.variables.get(name) {
+                Some(ConstValue::String(_)) => {
+                    ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                },
+                Some(ConstValue::Float(_)) => {
+                    ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}_float]\n", name));
+                },
+                Some(ConstValue::Number(_)) => {
+                    ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                    ctx.text_section.push_str("    call str_from_int\n");
+                },
+                Some(ConstValue::Boolean(_)) => {
+                    ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                    ctx.text_section.push_str("    call str_from_bool\n");
+                },
+                Some(ConstValue::Char(_)) => {
+                    ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                    ctx.text_section.push_str("    call str_from_char\n");
+                },
+                Some(ConstValue::Null) => {
+                    ctx.text_section.push_str("    mov rax, null_value\n");
+                },
+                Some(value @ (ConstValue::Array(_) | ConstValue::Map(_) | ConstValue::Struct { .. } | ConstValue::Enum { .. })) => {
+                    ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                    ctx.text_section.push_str(&format!("    mov rdx, {}\n", const_value_tag(value)));
+                    ctx.text_section.push_str("    call value_to_string\n");
+                },
+                None => panic!("Undefined variable: {}", name),
+            }
+        },
+        Expr::MethodCall { object, method, args } if is_string_expr(expr, &ctx.types) => {
+            generate_method_code(object, method, args, ctx);
+        },
+        Expr::BinaryOp { op: BinOp::Add, left, right, .. } if is_string_expr(expr, &ctx.types) => {
+            ctx.text_section.push_str("    ; Concatenate strings\n");
+            generate_string_code(left, ctx);
+            ctx.text_section.push_str("    push rax\n");
+            generate_string_code(right, ctx);
+            ctx.text_section.push_str("    mov rdx, rax\n");
+            ctx.text_section.push_str("    pop rsi\n");
+            ctx.text_section.push_str("    call str_concat\n");
+        },
+        Expr::Index { .. } | Expr::FieldAccess { .. } | Expr::ConsoleRead(_) | Expr::ProcessArgs | Expr::ProcessEnv(_)
+        | Expr::Fs { .. } | Expr::Time { .. } | Expr::Random { .. } => {
+            // Elements, fields and input bring their own tag, floats included
+            generate_value_code(expr, ctx);
+            ctx.text_section.push_str("    call value_to_string\n");
+        },
+        _ if is_float_expr(expr, &ctx.types) => {
+            // Float expressions are folded, see needs_runtime_evaluation
+            check_float_operands(expr, &ctx.types);
+            let value = evaluate_constant_expr(expr, &ctx.types);
+            let label = get_string_label(&mut ctx.counter, "strz");
+            ctx.data_section.push_str(&runtime::string_data(&label, &value.to_string()));
+            ctx.text_section.push_str(&format!("    mov rax, {}  ; \"{}\"\n", label, value));
+        },
+        _ => {
+            generate_value_code(expr, ctx);
+            ctx.text_section.push_str("    call value_to_string\n");
+        }
+    }
+}
+
+// Generate a runtime call to one of the string built-ins, the result is left in RAX
+fn generate_string_method_code(object: &Expr, method: &str, args: &[Expr], ctx: &mut Codegen) {
+    if !is_string_expr(object, &ctx.types) {
+        panic!("Method '{}' is not defined for this value", method);
+    }
+    
+    let arity_ok = match method {
+        "len" | "toUpper" | "toLower" | "trim" => args.is_empty(),
+        "substring" => args.len() == 1 || args.len() == 2,
+        "indexOf" | "contains" | "startsWith" => args.len() == 1,
+        "replace" => args.len() == 2,
+        "split" => args.len() == 1,
+        _ => panic!("Unknown string method: {}", method),
+    };
+    if !arity_ok {
+        panic!("Wrong number of arguments for string method '{}'", method);
+    }
+    
+    ctx.text_section.push_str(&format!("    ; String method: {}\n", method));
+    match method {
+        "len" | "toUpper" | "toLower" | "trim" => {
+            generate_string_code(object, ctx);
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            let helper = match method {
+                "len" => "str_len",
+                "toUpper" => "str_to_upper",
+                "toLower" => "str_to_lower",
+                _ => "str_trim",
+            };
+            ctx.text_section.push_str(&format!("    call {}\n", helper));
+        },
+        "substring" => {
+            if args.iter().any(|a| is_string_expr(a, &ctx.types)) {
+                panic!("substring() expects numeric indices");
+            }
+            if let Some(end) = args.get(1) {
+                generate_expression_code(end, ctx);
+            } else {
+                ctx.text_section.push_str("    mov rax, 0x7fffffffffffffff  ; Up to the end\n");
+            }
+            ctx.text_section.push_str("    push rax\n");
+            generate_expression_code(&args[0], ctx);
+            ctx.text_section.push_str("    push rax\n");
+            generate_string_code(object, ctx);
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            ctx.text_section.push_str("    pop rax\n");
+            ctx.text_section.push_str("    pop rdx\n");
+            ctx.text_section.push_str("    call str_substring\n");
+        },
+        "indexOf" | "contains" | "startsWith" | "split" => {
+            if !is_string_expr(&args[0], &ctx.types) {
+                panic!("{}() expects a string argument", method);
+            }
+            generate_string_code(&args[0], ctx);
+            ctx.text_section.push_str("    push rax\n");
+            generate_string_code(object, ctx);
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            ctx.text_section.push_str("    pop rdx\n");
+            if method == "split" {
+                ctx.text_section.push_str("    call str_split\n");
+            } else if method == "startsWith" {
+                ctx.text_section.push_str("    call str_starts_with\n");
+            } else {
+                ctx.text_section.push_str("    call str_index_of\n");
+                if method == "contains" {
+                    ctx.text_section.push_str("    cmp rax, -1\n");
+                    ctx.text_section.push_str("    setne al\n");
+                    ctx.text_section.push_str("    movzx rax, al\n");
+                }
+            }
+        },
+        "replace" => {
+            if !args.iter().all(|a| is_string_expr(a, &ctx.types)) {
+                panic!("replace() expects string arguments");
+            }
+            generate_string_code(&args[1], ctx);
+            ctx.text_section.push_str("    push rax\n");
+            generate_string_code(&args[0], ctx);
+            ctx.text_section.push_str("    push rax\n");
+            generate_string_code(object, ctx);
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            ctx.text_section.push_str("    pop rdx\n");
+            ctx.text_section.push_str("    pop r8\n");
+            ctx.text_section.push_str("    call str_replace\n");
+        },
+        _ => unreachable!(),
+    }
+}
+
+// Generate a runtime call to a built-in method, picked by the receiver's type
+fn generate_method_code(object: &Expr, method: &str, args: &[Expr], ctx: &mut Codegen) {
+    if is_array_expr(object, &ctx.types) {
+        generate_array_method_code(object, method, args, ctx);
+    } else if is_map_expr(object, &ctx.types) {
+        generate_map_method_code(object, method, args, ctx);
+    } else if is_char_expr(object, &ctx.types) {
+        generate_char_method_code(object, method, args, ctx);
+    } else {
+        generate_string_method_code(object, method, args, ctx);
+    }
+}
+
+// Generate a call to one of the char built-ins, the result is left in RAX
+fn generate_char_method_code(object: &Expr, method: &str, args: &[Expr], ctx: &mut Codegen) {
+    let helper = match method {
+        "code" => None,
+        "isDigit" => Some("char_is_digit"),
+        "isLetter" => Some("char_is_letter"),
+        "isWhitespace" => Some("char_is_whitespace"),
+        _ => panic!("Unknown char method: {}", method),
+    };
+    if !args.is_empty() {
+        panic!("Wrong number of arguments for char method '{}'", method);
+    }
+    
+    ctx.text_section.push_str(&format!("    ; Char method: {}\n", method));
+    generate_expression_code(object, ctx);
+    if let Some(helper) = helper {
+        ctx.text_section.push_str(&format!("    call {}\n", helper));
+    }
+}
+
+// Generate a runtime call to one of the array built-ins. The result is left in
+// RAX; pop() also leaves the type tag of the removed element in RDX.
+fn generate_array_method_code(object: &Expr, method: &str, args: &[Expr], ctx: &mut Codegen) {
+    let arity_ok = match method {
+        "len" | "pop" | "reverse" | "sort" => args.is_empty(),
+        "push" | "contains" => args.len() == 1,
+        "slice" => args.len() == 1 || args.len() == 2,
+        _ => panic!("Unknown array method: {}", method),
+    };
+    if !arity_ok {
+        panic!("Wrong number of arguments for array method '{}'", method);
+    }
+    if let Expr::Variable(name) = object
+        && ctx.types.constants.contains_key(name) && matches!(method, "push" | "pop") {
+        panic!("Cannot modify constant '{}'", name);
+    }
+    if method == "sort" {
+        check_sortable(&runtime_placeholder(object, &ctx.types));
+    }
+    
+    ctx.text_section.push_str(&format!("    ; Array method: {}\n", method));
+    match method {
+        "len" => {
+            generate_array_code(object, ctx);
+            ctx.text_section.push_str("    mov rax, [rax]       ; Length\n");
+        },
+        "pop" | "reverse" | "sort" => {
+            generate_array_code(object, ctx);
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            ctx.text_section.push_str(&format!("    call array_{}\n", method));
+        },
+        "push" | "contains" => {
+            generate_value_code(&args[0], ctx);
+            ctx.text_section.push_str("    push rax\n");
+            ctx.text_section.push_str("    push rdx\n");
+            generate_array_code(object, ctx);
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            ctx.text_section.push_str("    pop rdx\n");
+            ctx.text_section.push_str("    pop rax\n");
+            ctx.text_section.push_str(&format!("    call array_{}\n", method));
+        },
+        "slice" => {
+            if args.iter().any(|a| is_string_expr(a, &ctx.types)) {
+                panic!("slice() expects numeric indices");
+            }
+            if let Some(end) = args.get(1) {
+                generate_expression_code(end, ctx);
+            } else {
+                ctx.text_section.push_str("    mov rax, 0x7fffffffffffffff  ; Up to the end\n");
+            }
+            ctx.text_section.push_str("    push rax\n");
+            generate_expression_code(&args[0], ctx);
+            ctx.text_section.push_str("    push rax\n");
+            generate_array_code(object, ctx);
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            ctx.text_section.push_str("    pop rax\n");
+            ctx.text_section.push_str("    pop rdx\n");
+            ctx.text_section.push_str("    call array_slice\n");
+        },
+        _ => unreachable!(),
+    }
+}
+
+// Generate code that leaves a pointer to a runtime array in RAX
+fn generate_array_code(expr: &Expr, ctx: &mut Codegen) {
+    if is_constant_expr(expr, &ctx.types) {
+        let value = evaluate_constant_expr(expr, &ctx.types);
+        generate_const_value_code(&value, ctx);
+        return;
+    }
+    
+    match expr {
+        Expr::Variable(name) => {
+            ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+        },
+        Expr::Array(elements) => {
+            ctx.text_section.push_str(&format!("    ; Build array with {} elements\n", elements.len()));
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", elements.len()));
+            ctx.text_section.push_str("    call array_new\n");
+            ctx.text_section.push_str("    push rax\n");
+            for element in elements {
+                generate_value_code(element, ctx);
+                ctx.text_section.push_str("    mov rsi, [rsp]\n");
+                ctx.text_section.push_str("    call array_push\n");
+            }
+            ctx.text_section.push_str("    pop rax\n");
+        },
+        Expr::MethodCall { object, method, args } => {
+            generate_method_code(object, method, args, ctx);
+        },
+        Expr::Index { object, index } => {
+            generate_index_code(object, index, ctx);
+        },
+        Expr::FieldAccess { object, field } => {
+            generate_field_code(object, field, ctx);
+        },
+        _ => panic!("Expression is not an array"),
+    }
+}
+
+// Generate a runtime call to one of the map built-ins, the result is left in RAX
+fn generate_map_method_code(object: &Expr, method: &str, args: &[Expr], ctx: &mut Codegen) {
+    let arity_ok = match method {
+        "len" | "keys" => args.is_empty(),
+        "has" | "remove" => args.len() == 1,
+        _ => panic!("Unknown map method: {}", method),
+    };
+    if !arity_ok {
+        panic!("Wrong number of arguments for map method '{}'", method);
+    }
+    if let Expr::Variable(name) = object
+        && ctx.types.constants.contains_key(name) && method == "remove" {
+        panic!("Cannot modify constant '{}'", name);
+    }
+    
+    ctx.text_section.push_str(&format!("    ; Map method: {}\n", method));
+    match method {
+        "len" => {
+            generate_map_code(object, ctx);
+            ctx.text_section.push_str("    mov rax, [rax]       ; Count\n");
+        },
+        "keys" => {
+            generate_map_code(object, ctx);
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            ctx.text_section.push_str("    call map_keys\n");
+        },
+        "has" | "remove" => {
+            check_map_key(value_tag(&args[0], &ctx.types));
+            generate_value_code(&args[0], ctx);
+            ctx.text_section.push_str("    push rax\n");
+            ctx.text_section.push_str("    push rdx\n");
+            generate_map_code(object, ctx);
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            ctx.text_section.push_str("    pop rdx\n");
+            ctx.text_section.push_str("    pop rax\n");
+            ctx.text_section.push_str(&format!("    call map_{}\n", method));
+        },
+        _ => unreachable!(),
+    }
+}
+
+// Generate code that leaves a pointer to a runtime map in RAX
+fn generate_map_code(expr: &Expr, ctx: &mut Codegen) {
+    if is_constant_expr(expr, &ctx.types) {
+        let value = evaluate_constant_expr(expr, &ctx.types);
+        generate_const_value_code(&value, ctx);
+        return;
+    }
+    
+    match expr {
+        Expr::Variable(name) => {
+            ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+        },
+        Expr::Map(entries) => {
+            ctx.text_section.push_str(&format!("    ; Build map with {} entries\n", entries.len()));
+            ctx.text_section.push_str("    call map_new\n");
+            ctx.text_section.push_str("    push rax\n");
+            for (key, value) in entries {
+                check_map_key(value_tag(key, &ctx.types));
+                generate_value_code(value, ctx);
+                ctx.text_section.push_str("    push rax\n");
+                ctx.text_section.push_str("    push rdx\n");
+                generate_value_code(key, ctx);
+                ctx.text_section.push_str("    pop r9\n");
+                ctx.text_section.push_str("    pop r8\n");
+                ctx.text_section.push_str("    mov rsi, [rsp]\n");
+                ctx.text_section.push_str("    call map_set\n");
+            }
+            ctx.text_section.push_str("    pop rax\n");
+        },
+        Expr::MethodCall { object, method, args } => {
+            generate_method_code(object, method, args, ctx);
+        },
+        Expr::Index { object, index } => {
+            generate_index_code(object, index, ctx);
+        },
+        Expr::FieldAccess { object, field } => {
+            generate_field_code(object, field, ctx);
+        },
+        _ => panic!("Expression is not a map"),
+    }
+}
+
+// Generate code that reads an array element, map entry or character of a
+// string, leaving the value in RAX and its type tag in RDX. Missing elements
+// read as null.
+fn generate_index_code(object: &Expr, index: &Expr, ctx: &mut Codegen) {
+    if is_map_expr(object, &ctx.types) {
+        check_map_key(value_tag(index, &ctx.types));
+        ctx.text_section.push_str("    ; Map lookup\n");
+        generate_value_code(index, ctx);
+        ctx.text_section.push_str("    push rax\n");
+        ctx.text_section.push_str("    push rdx\n");
+        generate_map_code(object, ctx);
+        ctx.text_section.push_str("    mov rsi, rax\n");
+        ctx.text_section.push_str("    pop rdx\n");
+        ctx.text_section.push_str("    pop rax\n");
+        ctx.text_section.push_str("    call map_get\n");
+    } else if is_array_expr(object, &ctx.types) {
+        if value_tag(index, &ctx.types) != runtime::TAG_NUMBER {
+            panic!("Array indices must be numbers");
+        }
+        ctx.text_section.push_str("    ; Array element\n");
+        generate_expression_code(index, ctx);
+        ctx.text_section.push_str("    push rax\n");
+        generate_array_code(object, ctx);
+        ctx.text_section.push_str("    mov rsi, rax\n");
+        ctx.text_section.push_str("    pop rax\n");
+        ctx.text_section.push_str("    call array_get\n");
+    } else if is_string_expr(object, &ctx.types) {
+        if value_tag(index, &ctx.types) != runtime::TAG_NUMBER {
+            panic!("String indices must be numbers");
+        }
+        ctx.text_section.push_str("    ; Character of a string\n");
+        generate_expression_code(index, ctx);
+        ctx.text_section.push_str("    push rax\n");
+        generate_string_code(object, ctx);
+        ctx.text_section.push_str("    mov rsi, rax\n");
+        ctx.text_section.push_str("    pop rax\n");
+        ctx.text_section.push_str("    call str_char_at\n");
+    } else {
+        panic!("Only arrays, maps and strings can be indexed");
+    }
+}
+
+// Store into an array element or map entry: arr[i] = x; m[k] = x;
+fn generate_index_assign_code(object: &Expr, index: &Expr, value: &Expr, ctx: &mut Codegen) {
+    check_not_constant(object, &ctx.types);
+    
+    if is_map_expr(object, &ctx.types) {
+        check_map_key(value_tag(index, &ctx.types));
+        ctx.text_section.push_str("    ; Store map entry\n");
+        generate_value_code(value, ctx);
+        ctx.text_section.push_str("    push rax\n");
+        ctx.text_section.push_str("    push rdx\n");
+        generate_value_code(index, ctx);
+        ctx.text_section.push_str("    push rax\n");
+        ctx.text_section.push_str("    push rdx\n");
+        generate_map_code(object, ctx);
+        ctx.text_section.push_str("    mov rsi, rax\n");
+        ctx.text_section.push_str("    pop rdx\n");
+        ctx.text_section.push_str("    pop rax\n");
+        ctx.text_section.push_str("    pop r9\n");
+        ctx.text_section.push_str("    pop r8\n");
+        ctx.text_section.push_str("    call map_set\n");
+    } else if is_array_expr(object, &ctx.types) {
+        if value_tag(index, &ctx.types) != runtime::TAG_NUMBER {
+            panic!("Array indices must be numbers");
+        }
+        ctx.text_section.push_str("    ; Store array element\n");
+        generate_value_code(value, ctx);
+        ctx.text_section.push_str("    push rax\n");
+        ctx.text_section.push_str("    push rdx\n");
+        generate_expression_code(index, ctx);
+        ctx.text_section.push_str("    push rax\n");
+        generate_array_code(object, ctx);
+        ctx.text_section.push_str("    mov rsi, rax\n");
+        ctx.text_section.push_str("    pop rax\n");
+        ctx.text_section.push_str("    pop rdx\n");
+        ctx.text_section.push_str("    pop r8\n");
+        ctx.text_section.push_str("    call array_set\n");
+    } else {
+        panic!("Only arrays and maps can be indexed");
+    }
+    
+    // Keep the compile-time picture of the container up to date
+    let key = runtime_placeholder(index, &ctx.types);
+    let element = runtime_placeholder(value, &ctx.types);
+    let constant_index = is_constant_expr(index, &ctx.types);
+    match tracked_value_mut(object, &mut ctx.types.variables) {
+        Some(ConstValue::Map(entries)) => map_insert(entries, key, element),
+        Some(ConstValue::Array(values)) => match key {
+            ConstValue::Number(i) if constant_index && (i as usize) < values.len() && i >= 0 => {
+                values[i as usize] = element;
+            },
+            _ => values.push(element),
+        },
+        _ => {},
+    }
+}
+
+// Map keys are compared by value, which containers, structs and enums do not have
+fn check_map_key(tag: u8) {
+    if matches!(tag, runtime::TAG_ARRAY | runtime::TAG_MAP | runtime::TAG_STRUCT | runtime::TAG_ENUM) {
+        panic!("Map keys cannot be arrays, maps, structs or enums");
+    }
+}
+
+// Stores through a constant, such as origin.x = 1; or table[0] = 1;, are
+// rejected because constants are rebuilt from their folded value on every use
+fn check_not_constant(target: &Expr, types: &Types) {
+    let mut root = target;
+    while let Expr::Index { object, .. } | Expr::FieldAccess { object, .. } = root {
+        root = object;
+    }
+    if let Expr::Variable(name) = root
+        && types.constants.contains_key(name) {
+        panic!("Cannot modify constant '{}'", name);
+    }
+}
+
+// Data label of the runtime descriptor of a struct type
+fn struct_descriptor_label(name: &str) -> String {
+    format!("struct_{}_desc", name)
+}
+
+// Default value of a field with the given declared type
+fn default_field_value(type_name: &str, definitions: &Definitions) -> ConstValue {
+    match type_name {
+        "int" => ConstValue::Number(0),
+        "float" => ConstValue::Float(0.0),
+        "string" => ConstValue::String(String::new()),
+        "bool" => ConstValue::Boolean(false),
+        "char" => ConstValue::Char('\0'),
+        "array" => ConstValue::Array(Vec::new()),
+        "map" => ConstValue::Map(Vec::new()),
+        _ => definitions.structs.get(type_name).cloned()
+            .unwrap_or_else(|| panic!("Unknown type '{}'", type_name)),
+    }
+}
+
+// Name of a value's type, as written in struct declarations
+fn type_name(value: &ConstValue) -> String {
+    match value {
+        ConstValue::Number(_) => "int".to_string(),
+        ConstValue::Float(_) => "float".to_string(),
+        ConstValue::String(_) => "string".to_string(),
+        ConstValue::Boolean(_) => "bool".to_string(),
+        ConstValue::Char(_) => "char".to_string(),
+        ConstValue::Array(_) => "array".to_string(),
+        ConstValue::Map(_) => "map".to_string(),
+        ConstValue::Struct { name, .. } | ConstValue::Enum { name, .. } => name.clone(),
+        ConstValue::Null => "null".to_string(),
+    }
+}
+
+// A field only holds values of its declared type
+fn check_field_type(struct_name: &str, field: &str, declared: &ConstValue, value: &ConstValue) {
+    if type_name(declared) != type_name(value) {
+        panic!("Field '{}' of struct '{}' expects {}, found {}", field, struct_name, type_name(declared), type_name(value));
+    }
+}
+
+// Compile-time value of Name { field: value, ... } with its fields in
+// declaration order. Every declared field must be given exactly once.
+fn struct_literal_value(name: &str, fields: &[(String, Box<Expr>)], definitions: &Definitions,
+                        value_of: &dyn Fn(&Expr) -> ConstValue) -> ConstValue {
+    let mut layout = match definitions.structs.get(name) {
+        Some(ConstValue::Struct { fields, .. }) => fields.clone(),
+        _ => panic!("Unknown struct: {}", name),
+    };
+    let mut given: Vec<&String> = Vec::new();
+    for (field, value) in fields {
+        if given.contains(&field) {
+            panic!("Field '{}' given twice for struct '{}'", field, name);
+        }
+        given.push(field);
+        let slot = layout.iter_mut().find(|(f, _)| f == field)
+            .unwrap_or_else(|| panic!("Struct '{}' has no field '{}'", name, field));
+        let value = value_of(value);
+        check_field_type(name, field, &slot.1, &value);
+        slot.1 = value;
+    }
+    if let Some((missing, _)) = layout.iter().find(|(f, _)| !given.contains(&f)) {
+        panic!("Missing field '{}' for struct '{}'", missing, name);
+    }
+    ConstValue::Struct { name: name.to_string(), fields: layout }
+}
+
+// Look up a field of a compile-time struct value, with its position in the layout
+fn struct_field(value: ConstValue, field: &str) -> (usize, ConstValue) {
+    match value {
+        ConstValue::Struct { name, fields } => {
+            fields.into_iter().enumerate()
+                .find(|(_, (f, _))| f == field)
+                .map(|(i, (_, value))| (i, value))
+                .unwrap_or_else(|| panic!("Struct '{}' has no field '{}'", name, field))
+        },
+        _ => panic!("Only structs have fields"),
+    }
+}
+
+// Stand-in for object.field
+fn field_value(object: &Expr, field: &str, types: &Types) -> ConstValue {
+    struct_field(runtime_placeholder(object, types), field).1
+}
+
+// Offset of a field's type tag inside a runtime struct, the payload follows it
+fn field_offset(index: usize) -> usize {
+    8 + 16 * index
+}
+
+// The compile-time value held by a variable or one of its fields, so stores
+// and container methods can keep it up to date
+fn tracked_value_mut<'a>(expr: &Expr, variables: &'a mut HashMap<String, ConstValue>) -> Option<&'a mut ConstValue> {
+    match expr {
+        Expr::Variable(name) => variables.get_mut(name),
+        Expr::FieldAccess { object, field } => match tracked_value_mut(object, variables)? {
+            ConstValue::Struct { fields, .. } => fields.iter_mut().find(|(f, _)| f == field).map(|(_, value)| value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Generate code that leaves a pointer to a runtime struct in RAX
+fn generate_struct_code(expr: &Expr, ctx: &mut Codegen) {
+    if is_constant_expr(expr, &ctx.types) {
+        let value = evaluate_constant_expr(expr, &ctx.types);
+        generate_const_value_code(&value, ctx);
+        return;
+    }
+    
+    match expr {
+        Expr::Variable(name) => {
+            ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+        },
+        Expr::StructLiteral { name, fields } => {
+            let layout = match struct_literal_value(name, fields, &ctx.types.definitions, &|value| runtime_placeholder(value, &ctx.types)) {
+                ConstValue::Struct { fields, .. } => fields,
+                _ => unreachable!(),
+            };
+            ctx.text_section.push_str(&format!("    ; Build struct {}\n", name));
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", field_offset(layout.len())));
+            ctx.text_section.push_str("    call heap_alloc\n");
+            ctx.text_section.push_str(&format!("    mov qword [rax], {}\n", struct_descriptor_label(name)));
+            ctx.text_section.push_str("    push rax\n");
+            for (field, value) in fields {
+                let offset = field_offset(layout.iter().position(|(f, _)| f == field).unwrap());
+                generate_value_code(value, ctx);
+                ctx.text_section.push_str("    mov rsi, [rsp]\n");
+                ctx.text_section.push_str(&format!("    mov [rsi+{}], rdx  ; {}\n", offset, field));
+                ctx.text_section.push_str(&format!("    mov [rsi+{}], rax\n", offset + 8));
+            }
+            ctx.text_section.push_str("    pop rax\n");
+        },
+        Expr::MethodCall { object, method, args } => {
+            generate_method_code(object, method, args, ctx);
+        },
+        Expr::Index { object, index } => {
+            generate_index_code(object, index, ctx);
+        },
+        Expr::FieldAccess { object, field } => {
+            generate_field_code(object, field, ctx);
+        },
+        _ => panic!("Expression is not a struct"),
+    }
+}
+
+// Generate code that reads a struct field, leaving the value in RAX and its
+// type tag in RDX
+fn generate_field_code(object: &Expr, field: &str, ctx: &mut Codegen) {
+    let (index, _) = struct_field(runtime_placeholder(object, &ctx.types), field);
+    let offset = field_offset(index);
+    generate_struct_code(object, ctx);
+    ctx.text_section.push_str(&format!("    ; Read field {}\n", field));
+    ctx.text_section.push_str(&format!("    mov rdx, [rax+{}]\n", offset));
+    ctx.text_section.push_str(&format!("    mov rax, [rax+{}]\n", offset + 8));
+}
+
+// Store into a struct field: p.x = value;
+fn generate_field_assign_code(object: &Expr, field: &str, value: &Expr, ctx: &mut Codegen) {
+    check_not_constant(object, &ctx.types);
+    
+    let struct_name = match runtime_placeholder(object, &ctx.types) {
+        ConstValue::Struct { name, .. } => name,
+        _ => panic!("Only structs have fields"),
+    };
+    let (index, declared) = struct_field(runtime_placeholder(object, &ctx.types), field);
+    let element = runtime_placeholder(value, &ctx.types);
+    check_field_type(&struct_name, field, &declared, &element);
+    let offset = field_offset(index);
+    
+    ctx.text_section.push_str(&format!("    ; Store field {}\n", field));
+    generate_value_code(value, ctx);
+    ctx.text_section.push_str("    push rax\n");
+    ctx.text_section.push_str("    push rdx\n");
+    generate_struct_code(object, ctx);
+    ctx.text_section.push_str("    pop rdx\n");
+    ctx.text_section.push_str("    pop r8\n");
+    ctx.text_section.push_str(&format!("    mov [rax+{}], rdx\n", offset));
+    ctx.text_section.push_str(&format!("    mov [rax+{}], r8\n", offset + 8));
+    
+    track_container_mutations(value, &mut ctx.types);
+    if let Some(ConstValue::Struct { fields, .. }) = tracked_value_mut(object, &mut ctx.types.variables) {
+        fields[index].1 = element;
+    }
+}
+
+// Data label of the runtime descriptor of an enum variant
+fn variant_descriptor_label(enum_name: &str, variant: &str) -> String {
+    format!("enum_{}_{}", enum_name, variant)
+}
+
+// Compile-time value of Variant(args), checking the payload against the declaration
+fn variant_value(variant: &str, args: &[Expr], definitions: &Definitions,
+                 value_of: &dyn Fn(&Expr) -> ConstValue) -> ConstValue {
+    let (name, index, declared) = match definitions.variants.get(variant) {
+        Some(ConstValue::Enum { name, index, payload, .. }) => (name.clone(), *index, payload),
+        _ => panic!("Unknown enum variant: {}", variant),
+    };
+    if args.len() != declared.len() {
+        panic!("Variant '{}' expects {} values, found {}", variant, declared.len(), args.len());
+    }
+    let payload = args.iter().zip(declared).enumerate().map(|(i, (arg, declared))| {
+        let value = value_of(arg);
+        if type_name(declared) != type_name(&value) {
+            panic!("Value {} of variant '{}' expects {}, found {}", i + 1, variant, type_name(declared), type_name(&value));
+        }
+        value
+    }).collect();
+    ConstValue::Enum { name, variant: variant.to_string(), index, payload }
+}
+
+// How a match arm is tested against the matched value
+enum MatchCase {
+    Any,
+    Variant(usize),
+    Value(ConstValue),
+}
+
+// Resolve the patterns of a match against the type of the matched value. Every
+// arm must be reachable and together they must cover every possible value.
+fn check_match_arms(scrutinee: &ConstValue, arms: &[(Pattern, Vec<Expr>)], span: Span, types: &Types) -> Vec<MatchCase> {
+    let variants = match scrutinee {
+        ConstValue::Enum { name, .. } => types.definitions.enums[name].clone(),
+        _ => Vec::new(),
+    };
+    
+    let mut cases = Vec::new();
+    let mut seen_variants = HashSet::new();
+    let mut seen_values: Vec<ConstValue> = Vec::new();
+    let mut exhaustive = false;
+    for (i, (pattern, _)) in arms.iter().enumerate() {
+        if exhaustive {
+            panic!("Unreachable match arm {}: earlier arms already match every value at line {}, column {}", i + 1, span.line, span.column);
+        }
+        let case = match pattern {
+            Pattern::Wildcard => MatchCase::Any,
+            Pattern::Variant { name, bindings } if types.definitions.variants.contains_key(name) => {
+                let Some(ConstValue::Enum { name: enum_name, index, payload, .. }) = types.definitions.variants.get(name) else {
+                    unreachable!()
+                };
+                if type_name(scrutinee) != *enum_name {
+                    panic!("Pattern '{}' cannot match a value of type {} at line {}, column {}", name, type_name(scrutinee), span.line, span.column);
+                }
+                if !bindings.is_empty() && bindings.len() != payload.len() {
+                    panic!("Pattern '{}' binds {} values, the variant has {} at line {}, column {}", name, bindings.len(), payload.len(), span.line, span.column);
+                }
+                if !seen_variants.insert(*index) {
+                    panic!("Unreachable match arm {}: '{}' is already matched at line {}, column {}", i + 1, name, span.line, span.column);
+                }
+                MatchCase::Variant(*index)
+            },
+            Pattern::Variant { name, bindings } if bindings.is_empty() && types.constants.contains_key(name) => {
+                MatchCase::Value(types.constants[name].clone())
+            },
+            Pattern::Variant { name, .. } => panic!("Unknown enum variant: {} at line {}, column {}", name, span.line, span.column),
+            Pattern::Literal(literal) => MatchCase::Value(evaluate_constant_expr(literal, types)),
+        };
+        if let MatchCase::Value(value) = &case {
+            if !matches!(scrutinee, ConstValue::Null) && type_name(value) != type_name(scrutinee) {
+                panic!("Pattern {} cannot match a value of type {} at line {}, column {}", value, type_name(scrutinee), span.line, span.column);
+            }
+            if seen_values.iter().any(|seen| compare_const_values(seen, value) == Ordering::Equal) {
+                panic!("Unreachable match arm {}: {} is already matched at line {}, column {}", i + 1, value, span.line, span.column);
+            }
+            seen_values.push(value.clone());
+        }
+        exhaustive = matches!(case, MatchCase::Any)
+            || (!variants.is_empty() && seen_variants.len() == variants.len())
+            || (matches!(scrutinee, ConstValue::Boolean(_)) && seen_values.len() == 2);
+        cases.push(case);
+    }
+    
+    if !exhaustive {
+        let missing: Vec<String> = variants.iter().filter_map(|template| match template {
+            ConstValue::Enum { variant, index, .. } if !seen_variants.contains(index) => Some(variant.clone()),
+            _ => None,
+        }).collect();
+        if missing.is_empty() {
+            panic!("Non-exhaustive match: add a '_' arm at line {}, column {}", span.line, span.column);
+        }
+        panic!("Non-exhaustive match: {} not covered at line {}, column {}", missing.join(", "), span.line, span.column);
+    }
+    cases
+}
+
+// Stand-in for the payload of a matched variant. The matched value's own
+// payload is used when it is known to be that variant.
+fn variant_payload(scrutinee: &ConstValue, variant: &str, definitions: &Definitions) -> Vec<ConstValue> {
+    match scrutinee {
+        ConstValue::Enum { variant: known, payload, .. } if known == variant => payload.clone(),
+        _ => match definitions.variants.get(variant) {
+            Some(ConstValue::Enum { payload, .. }) => payload.clone(),
+            _ => unreachable!(),
+        },
+    }
+}
+
+// A variable hidden by a match binding until the end of the arm
+struct Shadowed {
+    name: String,
+    value: Option<ConstValue>, // None when no variable had the binding's name
+    tagged: bool,
+    save: String,              // Where its value waits while it is hidden
+}
+
+// Copy one payload value of the matched variant into the binding. A variable
+// with the same name is saved and gets its value back in unbind_payload.
+fn bind_payload(binding: &str, position: usize, payload: ConstValue, slot: &str, span: Span, ctx: &mut Codegen) -> Shadowed {
+    if ctx.types.constants.contains_key(binding) {
+        panic!("Cannot bind '{}', a constant with the same name already exists at line {}, column {}", binding, span.line, span.column);
+    }
+    if matches!(payload, ConstValue::Float(_)) {
+        panic!("Float payloads cannot be bound yet, match them with '_' at line {}, column {}", span.line, span.column);
+    }
+    let value = ctx.types.variables.get(binding).cloned();
+    let tagged = is_tagged_var(binding, &ctx.types.variables);
+    let save = get_string_label(&mut ctx.counter, "binding_save");
+    if value.is_some() {
+        ctx.bss_section.push_str(&format!("{}: resq 2  ; Variable {} while the binding hides it\n", save, binding));
+        ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", binding));
+        ctx.text_section.push_str(&format!("    mov [{}], rax\n", save));
+        if tagged {
+            ctx.text_section.push_str(&format!("    mov rax, [var_tag_{}]\n", binding));
+            ctx.text_section.push_str(&format!("    mov [{}+8], rax\n", save));
+        }
+    } else if ctx.bound.insert(binding.to_string()) {
+        ctx.bss_section.push_str(&format!("var_mem_{}: resq 1  ; Memory for variable {}\n", binding, binding));
+    }
+    ctx.text_section.push_str(&format!("    ; Bind {}\n", binding));
+    ctx.text_section.push_str(&format!("    mov rax, [{}+8]\n", slot));
+    if tagged {
+        ctx.text_section.push_str(&format!("    mov rdx, [rax+{}]\n", field_offset(position)));
+        ctx.text_section.push_str(&format!("    mov [var_tag_{}], rdx\n", binding));
+    }
+    ctx.text_section.push_str(&format!("    mov rax, [rax+{}]\n", field_offset(position) + 8));
+    ctx.text_section.push_str(&format!("    mov [var_mem_{}], rax\n", binding));
+    ctx.types.variables.insert(binding.to_string(), payload);
+    ctx.bindings.push(binding.to_string());
+    Shadowed { name: binding.to_string(), value, tagged, save }
+}
+
+// End a binding at the end of its arm, giving a hidden variable its value back
+fn unbind_payload(shadowed: Shadowed, ctx: &mut Codegen) {
+    ctx.bindings.pop();
+    let name = &shadowed.name;
+    let Some(value) = shadowed.value else {
+        ctx.types.variables.remove(name);
+        return;
+    };
+    ctx.text_section.push_str(&format!("    ; Unbind {}\n", name));
+    ctx.text_section.push_str(&format!("    mov rax, [{}]\n", shadowed.save));
+    ctx.text_section.push_str(&format!("    mov [var_mem_{}], rax\n", name));
+    if shadowed.tagged {
+        ctx.text_section.push_str(&format!("    mov rax, [{}+8]\n", shadowed.save));
+        ctx.text_section.push_str(&format!("    mov [var_tag_{}], rax\n", name));
+    }
+    ctx.types.variables.insert(name.clone(), value);
+}
+
+// Generate code that leaves a pointer to a runtime enum value in RAX
+fn generate_enum_code(expr: &Expr, ctx: &mut Codegen) {
+    if is_constant_expr(expr, &ctx.types) {
+        let value = evaluate_constant_expr(expr, &ctx.types);
+        generate_const_value_code(&value, ctx);
+        return;
+    }
+    
+    match expr {
+        Expr::Variable(name) => {
+            ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+        },
+        Expr::Variant { name, args } => {
+            let enum_name = match variant_value(name, args, &ctx.types.definitions, &|value| runtime_placeholder(value, &ctx.types)) {
+                ConstValue::Enum { name, .. } => name,
+                _ => unreachable!(),
+            };
+            ctx.text_section.push_str(&format!("    ; Build variant {}\n", name));
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", field_offset(args.len())));
+            ctx.text_section.push_str("    call heap_alloc\n");
+            ctx.text_section.push_str(&format!("    mov qword [rax], {}\n", variant_descriptor_label(&enum_name, name)));
+            ctx.text_section.push_str("    push rax\n");
+            for (i, arg) in args.iter().enumerate() {
+                generate_value_code(arg, ctx);
+                ctx.text_section.push_str("    mov rsi, [rsp]\n");
+                ctx.text_section.push_str(&format!("    mov [rsi+{}], rdx\n", field_offset(i)));
+                ctx.text_section.push_str(&format!("    mov [rsi+{}], rax\n", field_offset(i) + 8));
+            }
+            ctx.text_section.push_str("    pop rax\n");
+        },
+        Expr::MethodCall { object, method, args } => {
+            generate_method_code(object, method, args, ctx);
+        },
+        Expr::Index { object, index } => {
+            generate_index_code(object, index, ctx);
+        },
+        Expr::FieldAccess { object, field } => {
+            generate_field_code(object, field, ctx);
+        },
+        _ => panic!("Expression is not an enum value"),
+    }
+}
+
+// Generate code that leaves any value in RAX and its type tag in RDX
+fn generate_value_code(expr: &Expr, ctx: &mut Codegen) {
+    if is_constant_expr(expr, &ctx.types) {
+        let value = evaluate_constant_expr(expr, &ctx.types);
+        generate_const_value_code(&value, ctx);
+        return;
+    }
+    // Elements taken out of containers bring their own tag
+    if let Expr::MethodCall { object, method, args } = expr
+        && method == "pop" && is_array_expr(object, &ctx.types) {
+        generate_array_method_code(object, method, args, ctx);
+        return;
+    }
+    if let Expr::Index { object, index } = expr {
+        generate_index_code(object, index, ctx);
+        return;
+    }
+    if let Expr::FieldAccess { object, field } = expr {
+        generate_field_code(object, field, ctx);
+        return;
+    }
+    // Input, and variables holding values typed at runtime, bring their own tag
+    if let Expr::ConsoleRead(kind) = expr {
+        ctx.text_section.push_str(&format!("    call {}\n", console_read_helper(*kind)));
+        return;
+    }
+    if let Expr::Fs { op, args, span } = expr {
+        generate_fs_code(*op, args, *span, ctx);
+        return;
+    }
+    if let Expr::Math { function, args, span } = expr {
+        if is_known_at_compile_time(expr, &ctx.types) {
+            let value = evaluate_constant_expr(expr, &ctx.types);
+            generate_const_value_code(&value, ctx);
+        } else {
+            generate_math_code(*function, args, *span, ctx);
+        }
+        return;
+    }
+    if let Expr::Time { function, args } = expr {
+        generate_time_code(*function, args, ctx);
+        return;
+    }
+    if let Expr::Random { function, args, span } = expr {
+        generate_random_code(*function, args, *span, ctx);
+        return;
+    }
+    if let Expr::ProcessArgs = expr {
+        ctx.text_section.push_str("    call process_args\n");
+        return;
+    }
+    if let Expr::ProcessEnv(name) = expr {
+        if !is_string_expr(name, &ctx.types) {
+            panic!("process.env needs the name of a variable as a string");
+        }
+        generate_string_code(name, ctx);
+        ctx.text_section.push_str("    push rsi\n");
+        ctx.text_section.push_str("    mov rsi, rax\n");
+        ctx.text_section.push_str("    call process_env\n");
+        ctx.text_section.push_str("    pop rsi\n");
+        return;
+    }
+    if let Expr::Variable(name) = expr
+        && is_tagged_var(name, &ctx.types.variables) {
+        ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+        ctx.text_section.push_str(&format!("    mov rdx, [var_tag_{}]\n", name));
+        return;
+    }
+    
+    let tag = value_tag(expr, &ctx.types);
+    match tag {
+        runtime::TAG_ARRAY => generate_array_code(expr, ctx),
+        runtime::TAG_MAP => generate_map_code(expr, ctx),
+        runtime::TAG_STRUCT => generate_struct_code(expr, ctx),
+        runtime::TAG_ENUM => generate_enum_code(expr, ctx),
+        runtime::TAG_STRING | runtime::TAG_FLOAT => generate_string_code(expr, ctx),
+        runtime::TAG_NULL => ctx.text_section.push_str("    xor rax, rax\n"),
+        _ => generate_expression_code(expr, ctx),
+    }
+    ctx.text_section.push_str(&format!("    mov rdx, {}\n", tag));
+}
+
+// Generate code that rebuilds a folded value at runtime, the value in RAX and
+// its type tag in RDX
+fn generate_const_value_code(value: &ConstValue, ctx: &mut Codegen) {
+    match value {
+        ConstValue::Number(n) => {
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
+        },
+        ConstValue::Boolean(b) => {
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", if *b { 1 } else { 0 }));
+        },
+        ConstValue::Char(c) => {
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", *c as u32));
+        },
+        ConstValue::Null => {
+            ctx.text_section.push_str("    xor rax, rax\n");
+        },
+        ConstValue::String(_) | ConstValue::Float(_) => {
+            let label = get_string_label(&mut ctx.counter, "strz");
+            ctx.data_section.push_str(&runtime::string_data(&label, &value.to_string()));
+            ctx.text_section.push_str(&format!("    mov rax, {}  ; \"{}\"\n", label, value.to_string().escape_default()));
+        },
+        ConstValue::Array(values) => {
+            ctx.text_section.push_str(&format!("    ; Build array with {} elements\n", values.len()));
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", values.len()));
+            ctx.text_section.push_str("    call array_new\n");
+            ctx.text_section.push_str("    push rax\n");
+            for element in values {
+                generate_const_value_code(element, ctx);
+                ctx.text_section.push_str("    mov rsi, [rsp]\n");
+                ctx.text_section.push_str("    call array_push\n");
+            }
+            ctx.text_section.push_str("    pop rax\n");
+        },
+        ConstValue::Map(entries) => {
+            ctx.text_section.push_str(&format!("    ; Build map with {} entries\n", entries.len()));
+            ctx.text_section.push_str("    call map_new\n");
+            ctx.text_section.push_str("    push rax\n");
+            for (key, element) in entries {
+                generate_const_value_code(element, ctx);
+                ctx.text_section.push_str("    mov r8, rax\n");
+                ctx.text_section.push_str("    mov r9, rdx\n");
+                generate_const_value_code(key, ctx);
+                ctx.text_section.push_str("    mov rsi, [rsp]\n");
+                ctx.text_section.push_str("    call map_set\n");
+            }
+            ctx.text_section.push_str("    pop rax\n");
+        },
+        ConstValue::Struct { name, fields } => {
+            ctx.text_section.push_str(&format!("    ; Build struct {}\n", name));
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", field_offset(fields.len())));
+            ctx.text_section.push_str("    call heap_alloc\n");
+            ctx.text_section.push_str(&format!("    mov qword [rax], {}\n", struct_descriptor_label(name)));
+            ctx.text_section.push_str("    push rax\n");
+            for (i, (field, element)) in fields.iter().enumerate() {
+                generate_const_value_code(element, ctx);
+                ctx.text_section.push_str("    mov rsi, [rsp]\n");
+                ctx.text_section.push_str(&format!("    mov [rsi+{}], rdx  ; {}\n", field_offset(i), field));
+                ctx.text_section.push_str(&format!("    mov [rsi+{}], rax\n", field_offset(i) + 8));
+            }
+            ctx.text_section.push_str("    pop rax\n");
+        },
+        ConstValue::Enum { name, variant, payload, .. } => {
+            let label = variant_descriptor_label(name, variant);
+            if payload.is_empty() {
+                ctx.text_section.push_str(&format!("    mov rax, {}_value  ; {}\n", label, variant));
+            } else {
+                ctx.text_section.push_str(&format!("    ; Build variant {}\n", variant));
+                ctx.text_section.push_str(&format!("    mov rax, {}\n", field_offset(payload.len())));
+                ctx.text_section.push_str("    call heap_alloc\n");
+                ctx.text_section.push_str(&format!("    mov qword [rax], {}\n", label));
+                ctx.text_section.push_str("    push rax\n");
+                for (i, element) in payload.iter().enumerate() {
+                    generate_const_value_code(element, ctx);
+                    ctx.text_section.push_str("    mov rsi, [rsp]\n");
+                    ctx.text_section.push_str(&format!("    mov [rsi+{}], rdx\n", field_offset(i)));
+                    ctx.text_section.push_str(&format!("    mov [rsi+{}], rax\n", field_offset(i) + 8));
+                }
+                ctx.text_section.push_str("    pop rax\n");
+            }
+        },
+    }
+    ctx.text_section.push_str(&format!("    mov rdx, {}\n", const_value_tag(value)));
+}
+
+// Compute a variable's value at runtime and store it in its memory
+fn generate_runtime_store(name: &str, value: &Expr, ctx: &mut Codegen) {
+    let placeholder = runtime_placeholder(value, &ctx.types);
+    let tagged = is_tagged_var(name, &ctx.types.variables) || is_tagged_expr(value, &ctx.types);
+    if tagged && !is_tagged_var(name, &ctx.types.variables) {
+        // From here on the variable keeps its tag, starting out as its current type
+        let tag = const_value_tag(ctx.types.variables.get(name).unwrap_or(&placeholder));
+        ctx.data_section.push_str(&format!("var_tag_{} dq {}\n", name, tag));
+        ctx.types.variables.insert(tagged_key(name), ConstValue::Boolean(true));
+    }
+    ctx.text_section.push_str(&format!("    ; {} = value computed at runtime\n", name));
+    generate_value_code(value, ctx);
+    ctx.text_section.push_str(&format!("    mov qword [var_mem_{}], rax\n", name));
+    if tagged {
+        ctx.text_section.push_str(&format!("    mov qword [var_tag_{}], rdx\n", name));
+    }
+    track_container_mutations(value, &mut ctx.types);
+    ctx.types.variables.insert(name.to_string(), placeholder);
+}
+
+// A method call used as a statement, run for its side effects
+fn generate_method_statement(expr: &Expr, ctx: &mut Codegen) {
+    if let Expr::MethodCall { object, method, .. } = expr {
+        if let Expr::Variable(name) = object.as_ref()
+            && ctx.types.constants.contains_key(name) && matches!(method.as_str(), "push" | "pop" | "reverse" | "sort" | "remove") {
+            panic!("Cannot modify constant '{}'", name);
+        }
+        ctx.text_section.push_str(&format!("    ; Call {}() for its side effects\n", method));
+    }
+    generate_value_code(expr, ctx);
+    track_container_mutations(expr, &mut ctx.types);
+}
+
+// Keep the compile-time picture of container variables in step with push(),
+// pop() and remove(), so later code still knows the type of their elements
+fn track_container_mutations(expr: &Expr, types: &mut Types) {
+    match expr {
+        Expr::MethodCall { object, method, args } => {
+            track_container_mutations(object, types);
+            for arg in args {
+                track_container_mutations(arg, types);
+            }
+            if matches!(object.as_ref(), Expr::Variable(_) | Expr::FieldAccess { .. }) {
+                let argument = args.first().map(|arg| runtime_placeholder(arg, types));
+                let known_key = args.first().is_some_and(|arg| is_constant_expr(arg, types));
+                match (tracked_value_mut(object, &mut types.variables), method.as_str()) {
+                    (Some(ConstValue::Array(values)), "push") => values.extend(argument),
+                    (Some(ConstValue::Array(values)), "pop") => {
+                        values.pop();
+                    },
+                    (Some(ConstValue::Map(entries)), "remove") if known_key => {
+                        let key = argument.unwrap();
+                        entries.retain(|(k, _)| compare_const_values(k, &key) != Ordering::Equal);
+                    },
+                    _ => {},
+                }
+            }
+        },
+        Expr::BinaryOp { left, right, .. } => {
+            track_container_mutations(left, types);
+            track_container_mutations(right, types);
+        },
+        Expr::UnaryOp { operand, .. } => track_container_mutations(operand, types),
+        Expr::Array(elements) => {
+            for element in elements {
+                track_container_mutations(element, types);
+            }
+        },
+        Expr::StructLiteral { fields, .. } => {
+            for (_, value) in fields {
+                track_container_mutations(value, types);
+            }
+        },
+        Expr::Variant { args, .. } => {
+            for arg in args {
+                track_container_mutations(arg, types);
+            }
+        },
+        _ => {},
+    }
+}
+
+// Generate code for string concatenation
+fn generate_string_concat(expr: &Expr, ctx: &mut Codegen) {
+    match expr {
+        Expr::StringLiteral(s) => {
+            // Find the label for this string
+            let label = ctx.string_labels.get(s).expect("String literal not found in labels");
+            
+            ctx.text_section.push_str(&format!("    ; Append string: {}\n", s.escape_default()));
+            ctx.text_section.push_str(&format!("    mov rsi, {}\n", label));
+            ctx.text_section.push_str("    call append_string_without_newline\n");
+        },
+        Expr::Number(n) => {
+            ctx.text_section.push_str(&format!("    ; Append number: {}\n", n));
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
+            ctx.text_section.push_str("    call append_number\n");
+        },
+        Expr::Float(f) => {
+            // For float concatenation, convert to string and store in data section
+            let float_label = format!("float_concat_{}", &mut ctx.counter);
+            ctx.counter += 1;
+            let float_str = f.to_string();
+            ctx.data_section.push_str(&runtime::string_data(&float_label, &float_str));
+            
+            ctx.text_section.push_str(&format!("    ; Append float: {}\n", f));
+            ctx.text_section.push_str(&format!("    mov rsi, {}\n", float_label));
+            ctx.text_section.push_str("    call append_string_without_newline\n");
+        },
+        Expr::Boolean(b) => {
+            ctx.text_section.push_str(&format!("    ; Append boolean: {}\n", b));
+            if *b {
+                ctx.text_section.push_str("    mov rsi, true_str\n");
+            } else {
+                ctx.text_section.push_str("    mov rsi, false_str\n");
+            }
+            ctx.text_section.push_str("    call append_string_without_newline\n");
+        },
+        Expr::Null => {
+            ctx.text_section.push_str("    ; Append null\n");
+            ctx.text_section.push_str("    mov rsi, null_str\n");
+            ctx.text_section.push_str("    call append_string_without_newline\n");
+        },
+        Expr::Variable(name) if is_tagged_var(name, &ctx.types.variables) => {
+            ctx.text_section.push_str(&format!("    ; Append variable with a runtime type: {}\n", name));
+            ctx.text_section.push_str("    push rdi\n");
+            ctx.text_section.push_str("    push rcx\n");
+            generate_string_code(expr, ctx);
+            ctx.text_section.push_str("    pop rcx\n");
+            ctx.text_section.push_str("    pop rdi\n");
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            ctx.text_section.push_str("    call append_string\n");
+        },
+        Expr::Variable(name) => {
+            let in_constants = ctx.types.constants.contains_key(name);
+            let value = if in_constants {
+                ctx.types.constants.get(name).unwrap()
+            } else {
+                ctx.types.variables.get(name).unwrap()
+            };
+            match value {
+                ConstValue::Number(_) => {
+                    ctx.text_section.push_str(&format!("    ; Append numeric variable: {}\n", name));
+                    ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                    ctx.text_section.push_str("    call append_number\n");
+                },
+                ConstValue::Float(_) => {
+                    ctx.text_section.push_str(&format!("    ; Append float variable: {}\n", name));
+                    let float_label = get_var_label(name, Some("_float"));
+                    if in_constants {
+                        ctx.text_section.push_str(&format!("    mov rsi, {}\n", float_label));
+                    } else {
+                        ctx.text_section.push_str(&format!("    mov rsi, [var_mem_{}_float]\n", name));
+                    }
+                    ctx.text_section.push_str("    call append_string_without_newline\n");
+                },
+                ConstValue::String(_) => {
+                    ctx.text_section.push_str(&format!("    ; Append string variable: {}\n", name));
+                    let var_label = get_var_label(name, None);
+                    if in_constants {
+                        ctx.text_section.push_str(&format!("    mov rsi, {}\n", var_label));
+                    } else {
+                        ctx.text_section.push_str(&format!("    mov rsi, [var_mem_{}]\n", name));
+                    }
+                    ctx.text_section.push_str("    call append_string_without_newline\n");
+                },
+                ConstValue::Boolean(_) => {
+                    ctx.text_section.push_str(&format!("    ; Append boolean variable: {}\n", name));
+                    if in_constants {
+                        if let ConstValue::Boolean(b) = value {
+                            if *b {
+                                ctx.text_section.push_str("    mov rsi, true_str\n");
+                            } else {
+                                ctx.text_section.push_str("    mov rsi, false_str\n");
+                            }
+                        }
+                    } else {
+                        ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                        ctx.text_section.push_str("    cmp rax, 0\n");
+                        ctx.text_section.push_str(&format!("    je .false_{}\n", name));
+                        ctx.text_section.push_str("    mov rsi, true_str\n");
+                        ctx.text_section.push_str(&format!("    jmp .done_{}\n", name));
+                        ctx.text_section.push_str(&format!(".false_{}:\n", name));
+                        ctx.text_section.push_str("    mov rsi, false_str\n");
+                        ctx.text_section.push_str(&format!(".done_{}:\n", name));
+                    }
+                    ctx.text_section.push_str("    call append_string_without_newline\n");
+                },
+                ConstValue::Char(_) => {
+                    ctx.text_section.push_str(&format!("    ; Append char variable: {}\n", name));
+                    if in_constants {
+                        ctx.text_section.push_str(&format!("    mov rsi, {}\n", get_var_label(name, None)));
+                        ctx.text_section.push_str("    call append_string_without_newline\n");
+                    } else {
+                        ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                        ctx.text_section.push_str("    call str_from_char\n");
+                        ctx.text_section.push_str("    mov rsi, rax\n");
+                        ctx.text_section.push_str("    call append_string\n");
+                    }
+                },
+                ConstValue::Array(_) | ConstValue::Map(_) | ConstValue::Struct { .. } | ConstValue::Enum { .. } => {
+                    ctx.text_section.push_str(&format!("    ; Append container: {}\n", name));
+                    generate_string_code(expr, ctx);
+                    ctx.text_section.push_str("    mov rsi, rax\n");
+                    ctx.text_section.push_str("    call append_string\n");
+                },
+                ConstValue::Null => {
+                    ctx.text_section.push_str(&format!("    ; Append null constant: {}\n", name));
+                    ctx.text_section.push_str("    mov rsi, null_str\n");
+                    ctx.text_section.push_str("    call append_string_without_newline\n");
+                }
+            }
+        },
+        Expr::BinaryOp { .. } | Expr::UnaryOp { .. } => {
+            if let Expr::BinaryOp { op: BinOp::Add, left, right, .. } = expr
+                && (is_string_expr(left, &ctx.types) || is_string_expr(right, &ctx.types)) {
+                // If this is a string concatenation, process each part separately
+                generate_string_concat(left, ctx);
+                generate_string_concat(right, ctx);
+            } else if is_float_expr(expr, &ctx.types) {
+                // Float expressions are folded, see needs_runtime_evaluation
+                check_float_operands(expr, &ctx.types);
+                if let ConstValue::Float(f) = evaluate_constant_expr(expr, &ctx.types) {
+                    generate_string_concat(&Expr::Float(f), ctx);
+                }
+            } else {
+                ctx.text_section.push_str("    ; Append result of numeric expression\n");
+               
+                ctx.text_section.push_str("    push rbx\n");
+                ctx.text_section.push_str("    push rdx\n");
+                
+                generate_expression_code(expr, ctx);
+                
+                ctx.text_section.push_str("    ; Call append_number with result in RAX\n");
+                
+                ctx.text_section.push_str("    pop rdx\n");
+                ctx.text_section.push_str("    pop rbx\n");
+                
+                ctx.text_section.push_str("    call append_number\n");
+            }
+        },
+        Expr::MethodCall { .. } | Expr::Array(_) | Expr::Map(_) | Expr::Index { .. }
+        | Expr::StructLiteral { .. } | Expr::FieldAccess { .. } | Expr::Variant { .. } | Expr::ConsoleRead(_)
+        | Expr::ProcessArgs | Expr::ProcessEnv(_) | Expr::Fs { .. } | Expr::Math { .. } | Expr::Time { .. }
+        | Expr::Random { .. } | Expr::Char(_) | Expr::CharFromCode { .. } => {
+            ctx.text_section.push_str("    ; Append runtime value\n");
+            ctx.text_section.push_str("    push rdi\n");
+            ctx.text_section.push_str("    push rcx\n");
+            generate_string_code(expr, ctx);
+            ctx.text_section.push_str("    pop rcx\n");
+            ctx.text_section.push_str("    pop rdi\n");
+            ctx.text_section.push_str("    mov rsi, rax\n");
+            ctx.text_section.push_str("    call append_string\n");
+        },
+        _ => panic!("Unsupported expression in string concatenation"),
+    }
+}
+
+// Generate code for expressions
+fn generate_expression_code(expr: &Expr, ctx: &mut Codegen) {
+    match expr {
+        Expr::Number(n) => {
+            ctx.text_section.push_str(&format!("    ; Load number: {}\n", n));
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
+        },
+        Expr::Float(f) => {
+            // Floats would normally require FPU or SSE but for simplicity use integers
+            let int_val = (*f * 100.0) as i64; // Scale up by 100 to preserve some decimal places
+            ctx.text_section.push_str(&format!("    ; Load float: {} (scaled as integer)\n", f));
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", int_val));
+        },
+        Expr::Boolean(b) => {
+            ctx.text_section.push_str(&format!("    ; Load boolean: {}\n", b));
+            if *b {
+                ctx.text_section.push_str("    mov rax, 1\n");
+            } else {
+                ctx.text_section.push_str("    mov rax, 0\n");
+            }
+        },
+        Expr::Char(c) => {
+            ctx.text_section.push_str(&format!("    ; Load char: '{}'\n", c.escape_default()));
+            ctx.text_section.push_str(&format!("    mov rax, {}\n", *c as u32));
+        },
+        Expr::Variable(name) => {
+            if let Some(value) = ctx.types.constants.get(name) {
+                match value {
+                    ConstValue::Number(n) => {
+                        ctx.text_section.push_str(&format!("    ; Load numeric constant: {}\n", name));
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
+                    },
                     ConstValue::Float(f) => {
                         // Similar to above use integers for simplicity
                         let int_val = (*f * 100.0) as i64;
-                        text_section.push_str(&format!("    ; Load float constant: {} (scaled as integer)\n", name));
-                        text_section.push_str(&format!("    mov rax, {}\n", int_val));
+                        ctx.text_section.push_str(&format!("    ; Load float constant: {} (scaled as integer)\n", name));
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", int_val));
                     },
                     ConstValue::Boolean(b) => {
-                        text_section.push_str(&format!("    ; Load boolean constant: {}\n", name));
+                        ctx.text_section.push_str(&format!("    ; Load boolean constant: {}\n", name));
                         if *b {
-                            text_section.push_str("    mov rax, 1\n");
+                            ctx.text_section.push_str("    mov rax, 1\n");
                         } else {
-                            text_section.push_str("    mov rax, 0\n");
+                            ctx.text_section.push_str("    mov rax, 0\n");
                         }
                     },
+                    ConstValue::Char(c) => {
+                        ctx.text_section.push_str(&format!("    ; Load char constant: {}\n", name));
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", *c as u32));
+                    },
                     _ => panic!("Cannot use non-numeric constant in expression: {}", name),
                 }
-            } else if let Some(value) = variables.get(name) {
+            } else if let Some(value) = ctx.types.variables.get(name) {
                 match value {
                     ConstValue::Number(_) => {
-                        text_section.push_str(&format!("    ; Load numeric variable: {}\n", name));
-                        text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                        ctx.text_section.push_str(&format!("    ; Load numeric variable: {}\n", name));
+                        ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
                     },
                     ConstValue::Float(_) => {
-                        text_section.push_str(&format!("    ; Load float variable: {} (scaled as integer)\n", name));
-                        text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                        ctx.text_section.push_str(&format!("    ; Load float variable: {} (scaled as integer)\n", name));
+                        ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
                     },
                     ConstValue::Boolean(_) => {
-                        text_section.push_str(&format!("    ; Load boolean variable: {}\n", name));
-                        text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                        ctx.text_section.push_str(&format!("    ; Load boolean variable: {}\n", name));
+                        ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
+                    },
+                    ConstValue::Char(_) => {
+                        ctx.text_section.push_str(&format!("    ; Load char variable: {}\n", name));
+                        ctx.text_section.push_str(&format!("    mov rax, [var_mem_{}]\n", name));
                     },
                     _ => panic!("Cannot use non-numeric variable in expression: {}", name),
                 }
@@ -1121,104 +3494,764 @@ fn generate_expression_code(expr: &Expr, text_section: &mut String, constants: &
                 panic!("Undefined variable: {}", name);
             }
         },
-        Expr::BinaryOp { op, left, right } => {
+        Expr::BinaryOp { op, left, right, span } => {
             // Check if this is a string operation
-            if *op == BinOp::Add && (is_string_expr(left, constants, variables) || is_string_expr(right, constants, variables)) {
+            if *op == BinOp::Add && (is_string_expr(left, &ctx.types) || is_string_expr(right, &ctx.types)) {
                 panic!("String operations should be handled by generate_string_concat");
             }
+            if is_float_expr(expr, &ctx.types) {
+                check_float_operands(expr, &ctx.types);
+            }
+            check_char_operands(op, left, right, *span, &ctx.types);
+            
+            // Strings and values typed at runtime compare by value
+            if matches!(op, BinOp::Equal | BinOp::NotEqual)
+                && (compares_by_value(left, &ctx.types) || compares_by_value(right, &ctx.types)) {
+                generate_value_equality(*op == BinOp::Equal, left, right, ctx);
+                return;
+            }
+            
+            // Values typed at runtime, like floats from math, order by value
+            if matches!(op, BinOp::Lt | BinOp::Gt | BinOp::Lte | BinOp::Gte)
+                && (is_tagged_expr(left, &ctx.types) || is_tagged_expr(right, &ctx.types)) {
+                generate_value_ordering(op, left, right, *span, ctx);
+                return;
+            }
             
             // First, evaluate the right expression and push result to stack
-            generate_expression_code(right, text_section, constants, variables);
-            text_section.push_str("    push rax\n");
+            generate_operand_code(right, *span, ctx);
+            ctx.text_section.push_str("    push rax\n");
             
             // Then, evaluate the left expression (result in RAX)
-            generate_expression_code(left, text_section, constants, variables);
+            generate_operand_code(left, *span, ctx);
             
             // Pop right result into RBX
-            text_section.push_str("    pop rbx\n");
+            ctx.text_section.push_str("    pop rbx\n");
             
             // Perform the operation
             match op {
-                BinOp::Add => text_section.push_str("    add rax, rbx\n"),
-                BinOp::Sub => text_section.push_str("    sub rax, rbx\n"),
-                BinOp::Mul => text_section.push_str("    imul rax, rbx\n"),
-                BinOp::Div => {
-                    text_section.push_str("    xor rdx, rdx\n"); // Clear RDX for division
-                    text_section.push_str("    div rbx\n");
+                BinOp::Add => ctx.text_section.push_str("    add rax, rbx\n"),
+                BinOp::Sub => ctx.text_section.push_str("    sub rax, rbx\n"),
+                BinOp::Mul => ctx.text_section.push_str("    imul rax, rbx\n"),
+                BinOp::Div | BinOp::Mod => {
+                    ctx.text_section.push_str("    test rbx, rbx\n");
+                    generate_trap_check("jnz", "division_by_zero_trap", *span, ctx);
+                    
+                    // idiv faults on MIN / -1, so dividing by -1 negates instead
+                    // and wraps like the other arithmetic
+                    let divide_label = get_string_label(&mut ctx.counter, "divide");
+                    let done_label = get_string_label(&mut ctx.counter, "divide_done");
+                    ctx.text_section.push_str("    cmp rbx, -1\n");
+                    ctx.text_section.push_str(&format!("    jne {}\n", divide_label));
+                    if *op == BinOp::Div {
+                        ctx.text_section.push_str("    neg rax\n");
+                        if ctx.options.overflow_checks {
+                            generate_trap_check("jno", "overflow_trap", *span, ctx);
+                        }
+                    } else {
+                        ctx.text_section.push_str("    xor rax, rax\n");
+                    }
+                    ctx.text_section.push_str(&format!("    jmp {}\n", done_label));
+                    ctx.text_section.push_str(&format!("{}:\n", divide_label));
+                    
+                    // Signed division truncates toward zero and the remainder
+                    // takes the sign of the dividend: -7 % 3 == -1
+                    ctx.text_section.push_str("    cqo\n"); // Sign-extend RAX into RDX
+                    ctx.text_section.push_str("    idiv rbx\n");
+                    if *op == BinOp::Mod {
+                        ctx.text_section.push_str("    mov rax, rdx\n");
+                    }
+                    ctx.text_section.push_str(&format!("{}:\n", done_label));
+                },
+                BinOp::Pow => ctx.text_section.push_str("    call int_pow\n"),
+                BinOp::BitAnd => ctx.text_section.push_str("    and rax, rbx\n"),
+                BinOp::BitOr => ctx.text_section.push_str("    or rax, rbx\n"),
+                BinOp::BitXor => ctx.text_section.push_str("    xor rax, rbx\n"),
+                BinOp::Shl | BinOp::Shr => {
+                    // Shift counts use their low six bits, >> keeps the sign
+                    ctx.text_section.push_str("    push rcx\n");
+                    ctx.text_section.push_str("    mov rcx, rbx\n");
+                    ctx.text_section.push_str(if *op == BinOp::Shl { "    shl rax, cl\n" } else { "    sar rax, cl\n" });
+                    ctx.text_section.push_str("    pop rcx\n");
                 },
                 BinOp::Equal => {
-                    text_section.push_str("    cmp rax, rbx\n");
-                    text_section.push_str("    sete al\n");
-                    text_section.push_str("    movzx rax, al\n");
+                    ctx.text_section.push_str("    cmp rax, rbx\n");
+                    ctx.text_section.push_str("    sete al\n");
+                    ctx.text_section.push_str("    movzx rax, al\n");
                 },
                 BinOp::NotEqual => {
-                    text_section.push_str("    cmp rax, rbx\n");
-                    text_section.push_str("    setne al\n");
-                    text_section.push_str("    movzx rax, al\n");
+                    ctx.text_section.push_str("    cmp rax, rbx\n");
+                    ctx.text_section.push_str("    setne al\n");
+                    ctx.text_section.push_str("    movzx rax, al\n");
                 },
                 BinOp::Lt => {
-                    text_section.push_str("    cmp rax, rbx\n");
-                    text_section.push_str("    setl al\n");
-                    text_section.push_str("    movzx rax, al\n");
+                    ctx.text_section.push_str("    cmp rax, rbx\n");
+                    ctx.text_section.push_str("    setl al\n");
+                    ctx.text_section.push_str("    movzx rax, al\n");
                 },
                 BinOp::Gt => {
-                    text_section.push_str("    cmp rax, rbx\n");
-                    text_section.push_str("    setg al\n");
-                    text_section.push_str("    movzx rax, al\n");
+                    ctx.text_section.push_str("    cmp rax, rbx\n");
+                    ctx.text_section.push_str("    setg al\n");
+                    ctx.text_section.push_str("    movzx rax, al\n");
                 },
                 BinOp::Lte => {
-                    text_section.push_str("    cmp rax, rbx\n");
-                    text_section.push_str("    setle al\n");
-                    text_section.push_str("    movzx rax, al\n");
+                    ctx.text_section.push_str("    cmp rax, rbx\n");
+                    ctx.text_section.push_str("    setle al\n");
+                    ctx.text_section.push_str("    movzx rax, al\n");
                 },
                 BinOp::Gte => {
-                    text_section.push_str("    cmp rax, rbx\n");
-                    text_section.push_str("    setge al\n");
-                    text_section.push_str("    movzx rax, al\n");
+                    ctx.text_section.push_str("    cmp rax, rbx\n");
+                    ctx.text_section.push_str("    setge al\n");
+                    ctx.text_section.push_str("    movzx rax, al\n");
+                },
+            }
+            
+            // With --overflow-checks, results outside the signed range trap
+            // (int_pow reports overflow in the carry flag)
+            if ctx.options.overflow_checks {
+                match op {
+                    BinOp::Add | BinOp::Sub | BinOp::Mul => generate_trap_check("jno", "overflow_trap", *span, ctx),
+                    BinOp::Pow => generate_trap_check("jnc", "overflow_trap", *span, ctx),
+                    _ => {},
+                }
+            }
+        },
+        Expr::ConsoleRead(ReadKind::Int) => {
+            // Operators check for null first, elsewhere null input reads as 0
+            ctx.text_section.push_str("    call read_int\n");
+        },
+        Expr::ConsoleRead(_) => panic!("Cannot use console text input in a numeric expression"),
+        Expr::Fs { op: FsOp::Exists, .. } => {
+            generate_value_code(expr, ctx);
+        },
+        Expr::Fs { .. } => panic!("Only fs.exists can be used in a numeric or boolean expression"),
+        Expr::Math { function, args, .. } if math_returns_int(*function, args, &ctx.types) => {
+            generate_value_code(expr, ctx);
+        },
+        Expr::Math { function, span, .. } => {
+            panic!("math.{} of a runtime value gives a float, which cannot be used in arithmetic. Print it, store it or pass it to another math function at line {}, column {}",
+                   math_function_name(*function), span.line, span.column);
+        },
+        Expr::Time { function: TimeFn::Now, .. } | Expr::Random { function: RandomFn::Int, .. } => {
+            generate_value_code(expr, ctx);
+        },
+        Expr::Random { function: RandomFn::Float, span, .. } => {
+            panic!("random.float gives a float, which cannot be used in arithmetic. Print it, store it or pass it to a math function at line {}, column {}",
+                   span.line, span.column);
+        },
+        Expr::Time { .. } | Expr::Random { .. } => panic!("time.sleep and random.seed give no value"),
+        Expr::CharFromCode { code, span } => {
+            if is_constant_expr(expr, &ctx.types) {
+                if let ConstValue::Char(c) = evaluate_constant_expr(expr, &ctx.types) {
+                    ctx.text_section.push_str(&format!("    ; Load folded char: '{}'\n", c.escape_default()));
+                    ctx.text_section.push_str(&format!("    mov rax, {}\n", c as u32));
+                }
+                return;
+            }
+            if value_tag(code, &ctx.types) != runtime::TAG_NUMBER {
+                panic!("char.fromCode needs an integer at line {}, column {}", span.line, span.column);
+            }
+            ctx.text_section.push_str("    ; char.fromCode\n");
+            generate_expression_code(code, ctx);
+            ctx.text_section.push_str("    call char_from_code\n");
+            generate_trap_check("jnc", "char_code_trap", *span, ctx);
+        },
+        Expr::UnaryOp { op, operand, span } => {
+            generate_operand_code(operand, *span, ctx);
+            match op {
+                UnaryOp::Neg => {
+                    ctx.text_section.push_str("    neg rax\n");
+                    if ctx.options.overflow_checks {
+                        generate_trap_check("jno", "overflow_trap", *span, ctx);
+                    }
                 },
+                UnaryOp::BitNot => ctx.text_section.push_str("    not rax\n"),
+            }
+        },
+        Expr::MethodCall { object, method, args } => {
+            if is_constant_expr(expr, &ctx.types) {
+                match evaluate_constant_expr(expr, &ctx.types) {
+                    ConstValue::Number(n) => {
+                        ctx.text_section.push_str(&format!("    ; Load folded {}() result\n", method));
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
+                    },
+                    ConstValue::Boolean(b) => {
+                        ctx.text_section.push_str(&format!("    ; Load folded {}() result\n", method));
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", if b { 1 } else { 0 }));
+                    },
+                    ConstValue::Char(c) => {
+                        ctx.text_section.push_str(&format!("    ; Load folded {}() result\n", method));
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", c as u32));
+                    },
+                    _ => panic!("Cannot use non-numeric method result in expression: {}", method),
+                }
+            } else if is_string_expr(expr, &ctx.types) || is_array_expr(expr, &ctx.types) {
+                panic!("Cannot use non-numeric method result in expression: {}", method);
+            } else {
+                generate_method_code(object, method, args, ctx);
+            }
+        },
+        Expr::Index { object, index } => {
+            if is_constant_expr(expr, &ctx.types) {
+                match evaluate_constant_expr(expr, &ctx.types) {
+                    ConstValue::Number(n) => {
+                        ctx.text_section.push_str("    ; Load folded element\n");
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
+                    },
+                    ConstValue::Boolean(b) => {
+                        ctx.text_section.push_str("    ; Load folded element\n");
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", if b { 1 } else { 0 }));
+                    },
+                    ConstValue::Char(c) => {
+                        ctx.text_section.push_str("    ; Load folded element\n");
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", c as u32));
+                    },
+                    _ => panic!("Cannot use non-numeric element in expression"),
+                }
+            } else if !matches!(value_tag(expr, &ctx.types),
+                                runtime::TAG_NUMBER | runtime::TAG_BOOLEAN | runtime::TAG_CHAR | runtime::TAG_NULL) {
+                panic!("Cannot use non-numeric element in expression");
+            } else {
+                generate_index_code(object, index, ctx);
+            }
+        },
+        Expr::FieldAccess { object, field } => {
+            if is_constant_expr(expr, &ctx.types) {
+                match evaluate_constant_expr(expr, &ctx.types) {
+                    ConstValue::Number(n) => {
+                        ctx.text_section.push_str(&format!("    ; Load folded field {}\n", field));
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", n));
+                    },
+                    ConstValue::Boolean(b) => {
+                        ctx.text_section.push_str(&format!("    ; Load folded field {}\n", field));
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", if b { 1 } else { 0 }));
+                    },
+                    ConstValue::Char(c) => {
+                        ctx.text_section.push_str(&format!("    ; Load folded field {}\n", field));
+                        ctx.text_section.push_str(&format!("    mov rax, {}\n", c as u32));
+                    },
+                    _ => panic!("Cannot use non-numeric field in expression: {}", field),
+                }
+            } else if !matches!(value_tag(expr, &ctx.types), runtime::TAG_NUMBER | runtime::TAG_BOOLEAN | runtime::TAG_CHAR) {
+                panic!("Cannot use non-numeric field in expression: {}", field);
+            } else {
+                generate_field_code(object, field, ctx);
             }
         },
         _ => panic!("Unsupported expression type"),
     }
 }
 
+// A declaration's doc comment is kept in the assembly, above its code
+fn generate_doc_comment(doc: &Option<String>, ctx: &mut Codegen) {
+    if let Some(doc) = doc {
+        for line in doc.lines() {
+            ctx.text_section.push_str(&format!("    ;; {}\n", line));
+        }
+    }
+}
+
+// Chars compare with chars and join strings with +. Anything else would
+// quietly work on the code point, so it has to ask for code() instead.
+fn check_char_operands(op: &BinOp, left: &Expr, right: &Expr, span: Span, types: &Types) {
+    let left_char = is_char_expr(left, types);
+    let right_char = is_char_expr(right, types);
+    if !left_char && !right_char {
+        return;
+    }
+    if *op == BinOp::Add && (is_string_expr(left, types) || is_string_expr(right, types)) {
+        return;
+    }
+    let other = if left_char { right } else { left };
+    let comparison = matches!(op, BinOp::Equal | BinOp::NotEqual | BinOp::Lt | BinOp::Gt | BinOp::Lte | BinOp::Gte);
+    if comparison && (left_char && right_char) {
+        return;
+    }
+    if matches!(op, BinOp::Equal | BinOp::NotEqual)
+        && (matches!(other, Expr::Null) || is_tagged_expr(other, types)) {
+        return;
+    }
+    if comparison {
+        panic!("Cannot compare a char with a {} at line {}, column {}, compare it with a char such as 'a' or compare code() values",
+               type_name(&runtime_placeholder(other, types)), span.line, span.column);
+    }
+    panic!("Cannot do arithmetic on a char at line {}, column {}, use code() for its code point and char.fromCode(n) for the char of a number",
+           span.line, span.column);
+}
+
+// Equality operands that are not plain numbers or booleans
+fn compares_by_value(expr: &Expr, types: &Types) -> bool {
+    matches!(expr, Expr::Null) || is_string_expr(expr, types) || is_tagged_expr(expr, types)
+}
+
+// == and != through value_compare, so strings compare by text and null only
+// equals null. The result is 1 or 0 in RAX.
+fn generate_value_equality(equal: bool, left: &Expr, right: &Expr, ctx: &mut Codegen) {
+    ctx.text_section.push_str("    ; Compare values\n");
+    generate_value_code(right, ctx);
+    ctx.text_section.push_str("    push rax\n");
+    ctx.text_section.push_str("    push rdx\n");
+    generate_value_code(left, ctx);
+    ctx.text_section.push_str("    push rax\n");
+    ctx.text_section.push_str("    push rdx\n");
+    ctx.text_section.push_str("    push rsi\n");
+    ctx.text_section.push_str("    push rdi\n");
+    ctx.text_section.push_str("    lea rsi, [rsp+16]     ; Left [tag, value]\n");
+    ctx.text_section.push_str("    lea rdi, [rsp+32]     ; Right [tag, value]\n");
+    ctx.text_section.push_str("    call value_compare\n");
+    ctx.text_section.push_str("    pop rdi\n");
+    ctx.text_section.push_str("    pop rsi\n");
+    ctx.text_section.push_str("    add rsp, 32\n");
+    ctx.text_section.push_str("    test rax, rax\n");
+    ctx.text_section.push_str(if equal { "    sete al\n" } else { "    setne al\n" });
+    ctx.text_section.push_str("    movzx rax, al\n");
+}
+
+// < > <= >= through value_compare, so ints and floats compare by value. A
+// null operand stops the program. The result is 1 or 0 in RAX.
+fn generate_value_ordering(op: &BinOp, left: &Expr, right: &Expr, span: Span, ctx: &mut Codegen) {
+    ctx.text_section.push_str("    ; Order values\n");
+    for operand in [right, left] {
+        generate_value_code(operand, ctx);
+        if is_tagged_expr(operand, &ctx.types) {
+            ctx.text_section.push_str(&format!("    cmp rdx, {}\n", runtime::TAG_NULL));
+            generate_trap_check("jne", "operand_trap", span, ctx);
+        }
+        ctx.text_section.push_str("    push rax\n");
+        ctx.text_section.push_str("    push rdx\n");
+    }
+    ctx.text_section.push_str("    push rsi\n");
+    ctx.text_section.push_str("    push rdi\n");
+    ctx.text_section.push_str("    lea rsi, [rsp+16]     ; Left [tag, value]\n");
+    ctx.text_section.push_str("    lea rdi, [rsp+32]     ; Right [tag, value]\n");
+    ctx.text_section.push_str("    call value_compare\n");
+    ctx.text_section.push_str("    pop rdi\n");
+    ctx.text_section.push_str("    pop rsi\n");
+    ctx.text_section.push_str("    add rsp, 32\n");
+    ctx.text_section.push_str("    cmp rax, 0\n");
+    ctx.text_section.push_str(match op {
+        BinOp::Lt => "    setl al\n",
+        BinOp::Gt => "    setg al\n",
+        BinOp::Lte => "    setle al\n",
+        _ => "    setge al\n",
+    });
+    ctx.text_section.push_str("    movzx rax, al\n");
+}
+
+// Load an arithmetic operand into RAX. A variable or readInt() typed at
+// runtime must hold an integer, null or a float stops the program.
+fn generate_operand_code(operand: &Expr, span: Span, ctx: &mut Codegen) {
+    let checked = match operand {
+        Expr::Variable(name) => is_tagged_var(name, &ctx.types.variables) && matches!(ctx.types.variables.get(name), Some(ConstValue::Number(_))),
+        Expr::ConsoleRead(ReadKind::Int) => true,
+        _ => false,
+    };
+    if !checked {
+        generate_expression_code(operand, ctx);
+        return;
+    }
+    generate_value_code(operand, ctx);
+    ctx.text_section.push_str(&format!("    cmp rdx, {}\n", runtime::TAG_NUMBER));
+    generate_trap_check("je", "operand_trap", span, ctx);
+}
+
+// Variables given a value whose type is only known at runtime, such as input
+// that may be null, keep its type tag in var_tag_<name>
+fn tagged_key(name: &str) -> String {
+    format!("tagged {}", name)
+}
+
+fn is_tagged_var(name: &str, variables: &HashMap<String, ConstValue>) -> bool {
+    variables.contains_key(&tagged_key(name))
+}
+
+fn is_tagged_expr(expr: &Expr, types: &Types) -> bool {
+    match expr {
+        Expr::ConsoleRead(_) | Expr::ProcessEnv(_) => true,
+        Expr::Variable(name) => is_tagged_var(name, &types.variables),
+        Expr::Math { function, args, .. } => {
+            !is_known_at_compile_time(expr, types) && !math_returns_int(*function, args, types)
+        },
+        Expr::Random { function: RandomFn::Float, .. } => true,
+        // A missing element, key or char reads as null
+        Expr::Index { .. } => !is_constant_expr(expr, types),
+        _ => false,
+    }
+}
+
+// Runtime helper behind a console read, it leaves the value in RAX and its
+// tag in RDX
+fn console_read_helper(kind: ReadKind) -> &'static str {
+    match kind {
+        ReadKind::Line => "read_line",
+        ReadKind::Int => "read_int",
+        ReadKind::All => "read_all",
+    }
+}
+
+// Call one of the fs built-ins, the result is left in RAX and its tag in RDX.
+// A failed call stops the program through fs_error_trap.
+fn generate_fs_code(op: FsOp, args: &[Expr], span: Span, ctx: &mut Codegen) {
+    let (name, helper, arg_count) = match op {
+        FsOp::Read => ("read", "fs_read", 1),
+        FsOp::Write => ("write", "fs_write", 2),
+        FsOp::Append => ("append", "fs_append", 2),
+        FsOp::Exists => ("exists", "fs_exists", 1),
+        FsOp::Remove => ("remove", "fs_remove", 1),
+    };
+    if args.len() != arg_count {
+        panic!("fs.{} takes {} argument(s) but {} were given", name, arg_count, args.len());
+    }
+    if !is_string_expr(&args[0], &ctx.types) {
+        panic!("The path given to fs.{} must be a string", name);
+    }
+    
+    ctx.text_section.push_str(&format!("    ; fs.{}\n", name));
+    ctx.text_section.push_str("    push rsi\n");
+    generate_string_code(&args[0], ctx);
+    if let Some(text) = args.get(1) {
+        // Any value is written as its text
+        ctx.text_section.push_str("    push rax\n");
+        generate_string_code(text, ctx);
+        ctx.text_section.push_str("    mov rdx, rax\n");
+        ctx.text_section.push_str("    pop rax\n");
+    }
+    ctx.text_section.push_str("    mov rsi, rax\n");
+    ctx.text_section.push_str(&format!("    call {}\n", helper));
+    ctx.text_section.push_str("    pop rsi\n");
+    if op != FsOp::Exists {
+        generate_trap_check("jnc", "fs_error_trap", span, ctx);
+    }
+}
+
+// Call a clock helper, time.now() leaves the milliseconds since the Unix epoch
+// in RAX and time.sleep(ms) leaves null
+fn generate_time_code(function: TimeFn, args: &[Expr], ctx: &mut Codegen) {
+    match function {
+        TimeFn::Now => {
+            check_int_args("time.now", args, 0, &ctx.types);
+            ctx.text_section.push_str("    call time_now\n");
+        },
+        TimeFn::Sleep => {
+            check_int_args("time.sleep", args, 1, &ctx.types);
+            ctx.text_section.push_str("    ; time.sleep\n");
+            generate_expression_code(&args[0], ctx);
+            ctx.text_section.push_str("    call time_sleep\n");
+        },
+    }
+}
+
+// Call a random helper. The generator seeds itself from getrandom on first
+// use; random.seed(n) makes the sequence repeatable.
+fn generate_random_code(function: RandomFn, args: &[Expr], span: Span, ctx: &mut Codegen) {
+    match function {
+        RandomFn::Int => {
+            check_int_args("random.int", args, 2, &ctx.types);
+            if is_constant_expr(&args[0], &ctx.types) && is_constant_expr(&args[1], &ctx.types) {
+                let lo = evaluate_constant_expr(&args[0], &ctx.types);
+                let hi = evaluate_constant_expr(&args[1], &ctx.types);
+                if let (ConstValue::Number(lo), ConstValue::Number(hi)) = (lo, hi)
+                    && hi < lo {
+                    panic!("random.int({}, {}) has an empty range, lo must not be above hi", lo, hi);
+                }
+            }
+            ctx.text_section.push_str("    ; random.int\n");
+            ctx.text_section.push_str("    push rbx\n");
+            generate_expression_code(&args[1], ctx);
+            ctx.text_section.push_str("    push rax\n");
+            generate_expression_code(&args[0], ctx);
+            ctx.text_section.push_str("    pop rbx\n");
+            ctx.text_section.push_str("    call random_int\n");
+            ctx.text_section.push_str("    pop rbx\n");
+            generate_trap_check("jnc", "random_range_trap", span, ctx);
+        },
+        RandomFn::Float => {
+            check_int_args("random.float", args, 0, &ctx.types);
+            ctx.text_section.push_str("    call random_float\n");
+        },
+        RandomFn::Seed if args.is_empty() => {
+            ctx.text_section.push_str("    call random_seed_os\n");
+        },
+        RandomFn::Seed => {
+            check_int_args("random.seed", args, 1, &ctx.types);
+            ctx.text_section.push_str("    ; random.seed\n");
+            generate_expression_code(&args[0], ctx);
+            ctx.text_section.push_str("    call random_seed\n");
+        },
+    }
+}
+
+// Argument count of a time or random call, whose arguments are all integers
+fn check_int_args(name: &str, args: &[Expr], count: usize, types: &Types) {
+    if args.len() != count {
+        panic!("{} takes {} argument(s) but {} were given", name, count, args.len());
+    }
+    for arg in args {
+        if is_string_expr(arg, types) || is_float_expr(arg, types)
+            || is_boolean_expr(arg, types) {
+            panic!("{} needs integer arguments", name);
+        }
+    }
+}
+
+fn math_function_name(function: MathFn) -> &'static str {
+    match function {
+        MathFn::Sqrt => "sqrt",
+        MathFn::Pow => "pow",
+        MathFn::Abs => "abs",
+        MathFn::Floor => "floor",
+        MathFn::Ceil => "ceil",
+        MathFn::Round => "round",
+        MathFn::Min => "min",
+        MathFn::Max => "max",
+        MathFn::Sin => "sin",
+        MathFn::Cos => "cos",
+        MathFn::Log => "log",
+    }
+}
+
+// Expressions whose value is known while compiling: constants, and float
+// variables, which only ever hold folded values
+fn is_known_at_compile_time(expr: &Expr, types: &Types) -> bool {
+    match expr {
+        Expr::Variable(name) => {
+            types.constants.contains_key(name) || matches!(types.variables.get(name), Some(ConstValue::Float(_)))
+        },
+        Expr::BinaryOp { left, right, .. } => {
+            is_known_at_compile_time(left, types) && is_known_at_compile_time(right, types)
+        },
+        Expr::UnaryOp { operand, .. } => is_known_at_compile_time(operand, types),
+        Expr::Math { args, .. } => args.iter().all(|a| is_known_at_compile_time(a, types)),
+        _ => is_constant_expr(expr, types),
+    }
+}
+
+// Whether a math call gives an integer whatever its argument values are.
+// floor, ceil and round always do, abs, min and max when given integers.
+fn math_returns_int(function: MathFn, args: &[Expr], types: &Types) -> bool {
+    match function {
+        MathFn::Floor | MathFn::Ceil | MathFn::Round => true,
+        MathFn::Abs | MathFn::Min | MathFn::Max => {
+            args.iter().all(|a| !is_float_expr(a, types) && !is_tagged_expr(a, types))
+        },
+        _ => false,
+    }
+}
+
+// Argument count and types of a math call
+fn check_math_args(function: MathFn, args: &[Expr], types: &Types) {
+    let name = math_function_name(function);
+    let count = if matches!(function, MathFn::Pow | MathFn::Min | MathFn::Max) { 2 } else { 1 };
+    if args.len() != count {
+        panic!("math.{} takes {} argument(s) but {} were given", name, count, args.len());
+    }
+    for arg in args {
+        if matches!(arg, Expr::Null) || is_string_expr(arg, types) || is_boolean_expr(arg, types)
+            || is_array_expr(arg, types) || is_map_expr(arg, types)
+            || is_struct_expr(arg, types) || is_enum_expr(arg, types) {
+            panic!("math.{} needs number arguments", name);
+        }
+    }
+}
+
+// Fold a math call. Integers stay integers where the function allows it.
+fn evaluate_math(function: MathFn, values: &[ConstValue], span: Span) -> ConstValue {
+    let float = |value: &ConstValue| match value {
+        ConstValue::Number(n) => *n as f64,
+        ConstValue::Float(f) => *f,
+        _ => panic!("math.{} needs number arguments", math_function_name(function)),
+    };
+    let x = float(&values[0]);
+    match (function, values) {
+        (MathFn::Sqrt, _) => ConstValue::Float(x.sqrt()),
+        (MathFn::Pow, _) => ConstValue::Float(x.powf(float(&values[1]))),
+        (MathFn::Sin, _) => ConstValue::Float(x.sin()),
+        (MathFn::Cos, _) => ConstValue::Float(x.cos()),
+        (MathFn::Log, _) => ConstValue::Float(x.ln()),
+        (MathFn::Abs, [ConstValue::Number(n)]) => folded_number(n.checked_abs(), span),
+        (MathFn::Abs, _) => ConstValue::Float(x.abs()),
+        (MathFn::Floor | MathFn::Ceil | MathFn::Round, [ConstValue::Number(n)]) => ConstValue::Number(*n),
+        (MathFn::Floor, _) => folded_whole(x.floor(), span),
+        (MathFn::Ceil, _) => folded_whole(x.ceil(), span),
+        (MathFn::Round, _) => folded_whole(x.round(), span),
+        (MathFn::Min, [ConstValue::Number(a), ConstValue::Number(b)]) => ConstValue::Number(*a.min(b)),
+        (MathFn::Max, [ConstValue::Number(a), ConstValue::Number(b)]) => ConstValue::Number(*a.max(b)),
+        (MathFn::Min, _) => ConstValue::Float(x.min(float(&values[1]))),
+        (MathFn::Max, _) => ConstValue::Float(x.max(float(&values[1]))),
+    }
+}
+
+// Call a math routine on runtime arguments, the result is left in RAX and its
+// tag in RDX. An argument that turns out not to be a number, or a rounded
+// value outside 64 bits, is a runtime error.
+fn generate_math_code(function: MathFn, args: &[Expr], span: Span, ctx: &mut Codegen) {
+    check_math_args(function, args, &ctx.types);
+    let name = math_function_name(function);
+    ctx.text_section.push_str(&format!("    ; math.{}\n", name));
+    if let Some(second) = args.get(1) {
+        ctx.text_section.push_str("    push rbx\n");
+        ctx.text_section.push_str("    push rcx\n");
+        generate_value_code(second, ctx);
+        ctx.text_section.push_str("    push rax\n");
+        ctx.text_section.push_str("    push rdx\n");
+        generate_value_code(&args[0], ctx);
+        ctx.text_section.push_str("    pop rcx\n");
+        ctx.text_section.push_str("    pop rbx\n");
+        ctx.text_section.push_str(&format!("    call math_{}\n", name));
+        ctx.text_section.push_str("    pop rcx\n");
+        ctx.text_section.push_str("    pop rbx\n");
+    } else {
+        generate_value_code(&args[0], ctx);
+        ctx.text_section.push_str(&format!("    call math_{}\n", name));
+    }
+    generate_trap_check("jnc", "math_trap", span, ctx);
+}
+
+// Jump to a runtime trap reporting `span` unless the flags from the preceding
+// instruction satisfy `ok_jump`
+fn generate_trap_check(ok_jump: &str, trap: &str, span: Span, ctx: &mut Codegen) {
+    let ok_label = get_string_label(&mut ctx.counter, "trap_ok");
+    ctx.text_section.push_str(&format!("    {} {}\n", ok_jump, ok_label));
+    ctx.text_section.push_str(&format!("    mov rax, {}  ; Line\n", span.line));
+    ctx.text_section.push_str(&format!("    mov rbx, {}  ; Column\n", span.column));
+    ctx.text_section.push_str(&format!("    jmp {}\n", trap));
+    ctx.text_section.push_str(&format!("{}:\n", ok_label));
+}
+
+// Integer power on 64 bits, None when the result overflows. A negative
+// exponent gives the truncated value of 1 / base**-exponent.
+fn int_pow(base: i64, exponent: i64) -> Option<i64> {
+    if exponent < 0 {
+        return match base {
+            0 => panic!("Division by zero in constant expression"),
+            1 => Some(1),
+            -1 => Some(if exponent % 2 == 0 { 1 } else { -1 }),
+            _ => Some(0),
+        };
+    }
+    match base {
+        0 | 1 => Some(if exponent == 0 { 1 } else { base }),
+        -1 => Some(if exponent % 2 == 0 { 1 } else { -1 }),
+        _ => u32::try_from(exponent).ok().and_then(|exponent| base.checked_pow(exponent)),
+    }
+}
+
+// An integer operator computed on 64 bits like the runtime does, None when
+// the result does not fit. Division and modulo by zero are rejected by the
+// caller.
+fn fold_int(op: &BinOp, a: i64, b: i64) -> Option<i64> {
+    match op {
+        BinOp::Add => a.checked_add(b),
+        BinOp::Sub => a.checked_sub(b),
+        BinOp::Mul => a.checked_mul(b),
+        BinOp::Div => a.checked_div(b),
+        // Same sign as the dividend, like the idiv the runtime uses
+        BinOp::Mod => a.checked_rem(b),
+        BinOp::Pow => int_pow(a, b),
+        // Bitwise operators work on the two's complement representation
+        BinOp::BitAnd => Some(a & b),
+        BinOp::BitOr => Some(a | b),
+        BinOp::BitXor => Some(a ^ b),
+        // Shift counts use their low six bits as on x86-64, >> keeps the sign
+        BinOp::Shl => Some(a.wrapping_shl(b as u32 & 63)),
+        BinOp::Shr => Some(a >> (b & 63)),
+        _ => unreachable!(),
+    }
+}
+
+// A folded integer that overflowed 64 bits is a compile error rather than a
+// silently wrapped value.
+fn folded_number(value: Option<i64>, span: Span) -> ConstValue {
+    match value {
+        Some(n) => ConstValue::Number(n),
+        None => panic!("Constant expression result does not fit in 64 bits at line {}, column {}", span.line, span.column),
+    }
+}
+
+// A rounded float as an integer, an error when it is outside 64 bits or NaN
+fn folded_whole(value: f64, span: Span) -> ConstValue {
+    // i64::MAX is not representable, 2^63 is the first float that is too big
+    if value.is_nan() || value < i64::MIN as f64 || value >= 9223372036854775808.0 {
+        panic!("Rounded value {} does not fit in 64 bits at line {}, column {}", value, span.line, span.column);
+    }
+    ConstValue::Number(value as i64)
+}
+
 // Evaluate constant expressions at compile time
-fn evaluate_constant_expr(expr: &Expr, constants: &HashMap<String, ConstValue>, variables: &HashMap<String, ConstValue>) -> ConstValue {
+fn evaluate_constant_expr(expr: &Expr, types: &Types) -> ConstValue {
     match expr {
         Expr::Number(n) => ConstValue::Number(*n),
         Expr::Float(f) => ConstValue::Float(*f),
         Expr::StringLiteral(s) => ConstValue::String(s.clone()),
         Expr::Boolean(b) => ConstValue::Boolean(*b),
+        Expr::Char(c) => ConstValue::Char(*c),
         Expr::Null => ConstValue::Null,
         Expr::Array(elements) => {
             let evaluated_elements = elements.iter()
-                .map(|e| evaluate_constant_expr(e, constants, variables))
+                .map(|e| evaluate_constant_expr(e, types))
                 .collect::<Vec<_>>();
             ConstValue::Array(evaluated_elements)
         },
+        Expr::Map(entries) => {
+            let mut evaluated_entries = Vec::new();
+            for (key, value) in entries {
+                let key = evaluate_constant_expr(key, types);
+                let value = evaluate_constant_expr(value, types);
+                map_insert(&mut evaluated_entries, key, value);
+            }
+            ConstValue::Map(evaluated_entries)
+        },
+        Expr::Index { object, index } => {
+            let container = evaluate_constant_expr(object, types);
+            let key = evaluate_constant_expr(index, types);
+            match (container, key) {
+                (ConstValue::Array(values), ConstValue::Number(i)) => {
+                    usize::try_from(i).ok().and_then(|i| values.get(i).cloned()).unwrap_or(ConstValue::Null)
+                },
+                (ConstValue::Array(_), _) => panic!("Array indices must be numbers"),
+                (ConstValue::Map(entries), key) => {
+                    entries.into_iter()
+                        .find(|(k, _)| compare_const_values(k, &key) == Ordering::Equal)
+                        .map_or(ConstValue::Null, |(_, value)| value)
+                },
+                (ConstValue::String(s), ConstValue::Number(i)) => {
+                    usize::try_from(i).ok().and_then(|i| s.chars().nth(i)).map_or(ConstValue::Null, ConstValue::Char)
+                },
+                (ConstValue::String(_), _) => panic!("String indices must be numbers"),
+                _ => panic!("Only arrays, maps and strings can be indexed"),
+            }
+        },
+        Expr::StructLiteral { name, fields } => {
+            struct_literal_value(name, fields, &types.definitions, &|value| evaluate_constant_expr(value, types))
+        },
+        Expr::FieldAccess { object, field } => struct_field(evaluate_constant_expr(object, types), field).1,
+        Expr::Variant { name, args } => {
+            variant_value(name, args, &types.definitions, &|value| evaluate_constant_expr(value, types))
+        },
         Expr::Variable(name) => {
-            if let Some(value) = constants.get(name) {
+            if let Some(value) = types.constants.get(name) {
                 value.clone()
-            } else if let Some(value) = variables.get(name) {
+            } else if let Some(value) = types.variables.get(name) {
                 value.clone()
             } else {
                 panic!("Undefined variable in constant expression: {}", name);
             }
         },
-        Expr::BinaryOp { op, left, right } => {
-            let left_val = evaluate_constant_expr(left, constants, variables);
-            let right_val = evaluate_constant_expr(right, constants, variables);
+        Expr::BinaryOp { op, left, right, span } => {
+            check_char_operands(op, left, right, *span, types);
+            let left_val = evaluate_constant_expr(left, types);
+            let right_val = evaluate_constant_expr(right, types);
             
             match (op, &left_val, &right_val) {
-                // Integer arithmetic
-                (BinOp::Add, ConstValue::Number(a), ConstValue::Number(b)) => ConstValue::Number(a + b),
-                (BinOp::Sub, ConstValue::Number(a), ConstValue::Number(b)) => ConstValue::Number(a - b),
-                (BinOp::Mul, ConstValue::Number(a), ConstValue::Number(b)) => ConstValue::Number(a * b),
-                (BinOp::Div, ConstValue::Number(a), ConstValue::Number(b)) => {
-                    if *b == 0 {
-                        panic!("Division by zero in constant expression");
-                    }
-                    ConstValue::Number(a / b)
+                // Integer arithmetic and bitwise operators
+                (BinOp::Div, ConstValue::Number(_), ConstValue::Number(0)) => panic!("Division by zero in constant expression"),
+                (BinOp::Mod, ConstValue::Number(_), ConstValue::Number(0)) => panic!("Modulo by zero in constant expression"),
+                (BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod | BinOp::Pow
+                 | BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr, ConstValue::Number(a), ConstValue::Number(b)) => {
+                    folded_number(fold_int(op, *a, *b), *span)
+                },
+                (BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr, _, _) => {
+                    panic!("Bitwise operators need integer operands");
                 },
                 
                 // Float arithmetic
@@ -1231,6 +4264,13 @@ fn evaluate_constant_expr(expr: &Expr, constants: &HashMap<String, ConstValue>,
                     }
                     ConstValue::Float(a / b)
                 },
+                (BinOp::Mod, ConstValue::Float(a), ConstValue::Float(b)) => {
+                    if *b == 0.0 {
+                        panic!("Modulo by zero in constant expression");
+                    }
+                    ConstValue::Float(a % b)
+                },
+                (BinOp::Pow, ConstValue::Float(a), ConstValue::Float(b)) => ConstValue::Float(a.powf(*b)),
                 
                 // Mixed float-integer arithmetic
                 (BinOp::Add, ConstValue::Number(a), ConstValue::Float(b)) => ConstValue::Float(*a as f64 + b),
@@ -1251,6 +4291,20 @@ fn evaluate_constant_expr(expr: &Expr, constants: &HashMap<String, ConstValue>,
                     }
                     ConstValue::Float(a / *b as f64)
                 },
+                (BinOp::Mod, ConstValue::Number(a), ConstValue::Float(b)) => {
+                    if *b == 0.0 {
+                        panic!("Modulo by zero in constant expression");
+                    }
+                    ConstValue::Float(*a as f64 % b)
+                },
+                (BinOp::Mod, ConstValue::Float(a), ConstValue::Number(b)) => {
+                    if *b == 0 {
+                        panic!("Modulo by zero in constant expression");
+                    }
+                    ConstValue::Float(a % *b as f64)
+                },
+                (BinOp::Pow, ConstValue::Number(a), ConstValue::Float(b)) => ConstValue::Float((*a as f64).powf(*b)),
+                (BinOp::Pow, ConstValue::Float(a), ConstValue::Number(b)) => ConstValue::Float(a.powf(*b as f64)),
                 
                 // String concatenation
                 (BinOp::Add, ConstValue::String(a), ConstValue::String(b)) => {
@@ -1259,6 +4313,7 @@ fn evaluate_constant_expr(expr: &Expr, constants: &HashMap<String, ConstValue>,
                 (BinOp::Add, ConstValue::String(a), _) => {
                     let b_str = match &right_val {
                         ConstValue::Number(n) => n.to_string(),
+                        ConstValue::Char(c) => c.to_string(),
                         ConstValue::Float(f) => f.to_string(),
                         ConstValue::Boolean(b) => b.to_string(),
                         ConstValue::Null => "null".to_string(),
@@ -1269,6 +4324,7 @@ fn evaluate_constant_expr(expr: &Expr, constants: &HashMap<String, ConstValue>,
                 (BinOp::Add, _, ConstValue::String(b)) => {
                     let a_str = match &left_val {
                         ConstValue::Number(n) => n.to_string(),
+                        ConstValue::Char(c) => c.to_string(),
                         ConstValue::Float(f) => f.to_string(),
                         ConstValue::Boolean(b) => b.to_string(),
                         ConstValue::Null => "null".to_string(),
@@ -1316,6 +4372,14 @@ fn evaluate_constant_expr(expr: &Expr, constants: &HashMap<String, ConstValue>,
                 (BinOp::Equal, ConstValue::String(a), ConstValue::String(b)) => ConstValue::Boolean(a == b),
                 (BinOp::NotEqual, ConstValue::String(a), ConstValue::String(b)) => ConstValue::Boolean(a != b),
                 
+                // Char comparisons, by code point
+                (BinOp::Equal, ConstValue::Char(a), ConstValue::Char(b)) => ConstValue::Boolean(a == b),
+                (BinOp::NotEqual, ConstValue::Char(a), ConstValue::Char(b)) => ConstValue::Boolean(a != b),
+                (BinOp::Lt, ConstValue::Char(a), ConstValue::Char(b)) => ConstValue::Boolean(a < b),
+                (BinOp::Gt, ConstValue::Char(a), ConstValue::Char(b)) => ConstValue::Boolean(a > b),
+                (BinOp::Lte, ConstValue::Char(a), ConstValue::Char(b)) => ConstValue::Boolean(a <= b),
+                (BinOp::Gte, ConstValue::Char(a), ConstValue::Char(b)) => ConstValue::Boolean(a >= b),
+                
                 // Boolean comparisons
                 (BinOp::Equal, ConstValue::Boolean(a), ConstValue::Boolean(b)) => ConstValue::Boolean(a == b),
                 (BinOp::NotEqual, ConstValue::Boolean(a), ConstValue::Boolean(b)) => ConstValue::Boolean(a != b),
@@ -1323,6 +4387,219 @@ fn evaluate_constant_expr(expr: &Expr, constants: &HashMap<String, ConstValue>,
                 _ => panic!("Invalid operation on types in constant expression"),
             }
         },
+        Expr::UnaryOp { op, operand, span } => {
+            match (op, evaluate_constant_expr(operand, types)) {
+                (UnaryOp::Neg, ConstValue::Number(n)) => folded_number(n.checked_neg(), *span),
+                (UnaryOp::Neg, ConstValue::Float(f)) => ConstValue::Float(-f),
+                (UnaryOp::BitNot, ConstValue::Number(n)) => ConstValue::Number(!n),
+                (UnaryOp::BitNot, _) => panic!("Bitwise operators need integer operands"),
+                _ => panic!("Cannot negate a non-numeric value"),
+            }
+        },
+        Expr::Math { function, args, span } => {
+            check_math_args(*function, args, types);
+            let values = args.iter()
+                .map(|a| evaluate_constant_expr(a, types))
+                .collect::<Vec<_>>();
+            evaluate_math(*function, &values, *span)
+        },
+        Expr::MethodCall { object, method, args } => {
+            let receiver = evaluate_constant_expr(object, types);
+            let arg_values = args.iter()
+                .map(|a| evaluate_constant_expr(a, types))
+                .collect::<Vec<_>>();
+            
+            match receiver {
+                ConstValue::String(s) => evaluate_string_method(&s, method, &arg_values),
+                ConstValue::Array(values) => evaluate_array_method(&values, method, &arg_values),
+                ConstValue::Map(entries) => evaluate_map_method(&entries, method, &arg_values),
+                ConstValue::Char(c) => evaluate_char_method(c, method, &arg_values),
+                _ => panic!("Method '{}' is not defined for this value", method),
+            }
+        },
+        Expr::CharFromCode { code, span } => {
+            match evaluate_constant_expr(code, types) {
+                ConstValue::Number(n) => match u32::try_from(n).ok().and_then(char::from_u32) {
+                    Some(c) => ConstValue::Char(c),
+                    None => panic!("char.fromCode({}) at line {}, column {} is not a Unicode code point", n, span.line, span.column),
+                },
+                _ => panic!("char.fromCode needs an integer at line {}, column {}", span.line, span.column),
+            }
+        },
         _ => panic!("Unsupported expression in constant evaluation"),
     }
-}
\ No newline at end of file
+}
+
+// Fold a string built-in at compile time. Indices and lengths count characters,
+// case mapping and trimming only touch ASCII, matching the runtime helpers.
+fn evaluate_string_method(s: &str, method: &str, args: &[ConstValue]) -> ConstValue {
+    match (method, args) {
+        ("len", []) => ConstValue::Number(s.chars().count() as i64),
+        ("substring", [ConstValue::Number(start)]) => {
+            ConstValue::String(char_substring(s, *start, i64::MAX))
+        },
+        ("substring", [ConstValue::Number(start), ConstValue::Number(end)]) => {
+            ConstValue::String(char_substring(s, *start, *end))
+        },
+        ("indexOf", [ConstValue::String(needle)]) => {
+            match s.find(needle.as_str()) {
+                Some(byte_index) => ConstValue::Number(s[..byte_index].chars().count() as i64),
+                None => ConstValue::Number(-1),
+            }
+        },
+        ("contains", [ConstValue::String(needle)]) => ConstValue::Boolean(s.contains(needle.as_str())),
+        ("startsWith", [ConstValue::String(prefix)]) => ConstValue::Boolean(s.starts_with(prefix.as_str())),
+        ("toUpper", []) => ConstValue::String(s.to_ascii_uppercase()),
+        ("toLower", []) => ConstValue::String(s.to_ascii_lowercase()),
+        ("trim", []) => ConstValue::String(s.trim_matches(|c: char| c.is_ascii_whitespace()).to_string()),
+        ("split", [ConstValue::String(separator)]) => {
+            // An empty separator splits the string into its characters
+            let parts: Vec<ConstValue> = if separator.is_empty() {
+                s.chars().map(|c| ConstValue::String(c.to_string())).collect()
+            } else {
+                s.split(separator.as_str()).map(|part| ConstValue::String(part.to_string())).collect()
+            };
+            ConstValue::Array(parts)
+        },
+        ("replace", [ConstValue::String(from), ConstValue::String(to)]) => {
+            // An empty pattern leaves the string unchanged
+            if from.is_empty() {
+                ConstValue::String(s.to_string())
+            } else {
+                ConstValue::String(s.replace(from.as_str(), to))
+            }
+        },
+        ("len" | "substring" | "indexOf" | "contains" | "startsWith" | "toUpper" | "toLower" | "trim" | "split" | "replace", _) => {
+            panic!("Invalid arguments for string method '{}'", method)
+        },
+        _ => panic!("Unknown string method: {}", method),
+    }
+}
+
+// Fold a char built-in at compile time. The tests only know ASCII, like the
+// runtime helpers.
+fn evaluate_char_method(c: char, method: &str, args: &[ConstValue]) -> ConstValue {
+    if !args.is_empty() {
+        panic!("Wrong number of arguments for char method '{}'", method);
+    }
+    match method {
+        "code" => ConstValue::Number(c as i64),
+        "isDigit" => ConstValue::Boolean(c.is_ascii_digit()),
+        "isLetter" => ConstValue::Boolean(c.is_ascii_alphabetic()),
+        "isWhitespace" => ConstValue::Boolean(c.is_ascii_whitespace()),
+        _ => panic!("Unknown char method: {}", method),
+    }
+}
+
+// Characters start..end of a string, clamped to its length like the runtime helper
+fn char_substring(s: &str, start: i64, end: i64) -> String {
+    let len = s.chars().count() as i64;
+    let mut start = start.clamp(0, len);
+    let mut end = end.clamp(0, len);
+    if start > end {
+        std::mem::swap(&mut start, &mut end);
+    }
+    s.chars().skip(start as usize).take((end - start) as usize).collect()
+}
+
+// Fold an array built-in at compile time, mirroring the runtime helpers
+fn evaluate_array_method(values: &[ConstValue], method: &str, args: &[ConstValue]) -> ConstValue {
+    match (method, args) {
+        ("len", []) => ConstValue::Number(values.len() as i64),
+        ("slice", [ConstValue::Number(start)]) => ConstValue::Array(slice_range(values, *start, None)),
+        ("slice", [ConstValue::Number(start), ConstValue::Number(end)]) => {
+            ConstValue::Array(slice_range(values, *start, Some(*end)))
+        },
+        ("contains", [needle]) => {
+            ConstValue::Boolean(values.iter().any(|v| compare_const_values(v, needle) == Ordering::Equal))
+        },
+        ("reverse", []) => ConstValue::Array(values.iter().rev().cloned().collect()),
+        ("sort", []) => {
+            let mut sorted = values.to_vec();
+            check_sortable(&ConstValue::Array(sorted.clone()));
+            sorted.sort_by(compare_const_values);
+            ConstValue::Array(sorted)
+        },
+        ("push" | "pop", _) => panic!("{}() needs an array variable", method),
+        ("len" | "slice" | "contains" | "reverse" | "sort", _) => {
+            panic!("Invalid arguments for array method '{}'", method)
+        },
+        _ => panic!("Unknown array method: {}", method),
+    }
+}
+
+// Elements start..end of an array; negative indices count from the end and
+// both are clamped to the array like the runtime helper
+fn slice_range(values: &[ConstValue], start: i64, end: Option<i64>) -> Vec<ConstValue> {
+    let len = values.len() as i64;
+    let resolve = |index: i64| if index < 0 { (len + index).max(0) } else { index.min(len) };
+    let start = resolve(start);
+    let end = end.map_or(len, resolve);
+    if start >= end {
+        Vec::new()
+    } else {
+        values[start as usize..end as usize].to_vec()
+    }
+}
+
+// Order two values like the runtime's value_compare: ints and floats by
+// value, strings by their text and values of different types by their tag
+fn compare_const_values(a: &ConstValue, b: &ConstValue) -> Ordering {
+    match (a, b) {
+        (ConstValue::Number(x), ConstValue::Number(y)) => x.cmp(y),
+        (ConstValue::Number(_) | ConstValue::Float(_), ConstValue::Number(_) | ConstValue::Float(_)) => {
+            let value = |v: &ConstValue| match v {
+                ConstValue::Number(n) => *n as f64,
+                ConstValue::Float(f) => *f,
+                _ => unreachable!(),
+            };
+            value(a).partial_cmp(&value(b)).unwrap_or(Ordering::Equal)
+        },
+        (ConstValue::Boolean(x), ConstValue::Boolean(y)) => x.cmp(y),
+        (ConstValue::Char(x), ConstValue::Char(y)) => x.cmp(y),
+        (ConstValue::String(x), ConstValue::String(y)) => x.cmp(y),
+        (ConstValue::Array(_), ConstValue::Array(_))
+        | (ConstValue::Map(_), ConstValue::Map(_))
+        | (ConstValue::Struct { .. }, ConstValue::Struct { .. })
+        | (ConstValue::Enum { .. }, ConstValue::Enum { .. }) => {
+            a.to_string().cmp(&b.to_string())
+        },
+        _ => const_value_tag(a).cmp(&const_value_tag(b)),
+    }
+}
+
+// sort() orders numbers (ints and floats) or strings, other arrays have no
+// useful order
+fn check_sortable(value: &ConstValue) {
+    if let ConstValue::Array(values) = value {
+        let numbers = values.iter().all(|v| matches!(v, ConstValue::Number(_) | ConstValue::Float(_)));
+        let strings = values.iter().all(|v| matches!(v, ConstValue::String(_)));
+        if !numbers && !strings {
+            panic!("sort() only supports arrays of numbers or strings");
+        }
+    }
+}
+
+// Fold a map built-in at compile time
+fn evaluate_map_method(entries: &[(ConstValue, ConstValue)], method: &str, args: &[ConstValue]) -> ConstValue {
+    match (method, args) {
+        ("len", []) => ConstValue::Number(entries.len() as i64),
+        ("keys", []) => ConstValue::Array(entries.iter().map(|(key, _)| key.clone()).collect()),
+        ("has", [key]) => {
+            ConstValue::Boolean(entries.iter().any(|(k, _)| compare_const_values(k, key) == Ordering::Equal))
+        },
+        ("remove", _) => panic!("remove() needs a map variable"),
+        ("len" | "keys" | "has", _) => panic!("Invalid arguments for map method '{}'", method),
+        _ => panic!("Unknown map method: {}", method),
+    }
+}
+
+// Insert or update a map entry at compile time. Existing keys keep their
+// place in the insertion order, like in the runtime hash table.
+fn map_insert(entries: &mut Vec<(ConstValue, ConstValue)>, key: ConstValue, value: ConstValue) {
+    check_map_key(const_value_tag(&key));
+    match entries.iter_mut().find(|(k, _)| compare_const_values(k, &key) == Ordering::Equal) {
+        Some(entry) => entry.1 = value,
+        None => entries.push((key, value)),
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::lexer::{lex_lossless, Edition, LexError, LosslessToken, TemplatePart, Token, TokenStream, Trivia, TriviaKind};
+
+// The canonical layout: four spaces per level and lines of at most 100
+// characters where they can be broken
+const INDENT: &str = "    ";
+const MAX_WIDTH: usize = 100;
+
+// Format SPP source in the canonical style. Only the whitespace between
+// tokens changes, tokens and comments stay as they are written. Source the
+// lexer rejects is not formatted, its errors are returned instead.
+pub fn format_source(source: &str, edition: Edition) -> Result<String, Vec<LexError>> {
+    let (stream, errors) = lex_lossless(source, edition);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let groups = Groups::find(&stream.tokens);
+    let mut layout = Layout { broken: groups.broken_as_written(&stream.tokens), broken_ops: HashSet::new() };
+
+    // Lines that are too long are broken up one step at a time until they
+    // fit or cannot be broken any further
+    let mut rendered = render(&stream, &groups, &layout);
+    while layout.break_long_line(&rendered, &stream.tokens, &groups) {
+        rendered = render(&stream, &groups, &layout);
+    }
+
+    let (formatted_stream, _) = lex_lossless(&rendered.text, edition);
+    let before: Vec<&Token> = stream.tokens.iter().map(|token| &token.token).collect();
+    let after: Vec<&Token> = formatted_stream.tokens.iter().map(|token| &token.token).collect();
+    if !same_tokens(&before, &after) {
+        panic!("Formatting changed the tokens of the program, this is a bug in the formatter");
+    }
+    Ok(rendered.text)
+}
+
+// Whether two token lists are the same apart from where the code inside
+// template strings sits
+fn same_tokens(a: &[&Token], b: &[&Token]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| match (a, b) {
+        (Token::Template(a), Token::Template(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| match (a, b) {
+                (TemplatePart::Code(a, _), TemplatePart::Code(b, _)) => {
+                    same_tokens(&a.iter().collect::<Vec<_>>(), &b.iter().collect::<Vec<_>>())
+                },
+                (a, b) => a == b,
+            })
+        },
+        (a, b) => a == b,
+    })
+}
+
+// Matching brackets, by token index: (), [] and {}
+struct Groups {
+    closer: HashMap<usize, usize>,  // Opener to its closer
+    opener: HashMap<usize, usize>,  // Closer to its opener
+}
+
+impl Groups {
+    fn find(tokens: &[LosslessToken]) -> Groups {
+        let mut groups = Groups { closer: HashMap::new(), opener: HashMap::new() };
+        let mut stack: Vec<usize> = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            match token.token {
+                Token::LParen | Token::LBracket | Token::LBrace => stack.push(i),
+                Token::RParen | Token::RBracket | Token::RBrace => {
+                    let matching = stack.last().is_some_and(|&open| matches!(
+                        (&tokens[open].token, &token.token),
+                        (Token::LParen, Token::RParen) | (Token::LBracket, Token::RBracket) | (Token::LBrace, Token::RBrace)
+                    ));
+                    if matching {
+                        let open = stack.pop().unwrap();
+                        groups.closer.insert(open, i);
+                        groups.opener.insert(i, open);
+                    }
+                },
+                _ => {},
+            }
+        }
+        groups
+    }
+
+    // Groups that go over several lines whatever their width: the ones
+    // written that way, blocks of statements and groups holding a line
+    // comment. Inner groups are decided first, as a `{` holding a block is a
+    // block itself.
+    fn broken_as_written(&self, tokens: &[LosslessToken]) -> HashSet<usize> {
+        let mut broken = HashSet::new();
+        let mut openers: Vec<(usize, usize)> = self.closer.iter().map(|(&open, &close)| (open, close)).collect();
+        openers.sort_by_key(|&(_, close)| close);
+        for (open, close) in openers {
+            let newline_after_opener = tokens[open + 1].leading.iter().any(|trivia| trivia.text.contains('\n'));
+            let line_comment = tokens[open + 1..=close].iter()
+                .any(|token| token.leading.iter().any(|trivia| trivia.text.starts_with("//")));
+            let block = tokens[open].token == Token::LBrace && self.children(open, close).any(|i| {
+                tokens[i].token == Token::Semicolon || (tokens[i].token == Token::LBrace && broken.contains(&i))
+            });
+            if newline_after_opener || line_comment || block {
+                broken.insert(open);
+            }
+        }
+        broken
+    }
+
+    // Indices of the tokens directly inside a group, nested groups counting
+    // by their opener only
+    fn children(&self, open: usize, close: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut i = open + 1;
+        std::iter::from_fn(move || {
+            if i >= close {
+                return None;
+            }
+            let child = i;
+            i = self.closer.get(&child).map_or(child + 1, |&c| c + 1);
+            Some(child)
+        })
+    }
+}
+
+// The choices made so far: which groups go over several lines, and before
+// which operators a long line is broken
+struct Layout {
+    broken: HashSet<usize>,
+    broken_ops: HashSet<usize>,
+}
+
+impl Layout {
+    // Break up the first line that is too long and can still be broken, and
+    // tell whether there was one. The outermost group that still fits on the
+    // line goes first; without one, the line breaks before its loosest
+    // operators.
+    fn break_long_line(&mut self, rendered: &Rendered, tokens: &[LosslessToken], groups: &Groups) -> bool {
+        for (line, text) in rendered.text.lines().enumerate() {
+            if text.chars().count() <= MAX_WIDTH {
+                continue;
+            }
+            let on_line: Vec<usize> = (0..tokens.len()).filter(|&i| rendered.lines[i] == line).collect();
+
+            let group = on_line.iter().copied()
+                .filter(|i| groups.closer.get(i).is_some_and(|&close| close > i + 1) && !self.broken.contains(i))
+                .min_by_key(|&i| (rendered.depths[i], i));
+            if let Some(open) = group {
+                self.broken.insert(open);
+                return true;
+            }
+
+            let operators: Vec<usize> = on_line.iter().copied()
+                .skip(1)
+                .filter(|&i| !rendered.unary[i] && precedence(&tokens[i].token).is_some())
+                .collect();
+            let Some(depth) = operators.iter().map(|&i| rendered.depths[i]).min() else {
+                continue;
+            };
+            let loosest = operators.iter()
+                .filter(|&&i| rendered.depths[i] == depth)
+                .filter_map(|&i| precedence(&tokens[i].token))
+                .min();
+            let mut changed = false;
+            for &i in &operators {
+                if rendered.depths[i] == depth && precedence(&tokens[i].token) == loosest {
+                    changed |= self.broken_ops.insert(i);
+                }
+            }
+            if changed {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// Binding strength of a binary operator, loosest first. Assignments are not
+// broken.
+fn precedence(token: &Token) -> Option<u8> {
+    match token {
+        Token::Equal | Token::NotEqual | Token::LessThan | Token::GreaterThan
+        | Token::LessThanEqual | Token::GreaterThanEqual => Some(1),
+        Token::Pipe => Some(2),
+        Token::Caret => Some(3),
+        Token::Ampersand => Some(4),
+        Token::ShiftLeft | Token::ShiftRight => Some(5),
+        Token::Plus | Token::Minus => Some(6),
+        Token::Star | Token::Slash | Token::Percent => Some(7),
+        Token::StarStar => Some(8),
+        _ => None,
+    }
+}
+
+// Formatted source, with the output line each token starts on, how many
+// groups are open around it and whether it is a prefix operator
+struct Rendered {
+    text: String,
+    lines: Vec<usize>,
+    depths: Vec<usize>,
+    unary: Vec<bool>,
+}
+
+// Builds the output one piece at a time. Line breaks are only written once
+// the next piece comes, so that a trailing comment can still join the line.
+struct Writer {
+    text: String,
+    line: usize,
+    at_line_start: bool,
+    pending_newline: bool,
+    after_opener: bool,   // The last piece opened a group that is broken
+    after_comment: bool,
+}
+
+impl Writer {
+    fn newline(&mut self, blank: bool) {
+        if self.text.is_empty() {
+            return;
+        }
+        self.text.push('\n');
+        self.line += 1;
+        if blank {
+            self.text.push('\n');
+            self.line += 1;
+        }
+        self.at_line_start = true;
+        self.pending_newline = false;
+    }
+
+    fn write(&mut self, indent: usize, text: &str) {
+        if self.at_line_start {
+            self.text.push_str(&INDENT.repeat(indent));
+        }
+        self.text.push_str(text);
+        self.line += text.matches('\n').count();
+        self.at_line_start = false;
+    }
+}
+
+fn render(stream: &TokenStream, groups: &Groups, layout: &Layout) -> Rendered {
+    let tokens = &stream.tokens;
+    let mut writer = Writer {
+        text: String::new(),
+        line: 0,
+        at_line_start: true,
+        pending_newline: false,
+        after_opener: false,
+        after_comment: false,
+    };
+    let mut rendered = Rendered { text: String::new(), lines: Vec::new(), depths: Vec::new(), unary: Vec::new() };
+    let mut stack: Vec<usize> = Vec::new(); // Open groups, by opener
+
+    for (i, token) in tokens.iter().enumerate() {
+        let indent = stack.iter().filter(|open| layout.broken.contains(open)).count();
+        write_comments(&token.leading, indent, &mut writer);
+
+        let closes = groups.opener.get(&i).copied();
+        if let Some(open) = closes {
+            stack.retain(|&o| o != open);
+        }
+        let indent = stack.iter().filter(|open| layout.broken.contains(open)).count();
+        let prev = i.checked_sub(1).map(|p| &tokens[p].token);
+        let unary = match token.token {
+            Token::Tilde => true,
+            Token::Minus => prefix_position(prev),
+            _ => false,
+        };
+        let prev_unary = i > 0 && rendered.unary[i - 1];
+
+        let broken_close = closes.is_some_and(|open| layout.broken.contains(&open));
+        if broken_close || writer.pending_newline {
+            let blank = newlines_before(&token.leading) >= 2 && !writer.after_opener && !broken_close;
+            writer.newline(blank);
+            writer.write(indent, &token.text);
+        } else if layout.broken_ops.contains(&i) {
+            writer.newline(false);
+            writer.write(indent + 1, &token.text);
+        } else {
+            if let Some(prev) = prev && !writer.at_line_start
+                && (writer.after_comment || space_between(prev, prev_unary, &token.token)) {
+                writer.text.push(' ');
+            }
+            writer.write(indent, &token.text);
+        }
+        rendered.lines.push(writer.line - token.text.matches('\n').count());
+        rendered.depths.push(stack.len());
+        rendered.unary.push(unary);
+        writer.after_opener = false;
+        writer.after_comment = false;
+
+        // What comes after the token
+        let in_block = stack.last().is_none_or(|open| layout.broken.contains(open));
+        if groups.closer.contains_key(&i) {
+            stack.push(i);
+            if layout.broken.contains(&i) {
+                writer.pending_newline = true;
+                writer.after_opener = true;
+            }
+        } else {
+            writer.pending_newline = match token.token {
+                Token::Semicolon => in_block,
+                Token::Comma => !stack.is_empty() && in_block,
+                Token::RBrace => closes.is_some() && in_block
+                    && !tokens.get(i + 1).is_some_and(|next| continues_expression(&next.token)),
+                // A lint attribute goes on its own line above the statement
+                Token::RBracket => in_block && closes.is_some_and(|open| {
+                    open > 0 && matches!(tokens[open - 1].token, Token::Hash | Token::HashBang)
+                }),
+                _ => false,
+            };
+        }
+    }
+
+    write_comments(&stream.trailing, 0, &mut writer);
+    if !writer.text.is_empty() {
+        writer.text.push('\n');
+    }
+    rendered.text = writer.text;
+    rendered
+}
+
+// Write the comments in a token's leading trivia. A comment that followed
+// code on the same line stays there, the others get lines of their own.
+fn write_comments(trivia: &[Trivia], indent: usize, writer: &mut Writer) {
+    for (k, comment) in trivia.iter().enumerate() {
+        if comment.kind == TriviaKind::Whitespace {
+            continue;
+        }
+        let newlines = if k > 0 { trivia[k - 1].text.matches('\n').count() } else { 0 };
+        let line_comment = comment.text.starts_with("//");
+        let text = if line_comment { comment.text.trim_end().to_string() } else { reindent(comment, indent) };
+
+        if newlines == 0 && !writer.text.is_empty() {
+            writer.text.push_str(if line_comment { "  " } else { " " });
+            writer.write(indent, &text);
+        } else {
+            writer.newline(newlines >= 2 && !writer.after_opener);
+            writer.write(indent, &text);
+        }
+        if line_comment || trivia.get(k + 1).is_some_and(|next| next.text.contains('\n')) {
+            writer.pending_newline = true;
+        }
+        writer.after_opener = false;
+        writer.after_comment = true;
+    }
+}
+
+// A block comment's later lines keep their place relative to its first line
+fn reindent(comment: &Trivia, indent: usize) -> String {
+    let mut lines = comment.text.lines();
+    let mut text = lines.next().unwrap_or_default().to_string();
+    for line in lines {
+        let mut line = line;
+        for _ in 1..comment.span.column {
+            match line.strip_prefix([' ', '\t']) {
+                Some(rest) => line = rest,
+                None => break,
+            }
+        }
+        text.push('\n');
+        if !line.trim().is_empty() {
+            text.push_str(&INDENT.repeat(indent));
+            text.push_str(line.trim_end());
+        }
+    }
+    text
+}
+
+// Line breaks in the whitespace right before a token
+fn newlines_before(trivia: &[Trivia]) -> usize {
+    trivia.last()
+        .filter(|last| last.kind == TriviaKind::Whitespace)
+        .map_or(0, |last| last.text.matches('\n').count())
+}
+
+// Whether a '-' after `prev` is a prefix minus
+fn prefix_position(prev: Option<&Token>) -> bool {
+    !matches!(prev, Some(
+        Token::Identifier(_) | Token::Number(_) | Token::String(_) | Token::Char(_) | Token::Template(_)
+        | Token::RParen | Token::RBracket | Token::RBrace | Token::True | Token::False | Token::Null
+        | Token::PlusPlus | Token::MinusMinus
+    ))
+}
+
+// Tokens that carry on the expression or statement before them, so a '}'
+// right before them does not end a line
+fn continues_expression(token: &Token) -> bool {
+    precedence(token).is_some() || matches!(token,
+        Token::Semicolon | Token::Comma | Token::Dot | Token::RParen | Token::RBracket | Token::RBrace
+        | Token::Else | Token::Colon | Token::FatArrow | Token::LBracket)
+}
+
+// Whether a space goes between two tokens on the same line
+fn space_between(prev: &Token, prev_unary: bool, next: &Token) -> bool {
+    if prev_unary {
+        // Keep `- -x` from turning into `--x`
+        return *next == Token::Minus;
+    }
+    !matches!((prev, next),
+        (_, Token::Semicolon | Token::Comma | Token::Colon | Token::Dot | Token::RParen | Token::RBracket
+            | Token::PlusPlus | Token::MinusMinus)
+        | (Token::LParen | Token::LBracket | Token::Dot | Token::Hash | Token::HashBang, _)
+        | (Token::LBrace, Token::RBrace)
+        | (Token::Identifier(_) | Token::RParen | Token::RBracket | Token::Exit, Token::LParen)
+        | (Token::Identifier(_) | Token::RParen | Token::RBracket | Token::String(_) | Token::Template(_),
+            Token::LBracket))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(source: &str) -> String {
+        format_source(source, Edition::E2026).unwrap()
+    }
+
+    #[test]
+    fn canonical_style() {
+        let source = "let x=1+2*3;// sum\nif(x>3){\n\tconsole.print( x );\n/* keep */\n}else{console.print(\"small\");}\n\n\nconst LIST = [1,2,3];\nlet m = {\"a\":1, \"b\": -x};\n";
+        let expected = "let x = 1 + 2 * 3;  // sum\nif (x > 3) {\n    console.print(x);\n    /* keep */\n} else {\n    console.print(\"small\");\n}\n\nconst LIST = [1, 2, 3];\nlet m = { \"a\": 1, \"b\": -x };\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn long_lines_are_broken() {
+        let source = "let names = [\"alpha\", \"bravo\", \"charlie\", \"delta\", \"echo\", \"foxtrot\", \"golf\", \"hotel\", \"india\", \"juliet\"];\n";
+        let formatted = format(source);
+        assert_eq!(formatted.lines().count(), 12);
+        assert!(formatted.starts_with("let names = [\n    \"alpha\",\n"));
+        assert!(formatted.ends_with("    \"juliet\"\n];\n"));
+
+        let source = "let total = first_value_with_long_name + second_value_with_long_name + third_value_with_long_name + fourth;\n";
+        assert_eq!(format(source), "let total = first_value_with_long_name\n    + second_value_with_long_name\n    + third_value_with_long_name\n    + fourth;\n");
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let sources = [
+            "let x=1+2*3;// sum\nif(x>3){\n\tconsole.print( x );\n}else{console.print(\"small\");}\n",
+            "/// doc\nstruct P{x:int,y:int}\nenum C{Red,Blue(int)}\nmatch(c){Red=>{exit(0);}Blue(n)=>{exit(n);}_=>{}}\n",
+            "#[allow(unused_variable)]\nlet t=`a ${1+2} b`;let c='c';let s=r\"C:\\p\";\n",
+        ];
+        for source in sources {
+            let formatted = format(source);
+            assert_eq!(format(&formatted), formatted, "{}", source);
+        }
+    }
+
+    #[test]
+    fn examples_only_gain_a_final_newline() {
+        for source in [include_str!("../examples/sample.spp"), include_str!("../examples/test.spp")] {
+            let formatted = format_source(source, Edition::E2025).unwrap();
+            assert_eq!(formatted.trim_end(), source.trim_end());
+            assert!(formatted.ends_with(";\n"));
+        }
+    }
+
+    #[test]
+    fn lexer_errors_are_returned() {
+        let errors = format_source("let x = 1 @ 2;\n", Edition::E2026).unwrap_err();
+        assert_eq!(errors[0].message, "Unexpected character '@'");
+    }
+}
@@ -1,8 +1,14 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::Chars;
+
 // Remove the duplicate derive attributes
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Number(String),
     String(String),
+    Char(char),       // 'a', from edition 2026 on
     Identifier(String),
     Plus,
     Minus,
@@ -24,72 +30,415 @@ pub enum Token {
     LBrace,
     RBrace,
     Comma,
+    Colon,
+    FatArrow,
+    Percent,
+    StarStar,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
+    PlusAssign,
+    MinusAssign,
+    StarAssign,
+    SlashAssign,
+    PercentAssign,
+    PlusPlus,
+    MinusMinus,
+    Hash,             // # of a #[...] lint attribute
+    HashBang,         // #! of a #![...] lint attribute
+    // Keywords, see KEYWORDS
+    Let,
+    Const,
+    If,
+    Else,
+    Match,
+    Struct,
+    Enum,
+    Exit,
+    True,
+    False,
+    Null,
+    Console,
+    Template(Vec<TemplatePart>),  // `Hello ${name}`
+}
+
+// Reserved words. They are lexed as their own tokens and cannot be used as
+// names.
+const KEYWORDS: &[(&str, Token)] = &[
+    ("let", Token::Let),
+    ("const", Token::Const),
+    ("if", Token::If),
+    ("else", Token::Else),
+    ("match", Token::Match),
+    ("struct", Token::Struct),
+    ("enum", Token::Enum),
+    ("exit", Token::Exit),
+    ("true", Token::True),
+    ("false", Token::False),
+    ("null", Token::Null),
+    ("console", Token::Console),
+];
+
+impl Token {
+    // The reserved word of a keyword token
+    pub fn keyword(&self) -> Option<&'static str> {
+        KEYWORDS.iter().find(|(_, token)| token == self).map(|(word, _)| *word)
+    }
+}
+
+// Language edition, picked with --edition. Single quotes hold a string in
+// 2025, the default, and a char from 2026 on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Edition {
+    E2025,
+    E2026,
+}
+
+impl Edition {
+    pub fn from_year(year: &str) -> Option<Edition> {
+        match year {
+            "2025" => Some(Edition::E2025),
+            "2026" => Some(Edition::E2026),
+            _ => None,
+        }
+    }
+}
+
+// A piece of a template string: literal text, or the tokens of an embedded
+// ${...} expression with their positions
+#[derive(Debug, PartialEq, Clone)]
+pub enum TemplatePart {
+    Text(String),
+    Code(Vec<Token>, Vec<Span>),
+}
+
+// Position of a token in the source, both counted from 1
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+// A problem found while lexing, such as an unknown character
+#[derive(Debug, PartialEq, Clone)]
+pub struct LexError {
+    pub message: String,
+    pub span: Span,
+}
+
+// Source characters that keep track of the line and column they are at, and
+// of their byte offset
+struct SourceChars<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    offset: usize,
+}
+
+impl SourceChars<'_> {
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    // Whether the next characters are `text`
+    fn starts_with(&self, text: &str) -> bool {
+        let mut ahead = self.chars.clone();
+        text.chars().all(|c| ahead.next() == Some(c))
+    }
+
+    // The character after the next one
+    fn peek_second(&self) -> Option<char> {
+        let mut ahead = self.chars.clone();
+        ahead.next();
+        ahead.next()
+    }
+
+    fn span(&self) -> Span {
+        Span { line: self.line, column: self.column }
+    }
+}
+
+impl Iterator for SourceChars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+}
+
+// Doc comments (`///` and `/** */`), keyed by the index of the token that
+// follows them
+pub type Docs = HashMap<usize, String>;
+
+// Tokenize the source, also returning where each token starts and the doc
+// comments. Lexing goes on after an error so that every problem in the file is
+// reported at once.
+pub fn lex(source: &str, edition: Edition) -> (Vec<Token>, Vec<Span>, Docs, Vec<LexError>) {
+    let mut errors = Vec::new();
+    let mut docs = Docs::new();
+    let mut chars = SourceChars { chars: source.chars().peekable(), line: 1, column: 1, offset: 0 };
+    let lexed = lex_tokens(&mut chars, &mut errors, &mut docs, edition, false);
+    (lexed.tokens, lexed.spans, docs, errors)
+}
+
+// What a piece of trivia is
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TriviaKind {
+    Whitespace,
+    LineComment,   // // ...
+    BlockComment,  // /* ... */
+    DocComment,    // /// ... or /** ... */
+    Skipped,       // Text the lexer reported as an error
+}
+
+// Source text between tokens that the parser never sees
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub span: Span,
+}
+
+// A token together with its text as written and the trivia in front of it
+#[derive(Debug, PartialEq, Clone)]
+pub struct LosslessToken {
+    pub token: Token,
+    pub text: String,
+    pub span: Span,
+    pub leading: Vec<Trivia>,
+}
+
+// Every token of a file with its trivia, plus the trivia after the last token.
+// Nothing of the source is lost, so tools such as a formatter can rewrite it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TokenStream {
+    pub tokens: Vec<LosslessToken>,
+    pub trailing: Vec<Trivia>,
+}
+
+impl TokenStream {
+    // The source again, byte for byte
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+        for token in &self.tokens {
+            for trivia in &token.leading {
+                source.push_str(&trivia.text);
+            }
+            source.push_str(&token.text);
+        }
+        for trivia in &self.trailing {
+            source.push_str(&trivia.text);
+        }
+        source
+    }
+}
+
+// Tokenize the source like lex, but keep the whitespace and comments between
+// the tokens. A template string is one token; its text holds the ${...} code.
+pub fn lex_lossless(source: &str, edition: Edition) -> (TokenStream, Vec<LexError>) {
+    let mut errors = Vec::new();
+    let mut chars = SourceChars { chars: source.chars().peekable(), line: 1, column: 1, offset: 0 };
+    let lexed = lex_tokens(&mut chars, &mut errors, &mut Docs::new(), edition, false);
+    
+    // Walk the source again, reading the trivia in the gaps between tokens
+    let mut chars = SourceChars { chars: source.chars().peekable(), line: 1, column: 1, offset: 0 };
+    let mut stream = TokenStream { tokens: Vec::new(), trailing: Vec::new() };
+    for ((token, span), range) in lexed.tokens.into_iter().zip(lexed.spans).zip(lexed.ranges) {
+        let leading = trivia_until(&mut chars, source, range.start, &lexed.skipped);
+        while chars.offset < range.end && chars.next().is_some() {}
+        stream.tokens.push(LosslessToken { token, text: source[range].to_string(), span, leading });
+    }
+    stream.trailing = trivia_until(&mut chars, source, source.len(), &lexed.skipped);
+    (stream, errors)
+}
+
+// Split the source from `chars` up to byte `end`, which has no tokens in it,
+// into trivia. `skipped` are the byte ranges the lexer rejected.
+fn trivia_until(source_chars: &mut SourceChars, source: &str, end: usize, skipped: &[Range<usize>]) -> Vec<Trivia> {
+    let mut chars = SourceChars {
+        chars: source[source_chars.offset..end].chars().peekable(),
+        line: source_chars.line,
+        column: source_chars.column,
+        offset: source_chars.offset,
+    };
+    let mut trivia: Vec<Trivia> = Vec::new();
+    while chars.peek().is_some() {
+        let start = chars.offset;
+        let span = chars.span();
+        let skipped_range = skipped.iter().find(|range| range.start == start);
+        let kind = if skipped_range.is_some() {
+            TriviaKind::Skipped
+        } else if chars.starts_with("///") && !chars.starts_with("////") {
+            TriviaKind::DocComment
+        } else if chars.starts_with("//") {
+            TriviaKind::LineComment
+        } else if chars.starts_with("/**") && !chars.starts_with("/**/") && !chars.starts_with("/***") {
+            TriviaKind::DocComment
+        } else if chars.starts_with("/*") {
+            TriviaKind::BlockComment
+        } else if chars.peek().is_some_and(|c| c.is_whitespace()) {
+            TriviaKind::Whitespace
+        } else {
+            TriviaKind::Skipped
+        };
+        
+        if let Some(range) = skipped_range {
+            while chars.offset < range.end && chars.next().is_some() {}
+        } else if chars.starts_with("//") {
+            while chars.peek().is_some_and(|c| *c != '\n') {
+                chars.next();
+            }
+        } else if chars.starts_with("/*") {
+            chars.next();
+            chars.next();
+            block_comment_body(&mut chars);
+        } else {
+            chars.next();
+        }
+        let text = &source[start..chars.offset];
+        
+        // Runs of whitespace and of skipped text make one piece each
+        match trivia.last_mut() {
+            Some(last) if last.kind == kind && matches!(kind, TriviaKind::Whitespace | TriviaKind::Skipped) => {
+                last.text.push_str(text);
+            },
+            _ => trivia.push(Trivia { kind, text: text.to_string(), span }),
+        }
+    }
+    while source_chars.offset < end && source_chars.next().is_some() {}
+    trivia
+}
+
+// What lex_tokens read: the tokens with where they start and their byte
+// ranges, the byte ranges of text that was reported and dropped, and whether
+// the '}' closing an interpolation was found
+struct Lexed {
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+    ranges: Vec<Range<usize>>,
+    skipped: Vec<Range<usize>>,
+    closed: bool,
 }
 
-pub fn lex(source: &str) -> Vec<Token> {
+// Tokenize up to the end of the source or, for the expression inside a
+// template's ${...}, up to the '}' that closes it
+fn lex_tokens(chars: &mut SourceChars, errors: &mut Vec<LexError>, docs: &mut Docs, edition: Edition, in_interpolation: bool) -> Lexed {
     let mut tokens = Vec::new();
-    let mut chars = source.chars().peekable();
+    let mut spans = Vec::new();
+    let mut ranges = Vec::new();
+    let mut skipped = Vec::new();
+    let mut depth = 0; // Braces opened inside an interpolation
     
     while let Some(&c) = chars.peek() {
+        let start = chars.span();
+        let start_offset = chars.offset;
+        let error_count = errors.len();
         match c {
             // Skip whitespace
             c if c.is_whitespace() => {
                 chars.next();
             },
             
-            // Numbers
-            c if c.is_digit(10) => {
-                let mut number = String::new();
-                let mut has_dot = false;
+            // Numbers: decimal, 0x hex, 0b binary and 0o octal, with
+            // underscores, exponents and suffixes. The whole literal is read
+            // first so that a malformed one is reported instead of split up.
+            c if c.is_ascii_digit() => {
+                let mut literal = String::new();
+                let radix_prefix = c == '0' && matches!(chars.peek_second(), Some('x' | 'X' | 'b' | 'B' | 'o' | 'O'));
                 
                 while let Some(&c) = chars.peek() {
-                    if c.is_digit(10) {
-                        number.push(c);
-                        chars.next();
-                    } else if c == '.' && !has_dot {
-                        has_dot = true;
-                        number.push(c);
+                    let after_exponent = !radix_prefix && literal.ends_with(['e', 'E']);
+                    if c.is_alphanumeric() || c == '_'
+                        || (c == '.' && !radix_prefix && chars.peek_second().is_some_and(|n| n.is_ascii_digit()))
+                        || ((c == '+' || c == '-') && after_exponent) {
+                        literal.push(c);
                         chars.next();
                     } else {
                         break;
                     }
                 }
                 
-                tokens.push(Token::Number(number));
+                match number_literal(&literal) {
+                    Ok(number) => tokens.push(Token::Number(number)),
+                    Err(problem) => errors.push(LexError {
+                        message: format!("Malformed number literal '{}': {}", literal, problem),
+                        span: start,
+                    }),
+                }
             },
             
-            // Strings support both single and double quotes
+            // Triple-quoted strings span lines and lose their common indentation
+            '"' if chars.starts_with("\"\"\"") => {
+                for _ in 0..3 {
+                    chars.next();
+                }
+                let (body, terminated) = string_body(chars, "\"\"\"", true);
+                if !terminated {
+                    errors.push(LexError { message: "Unterminated string".to_string(), span: start });
+                }
+                tokens.push(Token::String(unescape(&dedent(body), errors)));
+            },
+            
+            // Chars: 'a', '\n', '\u{1F600}'
+            '\'' if edition == Edition::E2026 => {
+                chars.next();
+                let (body, terminated) = string_body(chars, "'", true);
+                let reported = errors.len();
+                let text = unescape(&body, errors);
+                let mut text_chars = text.chars();
+                match (text_chars.next(), text_chars.next()) {
+                    _ if !terminated => errors.push(LexError { message: "Unterminated char literal".to_string(), span: start }),
+                    (Some(c), None) => tokens.push(Token::Char(c)),
+                    _ if errors.len() > reported => {}, // A bad escape was reported already
+                    (None, _) => errors.push(LexError { message: "Empty char literal".to_string(), span: start }),
+                    (Some(_), Some(_)) => errors.push(LexError {
+                        message: format!("Char literal '{}' holds more than one character, use double quotes for a string", text.escape_default()),
+                        span: start,
+                    }),
+                }
+            },
+            
+            // Strings use double quotes, or single quotes before edition 2026
             '"' | '\'' => {
-                let quote_type = c; // Remember which quote type started the string
                 chars.next(); // Skip opening quote
-                let mut s = String::new();
-                
-                while let Some(&c) = chars.peek() {
-                    if c == quote_type {
-                        chars.next(); // Skip closing quote
-                        break;
-                    } else if c == '\\' {
-                        // Handle escape sequences
-                        chars.next(); 
-                        if let Some(&next_c) = chars.peek() {
-                            match next_c {
-                                'n' => s.push('\n'),
-                                't' => s.push('\t'),
-                                'r' => s.push('\r'),
-                                '\\' => s.push('\\'),
-                                '\'' => s.push('\''),
-                                '"' => s.push('"'),
-                                _ => s.push(next_c), 
-                            }
-                            chars.next();
-                        }
-                    } else {
-                        s.push(c);
-                        chars.next();
-                    }
+                let quote = c.to_string();
+                let (body, terminated) = string_body(chars, &quote, true);
+                if !terminated {
+                    errors.push(LexError { message: "Unterminated string".to_string(), span: start });
                 }
-                
-                tokens.push(Token::String(s));
+                tokens.push(Token::String(unescape(&body, errors)));
+            },
+            
+            // Template strings: `Hello ${name}`
+            '`' => {
+                chars.next();
+                let (parts, terminated) = template_parts(chars, errors, edition);
+                if !terminated {
+                    errors.push(LexError { message: "Unterminated template string".to_string(), span: start });
+                }
+                tokens.push(Token::Template(parts));
+            },
+            
+            // Raw strings keep backslashes as they are: r"C:\path"
+            'r' if chars.peek_second() == Some('"') => {
+                chars.next();
+                chars.next();
+                let (body, terminated) = string_body(chars, "\"", false);
+                if !terminated {
+                    errors.push(LexError { message: "Unterminated string".to_string(), span: start });
+                }
+                tokens.push(Token::String(body.into_iter().map(|(c, _)| c).collect()));
             },
             
             // Identifiers and keywords
@@ -105,43 +454,115 @@ pub fn lex(source: &str) -> Vec<Token> {
                     }
                 }
                 
-                // Keywords are just identifiers with special meaning
-                tokens.push(Token::Identifier(ident));
+                match KEYWORDS.iter().find(|(word, _)| *word == ident) {
+                    Some((_, keyword)) => tokens.push(keyword.clone()),
+                    None => tokens.push(Token::Identifier(ident)),
+                }
             },
             
             // Operators and punctuation
             '+' => {
                 chars.next();
-                tokens.push(Token::Plus);
+                if chars.peek() == Some(&'=') {
+                    chars.next();
+                    tokens.push(Token::PlusAssign);
+                } else if chars.peek() == Some(&'+') {
+                    chars.next();
+                    tokens.push(Token::PlusPlus);
+                } else {
+                    tokens.push(Token::Plus);
+                }
             },
             '-' => {
                 chars.next();
-                tokens.push(Token::Minus);
+                if chars.peek() == Some(&'=') {
+                    chars.next();
+                    tokens.push(Token::MinusAssign);
+                } else if chars.peek() == Some(&'-') {
+                    chars.next();
+                    tokens.push(Token::MinusMinus);
+                } else {
+                    tokens.push(Token::Minus);
+                }
             },
             '*' => {
                 chars.next();
-                tokens.push(Token::Star);
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    tokens.push(Token::StarStar);
+                } else if chars.peek() == Some(&'=') {
+                    chars.next();
+                    tokens.push(Token::StarAssign);
+                } else {
+                    tokens.push(Token::Star);
+                }
+            },
+            '%' => {
+                chars.next();
+                if chars.peek() == Some(&'=') {
+                    chars.next();
+                    tokens.push(Token::PercentAssign);
+                } else {
+                    tokens.push(Token::Percent);
+                }
+            },
+            '&' => {
+                chars.next();
+                tokens.push(Token::Ampersand);
+            },
+            '|' => {
+                chars.next();
+                tokens.push(Token::Pipe);
+            },
+            '^' => {
+                chars.next();
+                tokens.push(Token::Caret);
+            },
+            '~' => {
+                chars.next();
+                tokens.push(Token::Tilde);
+            },
+            '#' => {
+                chars.next();
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    tokens.push(Token::HashBang);
+                } else {
+                    tokens.push(Token::Hash);
+                }
             },
             '/' => {
 				chars.next();
 				if chars.peek() == Some(&'/') {
 					chars.next();
+					// `///` starts a doc comment, `////` is a plain one again
+					let doc = chars.peek() == Some(&'/') && chars.peek_second() != Some('/');
+					if doc {
+						chars.next();
+					}
+					let mut text = String::new();
 					while let Some(&c) = chars.peek() {
 						if c == '\n' {
 							break;
 						}
+						text.push(c);
 						chars.next();
 					}
+					if doc {
+						add_doc(docs, tokens.len(), text.strip_prefix(' ').unwrap_or(&text).trim_end());
+					}
 				} else if chars.peek() == Some(&'*') {
 					chars.next();
-					while let Some(&c) = chars.peek() {
-						chars.next();
-						if c == '*' && chars.peek() == Some(&'/') {
-							chars.next();
-							break;
-						}
+					// `/**` starts a doc comment, but `/**/` and `/***` are plain ones
+					let doc = chars.peek() == Some(&'*') && !matches!(chars.peek_second(), Some('/' | '*'));
+					match block_comment_body(chars) {
+						Some(body) if doc => add_doc(docs, tokens.len(), &doc_block_text(&body)),
+						Some(_) => {},
+						None => errors.push(LexError { message: "Unterminated block comment".to_string(), span: start }),
 					}
-					
+				} else if chars.peek() == Some(&'=') {
+					chars.next();
+					tokens.push(Token::SlashAssign);
 				} else {
 					tokens.push(Token::Slash);
 				}
@@ -151,6 +572,9 @@ pub fn lex(source: &str) -> Vec<Token> {
                 if chars.peek() == Some(&'=') {
                     chars.next();
                     tokens.push(Token::Equal);
+                } else if chars.peek() == Some(&'>') {
+                    chars.next();
+                    tokens.push(Token::FatArrow);
                 } else {
                     tokens.push(Token::Assign);
                 }
@@ -161,7 +585,7 @@ pub fn lex(source: &str) -> Vec<Token> {
                     chars.next();
                     tokens.push(Token::NotEqual);
                 } else {
-                    // Handle single ! if needed
+                    errors.push(LexError { message: "Unexpected character '!'".to_string(), span: start });
                 }
             },
             '<' => {
@@ -169,6 +593,9 @@ pub fn lex(source: &str) -> Vec<Token> {
                 if chars.peek() == Some(&'=') {
                     chars.next();
                     tokens.push(Token::LessThanEqual);
+                } else if chars.peek() == Some(&'<') {
+                    chars.next();
+                    tokens.push(Token::ShiftLeft);
                 } else {
                     tokens.push(Token::LessThan);
                 }
@@ -178,6 +605,9 @@ pub fn lex(source: &str) -> Vec<Token> {
                 if chars.peek() == Some(&'=') {
                     chars.next();
                     tokens.push(Token::GreaterThanEqual);
+                } else if chars.peek() == Some(&'>') {
+                    chars.next();
+                    tokens.push(Token::ShiftRight);
                 } else {
                     tokens.push(Token::GreaterThan);
                 }
@@ -209,22 +639,535 @@ pub fn lex(source: &str) -> Vec<Token> {
             ',' => {
                 chars.next();
                 tokens.push(Token::Comma);
+            },
+            ':' => {
+                chars.next();
+                tokens.push(Token::Colon);
             },
 			'{' => {
 				chars.next();
+				depth += 1;
 				tokens.push(Token::LBrace);
 			},
+			'}' if in_interpolation && depth == 0 => {
+				chars.next();
+				return Lexed { tokens, spans, ranges, skipped, closed: true };
+			},
 			'}' => {
 				chars.next();
+				depth -= 1;
 				tokens.push(Token::RBrace);
 			},
             
-            // Skip any other characters (or handle them as errors)
             _ => {
                 chars.next();
+                errors.push(LexError { message: format!("Unexpected character '{}'", c), span: start });
+            }
+        }
+        spans.resize(tokens.len(), start);
+        if ranges.len() < tokens.len() {
+            ranges.resize(tokens.len(), start_offset..chars.offset);
+        } else if errors.len() > error_count {
+            skipped.push(start_offset..chars.offset);
+        }
+    }
+    
+    Lexed { tokens, spans, ranges, skipped, closed: false }
+}
+// Read the characters of a string up to and past its closing `quote`, each
+// with its position. With `escapes` a backslash keeps the next character from
+// closing the string. Also returns whether the closing quote was found.
+fn string_body(chars: &mut SourceChars, quote: &str, escapes: bool) -> (Vec<(char, Span)>, bool) {
+    let mut body = Vec::new();
+    while let Some(&c) = chars.peek() {
+        if chars.starts_with(quote) {
+            for _ in 0..quote.len() {
+                chars.next();
+            }
+            return (body, true);
+        }
+        body.push((c, chars.span()));
+        chars.next();
+        if c == '\\' && escapes && let Some(&escaped) = chars.peek() {
+            body.push((escaped, chars.span()));
+            chars.next();
+        }
+    }
+    (body, false)
+}
+
+// The text of a block comment after its opening `/*`, up to the `*/` that
+// closes it. Comments nest, so each `/*` inside needs a `*/` of its own. None
+// when the source ends first.
+fn block_comment_body(chars: &mut SourceChars) -> Option<String> {
+    let mut body = String::new();
+    let mut depth = 1;
+    while let Some(c) = chars.next() {
+        if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            depth += 1;
+            body.push_str("/*");
+        } else if c == '*' && chars.peek() == Some(&'/') {
+            chars.next();
+            depth -= 1;
+            if depth == 0 {
+                return Some(body);
+            }
+            body.push_str("*/");
+        } else {
+            body.push(c);
+        }
+    }
+    None
+}
+
+// The text of a `/** */` comment, without the `*` that starts it or starts
+// each of its lines, and without blank lines around it
+fn doc_block_text(body: &str) -> String {
+    let mut lines: Vec<&str> = body.lines()
+        .map(|line| {
+            let line = line.trim_start();
+            let line = line.strip_prefix('*').unwrap_or(line);
+            line.strip_prefix(' ').unwrap_or(line).trim_end()
+        })
+        .collect();
+    while lines.first() == Some(&"") {
+        lines.remove(0);
+    }
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+// Attach a doc comment to the token at `index`. Several doc comments in a row
+// belong to the same token, one line each.
+fn add_doc(docs: &mut Docs, index: usize, text: &str) {
+    docs.entry(index)
+        .and_modify(|doc| {
+            doc.push('\n');
+            doc.push_str(text);
+        })
+        .or_insert_with(|| text.to_string());
+}
+
+// Read a template string after its opening backquote, splitting it into text
+// and the tokens of each ${...}. Also returns whether the closing backquote was
+// found.
+fn template_parts(chars: &mut SourceChars, errors: &mut Vec<LexError>, edition: Edition) -> (Vec<TemplatePart>, bool) {
+    let mut parts = Vec::new();
+    let mut text = Vec::new();
+    while let Some(&c) = chars.peek() {
+        if c == '`' {
+            chars.next();
+            if !text.is_empty() {
+                parts.push(TemplatePart::Text(unescape(&text, errors)));
+            }
+            return (parts, true);
+        }
+        if chars.starts_with("${") {
+            let start = chars.span();
+            chars.next();
+            chars.next();
+            if !text.is_empty() {
+                parts.push(TemplatePart::Text(unescape(&text, errors)));
+                text.clear();
             }
+            // Doc comments inside an interpolation document nothing
+            let Lexed { tokens, spans, closed, .. } = lex_tokens(chars, errors, &mut Docs::new(), edition, true);
+            if !closed {
+                errors.push(LexError { message: "Unterminated '${' in template string".to_string(), span: start });
+                return (parts, false);
+            }
+            if tokens.is_empty() {
+                errors.push(LexError { message: "Empty '${}' in template string".to_string(), span: start });
+            }
+            parts.push(TemplatePart::Code(tokens, spans));
+            continue;
+        }
+        text.push((c, chars.span()));
+        chars.next();
+        if c == '\\' && let Some(&escaped) = chars.peek() {
+            text.push((escaped, chars.span()));
+            chars.next();
+        }
+    }
+    if !text.is_empty() {
+        parts.push(TemplatePart::Text(unescape(&text, errors)));
+    }
+    (parts, false)
+}
+
+// Indentation of a triple-quoted string: a blank first line and a blank last
+// line (around the quotes) are dropped, and the indentation the remaining
+// lines share is removed
+fn dedent(body: Vec<(char, Span)>) -> Vec<(char, Span)> {
+    let mut lines: Vec<Vec<(char, Span)>> = vec![Vec::new()];
+    for (c, span) in body {
+        if c == '\n' {
+            lines.push(Vec::new());
+        } else {
+            lines.last_mut().unwrap().push((c, span));
         }
     }
+    let is_blank = |line: &Vec<(char, Span)>| line.iter().all(|(c, _)| *c == ' ' || *c == '\t' || *c == '\r');
+    if lines.len() > 1 && is_blank(&lines[0]) {
+        lines.remove(0);
+    }
+    if lines.len() > 1 && is_blank(lines.last().unwrap()) {
+        lines.pop();
+    }
     
-    tokens
-}
\ No newline at end of file
+    let indent = lines.iter()
+        .filter(|line| !is_blank(line))
+        .map(|line| line.iter().take_while(|(c, _)| *c == ' ' || *c == '\t').count())
+        .min()
+        .unwrap_or(0);
+    let mut text = Vec::new();
+    for (i, line) in lines.into_iter().enumerate() {
+        if i > 0 {
+            text.push(('\n', Span::default()));
+        }
+        text.extend(line.into_iter().skip(indent));
+    }
+    text
+}
+
+// Turn the escape sequences of a string body into the characters they stand
+// for, reporting unknown and malformed ones
+fn unescape(body: &[(char, Span)], errors: &mut Vec<LexError>) -> String {
+    let mut s = String::new();
+    let mut i = 0;
+    while i < body.len() {
+        let (c, span) = body[i];
+        i += 1;
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        let Some(&(escaped, _)) = body.get(i) else {
+            break; // Only in an unterminated string
+        };
+        i += 1;
+        match escaped {
+            'n' => s.push('\n'),
+            't' => s.push('\t'),
+            'r' => s.push('\r'),
+            '0' => s.push('\0'),
+            '\\' => s.push('\\'),
+            '\'' => s.push('\''),
+            '"' => s.push('"'),
+            '`' | '$' => s.push(escaped), // For template strings
+            'x' => {
+                let digits: String = body[i..].iter().take(2).map(|(c, _)| *c).collect();
+                // \x80 to \xFF stand for U+0080 to U+00FF like in JavaScript,
+                // strings hold characters rather than raw bytes
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(byte) if digits.len() == 2 => {
+                        s.push(char::from(byte));
+                        i += 2;
+                    },
+                    _ => errors.push(LexError { message: "Escape '\\x' needs two hex digits".to_string(), span }),
+                }
+            },
+            'u' => {
+                let rest: String = body[i..].iter().map(|(c, _)| *c).take(9).collect();
+                let digits = rest.strip_prefix('{').and_then(|r| r.split_once('}')).map(|(digits, _)| digits);
+                match digits {
+                    Some(digits) if (1..=6).contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_hexdigit()) => {
+                        match char::from_u32(u32::from_str_radix(digits, 16).unwrap()) {
+                            Some(c) => s.push(c),
+                            None => errors.push(LexError {
+                                message: format!("Escape '\\u{{{}}}' is not a Unicode character", digits),
+                                span,
+                            }),
+                        }
+                        i += digits.len() + 2;
+                    },
+                    _ => errors.push(LexError {
+                        message: "Escape '\\u' needs 1 to 6 hex digits in braces, like \\u{1F600}".to_string(),
+                        span,
+                    }),
+                }
+            },
+            _ => errors.push(LexError { message: format!("Unknown escape sequence '\\{}'", escaped), span }),
+        }
+    }
+    s
+}
+
+// Check a number literal and turn it into the text the parser reads: decimal
+// digits for an integer, and text with a '.' or an exponent for a float
+fn number_literal(literal: &str) -> Result<String, String> {
+    let radix = match literal.get(..2) {
+        Some("0x" | "0X") => Some(16),
+        Some("0b" | "0B") => Some(2),
+        Some("0o" | "0O") => Some(8),
+        _ => None,
+    };
+    
+    if let Some(radix) = radix {
+        let body = &literal[2..];
+        let end = body.find(|c: char| !c.is_digit(radix) && c != '_').unwrap_or(body.len());
+        let (digits, suffix) = body.split_at(end);
+        let digits = digits.replace('_', "");
+        if suffix.starts_with(|c: char| c.is_ascii_digit()) {
+            let name = match radix { 2 => "a binary", 8 => "an octal", _ => "a hex" };
+            return Err(format!("'{}' is not {} digit", &suffix[..1], name));
+        }
+        if digits.is_empty() {
+            return Err("no digits after the prefix".to_string());
+        }
+        if !matches!(suffix, "" | "i32" | "i64") {
+            return Err(format!("'{}' is not a valid suffix, expected i32 or i64", suffix));
+        }
+        return int_literal(u64::from_str_radix(&digits, radix).ok(), suffix);
+    }
+    
+    let bytes = literal.as_bytes();
+    let digits_from = |mut i: usize| {
+        while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'_') {
+            i += 1;
+        }
+        i
+    };
+    let mut end = digits_from(0);
+    let mut is_float = false;
+    if bytes.get(end) == Some(&b'.') {
+        is_float = true;
+        end = digits_from(end + 1);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let mut i = end + 1;
+        if matches!(bytes.get(i), Some(b'+' | b'-')) {
+            i += 1;
+        }
+        let exponent_end = digits_from(i);
+        if !literal[i..exponent_end].contains(|c: char| c.is_ascii_digit()) {
+            return Err("the exponent has no digits".to_string());
+        }
+        is_float = true;
+        end = exponent_end;
+    }
+    
+    let (number, suffix) = literal.split_at(end);
+    let mut number = number.replace('_', "");
+    match suffix {
+        "" => {},
+        "i32" | "i64" if !is_float => {},
+        "i32" | "i64" => return Err("an integer suffix cannot follow a float".to_string()),
+        "f32" => return Err("floats are 64-bit, the 'f32' suffix is not supported".to_string()),
+        "f" | "f64" => {
+            if !is_float {
+                number.push_str(".0");
+            }
+            is_float = true;
+        },
+        _ if suffix.starts_with('.') => return Err("more than one decimal point".to_string()),
+        _ => return Err(format!("'{}' is not a valid suffix, expected i32, i64, f or f64", suffix)),
+    }
+    if !is_float {
+        return int_literal(number.parse().ok(), suffix);
+    }
+    if number.parse::<f64>().is_err() {
+        return Err("not a valid float".to_string());
+    }
+    Ok(number)
+}
+
+// Integer literals are 64-bit, or 32-bit with the i32 suffix, which is kept
+// for the parser. A negative one is the negation of a literal, so the value
+// may be one past the maximum when the parser finds it directly negated.
+fn int_literal(value: Option<u64>, suffix: &str) -> Result<String, String> {
+    let (max, bits) = if suffix == "i32" { (i32::MAX as u64, 32) } else { (i64::MAX as u64, 64) };
+    match value {
+        Some(value) if value <= max + 1 => Ok(format!("{}{}", value, suffix)),
+        _ => Err(format!("the value does not fit in {} bits", bits)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        let (tokens, _, _, errors) = lex(source, Edition::E2026);
+        assert_eq!(errors, Vec::new(), "{}", source);
+        tokens
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        lex(source, Edition::E2026).3.into_iter().map(|e| e.message).collect()
+    }
+
+    fn number(text: &str) -> Token {
+        Token::Number(text.to_string())
+    }
+
+    #[test]
+    fn number_literals() {
+        assert_eq!(tokens("0xFF 0b1010 0o755 1_000_000"), vec![number("255"), number("10"), number("493"), number("1000000")]);
+        assert_eq!(tokens("1.5e-3 2E10 3.0f 4f 10i32 0x10i64 9223372036854775807"),
+                   vec![number("1.5e-3"), number("2E10"), number("3.0"), number("4.0"), number("10i32"), number("16i64"),
+                        number("9223372036854775807")]);
+    }
+
+    #[test]
+    fn malformed_number_literals() {
+        assert_eq!(errors("1.2.3"), ["Malformed number literal '1.2.3': more than one decimal point"]);
+        assert_eq!(errors("0x"), ["Malformed number literal '0x': no digits after the prefix"]);
+        assert_eq!(errors("0b102"), ["Malformed number literal '0b102': '2' is not a binary digit"]);
+        assert_eq!(errors("1e"), ["Malformed number literal '1e': the exponent has no digits"]);
+        assert_eq!(errors("1.5i32"), ["Malformed number literal '1.5i32': an integer suffix cannot follow a float"]);
+        assert_eq!(errors("7u8"), ["Malformed number literal '7u8': 'u8' is not a valid suffix, expected i32, i64, f or f64"]);
+    }
+
+    #[test]
+    fn number_literals_are_64_bit() {
+        let span = lex("let x =\n  99999999999999999999;", Edition::E2026).3[0].span;
+        assert_eq!((span.line, span.column), (2, 3));
+        // One past the maximum is left for the parser, which only allows it negated
+        assert_eq!(tokens("9223372036854775808 2147483648i32"), vec![number("9223372036854775808"), number("2147483648i32")]);
+        assert_eq!(errors("9223372036854775809"), ["Malformed number literal '9223372036854775809': the value does not fit in 64 bits"]);
+        assert_eq!(errors("0xFFFFFFFFFFFFFFFF"), ["Malformed number literal '0xFFFFFFFFFFFFFFFF': the value does not fit in 64 bits"]);
+        assert_eq!(errors("2147483649i32"), ["Malformed number literal '2147483649i32': the value does not fit in 32 bits"]);
+        assert_eq!(errors("0x1_0000_0000i32"), ["Malformed number literal '0x1_0000_0000i32': the value does not fit in 32 bits"]);
+        assert_eq!(errors("1.0f32"), ["Malformed number literal '1.0f32': floats are 64-bit, the 'f32' suffix is not supported"]);
+    }
+
+    fn string(text: &str) -> Token {
+        Token::String(text.to_string())
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(tokens(r#""a\tb\n" "\x41\u{e9}\u{1F600}" "\\ \' \"""#),
+                   vec![string("a\tb\n"), string("Aé😀"), string("\\ ' \"")]);
+        assert_eq!(errors(r#""\q""#), ["Unknown escape sequence '\\q'"]);
+        assert_eq!(errors(r#""\x4""#), ["Escape '\\x' needs two hex digits"]);
+        assert_eq!(errors(r#""\u{110000}""#), ["Escape '\\u{110000}' is not a Unicode character"]);
+    }
+
+    #[test]
+    fn nul_and_latin_1_escapes() {
+        assert_eq!(tokens(r#""a\0b" "\x00" "\u{0}" "\x7F\x80\xe9\xFF""#),
+                   vec![string("a\0b"), string("\0"), string("\0"), string("\u{7f}\u{80}é\u{ff}")]);
+        assert_eq!(tokens(r"`\u{000}`"), vec![Token::Template(vec![TemplatePart::Text("\0".to_string())])]);
+    }
+
+    #[test]
+    fn raw_and_triple_quoted_strings() {
+        assert_eq!(tokens(r#"r"C:\path\n""#), vec![string(r"C:\path\n")]);
+        assert_eq!(tokens("\"\"\"\n    first\n      second\n    \"\"\""), vec![string("first\n  second")]);
+    }
+
+    // Each error as "line:column message"
+    fn located_errors(source: &str) -> Vec<String> {
+        lex(source, Edition::E2026).3.into_iter()
+            .map(|e| format!("{}:{} {}", e.span.line, e.span.column, e.message))
+            .collect()
+    }
+
+    #[test]
+    fn errors_have_spans_and_lexing_goes_on() {
+        let (tokens, _, _, errors) = lex("let a = 1 @ 2;\nlet b = ! 3;", Edition::E2026);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(tokens.len(), 11);
+        assert_eq!(located_errors("let a = 1 @ 2;\nlet b = ! 3;"),
+                   ["1:11 Unexpected character '@'", "2:9 Unexpected character '!'"]);
+    }
+
+    #[test]
+    fn unterminated_strings_and_comments() {
+        assert_eq!(located_errors("let s = \"open;\n"), ["1:9 Unterminated string"]);
+        assert_eq!(located_errors("x;\n  r\"open"), ["2:3 Unterminated string"]);
+        assert_eq!(located_errors("`a ${b"), ["1:4 Unterminated '${' in template string", "1:1 Unterminated template string"]);
+        assert_eq!(located_errors("1; /* open\n /* nested */ x"), ["1:4 Unterminated block comment"]);
+    }
+
+    #[test]
+    fn keywords_have_their_own_tokens() {
+        assert_eq!(tokens("let if_ = true; const x = null; console"), vec![
+            Token::Let, Token::Identifier("if_".to_string()), Token::Assign, Token::True, Token::Semicolon,
+            Token::Const, Token::Identifier("x".to_string()), Token::Assign, Token::Null, Token::Semicolon,
+            Token::Console,
+        ]);
+        assert_eq!(Token::Match.keyword(), Some("match"));
+        assert_eq!(Token::Identifier("match".to_string()).keyword(), None);
+    }
+
+    #[test]
+    fn char_literals_by_edition() {
+        assert_eq!(tokens(r"'a' 'é' '\n' '\u{1F600}'"), vec![Token::Char('a'), Token::Char('é'), Token::Char('\n'), Token::Char('😀')]);
+        assert_eq!(lex("'text'", Edition::E2025).0, vec![string("text")]);
+        assert_eq!(errors("'ab'"), ["Char literal 'ab' holds more than one character, use double quotes for a string"]);
+        assert_eq!(errors("''"), ["Empty char literal"]);
+        assert_eq!(errors("'a"), ["Unterminated char literal"]);
+        assert_eq!(errors(r"'\q'"), ["Unknown escape sequence '\\q'"]);
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        assert_eq!(tokens("1 /* outer /* inner */ still outer */ 2"), vec![number("1"), number("2")]);
+        assert_eq!(tokens("/**/ 1 /***/"), vec![number("1")]);
+    }
+
+    #[test]
+    fn doc_comments_belong_to_the_next_token() {
+        let (tokens, _, docs, _) = lex("/// Answer\n/// to everything\nconst A = 42;\n/**\n * A point\n */\nlet p = 1;\n// plain\nlet q = 2;", Edition::E2026);
+        assert_eq!(tokens[0], Token::Const);
+        assert_eq!(tokens[5], Token::Let);
+        assert_eq!(docs, Docs::from([(0, "Answer\nto everything".to_string()), (5, "A point".to_string())]));
+    }
+
+    #[test]
+    fn lossless_stream_gives_back_the_source() {
+        let sources = [
+            "",
+            "  \n",
+            "let x = 0xFF; // hex\n\tconsole.print(x);\r\n",
+            "/// doc\n/* a /* nested */ b */ const A = \"\\u{e9}\"; `t ${A + 1}`\n",
+            "let s = r\"C:\\path\";\n\"\"\"\n    multi\n\"\"\"\n",
+            "let bad = 1 @ \"open",
+        ];
+        for source in sources {
+            for edition in [Edition::E2025, Edition::E2026] {
+                assert_eq!(lex_lossless(source, edition).0.to_source(), source);
+            }
+        }
+    }
+
+    #[test]
+    fn lossless_tokens_keep_their_trivia() {
+        let (stream, errors) = lex_lossless("let x = 1; // one\n/* two */ x", Edition::E2026);
+        assert!(errors.is_empty());
+        assert_eq!(stream.tokens.len(), 6);
+        let last = &stream.tokens[5];
+        assert_eq!((last.token.clone(), last.text.as_str()), (Token::Identifier("x".to_string()), "x"));
+        assert_eq!((last.span.line, last.span.column), (2, 11));
+        let kinds: Vec<(TriviaKind, &str)> = last.leading.iter().map(|t| (t.kind, t.text.as_str())).collect();
+        assert_eq!(kinds, [(TriviaKind::Whitespace, " "), (TriviaKind::LineComment, "// one"), (TriviaKind::Whitespace, "\n"),
+                           (TriviaKind::BlockComment, "/* two */"), (TriviaKind::Whitespace, " ")]);
+        assert_eq!(stream.tokens[1].text, "x");
+        assert!(stream.trailing.is_empty());
+
+        let (stream, _) = lex_lossless("1 @ 2", Edition::E2026);
+        let skipped: Vec<&str> = stream.tokens[1].leading.iter().filter(|t| t.kind == TriviaKind::Skipped).map(|t| t.text.as_str()).collect();
+        assert_eq!(skipped, ["@"]);
+    }
+
+    #[test]
+    fn template_parts() {
+        let Token::Template(parts) = &tokens("`Hi ${name}, ${a + 1}!`")[0] else { panic!("not a template") };
+        let code: Vec<Vec<Token>> = parts.iter().filter_map(|part| match part {
+            TemplatePart::Code(tokens, _) => Some(tokens.clone()),
+            TemplatePart::Text(_) => None,
+        }).collect();
+        assert_eq!(parts[0], TemplatePart::Text("Hi ".to_string()));
+        assert_eq!(parts[2], TemplatePart::Text(", ".to_string()));
+        assert_eq!(parts[4], TemplatePart::Text("!".to_string()));
+        assert_eq!(code, [vec![Token::Identifier("name".to_string())],
+                          vec![Token::Identifier("a".to_string()), Token::Plus, number("1")]]);
+        assert_eq!(tokens(r"`\${x} \`q\``"), vec![Token::Template(vec![TemplatePart::Text("${x} `q`".to_string())])]);
+    }
+}
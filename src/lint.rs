@@ -0,0 +1,382 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use crate::ast::{Expr, BinOp, Pattern, LintAttribute, LintLevel};
+use crate::codegen::Types;
+use crate::lexer::Span;
+
+// Every lint, they all warn unless told otherwise
+pub const LINTS: &[&str] = &[
+    "unused_variable",    // let that is never read or written
+    "unused_constant",    // const that is never read
+    "unused_assignment",  // let that is written but never read
+    "could_be_const",     // let with a constant value that never changes
+    "constant_condition", // if condition known at compile time
+    "unreachable_code",   // statements after an exit
+    "shadowing",          // match binding named like a variable
+    "mixed_comparison",   // comparison of values with different types
+];
+
+// Lint levels from --allow, --warn and --deny
+pub struct LintLevels {
+    levels: HashMap<&'static str, LintLevel>,
+}
+
+impl Default for LintLevels {
+    fn default() -> Self {
+        LintLevels { levels: LINTS.iter().map(|lint| (*lint, LintLevel::Warn)).collect() }
+    }
+}
+
+impl LintLevels {
+    // Set the level of one lint, or of every lint with "all"
+    pub fn set(&mut self, name: &str, level: LintLevel) -> Result<(), String> {
+        if name == "all" {
+            self.levels.values_mut().for_each(|l| *l = level);
+            return Ok(());
+        }
+        match self.levels.get_mut(name) {
+            Some(l) => {
+                *l = level;
+                Ok(())
+            },
+            None => Err(format!("Unknown lint '{}'", name)),
+        }
+    }
+}
+
+pub struct Diagnostic {
+    pub lint: &'static str,
+    pub level: LintLevel,   // Warn or Deny, allowed lints are dropped
+    pub message: String,
+    pub span: Span,
+}
+
+// Lint a program that made it through code generation. Attributes in the
+// source override the command line levels, the innermost one wins.
+pub fn check(exprs: &[Expr], types: &Types, attributes: &[LintAttribute], levels: &LintLevels) -> Vec<Diagnostic> {
+    for attribute in attributes {
+        for lint in &attribute.lints {
+            if lint != "all" && !LINTS.contains(&lint.as_str()) {
+                panic!("Unknown lint '{}' at line {}, column {}", lint, attribute.span.line, attribute.span.column);
+            }
+        }
+    }
+
+    let mut linter = Linter { types, declarations: Vec::new(), found: Vec::new() };
+    linter.block(exprs);
+    linter.unused();
+
+    let mut diagnostics: Vec<Diagnostic> = linter.found.into_iter()
+        .map(|(lint, message, span)| Diagnostic { lint, level: level_at(lint, span, attributes, levels), message, span })
+        .filter(|diagnostic| diagnostic.level != LintLevel::Allow)
+        .collect();
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.span.line, diagnostic.span.column));
+    diagnostics
+}
+
+fn level_at(lint: &str, span: Span, attributes: &[LintAttribute], levels: &LintLevels) -> LintLevel {
+    let position = (span.line, span.column);
+    let mut covering: Vec<&LintAttribute> = attributes.iter()
+        .filter(|a| a.lints.iter().any(|name| name == lint || name == "all"))
+        .filter(|a| (a.start.line, a.start.column) <= position && position <= (a.end.line, a.end.column))
+        .collect();
+    // Outer statements first, attributes on the same statement in source order
+    covering.sort_by_key(|a| (a.start.line, a.start.column, Reverse((a.end.line, a.end.column))));
+    covering.last().map_or(levels.levels[lint], |a| a.level)
+}
+
+// A const or let, and how the rest of the program uses it
+struct Declaration {
+    name: String,
+    span: Span,
+    constant: bool,
+    reads: usize,
+    writes: usize,       // Assignments, match bindings and changes through methods or indexes
+    scalar_value: bool,  // Initialized with a number, string, ... known at compile time
+}
+
+struct Linter<'a> {
+    types: &'a Types,
+    declarations: Vec<Declaration>,
+    found: Vec<(&'static str, String, Span)>,
+}
+
+impl Linter<'_> {
+    fn block(&mut self, statements: &[Expr]) {
+        let mut reported = false;
+        for (i, statement) in statements.iter().enumerate() {
+            self.statement(statement);
+            if !reported && i + 1 < statements.len() && let Some((span, what)) = exits(statement) {
+                self.found.push(("unreachable_code", format!("The statements after this {} never run", what), span));
+                reported = true;
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Expr) {
+        match statement {
+            Expr::Const { name, value, span, .. } => {
+                self.expression(value);
+                self.declare(name, *span, true, false);
+            },
+            Expr::Let { name, value, span, .. } => {
+                self.expression(value);
+                let scalar_value = self.types.is_scalar_constant(value);
+                self.declare(name, *span, false, scalar_value);
+            },
+            Expr::Assign { name, value } => {
+                self.expression(value);
+                self.write(name);
+            },
+            Expr::IndexAssign { object, index, value } => {
+                self.target(object);
+                self.expression(index);
+                self.expression(value);
+            },
+            Expr::FieldAssign { object, value, .. } => {
+                self.target(object);
+                self.expression(value);
+            },
+            Expr::MethodCall { object, method, args } => {
+                // A method call statement that changes its array doesn't read it
+                if matches!(method.as_str(), "push" | "pop" | "remove" | "reverse" | "sort") {
+                    self.target(object);
+                } else {
+                    self.expression(object);
+                }
+                args.iter().for_each(|arg| self.expression(arg));
+            },
+            Expr::If { branches, else_branch, spans } => {
+                for ((condition, body), span) in branches.iter().zip(spans) {
+                    self.expression(condition);
+                    if let Some(value) = self.types.constant_condition(condition) {
+                        self.found.push(("constant_condition", format!("This condition is always {}", value), *span));
+                    }
+                    self.block(body);
+                }
+                if let Some(body) = else_branch {
+                    self.block(body);
+                }
+            },
+            Expr::Match { value, arms, span } => {
+                self.expression(value);
+                for (pattern, body) in arms {
+                    self.pattern(pattern, *span);
+                    self.block(body);
+                }
+            },
+            _ => self.expression(statement),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Variable(name) => self.read(name),
+            Expr::BinaryOp { op, left, right, span } => {
+                let comparison = matches!(op, BinOp::Equal | BinOp::NotEqual | BinOp::Lt | BinOp::Gt | BinOp::Lte | BinOp::Gte);
+                if comparison && let Some((left, right)) = self.types.mismatched_types(left, right) {
+                    self.found.push(("mixed_comparison", format!("This compares {} with {}, values of different types", left, right), *span));
+                }
+                self.expression(left);
+                self.expression(right);
+            },
+            Expr::MethodCall { object, method, args } => {
+                if matches!(method.as_str(), "push" | "pop" | "remove") {
+                    self.target(object);
+                }
+                self.expression(object);
+                args.iter().for_each(|arg| self.expression(arg));
+            },
+            Expr::Array(items) => items.iter().for_each(|item| self.expression(item)),
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            },
+            Expr::StructLiteral { fields, .. } => fields.iter().for_each(|(_, value)| self.expression(value)),
+            Expr::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            },
+            Expr::UnaryOp { operand: inner, .. } | Expr::Print(inner) | Expr::ProcessEnv(inner)
+            | Expr::Exit { code: inner, .. } | Expr::ConsoleWrite { value: inner, .. }
+            | Expr::CharFromCode { code: inner, .. } | Expr::FieldAccess { object: inner, .. } => self.expression(inner),
+            Expr::Fs { args, .. } | Expr::Math { args, .. } | Expr::Time { args, .. }
+            | Expr::Random { args, .. } | Expr::Variant { args, .. } => args.iter().for_each(|arg| self.expression(arg)),
+            Expr::Printf { format, args, .. } => {
+                self.expression(format);
+                args.iter().for_each(|arg| self.expression(arg));
+            },
+            Expr::MatchValue { value, arms, span } => {
+                self.expression(value);
+                for (pattern, arm) in arms {
+                    self.pattern(pattern, *span);
+                    self.expression(arm);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, span: Span) {
+        match pattern {
+            Pattern::Literal(literal) => self.expression(literal),
+            Pattern::Variant { name, bindings } => {
+                // A bare name may be a constant that the arm compares with
+                self.read(name);
+                for binding in bindings.iter().flatten() {
+                    self.bind(binding, span);
+                }
+            },
+            Pattern::Wildcard => {},
+        }
+    }
+
+    // The array, map or struct an assignment or method changes: writing to
+    // arr[i] or p.x writes arr or p
+    fn target(&mut self, object: &Expr) {
+        match object {
+            Expr::Variable(name) => self.write(name),
+            Expr::Index { object, index } => {
+                self.target(object);
+                self.expression(index);
+            },
+            Expr::FieldAccess { object, .. } => self.target(object),
+            _ => self.expression(object),
+        }
+    }
+
+    fn declare(&mut self, name: &str, span: Span, constant: bool, scalar_value: bool) {
+        self.declarations.push(Declaration { name: name.to_string(), span, constant, reads: 0, writes: 0, scalar_value });
+    }
+
+    fn declaration(&mut self, name: &str) -> Option<&mut Declaration> {
+        self.declarations.iter_mut().rev().find(|d| d.name == name)
+    }
+
+    fn read(&mut self, name: &str) {
+        if let Some(declaration) = self.declaration(name) {
+            declaration.reads += 1;
+        }
+    }
+
+    fn write(&mut self, name: &str) {
+        if let Some(declaration) = self.declaration(name) {
+            declaration.writes += 1;
+        }
+    }
+
+    // A match binding hides a variable of the same name for the arm
+    fn bind(&mut self, name: &str, span: Span) {
+        if let Some(declaration) = self.declaration(name).filter(|d| !d.constant) {
+            let message = format!("The binding '{}' shadows the variable declared at line {}, column {}",
+                                  name, declaration.span.line, declaration.span.column);
+            self.found.push(("shadowing", message, span));
+        }
+    }
+
+    // Names starting with an underscore are meant to go unused
+    fn unused(&mut self) {
+        for d in self.declarations.iter().filter(|d| !d.name.starts_with('_')) {
+            let found = match (d.constant, d.reads, d.writes) {
+                (true, 0, _) => ("unused_constant", format!("Constant '{}' is never used", d.name)),
+                (false, 0, 0) => ("unused_variable", format!("Variable '{}' is never used", d.name)),
+                (false, 0, _) => ("unused_assignment", format!("Variable '{}' is assigned but never read", d.name)),
+                (false, _, 0) if d.scalar_value => ("could_be_const", format!("Variable '{}' never changes, it could be a const", d.name)),
+                _ => continue,
+            };
+            self.found.push((found.0, found.1, d.span));
+        }
+    }
+}
+
+// Where a statement always exits: an exit, an if whose branches and else all
+// exit, or a match whose arms all exit (matches are exhaustive)
+fn exits(statement: &Expr) -> Option<(Span, &'static str)> {
+    let block_exits = |body: &Vec<Expr>| body.iter().any(|s| exits(s).is_some());
+    match statement {
+        Expr::Exit { span, .. } => Some((*span, "exit")),
+        Expr::If { branches, else_branch: Some(else_body), spans } => {
+            (branches.iter().all(|(_, body)| block_exits(body)) && block_exits(else_body)).then_some((spans[0], "if"))
+        },
+        Expr::Match { arms, span, .. } => arms.iter().all(|(_, body)| block_exits(body)).then_some((*span, "match")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::{generate_nasm, CodegenOptions};
+    use crate::lexer::{lex, Edition};
+    use crate::parser::Parser;
+
+    // Lint a program the way the compiler does, as "lint line:column" lines
+    fn lint(source: &str, levels: &LintLevels) -> Vec<String> {
+        let (tokens, spans, docs, errors) = lex(source, Edition::E2026);
+        assert!(errors.is_empty());
+        let mut parser = Parser::new(tokens, spans, docs);
+        parser.parse_file_attributes();
+        let mut exprs = Vec::new();
+        while !parser.is_finished() {
+            exprs.push(parser.parse_statement().expect("syntax error"));
+        }
+        let options = CodegenOptions { source_file: "test.spp".to_string(), overflow_checks: false };
+        let (_, types) = generate_nasm(&exprs, &options);
+        check(&exprs, &types, parser.lint_attributes(), levels).iter()
+            .map(|d| format!("{:?} {} {}:{}", d.level, d.lint, d.span.line, d.span.column))
+            .collect()
+    }
+
+    fn warnings(source: &str) -> Vec<String> {
+        lint(source, &LintLevels::default())
+    }
+
+    #[test]
+    fn unused_declarations() {
+        assert_eq!(warnings("const A = 1;\nlet b = 2;\nlet c = 3;\nc = 4;\nlet _d = 5;\nexit(0);\n"),
+                   ["Warn unused_constant 1:1", "Warn unused_variable 2:1", "Warn unused_assignment 3:1"]);
+        assert_eq!(warnings("let n = 2;\nconsole.print(n);\nexit(0);\n"), ["Warn could_be_const 1:1"]);
+    }
+
+    #[test]
+    fn constants_read_by_match_patterns() {
+        let source = "const NOT_FOUND = 404;\nlet code = console.readInt();\nmatch (code) {\n    NOT_FOUND => { console.print(\"missing\"); }\n    _ => { console.print(\"ok\"); }\n}\nexit(0);\n";
+        assert_eq!(warnings(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn conditions_and_unreachable_code() {
+        assert_eq!(warnings("if (1 < 2) {\n    exit(0);\n}\nexit(1);\nconsole.print(3);\n"),
+                   ["Warn constant_condition 1:1", "Warn unreachable_code 4:1"]);
+    }
+
+    #[test]
+    fn mixed_comparisons() {
+        assert_eq!(warnings("let s = \"a\";\ns = \"b\";\nconsole.print(s == 1);\nexit(0);\n"), ["Warn mixed_comparison 3:17"]);
+        // Input may be null, so it compares with anything
+        assert_eq!(warnings("let s = console.readLine();\nconsole.print(s == 1);\nexit(0);\n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn levels_from_the_command_line() {
+        let source = "let b = 2;\nconst A = 1;\nexit(0);\n";
+        let mut levels = LintLevels::default();
+        levels.set("unused_variable", LintLevel::Deny).unwrap();
+        levels.set("unused_constant", LintLevel::Allow).unwrap();
+        assert_eq!(lint(source, &levels), ["Deny unused_variable 1:1"]);
+        levels.set("all", LintLevel::Allow).unwrap();
+        assert_eq!(lint(source, &levels), Vec::<String>::new());
+        assert_eq!(levels.set("unused", LintLevel::Warn), Err("Unknown lint 'unused'".to_string()));
+    }
+
+    #[test]
+    fn attributes_override_levels() {
+        let source = "#![deny(unused_variable)]\nlet a = 1;\n#[allow(unused_variable)]\nlet b = 2;\nexit(0);\n";
+        assert_eq!(warnings(source), ["Deny unused_variable 2:1"]);
+        // The innermost attribute wins
+        let source = "#[allow(all)]\nif (true) {\n    #[warn(unused_variable)]\n    let a = 1;\n    let b = 2;\n}\nexit(0);\n";
+        assert_eq!(warnings(source), ["Warn unused_variable 4:5"]);
+    }
+}
@@ -11,9 +11,14 @@ mod lexer;
 mod parser;
 mod ast;
 mod codegen;
+mod runtime;
+mod formatter;
+mod lint;
 
-use lexer::lex;
+use lexer::{lex, lex_lossless, Edition};
 use parser::Parser;
+use ast::LintLevel;
+use lint::LintLevels;
 // use crate::ast::{Expr, BinOp}; 
 // use std::collections::HashMap; 
 
@@ -56,11 +61,19 @@ fn main() {
         print_usage(&args[0]);
         process::exit(1);
     }
+    if args[1] == "fmt" {
+        format_files(&args[0], &args[2..]);
+        return;
+    }
 
     // Parse options
     let mut input_path = "";
     let mut output_path = "";
     let mut output_dir = ".";
+    let mut overflow_checks = false;
+    let mut edition = Edition::E2025;
+    let mut print_tokens = false;
+    let mut lint_levels = LintLevels::default();
     
     let mut i = 1;
     while i < args.len() {
@@ -79,6 +92,18 @@ fn main() {
             i += 1;
         } else if args[i].starts_with("--output-dir=") {
             output_dir = &args[i]["--output-dir=".len()..];
+        } else if args[i] == "--overflow-checks" {
+            overflow_checks = true;
+        } else if args[i] == "--tokens" {
+            print_tokens = true;
+        } else if let Some(year) = args[i].strip_prefix("--edition=") {
+            edition = parse_edition(year);
+        } else if let Some((flag, lint)) = args[i].split_once('=')
+            && let Some(level) = lint_level(flag) {
+            if let Err(message) = lint_levels.set(lint, level) {
+                eprintln!("Error: {}", message);
+                process::exit(1);
+            }
         } else if !args[i].starts_with("-") {
             input_path = &args[i];
         }
@@ -91,7 +116,11 @@ fn main() {
         process::exit(1);
     }
     
-    compile_file(input_path, output_path, output_dir);
+    if print_tokens {
+        dump_tokens(input_path, edition);
+        process::exit(0);
+    }
+    compile_file(input_path, output_path, output_dir, overflow_checks, edition, &lint_levels);
     
     // // Create a constants map to track defined constants
     // let mut constants = HashMap::new();
@@ -104,16 +133,134 @@ fn main() {
     // }
 }
 
+// The level a --warn=, --deny= or --allow= option sets
+fn lint_level(flag: &str) -> Option<LintLevel> {
+    match flag {
+        "--allow" => Some(LintLevel::Allow),
+        "--warn" => Some(LintLevel::Warn),
+        "--deny" => Some(LintLevel::Deny),
+        _ => None,
+    }
+}
+
+fn parse_edition(year: &str) -> Edition {
+    match Edition::from_year(year) {
+        Some(edition) => edition,
+        None => {
+            eprintln!("Error: Unknown edition '{}', expected 2025 or 2026", year);
+            process::exit(1);
+        }
+    }
+}
+
 fn print_usage(program_name: &str) {
     println!("Usage: {} [options] <input_file.spp>", program_name);
+    println!("       {} fmt [--check] [--edition=<year>] <file.spp>...", program_name);
     println!("Options:");
     println!("  -h, --help                Display this help message");
     println!("  -v, --version             Display version information");
     println!("  -o, --output=<file>       Specify output executable name");
     println!("  --output-dir=<dir>        Specify output directory (default: current dir)");
+    println!("  --overflow-checks         Stop with a runtime error when integer arithmetic overflows");
+    println!("  --edition=<year>          Language edition, 2025 (default) or 2026 where 'a' is a char");
+    println!("  --tokens                  Print the tokens with their whitespace and comments instead of compiling");
+    println!("  --warn=<lint>             Report a lint as a warning, the default for all lints");
+    println!("  --deny=<lint>             Report a lint as an error and stop");
+    println!("  --allow=<lint>            Turn a lint off");
+    println!("Lints: {}, or all for every lint.", lint::LINTS.join(", "));
+    println!("fmt rewrites the files in the canonical style. With --check it only lists the files");
+    println!("that are not formatted, and exits with 1 when there are any.");
+}
+
+// The fmt subcommand: format each file in place, or with --check only report
+// the ones that would change
+fn format_files(program_name: &str, args: &[String]) {
+    let mut check = false;
+    let mut edition = Edition::E2025;
+    let mut paths = Vec::new();
+    for arg in args {
+        if arg == "--check" {
+            check = true;
+        } else if let Some(year) = arg.strip_prefix("--edition=") {
+            edition = parse_edition(year);
+        } else if arg.starts_with("-") {
+            eprintln!("Error: Unknown fmt option '{}'", arg);
+            process::exit(1);
+        } else {
+            paths.push(arg.as_str());
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("Error: No input file specified");
+        print_usage(program_name);
+        process::exit(1);
+    }
+
+    let mut failed = false;
+    for path in paths {
+        let source = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => {
+                eprintln!("Error: The file '{}' could not be found or read.", path);
+                failed = true;
+                continue;
+            }
+        };
+        match formatter::format_source(&source, edition) {
+            Err(errors) => {
+                for error in &errors {
+                    eprintln!("Error: {} at {}:{}:{}", error.message, path, error.span.line, error.span.column);
+                }
+                failed = true;
+            },
+            Ok(formatted) if formatted == source => {},
+            Ok(_) if check => {
+                println!("{} is not formatted", path);
+                failed = true;
+            },
+            Ok(formatted) => {
+                if fs::write(path, formatted).is_err() {
+                    eprintln!("Error: The file '{}' could not be written.", path);
+                    failed = true;
+                }
+            },
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+// Print every token and piece of trivia, one per line with its position, for
+// tools and for checking the lexer
+fn dump_tokens(input_path: &str, edition: Edition) {
+    let source = match fs::read_to_string(input_path) {
+        Ok(content) => content,
+        Err(_) => {
+            eprintln!("Error: The file '{}' could not be found or read.", input_path);
+            process::exit(1);
+        }
+    };
+    let (stream, errors) = lex_lossless(&source, edition);
+    for error in &errors {
+        eprintln!("Error: {} at {}:{}:{}", error.message, input_path, error.span.line, error.span.column);
+    }
+    for token in &stream.tokens {
+        for trivia in &token.leading {
+            println!("{}:{} {:?} {:?}", trivia.span.line, trivia.span.column, trivia.kind, trivia.text);
+        }
+        println!("{}:{} {:?} {:?}", token.span.line, token.span.column, token.token, token.text);
+    }
+    for trivia in &stream.trailing {
+        println!("{}:{} {:?} {:?}", trivia.span.line, trivia.span.column, trivia.kind, trivia.text);
+    }
+    if stream.to_source() != source {
+        eprintln!("Error: The tokens do not add up to the source of '{}'", input_path);
+        process::exit(1);
+    }
 }
 
-fn compile_file(input_path: &str, output_path: &str, output_dir: &str) {
+fn compile_file(input_path: &str, output_path: &str, output_dir: &str, overflow_checks: bool, edition: Edition, lint_levels: &LintLevels) {
     // Ensure the file has .spp extension
     if !input_path.ends_with(".spp") {
         eprintln!("Error: Input file must have .spp extension");
@@ -151,23 +298,20 @@ fn compile_file(input_path: &str, output_path: &str, output_dir: &str) {
     fs::create_dir_all(&temp_dir).expect("Failed to create temp directory");
     
     // Parse source code
-    let tokens = lex(&source);
-    let mut parser = Parser::new(tokens);
+    let (tokens, spans, docs, lex_errors) = lex(&source, edition);
+    for error in &lex_errors {
+        eprintln!("Error: {} at {}:{}:{}", error.message, input_path, error.span.line, error.span.column);
+    }
+    if !lex_errors.is_empty() {
+        process::exit(1);
+    }
+    let mut parser = Parser::new(tokens, spans, docs);
     let mut exprs = Vec::new();
 
-    // Parse all statements
+    // Parse all statements, after the #![...] attributes for the whole file
+    parser.parse_file_attributes();
     while !parser.is_finished() {
-        if let Some(expr) = parser.parse_const_declaration() {
-            exprs.push(expr);
-        } else if let Some(expr) = parser.parse_let_declaration() {
-            exprs.push(expr);
-        } else if let Some(expr) = parser.parse_assignment() {
-            exprs.push(expr);
-        } else if let Some(expr) = parser.parse_console_print_expr() {
-            exprs.push(expr);
-        } else if let Some(expr) = parser.parse_exit_expr() {
-            exprs.push(expr);
-        } else if let Some(expr) = parser.parse_if_statement() {
+        if let Some(expr) = parser.parse_statement() {
             exprs.push(expr);
         } else {
             eprintln!("Syntax error at token position {}", parser.pos());
@@ -176,7 +320,22 @@ fn compile_file(input_path: &str, output_path: &str, output_dir: &str) {
     }
 
     // Generate NASM code
-    let asm_code = codegen::generate_nasm(&exprs);
+    let options = codegen::CodegenOptions {
+        source_file: input_path.to_string(),
+        overflow_checks,
+    };
+    let (asm_code, types) = codegen::generate_nasm(&exprs, &options);
+    
+    // Lint the program once it is known to compile
+    let diagnostics = lint::check(&exprs, &types, parser.lint_attributes(), lint_levels);
+    for diagnostic in &diagnostics {
+        let kind = if diagnostic.level == LintLevel::Deny { "Error" } else { "Warning" };
+        eprintln!("{}: {} at {}:{}:{} [{}]", kind, diagnostic.message, input_path,
+                  diagnostic.span.line, diagnostic.span.column, diagnostic.lint);
+    }
+    if diagnostics.iter().any(|diagnostic| diagnostic.level == LintLevel::Deny) {
+        process::exit(1);
+    }
     let asm_path = temp_dir.join("output.asm");
     fs::write(&asm_path, asm_code).expect("Failed to write assembly file");
 
@@ -1,14 +1,42 @@
-use crate::ast::{Expr, BinOp};
-use crate::lexer::Token;
+use crate::ast::{Expr, BinOp, UnaryOp, Pattern, LintAttribute, LintLevel, ReadKind, Stream, FsOp, MathFn, TimeFn, RandomFn};
+use crate::lexer::{Docs, Span, Token, TemplatePart};
 
 pub struct Parser {
     tokens: Vec<Token>,
+    spans: Vec<Span>,
+    docs: Docs,
     pos: usize,
+    lint_attributes: Vec<LintAttribute>,
+    in_match_arm: bool, // Parsing a match arm that is a single statement
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, pos: 0 }
+    pub fn new(tokens: Vec<Token>, spans: Vec<Span>, docs: Docs) -> Self {
+        Parser { tokens, spans, docs, pos: 0, lint_attributes: Vec::new(), in_match_arm: false }
+    }
+
+    // Where the current token starts in the source
+    fn span(&self) -> Span {
+        self.spans.get(self.pos).copied().unwrap_or_default()
+    }
+
+    // The doc comment right before the current token. Doc comments before
+    // anything but a declaration are read as plain comments.
+    fn doc(&self) -> Option<String> {
+        self.docs.get(&self.pos).cloned()
+    }
+
+    // Consume the ';' that ends a statement. A statement that is a whole match
+    // arm may end with ',' instead, or with the '}' that closes the match.
+    fn statement_end(&mut self) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Token::Semicolon) => {},
+            Some(Token::Comma) if self.in_match_arm => {},
+            Some(Token::RBrace) if self.in_match_arm => return true,
+            _ => return false,
+        }
+        self.pos += 1;
+        true
     }
 
     pub fn is_finished(&self) -> bool {
@@ -19,14 +47,27 @@ impl Parser {
         self.pos
     }
 
+    // A reserved word where a name belongs is reported as such instead of as
+    // a syntax error
+    fn reject_keyword(&self, what: &str) {
+        if let Some(word) = self.tokens.get(self.pos).and_then(Token::keyword) {
+            let span = self.span();
+            panic!("'{}' is a reserved word and cannot be used as a {} name at line {}, column {}",
+                   word, what, span.line, span.column);
+        }
+    }
+
     pub fn parse_let_declaration(&mut self) -> Option<Expr> {
         // Check for let keyword
-        if self.is_finished() || !matches!(&self.tokens[self.pos], Token::Identifier(id) if id == "let") {
+        if self.is_finished() || self.tokens[self.pos] != Token::Let {
             return None;
         }
+        let doc = self.doc();
+        let span = self.span();
         self.pos += 1;
         
         // Get variable name
+        self.reject_keyword("variable");
         if self.is_finished() || !matches!(&self.tokens[self.pos], Token::Identifier(_)) {
             return None;
         }
@@ -43,20 +84,18 @@ impl Parser {
         self.pos += 1;
         
         // Parse the value 
-        let value = match self.parse_expression() {
-            Some(expr) => expr,
-            None => return None,
-        };
+        let value = self.parse_value()?;
         
         // Expect ';'
-        if self.is_finished() || self.tokens[self.pos] != Token::Semicolon {
+        if !self.statement_end() {
             return None;
         }
-        self.pos += 1;
         
         Some(Expr::Let {
             name,
             value: Box::new(value),
+            doc,
+            span,
         })
     }
 
@@ -76,45 +115,152 @@ impl Parser {
         };
         self.pos += 1;
         
-        // Check for '='
-        if self.is_finished() || self.tokens[self.pos] != Token::Assign {
-            // Not an assignment, backtrack
+        // Element and field targets: name[index] = value; name.field = value;
+        let mut target = Expr::Variable(name.clone());
+        while !self.is_finished() {
+            if self.tokens[self.pos] == Token::LBracket {
+                self.pos += 1; // Consume '['
+                let index = match self.parse_expression() {
+                    Some(index) => index,
+                    None => {
+                        self.pos = start_pos;
+                        return None;
+                    }
+                };
+                if self.is_finished() || self.tokens[self.pos] != Token::RBracket {
+                    self.pos = start_pos;
+                    return None;
+                }
+                self.pos += 1;
+                target = Expr::Index {
+                    object: Box::new(target),
+                    index: Box::new(index),
+                };
+            } else if self.tokens[self.pos] == Token::Dot {
+                // A method call is not an assignment target
+                let field = match (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)) {
+                    (Some(Token::Identifier(field)), next) if next != Some(&Token::LParen) => field.clone(),
+                    _ => {
+                        self.pos = start_pos;
+                        return None;
+                    }
+                };
+                self.pos += 2;
+                target = Expr::FieldAccess {
+                    object: Box::new(target),
+                    field,
+                };
+            } else {
+                break;
+            }
+        }
+        
+        // Check for '=', a compound operator like '+=' or '++'/'--'
+        let compound = match self.tokens.get(self.pos) {
+            Some(Token::Assign) => None,
+            Some(Token::PlusAssign | Token::PlusPlus) => Some(BinOp::Add),
+            Some(Token::MinusAssign | Token::MinusMinus) => Some(BinOp::Sub),
+            Some(Token::StarAssign) => Some(BinOp::Mul),
+            Some(Token::SlashAssign) => Some(BinOp::Div),
+            Some(Token::PercentAssign) => Some(BinOp::Mod),
+            _ => {
+                // Not an assignment, backtrack
+                self.pos = start_pos;
+                return None;
+            }
+        };
+        let is_step = matches!(self.tokens[self.pos], Token::PlusPlus | Token::MinusMinus);
+        let span = self.span();
+        self.pos += 1;
+        
+        // Parse the value, x++ and x-- step by one. Only a plain x = ... can
+        // take its value from a match.
+        let value = if is_step {
+            Expr::Number(1)
+        } else {
+            let value = match (&target, &compound) {
+                (Expr::Variable(_), None) => self.parse_value(),
+                _ => self.parse_expression(),
+            };
+            match value {
+                Some(expr) => expr,
+                None => {
+                    self.pos = start_pos;
+                    return None;
+                },
+            }
+        };
+        
+        // x op= value is x = x op value, the target is read before it is written
+        let value = match compound {
+            Some(op) => Expr::BinaryOp {
+                op,
+                left: Box::new(target.clone()),
+                right: Box::new(value),
+                span,
+            },
+            None => value,
+        };
+        
+        // Expect ';'
+        if !self.statement_end() {
             self.pos = start_pos;
             return None;
         }
-        self.pos += 1;
         
-        // Parse the value
-        let value = match self.parse_expression() {
-            Some(expr) => expr,
-            None => {
+        match target {
+            Expr::Index { object, index } => Some(Expr::IndexAssign {
+                object,
+                index,
+                value: Box::new(value),
+            }),
+            Expr::FieldAccess { object, field } => Some(Expr::FieldAssign {
+                object,
+                field,
+                value: Box::new(value),
+            }),
+            _ => Some(Expr::Assign {
+                name,
+                value: Box::new(value),
+            }),
+        }
+    }
+
+    // Parse a method call used as a statement: arr.push(x);
+    pub fn parse_expression_statement(&mut self) -> Option<Expr> {
+        let start_pos = self.pos;
+        
+        // Only method calls and input have side effects worth a statement
+        let expr = match self.parse_postfix_expr() {
+            Some(expr @ (Expr::MethodCall { .. } | Expr::ConsoleRead(_) | Expr::Fs { .. } | Expr::Time { .. }
+                        | Expr::Random { .. })) => expr,
+            _ => {
                 self.pos = start_pos;
                 return None;
-            },
+            }
         };
         
         // Expect ';'
-        if self.is_finished() || self.tokens[self.pos] != Token::Semicolon {
+        if !self.statement_end() {
             self.pos = start_pos;
             return None;
         }
-        self.pos += 1;
         
-        Some(Expr::Assign {
-            name,
-            value: Box::new(value),
-        })
+        Some(expr)
     }
 
     // Parse a constant declaration: const name = value;
     pub fn parse_const_declaration(&mut self) -> Option<Expr> {
         // Check for 'const' keyword
-        if self.is_finished() || !matches!(&self.tokens[self.pos], Token::Identifier(id) if id == "const") {
+        if self.is_finished() || self.tokens[self.pos] != Token::Const {
             return None;
         }
+        let doc = self.doc();
+        let span = self.span();
         self.pos += 1;
         
         // Get variable name
+        self.reject_keyword("constant");
         if self.is_finished() || !matches!(&self.tokens[self.pos], Token::Identifier(_)) {
             return None;
         }
@@ -131,76 +277,281 @@ impl Parser {
         self.pos += 1;
         
         // Parse the value (can be any expression)
-        let value = match self.parse_expression() {
-            Some(expr) => expr,
-            None => return None,
-        };
+        let value = self.parse_expression()?;
         
         // Expect ';'
-        if self.is_finished() || self.tokens[self.pos] != Token::Semicolon {
+        if !self.statement_end() {
             return None;
         }
-        self.pos += 1;
         
         Some(Expr::Const {
             name,
             value: Box::new(value),
+            doc,
+            span,
         })
     }
 
     // Parse a console.print statement: console.print(expr);
+    // Parse console output: console.print(expr); console.write(expr);
+    // console.error(expr); console.eprint(expr); console.printf(format, args...);
     pub fn parse_console_print_expr(&mut self) -> Option<Expr> {
-        // Check for 'console'
-        if self.is_finished() || !matches!(&self.tokens[self.pos], Token::Identifier(id) if id == "console") {
+        let start_pos = self.pos;
+        let span = self.span();
+        
+        let member = self.parse_module_member("console");
+        let expr = match member.as_deref() {
+            Some("printf") => {
+                // The format and its arguments, ')' included
+                let mut args = match self.parse_call_arguments() {
+                    Some(args) if !args.is_empty() => args,
+                    _ => {
+                        self.pos = start_pos;
+                        return None;
+                    }
+                };
+                let format = args.remove(0);
+                Expr::Printf { format: Box::new(format), args, span }
+            },
+            Some(name @ ("print" | "write" | "error" | "eprint")) => {
+                // Parse the expression to print
+                let value = match self.parse_expression() {
+                    Some(expr) => Box::new(expr),
+                    None => {
+                        self.pos = start_pos;
+                        return None;
+                    }
+                };
+                
+                // Expect ')'
+                if self.is_finished() || self.tokens[self.pos] != Token::RParen {
+                    self.pos = start_pos;
+                    return None;
+                }
+                self.pos += 1;
+                
+                match name {
+                    "print" => Expr::Print(value),
+                    "write" => Expr::ConsoleWrite { value, stream: Stream::Stdout, newline: false },
+                    _ => Expr::ConsoleWrite { value, stream: Stream::Stderr, newline: true },
+                }
+            },
+            _ => {
+                self.pos = start_pos;
+                return None;
+            }
+        };
+        
+        // Expect ';'
+        if !self.statement_end() {
+            self.pos = start_pos;
             return None;
         }
-        self.pos += 1;
         
-        // Expect '.'
-        if self.is_finished() || self.tokens[self.pos] != Token::Dot {
+        Some(expr)
+    }
+
+    // Parse console input: console.readLine(), console.readInt(), console.readAll()
+    fn parse_console_read(&mut self) -> Option<Expr> {
+        let start_pos = self.pos;
+        
+        let kind = match self.parse_module_member("console").as_deref() {
+            Some("readLine") => ReadKind::Line,
+            Some("readInt") => ReadKind::Int,
+            Some("readAll") => ReadKind::All,
+            _ => {
+                self.pos = start_pos;
+                return None;
+            }
+        };
+        
+        // Expect ')'
+        if self.is_finished() || self.tokens[self.pos] != Token::RParen {
+            self.pos = start_pos;
             return None;
         }
         self.pos += 1;
         
-        // Expect 'print'
-        if self.is_finished() || !matches!(&self.tokens[self.pos], Token::Identifier(id) if id == "print") {
+        Some(Expr::ConsoleRead(kind))
+    }
+
+    // Parse the start of a built-in module call such as 'console.name(' and
+    // return the member name. The position is left after '(' on success only.
+    fn parse_module_member(&mut self, module: &str) -> Option<String> {
+        let is_module = match self.tokens.get(self.pos) {
+            Some(Token::Identifier(id)) => id == module,
+            Some(token) => token.keyword() == Some(module), // console
+            None => false,
+        };
+        if !is_module {
             return None;
         }
-        self.pos += 1;
+        match (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2), self.tokens.get(self.pos + 3)) {
+            (Some(Token::Dot), Some(Token::Identifier(member)), Some(Token::LParen)) => {
+                let member = member.clone();
+                self.pos += 4;
+                Some(member)
+            },
+            _ => None,
+        }
+    }
+
+    // Parse process.args() and process.env(name)
+    fn parse_process_call(&mut self) -> Option<Expr> {
+        let start_pos = self.pos;
+        let span = self.span();
         
-        // Expect '('
-        if self.is_finished() || self.tokens[self.pos] != Token::LParen {
-            return None;
+        let member = self.parse_module_member("process")?;
+        let mut args = match self.parse_call_arguments() {
+            Some(args) => args,
+            None => {
+                self.pos = start_pos;
+                return None;
+            }
+        };
+        let count = match member.as_str() {
+            "args" => 0,
+            "env" => 1,
+            _ => {
+                self.pos = start_pos;
+                return None;
+            }
+        };
+        if args.len() != count {
+            panic!("process.{} takes {} argument(s) but {} were given at line {}, column {}",
+                   member, count, args.len(), span.line, span.column);
         }
-        self.pos += 1;
+        match member.as_str() {
+            "args" => Some(Expr::ProcessArgs),
+            _ => Some(Expr::ProcessEnv(Box::new(args.remove(0)))),
+        }
+    }
+
+    // Parse a file call: fs.read(path), fs.write(path, text), fs.append(path, text),
+    // fs.exists(path), fs.remove(path). Argument counts are checked by codegen.
+    fn parse_fs_call(&mut self) -> Option<Expr> {
+        let start_pos = self.pos;
+        let span = self.span();
         
-        // Parse the expression to print
-        let expr = match self.parse_expression() {
-            Some(expr) => expr,
-            None => return None,
+        let op = match self.parse_module_member("fs").as_deref() {
+            Some("read") => FsOp::Read,
+            Some("write") => FsOp::Write,
+            Some("append") => FsOp::Append,
+            Some("exists") => FsOp::Exists,
+            Some("remove") => FsOp::Remove,
+            _ => {
+                self.pos = start_pos;
+                return None;
+            }
         };
+        match self.parse_call_arguments() {
+            Some(args) => Some(Expr::Fs { op, args, span }),
+            None => {
+                self.pos = start_pos;
+                None
+            }
+        }
+    }
+
+    // Parse a math call such as math.sqrt(x), or the constants math.PI and
+    // math.E, which become float literals
+    fn parse_math(&mut self) -> Option<Expr> {
+        let start_pos = self.pos;
+        let span = self.span();
         
-        // Expect ')'
-        if self.is_finished() || self.tokens[self.pos] != Token::RParen {
+        if !matches!(self.tokens.get(self.pos), Some(Token::Identifier(id)) if id == "math")
+            || self.tokens.get(self.pos + 1) != Some(&Token::Dot) {
             return None;
         }
-        self.pos += 1;
+        match self.tokens.get(self.pos + 2) {
+            Some(Token::Identifier(name)) if name == "PI" => {
+                self.pos += 3;
+                return Some(Expr::Float(std::f64::consts::PI));
+            },
+            Some(Token::Identifier(name)) if name == "E" => {
+                self.pos += 3;
+                return Some(Expr::Float(std::f64::consts::E));
+            },
+            _ => {}
+        }
         
-        // Expect ';'
-        if self.is_finished() || self.tokens[self.pos] != Token::Semicolon {
-            return None;
+        let function = match self.parse_module_member("math").as_deref() {
+            Some("sqrt") => MathFn::Sqrt,
+            Some("pow") => MathFn::Pow,
+            Some("abs") => MathFn::Abs,
+            Some("floor") => MathFn::Floor,
+            Some("ceil") => MathFn::Ceil,
+            Some("round") => MathFn::Round,
+            Some("min") => MathFn::Min,
+            Some("max") => MathFn::Max,
+            Some("sin") => MathFn::Sin,
+            Some("cos") => MathFn::Cos,
+            Some("log") => MathFn::Log,
+            _ => {
+                self.pos = start_pos;
+                return None;
+            }
+        };
+        match self.parse_call_arguments() {
+            Some(args) => Some(Expr::Math { function, args, span }),
+            None => {
+                self.pos = start_pos;
+                None
+            }
         }
-        self.pos += 1;
+    }
+
+    // Parse a clock call: time.now(), time.sleep(ms)
+    fn parse_time_call(&mut self) -> Option<Expr> {
+        let start_pos = self.pos;
+        
+        let function = match self.parse_module_member("time").as_deref() {
+            Some("now") => TimeFn::Now,
+            Some("sleep") => TimeFn::Sleep,
+            _ => {
+                self.pos = start_pos;
+                return None;
+            }
+        };
+        match self.parse_call_arguments() {
+            Some(args) => Some(Expr::Time { function, args }),
+            None => {
+                self.pos = start_pos;
+                None
+            }
+        }
+    }
+
+    // Parse a random call: random.int(lo, hi), random.float(), random.seed(n)
+    fn parse_random_call(&mut self) -> Option<Expr> {
+        let start_pos = self.pos;
+        let span = self.span();
         
-        Some(Expr::Print(Box::new(expr)))
+        let function = match self.parse_module_member("random").as_deref() {
+            Some("int") => RandomFn::Int,
+            Some("float") => RandomFn::Float,
+            Some("seed") => RandomFn::Seed,
+            _ => {
+                self.pos = start_pos;
+                return None;
+            }
+        };
+        match self.parse_call_arguments() {
+            Some(args) => Some(Expr::Random { function, args, span }),
+            None => {
+                self.pos = start_pos;
+                None
+            }
+        }
     }
 
     // Parse an exit statement: exit(expr);
     pub fn parse_exit_expr(&mut self) -> Option<Expr> {
         // Check for 'exit'
-        if self.is_finished() || !matches!(&self.tokens[self.pos], Token::Identifier(id) if id == "exit") {
+        if self.is_finished() || self.tokens[self.pos] != Token::Exit {
             return None;
         }
+        let span = self.span();
         self.pos += 1;
         
         // Expect '('
@@ -210,10 +561,7 @@ impl Parser {
         self.pos += 1;
         
         // Parse the exit code
-        let expr = match self.parse_expression() {
-            Some(expr) => expr,
-            None => return None,
-        };
+        let expr = self.parse_expression()?;
         
         // Expect ')'
         if self.is_finished() || self.tokens[self.pos] != Token::RParen {
@@ -222,17 +570,105 @@ impl Parser {
         self.pos += 1;
         
         // Expect ';'
-        if self.is_finished() || self.tokens[self.pos] != Token::Semicolon {
+        if !self.statement_end() {
             return None;
         }
-        self.pos += 1;
         
-        Some(Expr::Exit(Box::new(expr)))
+        Some(Expr::Exit { code: Box::new(expr), span })
+    }
+
+    // Parse char.fromCode(n), the char with code point n
+    fn parse_char_call(&mut self) -> Option<Expr> {
+        let start_pos = self.pos;
+        let span = self.span();
+        
+        if self.parse_module_member("char").as_deref() != Some("fromCode") {
+            self.pos = start_pos;
+            return None;
+        }
+        match self.parse_call_arguments() {
+            Some(mut args) if args.len() == 1 => Some(Expr::CharFromCode { code: Box::new(args.remove(0)), span }),
+            Some(args) => panic!("char.fromCode takes 1 argument but {} were given at line {}, column {}",
+                                 args.len(), span.line, span.column),
+            None => {
+                self.pos = start_pos;
+                None
+            }
+        }
     }
 
     // Parse an expression
     pub fn parse_expression(&mut self) -> Option<Expr> {
-        self.parse_additive_expr()
+        self.parse_comparison_expr()
+    }
+
+    // Operator precedence, loosest first:
+    //   == != < > <= >=
+    //   |
+    //   ^
+    //   &
+    //   << >>
+    //   + -
+    //   * / %
+    //   unary - ~
+    //   ** (right-associative, binds tighter than a unary operator on its left)
+
+    // Parse comparison expressions: term (==|!=|<|>|<=|>=) term
+    fn parse_comparison_expr(&mut self) -> Option<Expr> {
+        self.parse_binary_level(Self::parse_bitwise_or_expr, &[
+            (Token::Equal, BinOp::Equal),
+            (Token::NotEqual, BinOp::NotEqual),
+            (Token::LessThan, BinOp::Lt),
+            (Token::GreaterThan, BinOp::Gt),
+            (Token::LessThanEqual, BinOp::Lte),
+            (Token::GreaterThanEqual, BinOp::Gte),
+        ])
+    }
+
+    fn parse_bitwise_or_expr(&mut self) -> Option<Expr> {
+        self.parse_binary_level(Self::parse_bitwise_xor_expr, &[(Token::Pipe, BinOp::BitOr)])
+    }
+
+    fn parse_bitwise_xor_expr(&mut self) -> Option<Expr> {
+        self.parse_binary_level(Self::parse_bitwise_and_expr, &[(Token::Caret, BinOp::BitXor)])
+    }
+
+    fn parse_bitwise_and_expr(&mut self) -> Option<Expr> {
+        self.parse_binary_level(Self::parse_shift_expr, &[(Token::Ampersand, BinOp::BitAnd)])
+    }
+
+    fn parse_shift_expr(&mut self) -> Option<Expr> {
+        self.parse_binary_level(Self::parse_additive_expr, &[
+            (Token::ShiftLeft, BinOp::Shl),
+            (Token::ShiftRight, BinOp::Shr),
+        ])
+    }
+
+    // Parse a left-associative chain of the given operators over `operand`
+    fn parse_binary_level(
+        &mut self,
+        operand: fn(&mut Self) -> Option<Expr>,
+        operators: &[(Token, BinOp)],
+    ) -> Option<Expr> {
+        let mut left = operand(self)?;
+
+        while let Some(token) = self.tokens.get(self.pos) {
+            let Some((_, op)) = operators.iter().find(|(t, _)| t == token) else {
+                break;
+            };
+            let op = op.clone();
+            let span = self.span();
+            self.pos += 1;
+            let right = operand(self)?;
+            left = Expr::BinaryOp {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+                span,
+            };
+        }
+
+        Some(left)
     }
 
     // Parse additive expressions: term (+|-) term
@@ -242,96 +678,28 @@ impl Parser {
         while !self.is_finished() {
             match &self.tokens[self.pos] {
                 Token::Plus => {
+                    let span = self.span();
                     self.pos += 1;
                     if let Some(right) = self.parse_multiplicative_expr() {
                         left = Expr::BinaryOp {
                             op: BinOp::Add,
                             left: Box::new(left),
                             right: Box::new(right),
+                            span,
                         };
                     } else {
                         return None;
                     }
                 },
                 Token::Minus => {
+                    let span = self.span();
                     self.pos += 1;
                     if let Some(right) = self.parse_multiplicative_expr() {
                         left = Expr::BinaryOp {
                             op: BinOp::Sub,
                             left: Box::new(left),
                             right: Box::new(right),
-                        };
-                    } else {
-                        return None;
-                    }
-                },
-                Token::Equal => {
-                    self.pos += 1;
-                    if let Some(right) = self.parse_multiplicative_expr() {
-                        left = Expr::BinaryOp {
-                            op: BinOp::Equal,
-                            left: Box::new(left),
-                            right: Box::new(right),
-                        };
-                    } else {
-                        return None;
-                    }
-                },
-                Token::NotEqual => {
-                    self.pos += 1;
-                    if let Some(right) = self.parse_multiplicative_expr() {
-                        left = Expr::BinaryOp {
-                            op: BinOp::NotEqual,
-                            left: Box::new(left),
-                            right: Box::new(right),
-                        };
-                    } else {
-                        return None;
-                    }
-                },
-                Token::LessThan => {
-                    self.pos += 1;
-                    if let Some(right) = self.parse_multiplicative_expr() {
-                        left = Expr::BinaryOp {
-                            op: BinOp::Lt,
-                            left: Box::new(left),
-                            right: Box::new(right),
-                        };
-                    } else {
-                        return None;
-                    }
-                },
-                Token::GreaterThan => {
-                    self.pos += 1;
-                    if let Some(right) = self.parse_multiplicative_expr() {
-                        left = Expr::BinaryOp {
-                            op: BinOp::Gt,
-                            left: Box::new(left),
-                            right: Box::new(right),
-                        };
-                    } else {
-                        return None;
-                    }
-                },
-                Token::LessThanEqual => {
-                    self.pos += 1;
-                    if let Some(right) = self.parse_multiplicative_expr() {
-                        left = Expr::BinaryOp {
-                            op: BinOp::Lte,
-                            left: Box::new(left),
-                            right: Box::new(right),
-                        };
-                    } else {
-                        return None;
-                    }
-                },
-                Token::GreaterThanEqual => {
-                    self.pos += 1;
-                    if let Some(right) = self.parse_multiplicative_expr() {
-                        left = Expr::BinaryOp {
-                            op: BinOp::Gte,
-                            left: Box::new(left),
-                            right: Box::new(right),
+                            span,
                         };
                     } else {
                         return None;
@@ -344,31 +712,49 @@ impl Parser {
         Some(left)
     }
 
-    // Parse multiplicative expressions: factor (*|/) factor
+    // Parse multiplicative expressions: factor (*|/|%) factor
     fn parse_multiplicative_expr(&mut self) -> Option<Expr> {
-        let mut left = self.parse_primary()?;
+        let mut left = self.parse_unary_expr()?;
         
         while !self.is_finished() {
             match &self.tokens[self.pos] {
                 Token::Star => {
+                    let span = self.span();
                     self.pos += 1;
-                    if let Some(right) = self.parse_primary() {
+                    if let Some(right) = self.parse_unary_expr() {
                         left = Expr::BinaryOp {
                             op: BinOp::Mul,
                             left: Box::new(left),
                             right: Box::new(right),
+                            span,
                         };
                     } else {
                         return None;
                     }
                 },
                 Token::Slash => {
+                    let span = self.span();
                     self.pos += 1;
-                    if let Some(right) = self.parse_primary() {
+                    if let Some(right) = self.parse_unary_expr() {
                         left = Expr::BinaryOp {
                             op: BinOp::Div,
                             left: Box::new(left),
                             right: Box::new(right),
+                            span,
+                        };
+                    } else {
+                        return None;
+                    }
+                },
+                Token::Percent => {
+                    let span = self.span();
+                    self.pos += 1;
+                    if let Some(right) = self.parse_unary_expr() {
+                        left = Expr::BinaryOp {
+                            op: BinOp::Mod,
+                            left: Box::new(left),
+                            right: Box::new(right),
+                            span,
                         };
                     } else {
                         return None;
@@ -381,6 +767,150 @@ impl Parser {
         Some(left)
     }
 
+    // Parse prefix operators: -x, ~x
+    fn parse_unary_expr(&mut self) -> Option<Expr> {
+        let op = match self.tokens.get(self.pos) {
+            Some(Token::Minus) => UnaryOp::Neg,
+            Some(Token::Tilde) => UnaryOp::BitNot,
+            _ => return self.parse_power_expr(),
+        };
+        let span = self.span();
+        self.pos += 1;
+        // The smallest integer is written as the negation of a literal one
+        // past the maximum, which is only allowed here
+        if op == UnaryOp::Neg
+            && let Some(Token::Number(n)) = self.tokens.get(self.pos)
+            && !is_float_literal(n)
+            && !matches!(self.tokens.get(self.pos + 1), Some(Token::StarStar | Token::LBracket | Token::Dot))
+        {
+            let (value, max) = int_literal(n);
+            if value == max + 1 {
+                self.pos += 1;
+                return Some(Expr::Number((value as i64).wrapping_neg()));
+            }
+        }
+        let operand = self.parse_unary_expr()?;
+
+        Some(Expr::UnaryOp {
+            op,
+            operand: Box::new(operand),
+            span,
+        })
+    }
+
+    // Parse power expressions: base ** exponent, grouping to the right
+    fn parse_power_expr(&mut self) -> Option<Expr> {
+        let base = self.parse_postfix_expr()?;
+
+        if self.tokens.get(self.pos) == Some(&Token::StarStar) {
+            let span = self.span();
+            self.pos += 1;
+            let exponent = self.parse_unary_expr()?;
+            return Some(Expr::BinaryOp {
+                op: BinOp::Pow,
+                left: Box::new(base),
+                right: Box::new(exponent),
+                span,
+            });
+        }
+
+        Some(base)
+    }
+
+    // Parse member calls, field accesses and indexing chained onto a primary
+    // expression: expr.method(args), expr.field, expr[index]
+    fn parse_postfix_expr(&mut self) -> Option<Expr> {
+        let mut expr = self.parse_primary()?;
+        
+        loop {
+            if self.is_finished() {
+                break;
+            }
+            
+            if self.tokens[self.pos] == Token::LBracket {
+                self.pos += 1; // Consume '['
+                let index = self.parse_expression()?;
+                
+                // Expect ']'
+                if self.is_finished() || self.tokens[self.pos] != Token::RBracket {
+                    return None;
+                }
+                self.pos += 1;
+                
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    index: Box::new(index),
+                };
+                continue;
+            }
+            
+            if self.tokens[self.pos] != Token::Dot {
+                break;
+            }
+            self.pos += 1; // Consume '.'
+            
+            // Expect member name
+            let member = match self.tokens.get(self.pos) {
+                Some(Token::Identifier(id)) => id.clone(),
+                _ => return None,
+            };
+            self.pos += 1;
+            
+            // Without '(' it is a field access
+            if self.is_finished() || self.tokens[self.pos] != Token::LParen {
+                expr = Expr::FieldAccess {
+                    object: Box::new(expr),
+                    field: member,
+                };
+                continue;
+            }
+            self.pos += 1;
+            let method = member;
+            
+            let args = self.parse_call_arguments()?;
+            
+            expr = Expr::MethodCall {
+                object: Box::new(expr),
+                method,
+                args,
+            };
+        }
+        
+        Some(expr)
+    }
+
+    // Parse a comma separated argument list, the opening '(' already consumed
+    fn parse_call_arguments(&mut self) -> Option<Vec<Expr>> {
+        let mut args = Vec::new();
+        
+        // Handle empty argument list
+        if !self.is_finished() && self.tokens[self.pos] == Token::RParen {
+            self.pos += 1; // Consume ')'
+            return Some(args);
+        }
+        
+        loop {
+            args.push(self.parse_expression()?);
+            
+            if self.is_finished() {
+                return None; // Unexpected end of input
+            }
+            
+            if self.tokens[self.pos] == Token::RParen {
+                self.pos += 1; // Consume ')'
+                break;
+            }
+            
+            if self.tokens[self.pos] != Token::Comma {
+                return None; // Expected ',' or ')'
+            }
+            
+            self.pos += 1; // Consume ','
+        }
+        
+        Some(args)
+    }
+
     // Parse primary expressions: literal, variable, or parenthesized expression
     fn parse_primary(&mut self) -> Option<Expr> {
         if self.is_finished() {
@@ -401,6 +931,14 @@ impl Parser {
             return Some(expr);
         }
         
+        if let Some(expr) = self.parse_template_string() {
+            return Some(expr);
+        }
+        
+        if let Some(expr) = self.parse_char_literal() {
+            return Some(expr);
+        }
+        
         if let Some(expr) = self.parse_bool_literal() {
             return Some(expr);
         }
@@ -413,6 +951,46 @@ impl Parser {
             return Some(expr);
         }
         
+        if let Some(expr) = self.parse_map_literal() {
+            return Some(expr);
+        }
+        
+        if let Some(expr) = self.parse_struct_literal() {
+            return Some(expr);
+        }
+        
+        if let Some(expr) = self.parse_console_read() {
+            return Some(expr);
+        }
+        
+        if let Some(expr) = self.parse_process_call() {
+            return Some(expr);
+        }
+        
+        if let Some(expr) = self.parse_fs_call() {
+            return Some(expr);
+        }
+        
+        if let Some(expr) = self.parse_math() {
+            return Some(expr);
+        }
+        
+        if let Some(expr) = self.parse_time_call() {
+            return Some(expr);
+        }
+        
+        if let Some(expr) = self.parse_random_call() {
+            return Some(expr);
+        }
+        
+        if let Some(expr) = self.parse_char_call() {
+            return Some(expr);
+        }
+        
+        if let Some(expr) = self.parse_variant_constructor() {
+            return Some(expr);
+        }
+        
         if let Some(expr) = self.parse_variable() {
             return Some(expr);
         }
@@ -440,12 +1018,15 @@ impl Parser {
         }
         
         match &self.tokens[self.pos] {
-            Token::Number(n) if !n.contains('.') => {
-                self.pos += 1;
-                match n.parse::<i32>() {
-                    Ok(val) => Some(Expr::Number(val)),
-                    Err(_) => None
+            Token::Number(n) if !is_float_literal(n) => {
+                let (value, max) = int_literal(n);
+                if value > max {
+                    let span = self.span();
+                    panic!("Integer literal {} does not fit in {} bits, only its negation does at line {}, column {}",
+                           n, if max == i32::MAX as u64 { 32 } else { 64 }, span.line, span.column);
                 }
+                self.pos += 1;
+                Some(Expr::Number(value as i64))
             },
             _ => None
         }
@@ -458,7 +1039,7 @@ impl Parser {
         }
         
         match &self.tokens[self.pos] {
-            Token::Number(n) if n.contains('.') => {
+            Token::Number(n) if is_float_literal(n) => {
                 self.pos += 1;
                 match n.parse::<f64>() {
                     Ok(val) => Some(Expr::Float(val)),
@@ -484,6 +1065,55 @@ impl Parser {
         }
     }
 
+    // Parse a char literal
+    pub fn parse_char_literal(&mut self) -> Option<Expr> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Char(c)) => {
+                self.pos += 1;
+                Some(Expr::Char(*c))
+            },
+            _ => None
+        }
+    }
+
+    // Parse a template string. `a${x}b` becomes "a" + x + "b", so constant
+    // parts fold and the rest goes through string concatenation.
+    pub fn parse_template_string(&mut self) -> Option<Expr> {
+        let parts = match self.tokens.get(self.pos) {
+            Some(Token::Template(parts)) => parts.clone(),
+            _ => return None,
+        };
+        let span = self.span();
+        self.pos += 1;
+        
+        // Starting from a string keeps `${a}${b}` from adding two numbers
+        let mut parts = parts.into_iter().peekable();
+        let mut template = match parts.next_if(|part| matches!(part, TemplatePart::Text(_))) {
+            Some(TemplatePart::Text(text)) => Expr::StringLiteral(text),
+            _ => Expr::StringLiteral(String::new()),
+        };
+        for part in parts {
+            let piece = match part {
+                TemplatePart::Text(text) => Expr::StringLiteral(text),
+                TemplatePart::Code(tokens, spans) => {
+                    let start = spans.first().copied().unwrap_or(span);
+                    let mut parser = Parser::new(tokens, spans, Docs::new());
+                    match parser.parse_expression() {
+                        Some(expr) if parser.is_finished() => expr,
+                        _ => panic!("Invalid expression in template string at line {}, column {}", start.line, start.column),
+                    }
+                },
+            };
+            template = Expr::BinaryOp {
+                op: BinOp::Add,
+                left: Box::new(template),
+                right: Box::new(piece),
+                span,
+            };
+        }
+        Some(template)
+    }
+
     // Parse a boolean literal
     pub fn parse_bool_literal(&mut self) -> Option<Expr> {
         if self.is_finished() {
@@ -491,11 +1121,11 @@ impl Parser {
         }
         
         match &self.tokens[self.pos] {
-            Token::Identifier(id) if id == "true" => {
+            Token::True => {
                 self.pos += 1;
                 Some(Expr::Boolean(true))
             },
-            Token::Identifier(id) if id == "false" => {
+            Token::False => {
                 self.pos += 1;
                 Some(Expr::Boolean(false))
             },
@@ -510,7 +1140,7 @@ impl Parser {
         }
         
         match &self.tokens[self.pos] {
-            Token::Identifier(id) if id == "null" => {
+            Token::Null => {
                 self.pos += 1;
                 Some(Expr::Null)
             },
@@ -541,7 +1171,7 @@ impl Parser {
             
             // Parse element
             if let Some(element) = self.parse_expression() {
-                elements.push(Box::new(element));
+                elements.push(element);
             } else {
                 return None; // Expected an expression
             }
@@ -566,6 +1196,399 @@ impl Parser {
         Some(Expr::Array(elements))
     }
 
+    // Parse a map literal: { key: value, ... }. Only reached where an
+    // expression is expected, so it never competes with block braces.
+    pub fn parse_map_literal(&mut self) -> Option<Expr> {
+        if self.is_finished() || self.tokens[self.pos] != Token::LBrace {
+            return None;
+        }
+        
+        let start_pos = self.pos;
+        self.pos += 1; // Consume '{'
+        let mut entries = Vec::new();
+        
+        // Handle empty map
+        if !self.is_finished() && self.tokens[self.pos] == Token::RBrace {
+            self.pos += 1; // Consume '}'
+            return Some(Expr::Map(entries));
+        }
+        
+        loop {
+            let key = self.parse_expression();
+            
+            // Expect ':'
+            let key = match key {
+                Some(key) if !self.is_finished() && self.tokens[self.pos] == Token::Colon => key,
+                _ => {
+                    self.pos = start_pos;
+                    return None;
+                }
+            };
+            self.pos += 1;
+            
+            let value = self.parse_expression()?;
+            entries.push((Box::new(key), Box::new(value)));
+            
+            // Check for comma or closing brace
+            if self.is_finished() {
+                return None; // Unexpected end of input
+            }
+            
+            if self.tokens[self.pos] == Token::RBrace {
+                self.pos += 1; // Consume '}'
+                break;
+            }
+            
+            if self.tokens[self.pos] != Token::Comma {
+                return None; // Expected ',' or '}'
+            }
+            
+            self.pos += 1; // Consume ','
+        }
+        
+        Some(Expr::Map(entries))
+    }
+
+    // Parse a struct literal: Name { field: value, ... }
+    pub fn parse_struct_literal(&mut self) -> Option<Expr> {
+        // Needs 'Name {' followed by '}' or 'field:'
+        let name = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Identifier(name)), Some(Token::LBrace)) => name.clone(),
+            _ => return None,
+        };
+        match (self.tokens.get(self.pos + 2), self.tokens.get(self.pos + 3)) {
+            (Some(Token::RBrace), _) | (Some(Token::Identifier(_)), Some(Token::Colon)) => {},
+            _ => return None,
+        }
+        self.pos += 2; // Consume name and '{'
+        let mut fields = Vec::new();
+        
+        // Handle empty struct
+        if self.tokens[self.pos] == Token::RBrace {
+            self.pos += 1; // Consume '}'
+            return Some(Expr::StructLiteral { name, fields });
+        }
+        
+        loop {
+            // Expect 'field:'
+            let field = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+                (Some(Token::Identifier(field)), Some(Token::Colon)) => field.clone(),
+                _ => return None,
+            };
+            self.pos += 2;
+            
+            let value = self.parse_expression()?;
+            fields.push((field, Box::new(value)));
+            
+            // Check for comma or closing brace
+            if self.is_finished() {
+                return None; // Unexpected end of input
+            }
+            
+            if self.tokens[self.pos] == Token::RBrace {
+                self.pos += 1; // Consume '}'
+                break;
+            }
+            
+            if self.tokens[self.pos] != Token::Comma {
+                return None; // Expected ',' or '}'
+            }
+            
+            self.pos += 1; // Consume ','
+        }
+        
+        Some(Expr::StructLiteral { name, fields })
+    }
+
+    // Parse a struct declaration: struct Name { field: type, ... }
+    pub fn parse_struct_declaration(&mut self) -> Option<Expr> {
+        // Check for 'struct' keyword
+        if self.is_finished() || self.tokens[self.pos] != Token::Struct {
+            return None;
+        }
+        let start_pos = self.pos;
+        let doc = self.doc();
+        self.pos += 1;
+        
+        // Expect struct name and '{'
+        self.reject_keyword("struct");
+        let name = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Identifier(name)), Some(Token::LBrace)) => name.clone(),
+            _ => {
+                self.pos = start_pos;
+                return None;
+            }
+        };
+        self.pos += 2;
+        
+        let mut fields = Vec::new();
+        while !self.is_finished() && self.tokens[self.pos] != Token::RBrace {
+            // Expect 'field: type'
+            self.reject_keyword("field");
+            match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)) {
+                (Some(Token::Identifier(field)), Some(Token::Colon), Some(Token::Identifier(type_name))) => {
+                    fields.push((field.clone(), type_name.clone()));
+                },
+                _ => return None,
+            }
+            self.pos += 3;
+            
+            // Fields are separated by commas
+            if !self.is_finished() && self.tokens[self.pos] == Token::Comma {
+                self.pos += 1;
+            } else if self.is_finished() || self.tokens[self.pos] != Token::RBrace {
+                return None;
+            }
+        }
+        
+        // Expect '}'
+        if self.is_finished() {
+            return None;
+        }
+        self.pos += 1;
+        
+        Some(Expr::StructDecl { name, fields, doc })
+    }
+
+    // Parse an enum declaration: enum Name { Unit, WithPayload(type, ...), ... }
+    pub fn parse_enum_declaration(&mut self) -> Option<Expr> {
+        // Check for 'enum' keyword
+        if self.is_finished() || self.tokens[self.pos] != Token::Enum {
+            return None;
+        }
+        let start_pos = self.pos;
+        let doc = self.doc();
+        self.pos += 1;
+        
+        // Expect enum name and '{'
+        self.reject_keyword("enum");
+        let name = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Identifier(name)), Some(Token::LBrace)) => name.clone(),
+            _ => {
+                self.pos = start_pos;
+                return None;
+            }
+        };
+        self.pos += 2;
+        
+        let mut variants = Vec::new();
+        while !self.is_finished() && self.tokens[self.pos] != Token::RBrace {
+            self.reject_keyword("variant");
+            let variant = match &self.tokens[self.pos] {
+                Token::Identifier(variant) => variant.clone(),
+                _ => return None,
+            };
+            self.pos += 1;
+            
+            // Optional payload types
+            let mut payload = Vec::new();
+            if !self.is_finished() && self.tokens[self.pos] == Token::LParen {
+                self.pos += 1;
+                loop {
+                    match self.tokens.get(self.pos) {
+                        Some(Token::Identifier(type_name)) => payload.push(type_name.clone()),
+                        _ => return None,
+                    }
+                    self.pos += 1;
+                    match self.tokens.get(self.pos) {
+                        Some(Token::Comma) => self.pos += 1,
+                        Some(Token::RParen) => {
+                            self.pos += 1;
+                            break;
+                        },
+                        _ => return None,
+                    }
+                }
+            }
+            variants.push((variant, payload));
+            
+            // Variants are separated by commas
+            if !self.is_finished() && self.tokens[self.pos] == Token::Comma {
+                self.pos += 1;
+            } else if self.is_finished() || self.tokens[self.pos] != Token::RBrace {
+                return None;
+            }
+        }
+        
+        // Expect '}'
+        if self.is_finished() {
+            return None;
+        }
+        self.pos += 1;
+        
+        Some(Expr::EnumDecl { name, variants, doc })
+    }
+
+    // Parse an enum variant with a payload: Name(args)
+    pub fn parse_variant_constructor(&mut self) -> Option<Expr> {
+        let name = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Identifier(name)), Some(Token::LParen)) => name.clone(),
+            _ => return None,
+        };
+        let start_pos = self.pos;
+        self.pos += 2; // Consume name and '('
+        
+        match self.parse_call_arguments() {
+            Some(args) => Some(Expr::Variant { name, args }),
+            None => {
+                self.pos = start_pos;
+                None
+            }
+        }
+    }
+
+    // Parse a match pattern: _, a literal, or a variant with optional bindings
+    fn parse_pattern(&mut self) -> Option<Pattern> {
+        if self.is_finished() {
+            return None;
+        }
+        
+        let name = match &self.tokens[self.pos] {
+            Token::Identifier(id) if id == "_" => {
+                self.pos += 1;
+                return Some(Pattern::Wildcard);
+            },
+            Token::Identifier(id) => id.clone(),
+            _ => {
+                let literal = self.parse_float_literal()
+                    .or_else(|| self.parse_number_literal())
+                    .or_else(|| self.parse_string_literal())
+                    .or_else(|| self.parse_char_literal())
+                    .or_else(|| self.parse_bool_literal())
+                    .or_else(|| self.parse_null_literal())?;
+                return Some(Pattern::Literal(Box::new(literal)));
+            },
+        };
+        self.pos += 1;
+        
+        // Optional payload bindings
+        let mut bindings = Vec::new();
+        if !self.is_finished() && self.tokens[self.pos] == Token::LParen {
+            self.pos += 1;
+            loop {
+                self.reject_keyword("binding");
+                match self.tokens.get(self.pos) {
+                    Some(Token::Identifier(id)) if id == "_" => bindings.push(None),
+                    Some(Token::Identifier(id)) => bindings.push(Some(id.clone())),
+                    _ => return None,
+                }
+                self.pos += 1;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Comma) => self.pos += 1,
+                    Some(Token::RParen) => {
+                        self.pos += 1;
+                        break;
+                    },
+                    _ => return None,
+                }
+            }
+        }
+        
+        Some(Pattern::Variant { name, bindings })
+    }
+
+    // Parse the start of a match up to its opening brace: match (value) {
+    fn parse_match_head(&mut self) -> Option<(Expr, Span)> {
+        // Check for 'match' followed by '('
+        if self.tokens.get(self.pos) != Some(&Token::Match) || self.tokens.get(self.pos + 1) != Some(&Token::LParen) {
+            return None;
+        }
+        let span = self.span();
+        self.pos += 2;
+        
+        let value = self.parse_expression()?;
+        
+        // Expect ')' and '{'
+        if self.tokens.get(self.pos) != Some(&Token::RParen) || self.tokens.get(self.pos + 1) != Some(&Token::LBrace) {
+            return None;
+        }
+        self.pos += 2;
+        Some((value, span))
+    }
+
+    // Parse a match statement:
+    // match (value) { pattern => statement, pattern => { statements }, ... }
+    pub fn parse_match_statement(&mut self) -> Option<Expr> {
+        let (value, span) = self.parse_match_head()?;
+        
+        let mut arms = Vec::new();
+        while !self.is_finished() && self.tokens[self.pos] != Token::RBrace {
+            let pattern = self.parse_pattern()?;
+            
+            // Expect '=>'
+            if self.is_finished() || self.tokens[self.pos] != Token::FatArrow {
+                return None;
+            }
+            self.pos += 1;
+            
+            // The arm body is a block or a single statement, which may end
+            // with a comma instead of a semicolon
+            let body = if !self.is_finished() && self.tokens[self.pos] == Token::LBrace {
+                self.parse_block()?
+            } else {
+                let outer = std::mem::replace(&mut self.in_match_arm, true);
+                let statement = self.parse_statement();
+                self.in_match_arm = outer;
+                vec![statement?]
+            };
+            arms.push((pattern, body));
+            
+            // Blocks may be followed by a comma too
+            if !self.is_finished() && self.tokens[self.pos] == Token::Comma {
+                self.pos += 1;
+            }
+        }
+        
+        // Expect '}'
+        if self.is_finished() {
+            return None;
+        }
+        self.pos += 1;
+        
+        Some(Expr::Match { value: Box::new(value), arms, span })
+    }
+
+    // Parse a match that gives a value, the value of a let or an assignment:
+    // match (value) { pattern => expression, pattern => expression, ... }
+    pub fn parse_match_value(&mut self) -> Option<Expr> {
+        let (value, span) = self.parse_match_head()?;
+        
+        let mut arms = Vec::new();
+        while !self.is_finished() && self.tokens[self.pos] != Token::RBrace {
+            let pattern = self.parse_pattern()?;
+            
+            // Expect '=>'
+            if self.is_finished() || self.tokens[self.pos] != Token::FatArrow {
+                return None;
+            }
+            self.pos += 1;
+            arms.push((pattern, Box::new(self.parse_expression()?)));
+            
+            // Arms are separated by commas, the last one may have one too
+            match self.tokens.get(self.pos) {
+                Some(Token::Comma) => self.pos += 1,
+                Some(Token::RBrace) => {},
+                _ => return None,
+            }
+        }
+        
+        // Expect '}'
+        if self.is_finished() {
+            return None;
+        }
+        self.pos += 1;
+        
+        Some(Expr::MatchValue { value: Box::new(value), arms, span })
+    }
+
+    // The value of a let or an assignment, which may be a match
+    fn parse_value(&mut self) -> Option<Expr> {
+        if self.tokens.get(self.pos) == Some(&Token::Match) {
+            return self.parse_match_value();
+        }
+        self.parse_expression()
+    }
+
     // Parse a variable reference
     pub fn parse_variable(&mut self) -> Option<Expr> {
         if self.is_finished() {
@@ -584,9 +1607,10 @@ impl Parser {
 	// If-Statements
 	pub fn parse_if_statement(&mut self) -> Option<Expr> {
 		// Check for "if"
-		if self.is_finished() || !matches!(&self.tokens[self.pos], Token::Identifier(id) if id == "if") {
+		if self.is_finished() || self.tokens[self.pos] != Token::If {
 			return None;
 		}
+		let mut spans = vec![self.span()];
 		self.pos += 1;
 		
 		// expect '('
@@ -595,11 +1619,42 @@ impl Parser {
 		}
 		self.pos += 1;
 
-		// Parse condition
-		let condition = match self.parse_expression() {
-			Some(expr) => expr,
-			None => return None,
-		};
+		// Parse condition and then-block
+		let mut branches = vec![self.parse_condition_and_block()?];
+
+		// Parse else if chain and else-block, kept flat
+		let mut else_statements = None;
+		while !self.is_finished() && self.tokens[self.pos] == Token::Else {
+			self.pos += 1;
+
+			if !self.is_finished() && self.tokens[self.pos] == Token::If {
+				spans.push(self.span());
+				self.pos += 1;
+				
+				// expect '('
+				if self.is_finished() || self.tokens[self.pos] != Token::LParen {
+					return None;
+				}
+				self.pos += 1;
+				
+				branches.push(self.parse_condition_and_block()?);
+			} else {
+				else_statements = Some(self.parse_block()?);
+				break;
+			}
+		}
+		
+		Some(Expr::If {
+			branches,
+			else_branch: else_statements,
+			spans,
+		})
+	}
+
+	// Parse 'condition) { statements }' of an if or else if, the opening '('
+	// already consumed
+	fn parse_condition_and_block(&mut self) -> Option<(Box<Expr>, Vec<Expr>)> {
+		let condition = self.parse_expression()?;
 		
 		// expect ')'
 		if self.is_finished() || self.tokens[self.pos] != Token::RParen {
@@ -607,69 +1662,145 @@ impl Parser {
 		}
 		self.pos += 1;
 
+		let statements = self.parse_block()?;
+		Some((Box::new(condition), statements))
+	}
+
+	// Parse a block: '{' statements '}'
+	fn parse_block(&mut self) -> Option<Vec<Expr>> {
 		// expect '{'
 		if self.is_finished() || self.tokens[self.pos] != Token::LBrace {
 			return None;
 		}
 		self.pos += 1;
 
-		// Parse then-block
-		let mut then_statements = Vec::new();
+		// Statements inside the block end with ';' even in a match arm
+		let outer = std::mem::replace(&mut self.in_match_arm, false);
+		let mut statements = Vec::new();
 		while !self.is_finished() && self.tokens[self.pos] != Token::RBrace {
 			if let Some(stmt) = self.parse_statement() {
-				then_statements.push(Box::new(stmt));
+				statements.push(stmt);
 			} else {
+				self.in_match_arm = outer;
 				return None; // Invalid statement in block
 			}
 		}
+		self.in_match_arm = outer;
 
 		// expect '}'
 		if self.is_finished() || self.tokens[self.pos] != Token::RBrace {
 			return None;
 		}
 		self.pos += 1;
+		
+		Some(statements)
+	}
 
-		// Parse else-block
-		let mut else_statements = None;
-		if !self.is_finished() && matches!(&self.tokens[self.pos], Token::Identifier(id) if id == "else") {
-			self.pos += 1;
+	// Parse a statement with the lint attributes in front of it, which are
+	// kept with the source range of the statement
+	pub fn parse_statement(&mut self) -> Option<Expr> {
+		let attributes = self.parse_attributes(Token::Hash);
+		let start = self.pos;
+		let Some(stmt) = self.parse_plain_statement() else {
+			if let Some(&(_, _, span)) = attributes.last() {
+				panic!("Attribute at line {}, column {} is not followed by a statement", span.line, span.column);
+			}
+			return None;
+		};
+		for (level, lints, span) in attributes {
+			self.lint_attributes.push(LintAttribute {
+				level,
+				lints,
+				span,
+				start: self.spans[start],
+				end: self.spans[self.pos - 1],
+			});
+		}
+		Some(stmt)
+	}
+
+	// #![...] attributes at the start of the file set lint levels for all of it
+	pub fn parse_file_attributes(&mut self) {
+		for (level, lints, span) in self.parse_attributes(Token::HashBang) {
+			self.lint_attributes.push(LintAttribute {
+				level,
+				lints,
+				span,
+				start: Span::default(),
+				end: Span { line: usize::MAX, column: usize::MAX },
+			});
+		}
+	}
 
-			// expect '{'
-			if self.is_finished() || self.tokens[self.pos] != Token::LBrace {
-				return None;
+	pub fn lint_attributes(&self) -> &[LintAttribute] {
+		&self.lint_attributes
+	}
+
+	// Parse attributes starting with `marker` (# or #!): #[allow(name, ...)],
+	// #[warn(...)] or #[deny(...)]. Lint names are checked by the lint pass.
+	fn parse_attributes(&mut self, marker: Token) -> Vec<(LintLevel, Vec<String>, Span)> {
+		let mut attributes = Vec::new();
+		while self.tokens.get(self.pos) == Some(&marker) {
+			let span = self.span();
+			let malformed = || -> ! {
+				panic!("Malformed attribute at line {}, column {}, expected #[allow(name)], #[warn(name)] or #[deny(name)]",
+					   span.line, span.column)
+			};
+			let level = match (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)) {
+				(Some(Token::LBracket), Some(Token::Identifier(level))) => match level.as_str() {
+					"allow" => LintLevel::Allow,
+					"warn" => LintLevel::Warn,
+					"deny" => LintLevel::Deny,
+					_ => panic!("Unknown lint level '{}' at line {}, column {}, expected allow, warn or deny",
+								level, span.line, span.column),
+				},
+				_ => malformed(),
+			};
+			self.pos += 3;
+			if self.tokens.get(self.pos) != Some(&Token::LParen) {
+				malformed();
 			}
 			self.pos += 1;
 			
-			let mut statements = Vec::new();
-			while !self.is_finished() && self.tokens[self.pos] != Token::RBrace {
-				if let Some(stmt) = self.parse_statement() {
-					statements.push(Box::new(stmt));
-				} else {
-					return None; // Invalid statement in block
+			// Lint names separated by commas
+			let mut lints = Vec::new();
+			loop {
+				match self.tokens.get(self.pos) {
+					Some(Token::Identifier(name)) => lints.push(name.clone()),
+					_ => malformed(),
 				}
+				self.pos += 1;
+				if self.tokens.get(self.pos) != Some(&Token::Comma) {
+					break;
+				}
+				self.pos += 1;
 			}
-
-			// expect '}'
-			if self.is_finished() || self.tokens[self.pos] != Token::RBrace {
-				return None;
+			if self.tokens.get(self.pos) != Some(&Token::RParen) || self.tokens.get(self.pos + 1) != Some(&Token::RBracket) {
+				malformed();
 			}
-			self.pos += 1;
-			
-			else_statements = Some(statements);
+			self.pos += 2;
+			attributes.push((level, lints, span));
 		}
 		
-		Some(Expr::If {
-			condition: Box::new(condition),
-			then_branch: then_statements,
-			else_branch: else_statements,
-		})
+		if self.tokens.get(self.pos) == Some(&Token::HashBang) {
+			let span = self.span();
+			panic!("#![...] attributes belong at the start of the file, at line {}, column {}; use #[...] before a statement",
+				   span.line, span.column);
+		}
+		attributes
 	}
 
-	// This helper method parses a single statement
-	pub fn parse_statement(&mut self) -> Option<Expr> {
+	// Parse a single statement
+	fn parse_plain_statement(&mut self) -> Option<Expr> {
 		if let Some(stmt) = self.parse_const_declaration() {
 			return Some(stmt);
 		}
+		if let Some(stmt) = self.parse_struct_declaration() {
+			return Some(stmt);
+		}
+		if let Some(stmt) = self.parse_enum_declaration() {
+			return Some(stmt);
+		}
 		if let Some(stmt) = self.parse_let_declaration() {
 			return Some(stmt);
 		}
@@ -682,10 +1813,31 @@ impl Parser {
 		if let Some(stmt) = self.parse_exit_expr() {
 			return Some(stmt);
 		}
+		if let Some(stmt) = self.parse_expression_statement() {
+			return Some(stmt);
+		}
 		// Also if-statements could be nested
 		if let Some(stmt) = self.parse_if_statement() {
 			return Some(stmt);
 		}
+		if let Some(stmt) = self.parse_match_statement() {
+			return Some(stmt);
+		}
 		None
 	}
-}
\ No newline at end of file
+}
+
+// Number tokens hold a float when they have a decimal point or an exponent
+fn is_float_literal(number: &str) -> bool {
+    number.contains(['.', 'e', 'E'])
+}
+
+// The value of an integer literal token and the largest one its suffix
+// allows. The lexer lets through values one past that for negation.
+fn int_literal(number: &str) -> (u64, u64) {
+    let (digits, max) = match number.strip_suffix("i32") {
+        Some(digits) => (digits, i32::MAX as u64),
+        None => (number.strip_suffix("i64").unwrap_or(number), i64::MAX as u64),
+    };
+    (digits.parse().expect("integer literal out of range"), max)
+}
@@ -0,0 +1,3746 @@
+// Runtime support routines linked into every generated program.
+// Strings handled here are UTF-8 byte sequences with their byte length in the
+// 8 bytes before the first byte. The length is what counts, so a string may
+// hold NUL bytes; a NUL terminator after the last byte is kept for the system
+// calls that take paths. Every routine preserves all registers except RAX (and RDX where it returns a
+// type tag), so they can be called in the middle of a string concatenation
+// (RDI/RCX live).
+
+// Type tags stored next to every value held by a runtime container
+pub const TAG_NULL: u8 = 0;
+pub const TAG_NUMBER: u8 = 1;
+pub const TAG_STRING: u8 = 2;
+pub const TAG_BOOLEAN: u8 = 3;
+pub const TAG_FLOAT: u8 = 4;
+pub const TAG_ARRAY: u8 = 5;
+pub const TAG_MAP: u8 = 6;
+pub const TAG_STRUCT: u8 = 7;
+pub const TAG_ENUM: u8 = 8;
+pub const TAG_CHAR: u8 = 9;
+
+// Exit codes of the runtime traps. 136 is what the shell reported when a
+// division by zero still killed the program with SIGFPE.
+pub const EXIT_DIVISION_BY_ZERO: u8 = 136;
+pub const EXIT_OVERFLOW: u8 = 134;
+
+// Exit code when an operator is given a value typed at runtime that it cannot
+// use, such as a null from console.readInt()
+pub const EXIT_OPERAND_TYPE: u8 = 1;
+
+// A string in the data section laid out like a runtime string: the length,
+// then the label on the bytes and the terminator
+pub fn string_data(label: &str, text: &str) -> String {
+    format!("    dq {}\n{} db {}\n", text.len(), label, nasm_bytes(text))
+}
+
+// Operands of a db directive holding `text` and its terminator. Quotes,
+// control characters and the UTF-8 bytes of non-ASCII characters are written
+// as byte values, NASM strings have no escapes.
+fn nasm_bytes(text: &str) -> String {
+    let mut operands = Vec::new();
+    let mut run = String::new();
+    for byte in text.bytes() {
+        if byte == b'"' || !(0x20..0x7f).contains(&byte) {
+            if !run.is_empty() {
+                operands.push(format!("\"{}\"", run));
+                run.clear();
+            }
+            operands.push(byte.to_string());
+        } else {
+            run.push(byte as char);
+        }
+    }
+    if !run.is_empty() {
+        operands.push(format!("\"{}\"", run));
+    }
+    operands.push("0".to_string());
+    operands.join(", ")
+}
+
+// Emit the bump allocator used by all runtime values. The heap starts at the
+// program break and moves the break up with brk as it fills, so it is only
+// limited by the memory the system gives the program.
+pub fn emit_heap(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str("heap_ptr dq 0\n");
+    data_section.push_str("heap_end dq 0\n");
+    data_section.push_str("heap_exhausted_msg db \"runtime error: out of memory\", 10\n");
+    data_section.push_str("heap_exhausted_msg_len equ $ - heap_exhausted_msg\n");
+
+    text_section.push_str(r#"heap_alloc:
+    ; Allocate RAX bytes, returns the block in RAX
+    push rbx
+    push rcx
+    mov rbx, [heap_ptr]
+    test rbx, rbx
+    jz .start
+.bump:
+    lea rcx, [rax+rbx+7] ; Keep blocks 8 byte aligned
+    and rcx, -8
+    cmp rcx, [heap_end]
+    ja .grow
+    mov [heap_ptr], rcx
+    mov rax, rbx
+    pop rcx
+    pop rbx
+    ret
+.start:
+    ; The first allocation finds the program break
+    push rax
+    push rdi
+    push r11
+    mov rax, 12          ; sys_brk
+    xor rdi, rdi         ; Fails, giving the current break
+    syscall
+    mov [heap_end], rax
+    add rax, 7
+    and rax, -8
+    mov [heap_ptr], rax
+    mov rbx, rax
+    pop r11
+    pop rdi
+    pop rax
+    jmp .bump
+.grow:
+    ; Move the break past the end in RCX, rounded up to whole MiB. Memory
+    ; given by brk is zeroed like the rest of the heap
+    push rax
+    push rdi
+    push r11
+    lea rdi, [rcx+1048575]
+    and rdi, -1048576
+    push rdi
+    mov rax, 12          ; sys_brk
+    syscall
+    pop rdi
+    cmp rax, rdi
+    jb heap_exhausted    ; The break did not move
+    mov [heap_end], rax
+    pop r11
+    pop rdi
+    pop rax
+    jmp .bump
+
+str_alloc:
+    ; New string of RAX bytes, returned in RAX with its length and terminator
+    ; set; the caller writes the bytes
+    push rbx
+    mov rbx, rax
+    add rax, 9           ; The length before and the terminator after
+    call heap_alloc
+    mov [rax], rbx
+    add rax, 8
+    mov byte [rax+rbx], 0
+    pop rbx
+    ret
+
+str_finish:
+    ; End the string in RAX at RDI, for a string written into room that may
+    ; be left over: sets its length and the terminator
+    push rdi
+    mov byte [rdi], 0
+    sub rdi, rax
+    mov [rax-8], rdi
+    pop rdi
+    ret
+
+heap_exhausted:
+    mov rax, 1           ; sys_write
+    mov rdi, 2           ; stderr
+    mov rsi, heap_exhausted_msg
+    mov rdx, heap_exhausted_msg_len
+    syscall
+    mov rax, 60          ; sys_exit
+    mov rdi, 1
+    syscall
+
+"#);
+}
+
+// Emit the string built-ins (len, substring, indexOf, ...) and their helpers
+pub fn emit_string_helpers(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str(&string_data("true_value", "true"));
+    data_section.push_str(&string_data("false_value", "false"));
+    data_section.push_str(&string_data("null_value", "null"));
+    data_section.push_str("newline_char db 10\n");
+
+    text_section.push_str(r#"str_byte_len:
+    ; Length of the string in RSI in bytes, returned in RAX
+    mov rax, [rsi-8]
+    ret
+
+str_len:
+    ; Length of the string in RSI in characters, returned in RAX
+    push rbx
+    push rcx
+    push rsi
+    mov rcx, [rsi-8]     ; Bytes left
+    xor rax, rax
+.loop:
+    test rcx, rcx
+    jz .done
+    mov bl, [rsi]
+    and bl, 0xC0
+    cmp bl, 0x80         ; Continuation bytes do not start a character
+    je .next
+    inc rax
+.next:
+    inc rsi
+    dec rcx
+    jmp .loop
+.done:
+    pop rsi
+    pop rcx
+    pop rbx
+    ret
+
+str_char_offset:
+    ; Pointer to character RAX of the string in RSI (clamped to its end)
+    push rbx
+    push rcx
+    push rdx
+    mov rcx, rax         ; Characters left to skip
+    mov rdx, [rsi-8]
+    add rdx, rsi         ; End
+    mov rax, rsi
+.loop:
+    cmp rax, rdx
+    jae .done
+    mov bl, [rax]
+    and bl, 0xC0
+    cmp bl, 0x80         ; Continuation bytes belong to the current character
+    je .next
+    test rcx, rcx
+    jz .done
+    dec rcx
+.next:
+    inc rax
+    jmp .loop
+.done:
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+str_copy_range:
+    ; Copy the bytes from RSI up to RDX into a new string, returned in RAX
+    push rcx
+    push rsi
+    push rdi
+    mov rax, rdx
+    sub rax, rsi
+    mov rcx, rax
+    call str_alloc
+    mov rdi, rax
+    rep movsb
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+str_substring:
+    ; Characters RAX up to RDX of the string in RSI, returned in RAX
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    mov rbx, rax         ; Start index
+    mov rcx, rdx         ; End index
+    call str_len
+    ; Clamp both indices into [0, length]
+    test rbx, rbx
+    jns .start_positive
+    xor rbx, rbx
+.start_positive:
+    cmp rbx, rax
+    jle .start_clamped
+    mov rbx, rax
+.start_clamped:
+    test rcx, rcx
+    jns .end_positive
+    xor rcx, rcx
+.end_positive:
+    cmp rcx, rax
+    jle .end_clamped
+    mov rcx, rax
+.end_clamped:
+    ; Swapped indices select the same range
+    cmp rbx, rcx
+    jle .ordered
+    xchg rbx, rcx
+.ordered:
+    mov rax, rcx
+    call str_char_offset
+    mov rdx, rax         ; End pointer
+    mov rax, rbx
+    call str_char_offset
+    mov rsi, rax         ; Start pointer
+    call str_copy_range
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+str_index_of:
+    ; Character index of the string in RDX inside the string in RSI, or -1
+    push rbx
+    push rcx
+    push rdi
+    push r8
+    push r9
+    push r10
+    xor r8, r8           ; Character index of the current position
+    mov rdi, rsi         ; Current position
+    mov r9, [rdx-8]      ; Needle length
+    mov r10, [rsi-8]
+    add r10, rsi         ; End
+.scan:
+    mov rcx, r10
+    sub rcx, rdi
+    cmp rcx, r9
+    jb .not_found        ; Too little left for the needle
+    xor rcx, rcx
+.compare:
+    cmp rcx, r9
+    je .found            ; Whole needle matched
+    mov bl, [rdx+rcx]
+    cmp bl, [rdi+rcx]
+    jne .advance
+    inc rcx
+    jmp .compare
+.advance:
+    mov bl, [rdi]
+    inc rdi
+    and bl, 0xC0
+    cmp bl, 0x80
+    je .scan
+    inc r8
+    jmp .scan
+.found:
+    mov rax, r8
+    jmp .done
+.not_found:
+    mov rax, -1
+.done:
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rcx
+    pop rbx
+    ret
+
+str_starts_with:
+    ; 1 when the string in RSI starts with the string in RDX, else 0
+    push rbx
+    push rcx
+    mov rcx, [rdx-8]
+    cmp rcx, [rsi-8]
+    ja .no               ; The prefix is longer than the string
+    xor rcx, rcx
+.loop:
+    cmp rcx, [rdx-8]
+    je .yes
+    mov bl, [rdx+rcx]
+    cmp bl, [rsi+rcx]
+    jne .no
+    inc rcx
+    jmp .loop
+.yes:
+    mov rax, 1
+    jmp .done
+.no:
+    xor rax, rax
+.done:
+    pop rcx
+    pop rbx
+    ret
+
+str_to_upper:
+    ; Copy of the string in RSI with ASCII letters upper-cased
+    push rbx
+    push rcx
+    push rdi
+    push rsi
+    call str_byte_len
+    mov rcx, rax
+    call str_alloc
+    mov rdi, rax
+.loop:
+    test rcx, rcx
+    jz .done
+    mov bl, [rsi]
+    cmp bl, 'a'
+    jb .store
+    cmp bl, 'z'
+    ja .store
+    sub bl, 32
+.store:
+    mov [rdi], bl
+    inc rsi
+    inc rdi
+    dec rcx
+    jmp .loop
+.done:
+    pop rsi
+    pop rdi
+    pop rcx
+    pop rbx
+    ret
+
+str_to_lower:
+    ; Copy of the string in RSI with ASCII letters lower-cased
+    push rbx
+    push rcx
+    push rdi
+    push rsi
+    call str_byte_len
+    mov rcx, rax
+    call str_alloc
+    mov rdi, rax
+.loop:
+    test rcx, rcx
+    jz .done
+    mov bl, [rsi]
+    cmp bl, 'A'
+    jb .store
+    cmp bl, 'Z'
+    ja .store
+    add bl, 32
+.store:
+    mov [rdi], bl
+    inc rsi
+    inc rdi
+    dec rcx
+    jmp .loop
+.done:
+    pop rsi
+    pop rdi
+    pop rcx
+    pop rbx
+    ret
+
+byte_is_space:
+    ; ZF set when the byte in BL is ASCII whitespace
+    cmp bl, ' '
+    je .done
+    cmp bl, 9            ; \t
+    je .done
+    cmp bl, 10           ; \n
+    je .done
+    cmp bl, 12           ; \f
+    je .done
+    cmp bl, 13           ; \r
+.done:
+    ret
+
+str_trim:
+    ; Copy of the string in RSI without surrounding whitespace
+    push rbx
+    push rdx
+    push rsi
+    call str_byte_len
+    lea rdx, [rsi+rax]   ; End
+.skip_leading:
+    cmp rsi, rdx
+    jae .copy
+    mov bl, [rsi]
+    call byte_is_space
+    jne .skip_trailing
+    inc rsi
+    jmp .skip_leading
+.skip_trailing:
+    ; Stops at the first byte kept at the latest
+    mov bl, [rdx-1]
+    call byte_is_space
+    jne .copy
+    dec rdx
+    jmp .skip_trailing
+.copy:
+    call str_copy_range
+    pop rsi
+    pop rdx
+    pop rbx
+    ret
+
+str_concat:
+    ; New string holding RSI followed by RDX, returned in RAX
+    push rcx
+    push rdi
+    push rsi
+    mov rax, [rsi-8]
+    add rax, [rdx-8]
+    call str_alloc
+    mov rdi, rax
+    mov rcx, [rsi-8]
+    rep movsb
+    mov rsi, rdx
+    mov rcx, [rdx-8]
+    rep movsb
+    pop rsi
+    pop rdi
+    pop rcx
+    ret
+
+str_replace:
+    ; Copy of the string in RSI with every RDX replaced by R8
+    push rbx
+    push rcx
+    push rdi
+    push rsi
+    push r9
+    push r10
+    push r11
+    push r12
+    mov rbx, rsi         ; Source string
+    mov r9, [rdx-8]      ; Pattern length
+    mov r10, [r8-8]      ; Replacement length
+    mov r11, [rsi-8]     ; Result length, adjusted for every match
+    lea r12, [rsi+r11]   ; End of the source
+    test r9, r9
+    jz .allocate         ; An empty pattern leaves the string unchanged
+    mov rcx, rbx
+.count:
+    cmp rcx, r12
+    jae .allocate
+    call .match_at
+    jne .count_next
+    add r11, r10
+    sub r11, r9
+    add rcx, r9
+    jmp .count
+.count_next:
+    inc rcx
+    jmp .count
+.allocate:
+    mov rax, r11
+    call str_alloc
+    mov rdi, rax         ; Write position, RAX keeps the result
+    mov rcx, rbx         ; Scan position
+.copy:
+    cmp rcx, r12
+    jae .done
+    test r9, r9
+    jz .copy_byte
+    call .match_at
+    jne .copy_byte
+    push rcx
+    mov rsi, r8
+    mov rcx, r10
+    rep movsb
+    pop rcx
+    add rcx, r9
+    jmp .copy
+.copy_byte:
+    mov bl, [rcx]
+    mov [rdi], bl
+    inc rcx
+    inc rdi
+    jmp .copy
+.done:
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop rsi
+    pop rdi
+    pop rcx
+    pop rbx
+    ret
+.match_at:
+    ; ZF set when the pattern in RDX occurs at RCX, before the end in R12
+    push rax
+    push rbx
+    mov rax, r12
+    sub rax, rcx
+    cmp rax, r9
+    jb .match_done       ; Too little left, ZF is clear
+    xor rax, rax
+.match_loop:
+    cmp rax, r9
+    je .match_done
+    mov bl, [rdx+rax]
+    cmp bl, [rcx+rax]
+    jne .match_done
+    inc rax
+    jmp .match_loop
+.match_done:
+    pop rbx
+    pop rax
+    ret
+
+str_from_int:
+    ; Decimal representation of RAX as a new string
+    push rbx
+    push rcx
+    push rdi
+    mov rbx, rax
+    mov rax, 20          ; Enough for any 64-bit value
+    call str_alloc
+    push rax
+    mov rdi, rax
+    mov rax, rbx
+    xor rcx, rcx
+    call append_number
+    pop rax
+    call str_finish
+    pop rdi
+    pop rcx
+    pop rbx
+    ret
+
+str_from_bool:
+    ; "true" or "false" for the boolean in RAX
+    test rax, rax
+    mov rax, false_value
+    jz .done
+    mov rax, true_value
+.done:
+    ret
+
+print_string:
+    ; Print the string in RSI followed by a newline
+    call str_byte_len
+    mov rdx, rax
+    mov rax, 1           ; sys_write
+    mov rdi, 1           ; stdout
+    syscall
+    mov rax, 1           ; sys_write
+    mov rdi, 1           ; stdout
+    mov rsi, newline_char
+    mov rdx, 1
+    syscall
+    ret
+
+"#);
+}
+
+// Emit the helpers behind console.write, console.error and console.printf
+pub fn emit_output_helpers(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str(&string_data("format_empty", ""));
+    data_section.push_str("format_error_msg db \"runtime error: format string does not match its arguments\", 10\n");
+    data_section.push_str("format_error_msg_len equ $ - format_error_msg\n");
+
+    text_section.push_str(r#"write_string:
+    ; Write the string in RSI to the file descriptor in RDI
+    push rcx
+    push rdx
+    push r11
+    call str_byte_len
+    mov rdx, rax
+    mov rax, 1           ; sys_write
+    syscall
+    pop r11
+    pop rdx
+    pop rcx
+    ret
+
+write_line:
+    ; Write the string in RSI followed by a newline to the file descriptor in RDI
+    call write_string
+    push rsi
+    mov rsi, newline_char
+    push rcx
+    push rdx
+    push r11
+    mov rdx, 1
+    mov rax, 1           ; sys_write
+    syscall
+    pop r11
+    pop rdx
+    pop rcx
+    pop rsi
+    ret
+
+str_fixed_decimals:
+    ; Number text in RSI rounded to RBX decimals, returned in RAX. Text that
+    ; is not a number is returned unchanged
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push r8
+    mov rax, rsi
+    xor r8, r8           ; Set when negative
+    cmp byte [rsi], '-'
+    jne .check
+    mov r8, 1
+    inc rsi
+.check:
+    movzx rcx, byte [rsi]
+    sub rcx, '0'
+    cmp rcx, 9
+    ja .done             ; Not a number, RAX still holds the text
+    call str_byte_len
+    lea rax, [rax+rbx+4] ; Sign, carry digit, dot and terminator
+    call heap_alloc
+    lea rdi, [rax+2]     ; Digits start after room for the sign and a carry
+    mov rdx, rdi
+.integer:
+    mov cl, [rsi]
+    cmp cl, '0'
+    jb .integer_done
+    cmp cl, '9'
+    ja .integer_done
+    mov [rdi], cl
+    inc rsi
+    inc rdi
+    jmp .integer
+.integer_done:
+    cmp cl, '.'
+    jne .fraction_start
+    inc rsi              ; Skip the source dot
+.fraction_start:
+    test rbx, rbx
+    jz .round
+    mov byte [rdi], '.'
+    inc rdi
+.fraction:
+    test rbx, rbx
+    jz .round
+    mov cl, [rsi]
+    cmp cl, '0'
+    jb .pad
+    cmp cl, '9'
+    ja .pad
+    inc rsi
+    jmp .store
+.pad:
+    mov cl, '0'          ; The source ran out of digits
+.store:
+    mov [rdi], cl
+    inc rdi
+    dec rbx
+    jmp .fraction
+.round:
+    mov byte [rdi], 0
+    mov cl, [rsi]        ; First dropped digit
+    cmp cl, '5'
+    jb .sign
+    cmp cl, '9'
+    ja .sign
+    mov rcx, rdi
+.carry:
+    dec rcx
+    cmp rcx, rdx
+    jb .new_digit
+    cmp byte [rcx], '.'
+    je .carry
+    cmp byte [rcx], '9'
+    jne .increment
+    mov byte [rcx], '0'
+    jmp .carry
+.increment:
+    inc byte [rcx]
+    jmp .sign
+.new_digit:
+    dec rdx              ; Every digit was a 9
+    mov byte [rdx], '1'
+.sign:
+    test r8, r8
+    jz .result
+    dec rdx
+    mov byte [rdx], '-'
+.result:
+    ; The text starts where the sign or a carry left it, copy it out
+    mov rsi, rdx
+    mov rdx, rdi
+    call str_copy_range
+.done:
+    pop r8
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+str_format:
+    ; Fill the placeholders of the format string in RSI with the array of
+    ; values in RDX, the text is returned in RAX. {} takes the next value,
+    ; {:.N} the next number with N decimals, {{ and }} are literal braces
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    mov r8, rdx          ; Values
+    xor r9, r9           ; Next value
+    mov r10, format_empty ; Text so far
+    mov r11, rsi         ; Cursor
+    mov r12, rsi         ; Start of the text not copied yet
+    mov r13, [rsi-8]
+    add r13, rsi         ; End, a brace read past it finds the terminator
+.next:
+    cmp r11, r13
+    jae .finish
+    mov bl, [r11]
+    cmp bl, '{'
+    je .open
+    cmp bl, '}'
+    je .close
+    inc r11
+    jmp .next
+.close:
+    cmp byte [r11+1], '}'
+    jne format_error
+    call .flush
+    lea r12, [r11+1]     ; Keep one brace
+    add r11, 2
+    jmp .next
+.open:
+    call .flush
+    cmp byte [r11+1], '{'
+    jne .placeholder
+    lea r12, [r11+1]
+    add r11, 2
+    jmp .next
+.placeholder:
+    mov rbx, -1          ; No precision
+    add r11, 1
+    cmp byte [r11], '}'
+    je .value
+    cmp byte [r11], ':'
+    jne format_error
+    cmp byte [r11+1], '.'
+    jne format_error
+    add r11, 2
+    xor rbx, rbx
+    cmp byte [r11], '}'
+    je format_error      ; {:.} has no digits
+.precision:
+    movzx rcx, byte [r11]
+    cmp rcx, '}'
+    je .value
+    sub rcx, '0'
+    cmp rcx, 9
+    ja format_error
+    imul rbx, rbx, 10
+    add rbx, rcx
+    inc r11
+    jmp .precision
+.value:
+    inc r11              ; Past '}'
+    mov r12, r11
+    cmp r9, [r8]
+    jae format_error     ; More placeholders than values
+    mov rcx, r9
+    shl rcx, 4
+    add rcx, [r8+16]
+    mov rax, [rcx+8]
+    mov rdx, [rcx]
+    inc r9
+    call value_to_string
+    test rbx, rbx
+    js .append
+    mov rsi, rax
+    call str_fixed_decimals
+.append:
+    mov rdx, rax
+    mov rsi, r10
+    call str_concat
+    mov r10, rax
+    jmp .next
+.finish:
+    call .flush
+    cmp r9, [r8]
+    jne format_error     ; Values left over
+    mov rax, r10
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+.flush:
+    ; Append the text from R12 up to the cursor
+    mov rsi, r12
+    mov rdx, r11
+    call str_copy_range
+    mov rdx, rax
+    mov rsi, r10
+    call str_concat
+    mov r10, rax
+    ret
+
+format_error:
+    mov rax, 1           ; sys_write
+    mov rdi, 2           ; stderr
+    mov rsi, format_error_msg
+    mov rdx, format_error_msg_len
+    syscall
+    mov rax, 60          ; sys_exit
+    mov rdi, 1
+    syscall
+
+"#);
+}
+
+// Emit the growable array helpers. An array is a pointer to a header
+// [length, capacity, data]; data holds 16 byte elements [tag, payload].
+pub fn emit_array_helpers(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str(&string_data("array_close_value", "]"));
+    data_section.push_str("index_error_msg db \"runtime error: array index out of range\", 10\n");
+    data_section.push_str("index_error_msg_len equ $ - index_error_msg\n");
+
+    text_section.push_str(r#"array_new:
+    ; New empty array with room for RAX elements, returned in RAX
+    push rbx
+    push rcx
+    mov rcx, rax
+    test rcx, rcx
+    jnz .sized
+    mov rcx, 4           ; Minimum capacity
+.sized:
+    mov rax, 24
+    call heap_alloc
+    mov rbx, rax
+    mov qword [rbx], 0   ; Length
+    mov [rbx+8], rcx     ; Capacity
+    mov rax, rcx
+    shl rax, 4
+    call heap_alloc
+    mov [rbx+16], rax    ; Data
+    mov rax, rbx
+    pop rcx
+    pop rbx
+    ret
+
+array_push:
+    ; Append the value RAX with tag RDX to the array in RSI, returns the new length
+    push rbx
+    push rcx
+    push rdi
+    push r8
+    mov r8, rax          ; Payload
+    mov rcx, [rsi]       ; Length
+    cmp rcx, [rsi+8]
+    jb .store
+    ; Full: double the capacity and move the elements over
+    mov rax, [rsi+8]
+    shl rax, 5
+    call heap_alloc
+    mov rdi, rax
+    mov rbx, [rsi+16]
+    push rcx
+    shl rcx, 1           ; Two qwords per element
+.copy:
+    test rcx, rcx
+    jz .copied
+    dec rcx
+    mov rax, [rbx+rcx*8]
+    mov [rdi+rcx*8], rax
+    jmp .copy
+.copied:
+    pop rcx
+    mov [rsi+16], rdi
+    mov rax, [rsi+8]
+    shl rax, 1
+    mov [rsi+8], rax
+.store:
+    mov rbx, [rsi+16]
+    mov rax, rcx
+    shl rax, 4
+    mov [rbx+rax], rdx
+    mov [rbx+rax+8], r8
+    inc rcx
+    mov [rsi], rcx
+    mov rax, rcx
+    pop r8
+    pop rdi
+    pop rcx
+    pop rbx
+    ret
+
+array_get:
+    ; Element RAX of the array in RSI: payload in RAX, tag in RDX; null when
+    ; the index is out of range
+    cmp rax, [rsi]
+    jae .missing         ; Unsigned compare also rejects negative indices
+    shl rax, 4
+    add rax, [rsi+16]
+    mov rdx, [rax]
+    mov rax, [rax+8]
+    ret
+.missing:
+    xor rax, rax
+    xor rdx, rdx
+    ret
+
+array_set:
+    ; Store the value R8 with tag RDX at index RAX of the array in RSI,
+    ; returns the array. Writing outside the array is a runtime error.
+    cmp rax, [rsi]
+    jae .out_of_range
+    shl rax, 4
+    add rax, [rsi+16]
+    mov [rax], rdx
+    mov [rax+8], r8
+    mov rax, rsi
+    ret
+.out_of_range:
+    mov rax, 1           ; sys_write
+    mov rdi, 2           ; stderr
+    mov rsi, index_error_msg
+    mov rdx, index_error_msg_len
+    syscall
+    mov rax, 60          ; sys_exit
+    mov rdi, 1
+    syscall
+
+array_pop:
+    ; Remove the last element of the array in RSI: payload in RAX, tag in RDX
+    push rbx
+    mov rbx, [rsi]
+    test rbx, rbx
+    jz .empty            ; Popping an empty array gives null
+    dec rbx
+    mov [rsi], rbx
+    shl rbx, 4
+    add rbx, [rsi+16]
+    mov rdx, [rbx]
+    mov rax, [rbx+8]
+    pop rbx
+    ret
+.empty:
+    xor rdx, rdx
+    xor rax, rax
+    pop rbx
+    ret
+
+array_slice:
+    ; Elements RAX up to RDX of the array in RSI as a new array; negative
+    ; indices count from the end
+    push rbx
+    push rcx
+    push rdx
+    push rdi
+    push r8
+    push r9
+    mov rcx, [rsi]       ; Length
+    test rax, rax
+    jns .start_positive
+    add rax, rcx
+    jns .start_positive
+    xor rax, rax
+.start_positive:
+    cmp rax, rcx
+    jle .start_done
+    mov rax, rcx
+.start_done:
+    mov rbx, rax         ; Start index
+    mov rax, rdx
+    test rax, rax
+    jns .end_positive
+    add rax, rcx
+    jns .end_positive
+    xor rax, rax
+.end_positive:
+    cmp rax, rcx
+    jle .end_done
+    mov rax, rcx
+.end_done:
+    mov r8, rax          ; End index
+    sub rax, rbx
+    jns .sized
+    xor rax, rax         ; Start past the end gives an empty array
+.sized:
+    call array_new
+    mov rdi, rax
+    mov r9, [rsi+16]
+.copy:
+    cmp rbx, r8
+    jge .done
+    mov rax, rbx
+    shl rax, 4
+    mov rdx, [r9+rax]
+    mov rax, [r9+rax+8]
+    push rsi
+    mov rsi, rdi
+    call array_push
+    pop rsi
+    inc rbx
+    jmp .copy
+.done:
+    mov rax, rdi
+    pop r9
+    pop r8
+    pop rdi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+value_compare:
+    ; Compare the elements at RSI and RDI: -1, 0 or 1 in RAX. Ints and floats
+    ; compare by value, strings by text and other values of different types
+    ; by their tag
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    mov rax, [rsi]
+    mov rbx, [rdi]
+    cmp rax, rbx
+    jne .mixed
+    cmp rax, 2           ; String
+    je .text
+    cmp rax, 4           ; Float (kept as its text)
+    je .numbers
+    mov rax, [rsi+8]
+    mov rbx, [rdi+8]
+    cmp rax, rbx
+    jmp .ordered
+.mixed:
+    cmp rax, 1           ; Int
+    je .left_number
+    cmp rax, 4
+    jne .by_tag
+.left_number:
+    cmp rbx, 1
+    je .numbers
+    cmp rbx, 4
+    je .numbers
+.by_tag:
+    cmp rax, rbx
+.ordered:
+    jl .less
+    jg .greater
+.equal:
+    xor rax, rax
+    jmp .done
+.less:
+    mov rax, -1
+    jmp .done
+.greater:
+    mov rax, 1
+    jmp .done
+.numbers:
+    ; Both are ints or floats, compare them as doubles
+    push rdx
+    sub rsp, 24
+    movsd [rsp], xmm0
+    movsd [rsp+8], xmm1
+    mov rax, [rdi+8]
+    mov rdx, [rdi]
+    call value_to_double
+    movsd [rsp+16], xmm0 ; str_to_float uses XMM1, keep the right one here
+    mov rax, [rsi+8]
+    mov rdx, [rsi]
+    call value_to_double
+    xor rax, rax
+    ucomisd xmm0, [rsp+16]
+    je .numbers_done     ; Equal, or NaN
+    mov rax, -1
+    jb .numbers_done
+    mov rax, 1
+.numbers_done:
+    movsd xmm0, [rsp]
+    movsd xmm1, [rsp+8]
+    add rsp, 24
+    pop rdx
+    jmp .done
+.text:
+    mov rsi, [rsi+8]
+    mov rdi, [rdi+8]
+    mov rcx, [rsi-8]     ; Bytes left on the left
+    mov rdx, [rdi-8]     ; And on the right
+.text_loop:
+    test rcx, rcx
+    jz .left_ended
+    test rdx, rdx
+    jz .greater          ; The right text is a prefix of the left one
+    mov al, [rsi]
+    mov bl, [rdi]
+    cmp al, bl
+    jb .less
+    ja .greater
+    inc rsi
+    inc rdi
+    dec rcx
+    dec rdx
+    jmp .text_loop
+.left_ended:
+    test rdx, rdx
+    jz .equal
+    jmp .less
+.done:
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+array_contains:
+    ; 1 when the array in RSI holds the value RAX with tag RDX, else 0
+    push rbx
+    push rcx
+    push rsi
+    push rdi
+    push rax
+    push rdx
+    mov rdi, rsp         ; Needle element [tag, payload]
+    mov rcx, [rsi]
+    mov rbx, [rsi+16]
+.loop:
+    test rcx, rcx
+    jz .missing
+    mov rsi, rbx
+    call value_compare
+    test rax, rax
+    jz .found
+    add rbx, 16
+    dec rcx
+    jmp .loop
+.found:
+    mov rax, 1
+    jmp .done
+.missing:
+    xor rax, rax
+.done:
+    add rsp, 16
+    pop rdi
+    pop rsi
+    pop rcx
+    pop rbx
+    ret
+
+array_reverse:
+    ; Reverse the array in RSI in place, returns the array
+    push rbx
+    push rcx
+    push rdx
+    push rdi
+    mov rbx, [rsi+16]    ; Front element
+    mov rcx, [rsi]
+    test rcx, rcx
+    jz .done
+    dec rcx
+    shl rcx, 4
+    lea rdi, [rbx+rcx]   ; Back element
+.loop:
+    cmp rbx, rdi
+    jae .done
+    mov rax, [rbx]
+    mov rdx, [rdi]
+    mov [rbx], rdx
+    mov [rdi], rax
+    mov rax, [rbx+8]
+    mov rdx, [rdi+8]
+    mov [rbx+8], rdx
+    mov [rdi+8], rax
+    add rbx, 16
+    sub rdi, 16
+    jmp .loop
+.done:
+    mov rax, rsi
+    pop rdi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+array_sort:
+    ; Sort the array in RSI in place (insertion sort), returns the array
+    push rbx
+    push rcx
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    mov r10, rsi
+    mov r8, [rsi+16]     ; Data
+    mov r9, [rsi]        ; Length
+    mov rcx, 1
+.outer:
+    cmp rcx, r9
+    jae .done
+    mov rax, rcx
+    shl rax, 4
+    push qword [r8+rax+8]
+    push qword [r8+rax]  ; Key element on the stack
+    mov rbx, rcx
+.inner:
+    test rbx, rbx
+    jz .insert
+    mov rax, rbx
+    dec rax
+    shl rax, 4
+    lea rsi, [r8+rax]
+    mov rdi, rsp
+    call value_compare
+    cmp rax, 0
+    jle .insert          ; Previous element is not greater than the key
+    mov rax, [rsi]
+    mov [rsi+16], rax
+    mov rax, [rsi+8]
+    mov [rsi+24], rax
+    dec rbx
+    jmp .inner
+.insert:
+    mov rax, rbx
+    shl rax, 4
+    pop qword [r8+rax]
+    pop qword [r8+rax+8]
+    inc rcx
+    jmp .outer
+.done:
+    mov rax, r10
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rcx
+    pop rbx
+    ret
+
+str_split:
+    ; Split the string in RSI at every occurrence of RDX into a new array of
+    ; strings; an empty separator splits it into characters
+    push rbx
+    push rcx
+    push rdx
+    push rdi
+    push r8
+    push r9
+    push r10
+    mov r8, rdx          ; Separator
+    xor rax, rax
+    call array_new
+    mov rdi, rax         ; Result
+    mov r9, [r8-8]       ; Separator length
+    mov r10, [rsi-8]
+    add r10, rsi         ; End
+    mov rbx, rsi         ; Start of the current part
+    mov rcx, rsi         ; Scan position
+    test r9, r9
+    jz .chars
+.scan:
+    mov rax, r10
+    sub rax, rcx
+    cmp rax, r9
+    jb .last             ; Too little left for a separator
+    xor rax, rax
+.match:
+    cmp rax, r9
+    je .matched
+    mov dl, [r8+rax]
+    cmp dl, [rcx+rax]
+    jne .next
+    inc rax
+    jmp .match
+.next:
+    inc rcx
+    jmp .scan
+.matched:
+    call .emit_part
+    add rcx, r9
+    mov rbx, rcx
+    jmp .scan
+.last:
+    mov rcx, r10
+    call .emit_part
+    jmp .done
+.chars:
+    cmp rcx, r10
+    jae .done
+    mov rbx, rcx
+    inc rcx
+.continuation:
+    cmp rcx, r10
+    jae .char_done
+    mov al, [rcx]
+    and al, 0xC0
+    cmp al, 0x80         ; Skip UTF-8 continuation bytes
+    jne .char_done
+    inc rcx
+    jmp .continuation
+.char_done:
+    call .emit_part
+    jmp .chars
+.done:
+    mov rax, rdi
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+.emit_part:
+    ; Push the bytes from RBX up to RCX onto the result as a string
+    push rsi
+    push rdx
+    mov rsi, rbx
+    mov rdx, rcx
+    call str_copy_range
+    mov rsi, rdi
+    mov rdx, 2           ; String tag
+    call array_push
+    pop rdx
+    pop rsi
+    ret
+
+value_to_string:
+    ; Text of the value RAX with tag RDX, returned in RAX
+    cmp rdx, 0
+    je .null
+    cmp rdx, 1
+    je .number
+    cmp rdx, 3
+    je .boolean
+    cmp rdx, 5
+    je .array
+    cmp rdx, 6
+    je map_to_string
+    cmp rdx, 7
+    je struct_to_string
+    cmp rdx, 8
+    je enum_to_string
+    cmp rdx, 9
+    je str_from_char
+    ret                  ; Strings and floats already are text
+.null:
+    mov rax, null_value
+    ret
+.number:
+    jmp str_from_int
+.boolean:
+    jmp str_from_bool
+.array:
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push r8
+    mov rbx, rax         ; Array
+    mov r8, array_open   ; Text so far
+    xor rcx, rcx
+.element:
+    cmp rcx, [rbx]
+    jae .close
+    test rcx, rcx
+    jz .append
+    mov rsi, r8
+    mov rdx, array_separator
+    call str_concat
+    mov r8, rax
+.append:
+    mov rsi, [rbx+16]
+    mov rax, rcx
+    shl rax, 4
+    mov rdx, [rsi+rax]
+    mov rax, [rsi+rax+8]
+    call value_to_string
+    mov rdx, rax
+    mov rsi, r8
+    call str_concat
+    mov r8, rax
+    inc rcx
+    jmp .element
+.close:
+    mov rsi, r8
+    mov rdx, array_close_value
+    call str_concat
+    pop r8
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+print_value:
+    ; Print the value RAX with tag RDX followed by a newline
+    call value_to_string
+    mov rsi, rax
+    jmp print_string
+
+"#);
+}
+
+// Emit the hash map helpers. A map is a pointer to a header
+// [count, bucket count, buckets, first entry, last entry]. Each entry is
+// [key tag, key, value tag, value, next in bucket, next, previous]; the
+// next/previous links keep the insertion order for keys() and printing.
+pub fn emit_map_helpers(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str(&string_data("map_open", "{"));
+    data_section.push_str(&string_data("map_close_value", "}"));
+    data_section.push_str(&string_data("map_key_separator", ": "));
+
+    text_section.push_str(r#"map_new:
+    ; New empty map, returned in RAX
+    push rbx
+    mov rax, 40
+    call heap_alloc
+    mov rbx, rax
+    mov qword [rbx], 0   ; Count
+    mov qword [rbx+8], 8 ; Buckets
+    mov qword [rbx+24], 0
+    mov qword [rbx+32], 0
+    mov rax, 64
+    call heap_alloc      ; Fresh heap memory is zeroed, so all buckets are empty
+    mov [rbx+16], rax
+    mov rax, rbx
+    pop rbx
+    ret
+
+map_hash:
+    ; Hash of the key RAX with tag RDX, returned in RAX
+    cmp rdx, 2
+    je .text
+    cmp rdx, 4
+    je .text
+    push rbx
+    mov rbx, 0x9e3779b97f4a7c15
+    imul rax, rbx
+    xor rax, rdx
+    pop rbx
+    ret
+.text:
+    ; FNV-1a over the bytes of the text
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    mov rsi, rax
+    mov rdx, [rsi-8]     ; Bytes left
+    mov rax, 0xcbf29ce484222325
+    mov rbx, 0x100000001b3
+.loop:
+    test rdx, rdx
+    jz .done
+    movzx rcx, byte [rsi]
+    xor rax, rcx
+    imul rax, rbx
+    inc rsi
+    dec rdx
+    jmp .loop
+.done:
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+map_bucket:
+    ; Address of the bucket for the key RAX with tag RDX in the map in RSI
+    push rbx
+    call map_hash
+    mov rbx, rax
+    shr rbx, 29          ; Fold the high bits into the bucket index
+    xor rax, rbx
+    mov rbx, [rsi+8]
+    dec rbx
+    and rax, rbx
+    shl rax, 3
+    add rax, [rsi+16]
+    pop rbx
+    ret
+
+map_find:
+    ; Entry holding the key RAX with tag RDX in the map in RSI, or 0 in RAX
+    push rbx
+    push rsi
+    push rdi
+    push rax
+    push rdx
+    mov rdi, rsp         ; Key as an element [tag, payload]
+    call map_bucket
+    mov rbx, [rax]
+.loop:
+    test rbx, rbx
+    jz .done
+    mov rsi, rbx         ; Entries start with their key element
+    call value_compare
+    test rax, rax
+    jz .done
+    mov rbx, [rbx+32]
+    jmp .loop
+.done:
+    mov rax, rbx
+    add rsp, 16
+    pop rdi
+    pop rsi
+    pop rbx
+    ret
+
+map_get:
+    ; Value under the key RAX with tag RDX in the map in RSI: value in RAX,
+    ; tag in RDX; null when the key is missing
+    call map_find
+    test rax, rax
+    jz .missing
+    mov rdx, [rax+16]
+    mov rax, [rax+24]
+    ret
+.missing:
+    xor rdx, rdx
+    ret
+
+map_has:
+    ; 1 when the map in RSI holds the key RAX with tag RDX, else 0
+    call map_find
+    test rax, rax
+    setnz al
+    movzx rax, al
+    ret
+
+map_set:
+    ; Store the value R8 with tag R9 under the key RAX with tag RDX in the
+    ; map in RSI, returns the map
+    push rbx
+    push rcx
+    push rax
+    call map_find
+    test rax, rax
+    jz .insert
+    mov [rax+16], r9     ; Existing keys keep their place in the order
+    mov [rax+24], r8
+    pop rax
+    jmp .done
+.insert:
+    pop rax
+    mov rcx, [rsi]
+    cmp rcx, [rsi+8]
+    jb .room
+    call map_grow
+.room:
+    mov rcx, rax         ; Key
+    mov rax, 56
+    call heap_alloc
+    mov rbx, rax         ; New entry
+    mov [rbx], rdx
+    mov [rbx+8], rcx
+    mov [rbx+16], r9
+    mov [rbx+24], r8
+    ; Link it at the end of the insertion order
+    mov rax, [rsi+32]
+    mov [rbx+48], rax
+    mov qword [rbx+40], 0
+    test rax, rax
+    jz .first
+    mov [rax+40], rbx
+    jmp .ordered
+.first:
+    mov [rsi+24], rbx
+.ordered:
+    mov [rsi+32], rbx
+    ; And at the front of its bucket
+    mov rax, rcx
+    call map_bucket
+    mov rcx, [rax]
+    mov [rbx+32], rcx
+    mov [rax], rbx
+    inc qword [rsi]
+.done:
+    mov rax, rsi
+    pop rcx
+    pop rbx
+    ret
+
+map_grow:
+    ; Double the bucket count of the map in RSI and rehash its entries
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    mov rax, [rsi+8]
+    shl rax, 1
+    mov [rsi+8], rax
+    shl rax, 3
+    call heap_alloc
+    mov [rsi+16], rax
+    mov rbx, [rsi+24]
+.loop:
+    test rbx, rbx
+    jz .done
+    mov rdx, [rbx]
+    mov rax, [rbx+8]
+    call map_bucket
+    mov rcx, [rax]
+    mov [rbx+32], rcx
+    mov [rax], rbx
+    mov rbx, [rbx+40]
+    jmp .loop
+.done:
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    ret
+
+map_remove:
+    ; Remove the key RAX with tag RDX from the map in RSI: 1 in RAX when it
+    ; was there, else 0
+    push rbx
+    push rcx
+    push rdi
+    call map_find
+    test rax, rax
+    jz .done
+    mov rbx, rax         ; Entry
+    mov rax, [rbx+8]
+    call map_bucket
+.bucket:
+    cmp [rax], rbx
+    je .unlink_bucket
+    mov rax, [rax]
+    add rax, 32          ; Link field of the previous entry in the bucket
+    jmp .bucket
+.unlink_bucket:
+    mov rcx, [rbx+32]
+    mov [rax], rcx
+    ; Unlink it from the insertion order
+    mov rcx, [rbx+40]    ; Next
+    mov rdi, [rbx+48]    ; Previous
+    test rdi, rdi
+    jz .was_first
+    mov [rdi+40], rcx
+    jmp .fix_next
+.was_first:
+    mov [rsi+24], rcx
+.fix_next:
+    test rcx, rcx
+    jz .was_last
+    mov [rcx+48], rdi
+    jmp .unlinked
+.was_last:
+    mov [rsi+32], rdi
+.unlinked:
+    dec qword [rsi]
+    mov rax, 1
+.done:
+    pop rdi
+    pop rcx
+    pop rbx
+    ret
+
+map_keys:
+    ; Keys of the map in RSI in insertion order, as a new array
+    push rbx
+    push rdx
+    push rsi
+    mov rax, [rsi]
+    call array_new
+    mov rbx, [rsi+24]
+    mov rsi, rax
+.loop:
+    test rbx, rbx
+    jz .done
+    mov rdx, [rbx]
+    mov rax, [rbx+8]
+    call array_push
+    mov rbx, [rbx+40]
+    jmp .loop
+.done:
+    mov rax, rsi
+    pop rsi
+    pop rdx
+    pop rbx
+    ret
+
+map_to_string:
+    ; Text of the map in RAX, as {key: value, ...}
+    push rbx
+    push rdx
+    push rsi
+    push r8
+    mov rbx, [rax+24]    ; First entry
+    mov r8, map_open     ; Text so far
+.entry:
+    test rbx, rbx
+    jz .close
+    cmp r8, map_open
+    je .append
+    mov rsi, r8
+    mov rdx, array_separator
+    call str_concat
+    mov r8, rax
+.append:
+    mov rdx, [rbx]
+    mov rax, [rbx+8]
+    call value_to_string
+    mov rdx, rax
+    mov rsi, r8
+    call str_concat
+    mov rsi, rax
+    mov rdx, map_key_separator
+    call str_concat
+    mov r8, rax
+    mov rdx, [rbx+16]
+    mov rax, [rbx+24]
+    call value_to_string
+    mov rdx, rax
+    mov rsi, r8
+    call str_concat
+    mov r8, rax
+    mov rbx, [rbx+40]
+    jmp .entry
+.close:
+    mov rsi, r8
+    mov rdx, map_close_value
+    call str_concat
+    pop r8
+    pop rsi
+    pop rdx
+    pop rbx
+    ret
+
+"#);
+}
+
+// Emit the struct helpers. A struct is a pointer to [descriptor, fields...]
+// where every field is a [tag, payload] pair in declaration order. The
+// descriptor is [name, field count, field names...] and is emitted in the
+// data section by the struct declaration.
+pub fn emit_struct_helpers(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str(&string_data("struct_open", " { "));
+    data_section.push_str(&string_data("struct_close_value", " }"));
+    data_section.push_str(&string_data("struct_empty", " {}"));
+
+    text_section.push_str(r#"struct_to_string:
+    ; Text of the struct in RAX, as Name { field: value, ... }
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push r8
+    mov rbx, rax         ; Struct
+    mov rdi, [rbx]       ; Descriptor
+    mov r8, [rdi]        ; Text so far starts with the name
+    cmp qword [rdi+8], 0
+    jne .fields
+    mov rsi, r8
+    mov rdx, struct_empty
+    call str_concat
+    jmp .done
+.fields:
+    mov rsi, r8
+    mov rdx, struct_open
+    call str_concat
+    mov r8, rax
+    xor rcx, rcx
+.field:
+    cmp rcx, [rdi+8]
+    jae .close
+    test rcx, rcx
+    jz .append
+    mov rsi, r8
+    mov rdx, array_separator
+    call str_concat
+    mov r8, rax
+.append:
+    mov rsi, r8
+    mov rdx, [rdi+16+rcx*8] ; Field name
+    call str_concat
+    mov rsi, rax
+    mov rdx, map_key_separator
+    call str_concat
+    mov r8, rax
+    mov rax, rcx
+    shl rax, 4
+    mov rdx, [rbx+rax+8]
+    mov rax, [rbx+rax+16]
+    call value_to_string
+    mov rdx, rax
+    mov rsi, r8
+    call str_concat
+    mov r8, rax
+    inc rcx
+    jmp .field
+.close:
+    mov rsi, r8
+    mov rdx, struct_close_value
+    call str_concat
+.done:
+    pop r8
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+"#);
+}
+
+// Emit the enum helpers. An enum value is a pointer to [variant descriptor,
+// payload...] with the payload laid out like struct fields. The descriptor is
+// [name, variant index, payload count]; unit variants share one static value.
+pub fn emit_enum_helpers(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str(&string_data("enum_open", "("));
+    data_section.push_str(&string_data("enum_close", ")"));
+
+    text_section.push_str(r#"enum_to_string:
+    ; Text of the enum value in RAX, as Variant or Variant(payload, ...)
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push r8
+    mov rbx, rax         ; Enum value
+    mov rdi, [rbx]       ; Variant descriptor
+    mov rax, [rdi]       ; Variant name
+    cmp qword [rdi+16], 0
+    je .done
+    mov rsi, rax
+    mov rdx, enum_open
+    call str_concat
+    mov r8, rax
+    xor rcx, rcx
+.payload:
+    cmp rcx, [rdi+16]
+    jae .close
+    test rcx, rcx
+    jz .append
+    mov rsi, r8
+    mov rdx, array_separator
+    call str_concat
+    mov r8, rax
+.append:
+    mov rax, rcx
+    shl rax, 4
+    mov rdx, [rbx+rax+8]
+    mov rax, [rbx+rax+16]
+    call value_to_string
+    mov rdx, rax
+    mov rsi, r8
+    call str_concat
+    mov r8, rax
+    inc rcx
+    jmp .payload
+.close:
+    mov rsi, r8
+    mov rdx, enum_close
+    call str_concat
+.done:
+    pop r8
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+"#);
+}
+
+// Emit the integer helpers behind operators that need more than one
+// instruction
+pub fn emit_integer_helpers(text_section: &mut String) {
+    text_section.push_str(r#"int_pow:
+    ; RAX raised to the power RBX, wrapping on overflow. A negative exponent
+    ; gives the truncated value of 1 / RAX**-RBX: 1 or -1 for a base of 1 or
+    ; -1, otherwise 0
+    ; The carry flag is set when the result overflowed
+    push rbx
+    push rcx
+    push r8
+    xor r8, r8           ; Overflow seen
+    test rbx, rbx
+    jns .positive
+    cmp rax, 1
+    je .done
+    cmp rax, -1
+    jne .zero
+    test rbx, 1
+    jnz .done            ; -1 to an odd power stays -1
+    mov rax, 1
+    jmp .done
+.zero:
+    xor rax, rax
+    jmp .done
+.positive:
+    mov rcx, rax         ; Base, squared each round
+    mov rax, 1           ; Result
+    test rbx, rbx
+    jz .done
+.loop:
+    test rbx, 1
+    jz .square
+    imul rax, rcx
+    jno .square
+    mov r8, 1
+.square:
+    shr rbx, 1
+    jz .done             ; The last square is never used
+    imul rcx, rcx
+    jno .loop
+    mov r8, 1
+    jmp .loop
+.done:
+    bt r8, 0             ; Carry = overflow
+    pop r8
+    pop rcx
+    pop rbx
+    ret
+
+"#);
+}
+
+// Emit the traps that checked arithmetic jumps to. They take the source line
+// in RAX and the column in RBX, print
+// "runtime error: <what> at <file>:<line>:<column>" to stderr and exit.
+pub fn emit_trap_helpers(text_section: &mut String, data_section: &mut String, source_file: &str) {
+    data_section.push_str(&string_data("division_by_zero_msg", "runtime error: division by zero at "));
+    data_section.push_str(&string_data("overflow_msg", "runtime error: integer overflow at "));
+    data_section.push_str(&string_data("null_operand_msg", "runtime error: null operand at "));
+    data_section.push_str(&string_data("int_operand_msg", "runtime error: arithmetic needs integers at "));
+    data_section.push_str(&string_data("trap_source_file", &format!("{}:", source_file)));
+    data_section.push_str(&string_data("trap_separator", ":"));
+
+    text_section.push_str(&format!(r#"division_by_zero_trap:
+    mov rsi, division_by_zero_msg
+    mov rdi, {}
+    jmp runtime_trap
+
+overflow_trap:
+    mov rsi, overflow_msg
+    mov rdi, {}
+    jmp runtime_trap
+
+operand_trap:
+    ; An operand typed at runtime holds null, or a value that is not an
+    ; integer in arithmetic. Its tag is in RDX
+    mov rsi, null_operand_msg
+    cmp rdx, {}
+    je .report
+    mov rsi, int_operand_msg
+.report:
+    mov rdi, {}
+    jmp runtime_trap
+
+runtime_trap:
+    ; Report the message in RSI with the location in RAX:RBX, exit with RDI
+    push rdi
+    mov r8, rax          ; Line
+    mov r9, rbx          ; Column
+    mov rdx, trap_source_file
+    call str_concat
+    mov rsi, rax
+    mov rax, r8
+    call str_from_int
+    mov rdx, rax
+    call str_concat
+    mov rsi, rax
+    mov rdx, trap_separator
+    call str_concat
+    mov rsi, rax
+    mov rax, r9
+    call str_from_int
+    mov rdx, rax
+    call str_concat
+    mov rsi, rax
+    call str_byte_len
+    mov rdx, rax
+    mov rax, 1           ; sys_write
+    mov rdi, 2           ; stderr
+    syscall
+    mov rax, 1           ; sys_write
+    mov rdi, 2           ; stderr
+    mov rsi, newline_char
+    mov rdx, 1
+    syscall
+    pop rdi
+    mov rax, 60          ; sys_exit
+    syscall
+
+"#, EXIT_DIVISION_BY_ZERO, EXIT_OVERFLOW, TAG_NULL, EXIT_OPERAND_TYPE));
+}
+
+// Emit the console input built-ins. Input is read from stdin through one
+// shared buffer; every reader returns its value in RAX and the tag in RDX,
+// null once stdin has nothing left.
+pub fn emit_input_helpers(text_section: &mut String, data_section: &mut String, bss_section: &mut String) {
+    data_section.push_str("stdin_pos dq 0\n");
+    data_section.push_str("stdin_len dq 0\n");
+
+    bss_section.push_str("stdin_buffer: resb 4096\n");
+
+    text_section.push_str(&format!(r#"stdin_fill:
+    ; Number of unread bytes in stdin_buffer, refilled when empty. 0 at end
+    ; of input, read errors count as end of input too
+    mov rax, [stdin_len]
+    sub rax, [stdin_pos]
+    jnz .done
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push r11
+    xor rax, rax         ; sys_read
+    xor rdi, rdi         ; stdin
+    mov rsi, stdin_buffer
+    mov rdx, 4096
+    syscall
+    pop r11
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    mov qword [stdin_pos], 0
+    test rax, rax
+    jns .filled
+    xor rax, rax
+.filled:
+    mov [stdin_len], rax
+.done:
+    ret
+
+stdin_read:
+    ; Read up to the byte in RBX (-1 reads everything) into a new string,
+    ; the stop byte itself is dropped. Null when input had already ended
+    push rbx
+    push rcx
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    push r11
+    mov rax, 64
+    call str_alloc
+    mov r8, rax          ; Text read so far
+    xor r9, r9           ; Its length
+    mov r10, 64          ; Its capacity
+    xor r11, r11         ; Set once anything was read
+.next:
+    call stdin_fill
+    test rax, rax
+    jz .end_of_input
+    mov rsi, [stdin_pos]
+    movzx rax, byte [stdin_buffer+rsi]
+    inc qword [stdin_pos]
+    mov r11, 1
+    cmp rax, rbx
+    je .done
+    cmp r9, r10
+    jb .store
+    push rax             ; Out of room for the byte
+    lea rax, [r10*2]
+    mov r10, rax
+    call str_alloc
+    mov rsi, r8
+    mov rdi, rax
+    mov rcx, r9
+    rep movsb
+    mov r8, rax
+    pop rax
+.store:
+    mov [r8+r9], al
+    inc r9
+    jmp .next
+.end_of_input:
+    test r11, r11
+    jnz .done
+    xor rax, rax
+    mov rdx, {null}
+    jmp .return
+.done:
+    mov rax, r8
+    lea rdi, [r8+r9]
+    call str_finish
+    mov rdx, {string}
+.return:
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rcx
+    pop rbx
+    ret
+
+read_line:
+    ; console.readLine(), the next line without its line ending
+    push rbx
+    push rcx
+    push rsi
+    mov rbx, 10          ; \n
+    call stdin_read
+    test rdx, rdx
+    jz .done
+    mov rcx, rax
+    mov rsi, rax
+    call str_byte_len
+    test rax, rax
+    jz .keep
+    cmp byte [rcx+rax-1], 13
+    jne .keep
+    mov byte [rcx+rax-1], 0  ; Drop the \r of a \r\n ending
+    dec qword [rcx-8]
+.keep:
+    mov rax, rcx
+.done:
+    pop rsi
+    pop rcx
+    pop rbx
+    ret
+
+read_all:
+    ; console.readAll(), everything left on stdin
+    push rbx
+    mov rbx, -1
+    call stdin_read
+    pop rbx
+    ret
+
+read_int:
+    ; console.readInt(), the next line as a number. Null when the line is
+    ; not a number or input has ended
+    call read_line
+    test rdx, rdx
+    jz .done
+    push rsi
+    mov rsi, rax
+    call str_to_int
+    pop rsi
+.done:
+    ret
+
+str_to_int:
+    ; Parse the string in RSI as a decimal number, surrounding whitespace
+    ; allowed. RDX is {number} on success, otherwise {null} with RAX 0, also
+    ; when the number does not fit in 64 bits
+    push rbx
+    push rcx
+    push rsi
+    push r8
+    push r9
+    call str_trim
+    mov rsi, rax
+    mov r9, [rsi-8]
+    add r9, rsi          ; End
+    xor rax, rax         ; Value
+    xor r8, r8           ; Set when negative
+    mov bl, [rsi]
+    cmp bl, '-'
+    jne .plus
+    mov r8, 1
+    inc rsi
+    jmp .first_digit
+.plus:
+    cmp bl, '+'
+    jne .first_digit
+    inc rsi
+.first_digit:
+    cmp rsi, r9
+    je .invalid          ; No digits at all
+.digit:
+    cmp rsi, r9
+    je .valid
+    movzx rcx, byte [rsi]
+    sub rcx, '0'
+    cmp rcx, 9
+    ja .invalid
+    imul rax, rax, 10    ; Digits are taken away from 0, so that the
+    jo .invalid          ; smallest number fits too
+    sub rax, rcx
+    jo .invalid
+    inc rsi
+    jmp .digit
+.valid:
+    test r8, r8
+    jnz .negative
+    neg rax
+    jo .invalid          ; Above the largest number
+.negative:
+    mov rdx, {number}
+    jmp .done
+.invalid:
+    xor rax, rax
+    mov rdx, {null}
+.done:
+    pop r9
+    pop r8
+    pop rsi
+    pop rcx
+    pop rbx
+    ret
+
+"#, null = TAG_NULL, string = TAG_STRING, number = TAG_NUMBER));
+}
+
+// Emit process.args() and process.env(). _start saves its stack pointer in
+// process_stack, which points at argc followed by the argument pointers, a
+// 0, the environment pointers and another 0. Those are NUL-terminated, so
+// they are copied into strings with a length.
+pub fn emit_process_helpers(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str("process_stack dq 0\n");
+
+    text_section.push_str(&format!(r#"str_from_terminated:
+    ; Copy of the NUL-terminated bytes at RSI as a new string in RAX
+    push rdx
+    mov rdx, rsi
+.end:
+    cmp byte [rdx], 0
+    je .copy
+    inc rdx
+    jmp .end
+.copy:
+    call str_copy_range
+    pop rdx
+    ret
+
+process_args:
+    ; process.args(), the command line arguments as an array of strings,
+    ; program name first. RDX is the array tag
+    push rsi
+    push r8
+    push r9
+    mov r8, [process_stack]
+    mov rax, [r8]        ; argc
+    call array_new
+    mov rsi, rax
+    xor r9, r9
+.next:
+    cmp r9, [r8]
+    jae .done
+    push rsi
+    mov rsi, [r8+r9*8+8]
+    call str_from_terminated
+    pop rsi
+    mov rdx, {string}
+    call array_push
+    inc r9
+    jmp .next
+.done:
+    mov rax, rsi
+    mov rdx, {array}
+    pop r9
+    pop r8
+    pop rsi
+    ret
+
+process_env:
+    ; process.env(name) for the name in RSI: the value as a string, null
+    ; when the variable is not set
+    push rbx
+    push rcx
+    push rdi
+    push r8
+    mov r8, [process_stack]
+    mov rcx, [r8]        ; argc
+    lea r8, [r8+rcx*8+16] ; Past argc, the arguments and their 0
+.entry:
+    mov rdi, [r8]
+    test rdi, rdi
+    jz .missing
+    xor rcx, rcx
+.compare:
+    cmp rcx, [rsi-8]
+    je .name_end
+    mov bl, [rsi+rcx]
+    test bl, bl
+    jz .skip             ; No variable name holds a NUL
+    cmp bl, [rdi+rcx]
+    jne .skip
+    inc rcx
+    jmp .compare
+.name_end:
+    cmp byte [rdi+rcx], '='
+    jne .skip
+    push rsi
+    lea rsi, [rdi+rcx+1]
+    call str_from_terminated
+    pop rsi
+    mov rdx, {string}
+    jmp .done
+.skip:
+    add r8, 8
+    jmp .entry
+.missing:
+    xor rax, rax
+    mov rdx, {null}
+.done:
+    pop r8
+    pop rdi
+    pop rcx
+    pop rbx
+    ret
+
+"#, string = TAG_STRING, array = TAG_ARRAY, null = TAG_NULL));
+}
+
+// Exit code of a failed fs call
+pub const EXIT_FILE_ERROR: u8 = 1;
+
+// Names of the errno values a file call can fail with
+const ERRNO_NAMES: &[(usize, &str)] = &[
+    (1, "EPERM"), (2, "ENOENT"), (4, "EINTR"), (5, "EIO"), (6, "ENXIO"), (9, "EBADF"),
+    (12, "ENOMEM"), (13, "EACCES"), (14, "EFAULT"), (16, "EBUSY"), (17, "EEXIST"),
+    (19, "ENODEV"), (20, "ENOTDIR"), (21, "EISDIR"), (22, "EINVAL"), (23, "ENFILE"),
+    (24, "EMFILE"), (26, "ETXTBSY"), (27, "EFBIG"), (28, "ENOSPC"), (30, "EROFS"),
+    (31, "EMLINK"), (36, "ENAMETOOLONG"), (39, "ENOTEMPTY"), (40, "ELOOP"),
+    (122, "EDQUOT"),
+];
+
+// Emit the fs built-ins, made of raw open/read/write/close/unlink/access
+// syscalls. Each returns its value in RAX and the tag in RDX; on failure it
+// sets the carry flag and leaves what failed in fs_errno, fs_error_op and
+// fs_error_path for fs_error_trap.
+pub fn emit_fs_helpers(text_section: &mut String, data_section: &mut String) {
+    let errno_count = ERRNO_NAMES.last().unwrap().0 + 1;
+    let mut table = vec!["0".to_string(); errno_count];
+    for (errno, name) in ERRNO_NAMES {
+        data_section.push_str(&string_data(&format!("errno_name_{}", errno), name));
+        table[*errno] = format!("errno_name_{}", errno);
+    }
+    data_section.push_str(&format!("errno_names dq {}\n", table.join(", ")));
+    data_section.push_str(&string_data("errno_unknown", "errno "));
+    data_section.push_str("fs_errno dq 0\n");
+    data_section.push_str("fs_error_op dq 0\n");
+    data_section.push_str("fs_error_path dq 0\n");
+    data_section.push_str(&string_data("fs_read_name", "fs.read"));
+    data_section.push_str(&string_data("fs_write_name", "fs.write"));
+    data_section.push_str(&string_data("fs_append_name", "fs.append"));
+    data_section.push_str(&string_data("fs_remove_name", "fs.remove"));
+    data_section.push_str(&string_data("fs_error_prefix", "runtime error: "));
+    data_section.push_str(&string_data("fs_error_open", "(\""));
+    data_section.push_str(&string_data("fs_error_close", "\") failed with "));
+    data_section.push_str(&string_data("fs_error_at", " at "));
+
+    text_section.push_str(&format!(r#"fs_read:
+    ; fs.read(path) for the path in RSI, the whole file as a string
+    push rbx
+    push rcx
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    push r11
+    mov qword [fs_error_op], fs_read_name
+    mov [fs_error_path], rsi
+    call fs_path_check
+    jc .fail
+    mov rax, 2           ; sys_open
+    mov rdi, rsi
+    xor rsi, rsi         ; O_RDONLY
+    xor rdx, rdx
+    syscall
+    test rax, rax
+    js .fail
+    mov r8, rax          ; File descriptor
+    sub rsp, 144         ; struct stat
+    mov rax, 5           ; sys_fstat
+    mov rdi, r8
+    mov rsi, rsp
+    syscall
+    mov rbx, [rsp+48]    ; st_size
+    add rsp, 144
+    test rax, rax
+    jz .sized
+    xor rbx, rbx
+.sized:
+    ; Room for the size fstat gives and a byte more to see the end in one
+    ; pass. Files that report no size, like those in /proc, grow below
+    inc rbx              ; Capacity
+    cmp rbx, 4096
+    jae .allocate
+    mov rbx, 4096
+.allocate:
+    mov rax, rbx
+    call str_alloc
+    mov r9, rax          ; Text read so far
+    xor r10, r10         ; Its length
+.read:
+    cmp r10, rbx
+    jb .room
+    lea rax, [rbx*2]
+    mov rbx, rax
+    call str_alloc
+    mov rsi, r9
+    mov rdi, rax
+    mov rcx, r10
+    rep movsb
+    mov r9, rax
+.room:
+    xor rax, rax         ; sys_read
+    mov rdi, r8
+    lea rsi, [r9+r10]
+    mov rdx, rbx
+    sub rdx, r10
+    syscall
+    test rax, rax
+    js .read_failed
+    jz .end_of_file
+    add r10, rax
+    jmp .read
+.read_failed:
+    push rax
+    mov rax, 3           ; sys_close
+    mov rdi, r8
+    syscall
+    pop rax
+    jmp .fail
+.end_of_file:
+    mov rax, 3           ; sys_close
+    mov rdi, r8
+    syscall
+    mov rax, r9
+    lea rdi, [r9+r10]
+    call str_finish
+    mov rdx, {string}
+    clc
+    jmp .done
+.fail:
+    neg rax
+    mov [fs_errno], rax
+    xor rax, rax
+    mov rdx, {null}
+    stc
+.done:
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rcx
+    pop rbx
+    ret
+
+fs_write:
+    ; fs.write(path, text) for the path in RSI and the text in RDX, replaces
+    ; the file. Returns null
+    mov qword [fs_error_op], fs_write_name
+    push rbx
+    mov rbx, 0x241       ; O_WRONLY | O_CREAT | O_TRUNC
+    call fs_write_file
+    pop rbx
+    ret
+
+fs_append:
+    ; fs.append(path, text) for the path in RSI and the text in RDX, adds to
+    ; the end of the file. Returns null
+    mov qword [fs_error_op], fs_append_name
+    push rbx
+    mov rbx, 0x441       ; O_WRONLY | O_CREAT | O_APPEND
+    call fs_write_file
+    pop rbx
+    ret
+
+fs_write_file:
+    ; Open the path in RSI with the flags in RBX and write the text in RDX
+    push rcx
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r11
+    mov [fs_error_path], rsi
+    mov r9, rdx          ; Text
+    call fs_path_check
+    jc .fail
+    mov rax, 2           ; sys_open
+    mov rdi, rsi
+    mov rsi, rbx
+    mov rdx, 420         ; 0644
+    syscall
+    test rax, rax
+    js .fail
+    mov r8, rax          ; File descriptor
+    mov rsi, r9
+    call str_byte_len
+    mov rdx, rax
+.write:
+    test rdx, rdx
+    jz .close
+    mov rax, 1           ; sys_write
+    mov rdi, r8
+    syscall
+    test rax, rax
+    js .write_failed
+    add rsi, rax
+    sub rdx, rax
+    jmp .write
+.write_failed:
+    push rax
+    mov rax, 3           ; sys_close
+    mov rdi, r8
+    syscall
+    pop rax
+    jmp .fail
+.close:
+    mov rax, 3           ; sys_close
+    mov rdi, r8
+    syscall
+    test rax, rax
+    js .fail
+    xor rax, rax
+    mov rdx, {null}
+    clc
+    jmp .done
+.fail:
+    neg rax
+    mov [fs_errno], rax
+    xor rax, rax
+    mov rdx, {null}
+    stc
+.done:
+    pop r11
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+fs_exists:
+    ; fs.exists(path) for the path in RSI, a boolean. Never fails
+    push rcx
+    push rsi
+    push rdi
+    push r11
+    call fs_path_check
+    jc .answer           ; A path with a NUL names no file
+    mov rax, 21          ; sys_access
+    mov rdi, rsi
+    xor rsi, rsi         ; F_OK
+    syscall
+.answer:
+    test rax, rax
+    sete al
+    movzx rax, al
+    mov rdx, {boolean}
+    clc
+    pop r11
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+fs_remove:
+    ; fs.remove(path) for the path in RSI, deletes the file. Returns null
+    push rcx
+    push rdi
+    push r11
+    mov qword [fs_error_op], fs_remove_name
+    mov [fs_error_path], rsi
+    call fs_path_check
+    jc .fail
+    mov rax, 87          ; sys_unlink
+    mov rdi, rsi
+    syscall
+    test rax, rax
+    js .fail
+    xor rax, rax
+    mov rdx, {null}
+    clc
+    jmp .done
+.fail:
+    neg rax
+    mov [fs_errno], rax
+    xor rax, rax
+    mov rdx, {null}
+    stc
+.done:
+    pop r11
+    pop rdi
+    pop rcx
+    ret
+
+fs_path_check:
+    ; Carry set and -EINVAL in RAX when the path in RSI holds a NUL byte,
+    ; which the kernel would take as its end
+    push rcx
+    mov rcx, [rsi-8]
+.loop:
+    test rcx, rcx
+    jz .done             ; Carry clear
+    cmp byte [rsi+rcx-1], 0
+    je .nul
+    dec rcx
+    jmp .loop
+.nul:
+    mov rax, -22         ; EINVAL
+    stc
+.done:
+    pop rcx
+    ret
+
+errno_name:
+    ; Name of the errno value in RAX, "errno N" when it has none
+    cmp rax, {errno_count}
+    jae .unknown
+    cmp qword [errno_names+rax*8], 0
+    je .unknown
+    mov rax, [errno_names+rax*8]
+    ret
+.unknown:
+    push rsi
+    push rdx
+    call str_from_int
+    mov rdx, rax
+    mov rsi, errno_unknown
+    call str_concat
+    pop rdx
+    pop rsi
+    ret
+
+fs_error_trap:
+    ; Report the failed fs call with the location in RAX:RBX and exit
+    mov r8, rax
+    mov rsi, fs_error_prefix
+    mov rdx, [fs_error_op]
+    call str_concat
+    mov rsi, rax
+    mov rdx, fs_error_open
+    call str_concat
+    mov rsi, rax
+    mov rdx, [fs_error_path]
+    call str_concat
+    mov rsi, rax
+    mov rdx, fs_error_close
+    call str_concat
+    mov rsi, rax
+    mov rax, [fs_errno]
+    call errno_name
+    mov rdx, rax
+    call str_concat
+    mov rsi, rax
+    mov rdx, fs_error_at
+    call str_concat
+    mov rsi, rax
+    mov rax, r8
+    mov rdi, {exit}
+    jmp runtime_trap
+
+"#, string = TAG_STRING, null = TAG_NULL, boolean = TAG_BOOLEAN, errno_count = errno_count, exit = EXIT_FILE_ERROR));
+}
+
+// Exit code when a math function is given something that is not a number,
+// or rounds a float to an integer outside 64 bits
+pub const EXIT_MATH: u8 = 1;
+
+// Emit the math built-ins. Floats are held as their text at runtime, so each
+// function turns its arguments into doubles, computes with SSE or the x87 unit
+// and turns a float result back into text. One argument comes in RAX/RDX, a
+// second one in RBX/RCX; the result is left in RAX/RDX. The carry flag is set,
+// with the message in RSI, when an argument is not a number or a rounded
+// value does not fit in 64 bits. XMM0-XMM2 are scratch registers.
+pub fn emit_math_helpers(text_section: &mut String, data_section: &mut String) {
+    let double = |value: f64| format!("{:#x}", value.to_bits());
+    data_section.push_str(&format!("float_one dq {}\n", double(1.0)));
+    data_section.push_str(&format!("float_half dq {}\n", double(0.5)));
+    data_section.push_str(&format!("float_nan dq {}\n", double(f64::NAN)));
+    data_section.push_str(&format!("float_inf dq {}\n", double(f64::INFINITY)));
+    data_section.push_str(&format!("float_int_min dq {}\n", double(i64::MIN as f64)));
+    data_section.push_str(&format!("float_int_end dq {}\n", double(9223372036854775808.0)));
+    let powers: Vec<String> = (0..=22).map(|i| double(10f64.powi(i))).collect();
+    data_section.push_str(&format!("float_pow10 dq {}\n", powers.join(", ")));
+    let powers: Vec<String> = (0..=18).map(|i| 10u64.pow(i).to_string()).collect();
+    data_section.push_str(&format!("int_pow10 dq {}\n", powers.join(", ")));
+    data_section.push_str(&string_data("float_nan_text", "NaN"));
+    data_section.push_str(&string_data("float_inf_text", "inf"));
+    data_section.push_str(&string_data("float_neg_inf_text", "-inf"));
+    data_section.push_str(&string_data("math_type_msg", "runtime error: math function needs a number at "));
+    data_section.push_str(&string_data("math_range_msg", "runtime error: rounded value does not fit in 64 bits at "));
+
+    text_section.push_str(&format!(r#"value_to_double:
+    ; The number RAX with tag RDX as a double in XMM0
+    cmp rdx, {number}
+    jne .float
+    cvtsi2sd xmm0, rax
+    clc
+    ret
+.float:
+    cmp rdx, {float}
+    jne .not_number
+    push rsi
+    mov rsi, rax
+    call str_to_float
+    pop rsi
+    clc
+    ret
+.not_number:
+    mov rsi, math_type_msg
+    stc
+    ret
+
+str_to_float:
+    ; Parse the float text in RSI into XMM0: digits with an optional dot and
+    ; exponent, NaN or inf. The digits are read as an integer and scaled by
+    ; the power of ten on the x87 unit, whose 64-bit mantissa keeps the
+    ; value exact enough to round to the nearest double
+    push rax
+    push rcx
+    push rdx
+    push rsi
+    push r8
+    push r9
+    push r10
+    sub rsp, 8
+    xor rax, rax         ; Digits
+    xor r8, r8           ; Negative
+    xor r9, r9           ; Power of ten to scale the digits by
+    xor r10, r10         ; Dot seen
+    cmp byte [rsi], '-'
+    jne .special
+    mov r8, 1
+    inc rsi
+.special:
+    cmp byte [rsi], 'N'
+    jne .infinity
+    movsd xmm0, [float_nan]
+    jmp .sign
+.infinity:
+    cmp byte [rsi], 'i'
+    jne .digits
+    movsd xmm0, [float_inf]
+    jmp .sign
+.digits:
+    movzx rcx, byte [rsi]
+    inc rsi
+    cmp rcx, '.'
+    je .dot
+    sub rcx, '0'
+    cmp rcx, 9
+    ja .exponent
+    sub r9, r10          ; A digit after the dot is a tenth of the one before
+    mov rdx, 100000000000000000
+    cmp rax, rdx
+    jae .extra           ; 18 digits are kept, later ones only scale
+    imul rax, rax, 10
+    add rax, rcx
+    jmp .digits
+.extra:
+    inc r9
+    jmp .digits
+.dot:
+    mov r10, 1
+    jmp .digits
+.exponent:
+    cmp rcx, 'e' - '0'
+    je .exponent_sign
+    cmp rcx, 'E' - '0'
+    jne .scale
+.exponent_sign:
+    xor rdx, rdx         ; Exponent
+    xor r10, r10         ; Negative exponent
+    cmp byte [rsi], '+'
+    je .exponent_signed
+    cmp byte [rsi], '-'
+    jne .exponent_digits
+    mov r10, 1
+.exponent_signed:
+    inc rsi
+.exponent_digits:
+    movzx rcx, byte [rsi]
+    sub rcx, '0'
+    cmp rcx, 9
+    ja .exponent_done
+    inc rsi
+    cmp rdx, 100000
+    jae .exponent_digits ; Already out of range of any double
+    imul rdx, rdx, 10
+    add rdx, rcx
+    jmp .exponent_digits
+.exponent_done:
+    test r10, r10
+    jz .exponent_add
+    neg rdx
+.exponent_add:
+    add r9, rdx
+.scale:
+    mov [rsp], rax
+    fild qword [rsp]
+    mov rcx, r9
+    call scale_pow10
+    fstp qword [rsp]
+    movsd xmm0, [rsp]
+.sign:
+    test r8, r8
+    jz .done
+    movq rax, xmm0
+    btc rax, 63
+    movq xmm0, rax
+.done:
+    add rsp, 8
+    pop r10
+    pop r9
+    pop r8
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rax
+    ret
+
+scale_pow10:
+    ; ST0 times ten to the power RCX, which may be negative. Powers up to
+    ; 10^22 are exact doubles, larger ones are applied in steps
+    push rcx
+    test rcx, rcx
+    js .divide
+.multiply:
+    cmp rcx, 22
+    jbe .multiply_last
+    fmul qword [float_pow10+22*8]
+    sub rcx, 22
+    jmp .multiply
+.multiply_last:
+    fmul qword [float_pow10+rcx*8]
+    jmp .done
+.divide:
+    neg rcx
+.divide_step:
+    cmp rcx, 22
+    jbe .divide_last
+    fdiv qword [float_pow10+22*8]
+    sub rcx, 22
+    jmp .divide_step
+.divide_last:
+    fdiv qword [float_pow10+rcx*8]
+.done:
+    pop rcx
+    ret
+
+float_to_string:
+    ; Text of the double in XMM0, returned in RAX. Like Rust's Display for
+    ; f64, and so like folded floats, it is the shortest decimal that reads
+    ; back as the same double, written out without an exponent
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push r8
+    push r9
+    push r10
+    push r11
+    sub rsp, 16
+    xor r8, r8
+    movq rax, xmm0
+    btr rax, 63
+    setc r8b             ; Negative
+    mov rbx, 0x7ff0000000000000
+    cmp rax, rbx
+    ja .nan
+    je .infinity
+    mov [rsp], rax       ; The value without its sign
+    xor r9, r9           ; Power of ten of the last digit
+    xor r10, r10         ; Digits, 0 for zero
+    test rax, rax
+    jz .text
+    fldlg2
+    fld qword [rsp]
+    fyl2x
+    fistp qword [rsp+8]
+    mov r9, [rsp+8]      ; Power of ten of the first digit, corrected below
+    mov r11, 15          ; Significant digits, 15 always read back right
+    mov rbx, 0x0010000000000000
+    cmp rax, rbx
+    jae .round
+    mov r11, 1           ; Subnormals are less precise, try from one digit
+.round:
+    ; The value rounded to R11 digits
+    lea rcx, [r11-1]
+    sub rcx, r9
+    fld qword [rsp]
+    call scale_pow10
+    fistp qword [rsp+8]
+    mov r10, [rsp+8]
+    cmp r10, [int_pow10+r11*8]
+    jae .too_many
+    cmp r10, [int_pow10+r11*8-8]
+    jb .too_few
+    ; Keep these digits when they read back as the value, else try one more
+    fild qword [rsp+8]
+    mov rcx, r9
+    sub rcx, r11
+    inc rcx
+    call scale_pow10
+    fstp qword [rsp+8]
+    mov rax, [rsp+8]
+    cmp rax, [rsp]
+    je .found
+    cmp r11, 17          ; 17 digits tell every double apart
+    je .found
+    inc r11
+    jmp .round
+.too_many:
+    inc r9
+    jmp .round
+.too_few:
+    dec r9
+    jmp .round
+.found:
+    sub r9, r11
+    inc r9
+    mov rbx, 10
+.trim:
+    mov rax, r10
+    xor rdx, rdx
+    div rbx
+    test rdx, rdx
+    jnz .text
+    mov r10, rax
+    inc r9
+    jmp .trim
+.text:
+    ; The value is R10 * 10^R9
+    mov rax, r9
+    test rax, rax
+    jns .size
+    neg rax
+.size:
+    add rax, 24          ; Sign, "0.", digits and the zeros
+    call str_alloc
+    mov rbx, rax
+    mov rdi, rax
+    test r8, r8
+    jz .digits
+    mov byte [rdi], '-'
+    inc rdi
+.digits:
+    mov rax, r10
+    call str_from_int
+    mov rsi, rax
+    mov rdx, [rsi-8]     ; Digit count
+    test r9, r9
+    js .fraction
+    mov rcx, rdx
+    rep movsb
+    mov rcx, r9          ; Zeros after a whole number
+    mov al, '0'
+    rep stosb
+    jmp .finish
+.fraction:
+    mov r11, r9
+    neg r11              ; Digits after the dot
+    cmp rdx, r11
+    jbe .below_one
+    mov rcx, rdx
+    sub rcx, r11
+    rep movsb
+    mov byte [rdi], '.'
+    inc rdi
+    mov rcx, r11
+    rep movsb
+    jmp .finish
+.below_one:
+    mov byte [rdi], '0'
+    mov byte [rdi+1], '.'
+    add rdi, 2
+    mov rcx, r11
+    sub rcx, rdx
+    mov al, '0'
+    rep stosb
+    mov rcx, rdx
+    rep movsb
+.finish:
+    mov rax, rbx
+    call str_finish
+    jmp .done
+.nan:
+    mov rax, float_nan_text
+    jmp .done
+.infinity:
+    mov rax, float_inf_text
+    test r8, r8
+    jz .done
+    mov rax, float_neg_inf_text
+.done:
+    add rsp, 16
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    ret
+
+float_result:
+    ; XMM0 as a float value, its text in RAX and the tag in RDX
+    call float_to_string
+    mov rdx, {float}
+    clc
+    ret
+
+float_whole:
+    ; Carry clear when XMM0 rounds to an integer that fits in 64 bits, else
+    ; carry set and the message in RSI. Doubles this large are whole already
+    ucomisd xmm0, [float_int_min]
+    jb .outside          ; Below -2^63, or NaN
+    ucomisd xmm0, [float_int_end]
+    jae .outside
+    clc
+    ret
+.outside:
+    mov rsi, math_range_msg
+    stc
+    ret
+
+float_floor:
+    ; XMM0 rounded down, as an integer in RAX, carry set when it does not fit
+    call float_whole
+    jc .done
+    cvttsd2si rax, xmm0
+    cvtsi2sd xmm1, rax
+    ucomisd xmm0, xmm1
+    jae .whole
+    dec rax
+.whole:
+    clc
+.done:
+    ret
+
+float_ceil:
+    ; XMM0 rounded up, as an integer in RAX, carry set when it does not fit
+    call float_whole
+    jc .done
+    cvttsd2si rax, xmm0
+    cvtsi2sd xmm1, rax
+    ucomisd xmm0, xmm1
+    jbe .whole
+    inc rax
+.whole:
+    clc
+.done:
+    ret
+
+math_sqrt:
+    call value_to_double
+    jc .done
+    sqrtsd xmm0, xmm0
+    call float_result
+.done:
+    ret
+
+math_sin:
+    call value_to_double
+    jc .done
+    sub rsp, 8
+    movsd [rsp], xmm0
+    fld qword [rsp]
+    fsin
+    fstp qword [rsp]
+    movsd xmm0, [rsp]
+    add rsp, 8
+    call float_result
+.done:
+    ret
+
+math_cos:
+    call value_to_double
+    jc .done
+    sub rsp, 8
+    movsd [rsp], xmm0
+    fld qword [rsp]
+    fcos
+    fstp qword [rsp]
+    movsd xmm0, [rsp]
+    add rsp, 8
+    call float_result
+.done:
+    ret
+
+math_log:
+    ; Natural logarithm, ln(x) = ln(2) * log2(x)
+    call value_to_double
+    jc .done
+    sub rsp, 8
+    movsd [rsp], xmm0
+    fldln2
+    fld qword [rsp]
+    fyl2x
+    fstp qword [rsp]
+    movsd xmm0, [rsp]
+    add rsp, 8
+    call float_result
+.done:
+    ret
+
+math_abs:
+    ; Integers stay integers
+    cmp rdx, {number}
+    jne .float
+    test rax, rax
+    jns .done
+    neg rax
+    clc
+    ret
+.float:
+    call value_to_double
+    jc .done
+    movq rax, xmm0
+    btr rax, 63
+    movq xmm0, rax
+    call float_result
+.done:
+    ret
+
+math_floor:
+    cmp rdx, {number}
+    je .done             ; Already whole, carry clear
+    call value_to_double
+    jc .done
+    call float_floor
+    jc .done
+    mov rdx, {number}
+.done:
+    ret
+
+math_ceil:
+    cmp rdx, {number}
+    je .done
+    call value_to_double
+    jc .done
+    call float_ceil
+    jc .done
+    mov rdx, {number}
+.done:
+    ret
+
+math_round:
+    ; Halfway values round away from zero
+    cmp rdx, {number}
+    je .done
+    call value_to_double
+    jc .done
+    xorpd xmm1, xmm1
+    ucomisd xmm0, xmm1
+    jb .negative
+    addsd xmm0, [float_half]
+    call float_floor
+    jmp .whole
+.negative:
+    subsd xmm0, [float_half]
+    call float_ceil
+.whole:
+    jc .done
+    mov rdx, {number}
+.done:
+    ret
+
+math_min:
+    push r8
+    xor r8, r8
+    call math_pick
+    pop r8
+    ret
+
+math_max:
+    push r8
+    mov r8, 1
+    call math_pick
+    pop r8
+    ret
+
+math_pick:
+    ; The smaller (R8 = 0) or larger (R8 = 1) of the two arguments. Two
+    ; integers give an integer, anything else a float
+    cmp rdx, {number}
+    jne .floats
+    cmp rcx, {number}
+    jne .floats
+    test r8, r8
+    jnz .int_max
+    cmp rax, rbx
+    cmovg rax, rbx
+    clc
+    ret
+.int_max:
+    cmp rax, rbx
+    cmovl rax, rbx
+    clc
+    ret
+.floats:
+    call value_to_double
+    jc .done
+    movapd xmm2, xmm0
+    push rax
+    push rdx
+    mov rax, rbx
+    mov rdx, rcx
+    call value_to_double
+    pop rdx
+    pop rax
+    jc .done
+    test r8, r8
+    jnz .float_max
+    minsd xmm0, xmm2
+    call float_result
+    ret
+.float_max:
+    maxsd xmm0, xmm2
+    call float_result
+.done:
+    ret
+
+math_pow:
+    ; The first argument raised to the second, a float
+    call value_to_double
+    jc .done
+    movapd xmm2, xmm0    ; Base
+    push rax
+    push rdx
+    mov rax, rbx
+    mov rdx, rcx
+    call value_to_double ; Exponent
+    pop rdx
+    pop rax
+    jc .done
+    push r8
+    sub rsp, 16
+    movsd [rsp], xmm0
+    xor r8, r8           ; Negate the result
+    xorpd xmm1, xmm1
+    ucomisd xmm0, xmm1
+    jne .exponent
+    movsd xmm0, [float_one]  ; x ** 0 is 1
+    jmp .result
+.exponent:
+    ucomisd xmm2, xmm1
+    ja .positive_base
+    jb .negative_base
+    xorpd xmm0, xmm0     ; 0 ** y is 0 for y > 0, inf for y < 0
+    ucomisd xmm1, [rsp]
+    jb .result
+    movsd xmm0, [float_inf]
+    jmp .result
+.negative_base:
+    ; Only whole exponents are defined, odd ones keep the sign
+    cvttsd2si rax, xmm0
+    cvtsi2sd xmm1, rax
+    ucomisd xmm0, xmm1
+    jne .nan
+    and rax, 1
+    mov r8, rax
+    movq rax, xmm2
+    btr rax, 63
+    movq xmm2, rax
+.positive_base:
+    ; 2 ** (y * log2(x)), split into a whole and a fractional power of two
+    movsd [rsp+8], xmm2
+    fld qword [rsp]
+    fld qword [rsp+8]
+    fyl2x
+    fld st0
+    frndint
+    fsub st1, st0
+    fxch
+    f2xm1
+    fld1
+    faddp
+    fscale
+    fstp st1
+    fstp qword [rsp]
+    movsd xmm0, [rsp]
+    test r8, r8
+    jz .result
+    movq rax, xmm0
+    btc rax, 63
+    movq xmm0, rax
+    jmp .result
+.nan:
+    movsd xmm0, [float_nan]
+.result:
+    add rsp, 16
+    pop r8
+    call float_result
+.done:
+    ret
+
+math_trap:
+    ; A math function failed with the message in RSI, the location is in
+    ; RAX:RBX
+    mov rdi, {exit}
+    jmp runtime_trap
+
+"#, number = TAG_NUMBER, float = TAG_FLOAT, exit = EXIT_MATH));
+}
+
+// Emit the clock built-ins, made of clock_gettime and nanosleep syscalls
+pub fn emit_time_helpers(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str("time_spec dq 0, 0\n");
+
+    text_section.push_str(&format!(r#"time_now:
+    ; time.now(), the milliseconds since the Unix epoch in RAX
+    push rcx
+    push rdi
+    push rsi
+    push r11
+    mov rax, 228         ; sys_clock_gettime
+    xor rdi, rdi         ; CLOCK_REALTIME
+    mov rsi, time_spec
+    syscall
+    mov rax, [time_spec+8]
+    xor rdx, rdx
+    mov rcx, 1000000
+    div rcx              ; Nanoseconds to milliseconds
+    imul rcx, [time_spec], 1000
+    add rax, rcx
+    mov rdx, {number}
+    pop r11
+    pop rsi
+    pop rdi
+    pop rcx
+    ret
+
+time_sleep:
+    ; time.sleep(ms) for the milliseconds in RAX, nothing for a negative
+    ; count. A sleep interrupted by a signal goes on for the time left
+    push rcx
+    push rdi
+    push rsi
+    push r11
+    test rax, rax
+    jle .done
+    xor rdx, rdx
+    mov rcx, 1000
+    div rcx
+    mov [time_spec], rax
+    imul rdx, rdx, 1000000
+    mov [time_spec+8], rdx
+.sleep:
+    mov rax, 35          ; sys_nanosleep
+    mov rdi, time_spec
+    mov rsi, time_spec   ; The time left goes back into the same spec
+    syscall
+    cmp rax, -4          ; EINTR
+    je .sleep
+.done:
+    xor rax, rax
+    mov rdx, {null}
+    pop r11
+    pop rsi
+    pop rdi
+    pop rcx
+    ret
+
+"#, number = TAG_NUMBER, null = TAG_NULL));
+}
+
+// Exit code when random.int is given an empty range
+pub const EXIT_RANDOM_RANGE: u8 = 1;
+
+// Emit the random built-ins, a splitmix64 generator. It seeds itself from
+// getrandom on first use, or from the clock when getrandom fails, unless
+// random.seed(n) gave it a seed first.
+pub fn emit_random_helpers(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str("random_state dq 0\n");
+    data_section.push_str("random_seeded db 0\n");
+    data_section.push_str(&format!("random_float_scale dq {:#x}\n", 2f64.powi(-53).to_bits()));
+    data_section.push_str(&string_data("random_range_msg", "runtime error: random.int needs lo <= hi at "));
+
+    text_section.push_str(&format!(r#"random_next:
+    ; The next 64 random bits in RAX
+    cmp byte [random_seeded], 0
+    jne .step
+    push rdx
+    call random_seed_os
+    pop rdx
+.step:
+    push rcx
+    mov rax, 0x9e3779b97f4a7c15
+    add rax, [random_state]
+    mov [random_state], rax
+    mov rcx, rax
+    shr rcx, 30
+    xor rax, rcx
+    mov rcx, 0xbf58476d1ce4e5b9
+    imul rax, rcx
+    mov rcx, rax
+    shr rcx, 27
+    xor rax, rcx
+    mov rcx, 0x94d049bb133111eb
+    imul rax, rcx
+    mov rcx, rax
+    shr rcx, 31
+    xor rax, rcx
+    pop rcx
+    ret
+
+random_seed:
+    ; random.seed(n) for the seed in RAX, leaves null
+    mov [random_state], rax
+    mov byte [random_seeded], 1
+    xor rax, rax
+    mov rdx, {null}
+    ret
+
+random_seed_os:
+    ; random.seed() without a seed: 8 bytes from getrandom, falling back to
+    ; the nanoseconds of the clock. Leaves null
+    push rcx
+    push rdi
+    push rsi
+    push r11
+    mov rax, 318         ; sys_getrandom
+    mov rdi, random_state
+    mov rsi, 8
+    xor rdx, rdx
+    syscall
+    cmp rax, 8
+    je .seeded
+    mov rax, 228         ; sys_clock_gettime
+    xor rdi, rdi         ; CLOCK_REALTIME
+    mov rsi, time_spec
+    syscall
+    imul rax, [time_spec], 1000000000
+    add rax, [time_spec+8]
+    mov [random_state], rax
+.seeded:
+    mov byte [random_seeded], 1
+    xor rax, rax
+    mov rdx, {null}
+    pop r11
+    pop rsi
+    pop rdi
+    pop rcx
+    ret
+
+random_int:
+    ; random.int(lo, hi) for lo in RAX and hi in RBX, both included. The
+    ; carry flag is set when hi is below lo
+    cmp rbx, rax
+    jl .empty
+    push rcx
+    push rax
+    mov rcx, rbx
+    sub rcx, rax
+    inc rcx              ; Size of the range, 0 when it spans all 64 bits
+    call random_next
+    test rcx, rcx
+    jz .full
+    mul rcx              ; RDX = bits * size / 2**64, below size
+    mov rax, rdx
+.full:
+    pop rcx
+    add rax, rcx
+    pop rcx
+    mov rdx, {number}
+    clc
+    ret
+.empty:
+    stc
+    ret
+
+random_float:
+    ; random.float(), a float in [0, 1) made of the top 53 random bits
+    call random_next
+    shr rax, 11
+    cvtsi2sd xmm0, rax
+    mulsd xmm0, [random_float_scale]
+    jmp float_result
+
+random_range_trap:
+    ; random.int was given hi below lo, the location is in RAX:RBX
+    mov rsi, random_range_msg
+    mov rdi, {exit}
+    jmp runtime_trap
+
+"#, number = TAG_NUMBER, null = TAG_NULL, exit = EXIT_RANDOM_RANGE));
+}
+
+// Exit code when char.fromCode is given a number that is no code point
+pub const EXIT_CHAR_CODE: u8 = 1;
+
+// Emit the char helpers. A char is its Unicode code point, strings hold it
+// UTF-8 encoded.
+pub fn emit_char_helpers(text_section: &mut String, data_section: &mut String) {
+    data_section.push_str(&string_data("char_code_msg", "runtime error: char.fromCode needs a Unicode code point at "));
+
+    text_section.push_str(&format!(r#"str_char_at:
+    ; Character RAX of the string in RSI as a char in RAX, tag in RDX. Null
+    ; when the index is outside the string
+    test rax, rax
+    js .missing
+    call str_char_offset
+    push rbx
+    mov rbx, [rsi-8]
+    add rbx, rsi
+    cmp rax, rbx
+    pop rbx
+    jae .missing         ; Clamped to the end
+    push rsi
+    mov rsi, rax
+    call char_decode
+    pop rsi
+    mov rdx, {char}
+    ret
+.missing:
+    xor rax, rax
+    mov rdx, {null}
+    ret
+
+char_decode:
+    ; Code point of the UTF-8 character at RSI, returned in RAX
+    push rbx
+    push rcx
+    push rsi
+    movzx rax, byte [rsi]
+    cmp rax, 0x80
+    jb .done             ; ASCII
+    cmp rax, 0xE0
+    jb .two
+    cmp rax, 0xF0
+    jb .three
+    and rax, 0x07
+    mov rcx, 3           ; Continuation bytes
+    jmp .continue
+.three:
+    and rax, 0x0F
+    mov rcx, 2
+    jmp .continue
+.two:
+    and rax, 0x1F
+    mov rcx, 1
+.continue:
+    inc rsi
+    movzx rbx, byte [rsi]
+    and rbx, 0x3F
+    shl rax, 6
+    or rax, rbx
+    dec rcx
+    jnz .continue
+.done:
+    pop rsi
+    pop rcx
+    pop rbx
+    ret
+
+str_from_char:
+    ; The char RAX as a new string
+    push rbx
+    push rcx
+    push rdi
+    mov rbx, rax
+    mov rax, 4           ; Up to four bytes
+    call str_alloc
+    push rax
+    mov rdi, rax
+    cmp rbx, 0x80
+    jb .one
+    cmp rbx, 0x800
+    jb .two
+    cmp rbx, 0x10000
+    jb .three
+    mov rcx, rbx
+    shr rcx, 18
+    or cl, 0xF0
+    mov [rdi], cl
+    inc rdi
+    mov rcx, rbx
+    shr rcx, 12
+    and cl, 0x3F
+    or cl, 0x80
+    mov [rdi], cl
+    inc rdi
+    jmp .last_two
+.three:
+    mov rcx, rbx
+    shr rcx, 12
+    or cl, 0xE0
+    mov [rdi], cl
+    inc rdi
+.last_two:
+    mov rcx, rbx
+    shr rcx, 6
+    and cl, 0x3F
+    or cl, 0x80
+    mov [rdi], cl
+    inc rdi
+    jmp .last
+.two:
+    mov rcx, rbx
+    shr rcx, 6
+    or cl, 0xC0
+    mov [rdi], cl
+    inc rdi
+.last:
+    and bl, 0x3F
+    or bl, 0x80
+.one:
+    mov [rdi], bl
+    inc rdi
+    pop rax
+    call str_finish
+    pop rdi
+    pop rcx
+    pop rbx
+    ret
+
+char_from_code:
+    ; char.fromCode(n) for n in RAX. The carry flag is set when n is no
+    ; Unicode code point: negative, a surrogate or above 10FFFF
+    cmp rax, 0x10FFFF
+    ja .invalid          ; Negative numbers are huge unsigned
+    cmp rax, 0xD800
+    jb .valid
+    cmp rax, 0xDFFF
+    jbe .invalid
+.valid:
+    mov rdx, {char}
+    clc
+    ret
+.invalid:
+    stc
+    ret
+
+char_is_digit:
+    ; 1 in RAX when the char in RAX is an ASCII digit, else 0
+    sub rax, '0'
+    cmp rax, 10
+    setb al
+    movzx rax, al
+    ret
+
+char_is_letter:
+    ; 1 in RAX when the char in RAX is an ASCII letter, else 0
+    or rax, 0x20         ; Lower case
+    sub rax, 'a'
+    cmp rax, 26
+    setb al
+    movzx rax, al
+    ret
+
+char_is_whitespace:
+    ; 1 in RAX when the char in RAX is ASCII whitespace, else 0
+    push rbx
+    cmp rax, 0x80
+    jae .no
+    mov bl, al
+    call byte_is_space
+    jne .no
+    mov rax, 1
+    pop rbx
+    ret
+.no:
+    xor rax, rax
+    pop rbx
+    ret
+
+char_code_trap:
+    ; char.fromCode was given a number that is no code point, the location
+    ; is in RAX:RBX
+    mov rsi, char_code_msg
+    mov rdi, {exit}
+    jmp runtime_trap
+
+"#, char = TAG_CHAR, null = TAG_NULL, exit = EXIT_CHAR_CODE));
+}
@@ -0,0 +1,876 @@
+// End-to-end tests: compile .spp programs with the skibidipp binary and run
+// them. Running needs nasm and ld, so the run tests are ignored by default;
+// `cargo test -- --include-ignored` runs them too.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// A fresh directory for one test's source and executable
+fn test_dir() -> PathBuf {
+    let id = NEXT_DIR.fetch_add(1, Ordering::SeqCst);
+    let dir = std::env::temp_dir().join(format!("spp-test-{}-{}", std::process::id(), id));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Compile a program with the given compiler options
+fn compile(source: &str, args: &[&str]) -> (Output, PathBuf) {
+    let dir = test_dir();
+    let source_path = dir.join("main.spp");
+    fs::write(&source_path, source).unwrap();
+    let exe_path = dir.join("main");
+    let output = Command::new(env!("CARGO_BIN_EXE_skibidipp"))
+        .arg(&source_path)
+        .arg(format!("--output={}", exe_path.display()))
+        .args(args)
+        .output()
+        .unwrap();
+    (output, exe_path)
+}
+
+// What the compiler printed to stderr for a program it rejected
+fn compile_error(source: &str, args: &[&str]) -> String {
+    let (output, _) = compile(source, args);
+    assert!(!output.status.success(), "expected a compile error");
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+struct Run {
+    stdout: String,
+    stderr: String,
+    code: i32,
+}
+
+// Compile and run a program. A missing assembler fails the test instead of
+// skipping it, so an ignored test that was asked for always really runs.
+fn run_with(source: &str, args: &[&str], program_args: &[&str], stdin: &str) -> Run {
+    assert!(Command::new("nasm").arg("-v").output().is_ok(), "nasm is needed to run this test");
+    let (output, exe_path) = compile(source, args);
+    assert!(output.status.success(), "compile failed: {}", String::from_utf8_lossy(&output.stderr));
+    let mut child = Command::new(&exe_path)
+        .args(program_args)
+        .current_dir(exe_path.parent().unwrap())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    Run {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        code: output.status.code().unwrap_or(-1),
+    }
+}
+
+// Run a program that should exit with 0 and return its output
+fn output_of(source: &str) -> String {
+    let run = run_with(source, &[], &[], "");
+    assert_eq!(run.code, 0, "stderr: {}", run.stderr);
+    run.stdout
+}
+
+// String methods (user-026)
+
+#[test]
+#[ignore = "requires nasm"]
+fn string_methods_on_runtime_strings() {
+    let out = output_of(r#"
+let name = "  Stiwyy ";
+let trimmed = name.trim();
+console.print(trimmed.toUpper());
+console.print(trimmed.toLower());
+console.print(trimmed.len());
+console.print(trimmed.substring(1, 3));
+console.print(trimmed.indexOf("wy"));
+console.print(trimmed.contains("iw"));
+console.print(trimmed.startsWith("St"));
+console.print("a,b,c".split(","));
+console.print(trimmed.replace("y", "Y"));
+exit(0);
+"#);
+    assert_eq!(out, "STIWYY\nstiwyy\n6\nti\n3\ntrue\ntrue\n[a, b, c]\nStiwYY\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn string_methods_fold_for_constants() {
+    let out = output_of(r#"
+const s = "héllo";
+console.print(s.len());
+console.print(s.substring(1));
+console.print(s.indexOf("z"));
+exit(0);
+"#);
+    assert_eq!(out, "5\néllo\n-1\n");
+}
+
+#[test]
+fn unknown_string_method_is_a_compile_error() {
+    let stderr = compile_error("let s = \"abc\";\nconsole.print(s.shout());\nexit(0);\n", &[]);
+    assert!(stderr.contains("shout"), "{}", stderr);
+}
+
+// Array methods (user-027)
+
+#[test]
+#[ignore = "requires nasm"]
+fn array_methods_on_runtime_arrays() {
+    let out = output_of(r#"
+let a = [3, 1, 2];
+a.push(5);
+console.print(a.len());
+console.print(a.pop());
+console.print(a.slice(1, 3));
+console.print(a.contains(2));
+a.sort();
+console.print(a);
+a.reverse();
+console.print(a);
+let names = ["bob", "al"];
+names.sort();
+console.print(names);
+exit(0);
+"#);
+    assert_eq!(out, "4\n5\n[1, 2]\ntrue\n[1, 2, 3]\n[3, 2, 1]\n[al, bob]\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn floats_are_ordered_by_value() {
+    let out = output_of(r#"
+let a = [10.5, 9.5];
+a.push(2);
+a.sort();
+console.print(a);
+console.print(a.contains(9.5));
+console.print(a.contains(9));
+const c = [10.5, 9.5, 2];
+console.print(c.sort());
+exit(0);
+"#);
+    assert_eq!(out, "[2, 9.5, 10.5]\ntrue\nfalse\n[2, 9.5, 10.5]\n");
+}
+
+// Maps (user-028)
+
+#[test]
+#[ignore = "requires nasm"]
+fn map_lookup_assignment_and_methods() {
+    let out = output_of(r#"
+let m = { "a": 1, "b": 2 };
+m["c"] = 3;
+m["a"] = 10;
+console.print(m["a"]);
+console.print(m["zz"] == null);
+console.print(m.has("b"));
+m.remove("b");
+console.print(m.has("b"));
+console.print(m.len());
+console.print(m.keys());
+exit(0);
+"#);
+    assert_eq!(out, "10\n1\ntrue\nfalse\n2\n[a, c]\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn map_literals_inside_blocks() {
+    let out = output_of(r#"
+if (true) {
+    let n = { 1: "one", true: "yes", null: "none" };
+    console.print(n[1]);
+    console.print(n[true]);
+    console.print(n[null]);
+}
+exit(0);
+"#);
+    assert_eq!(out, "one\nyes\nnone\n");
+}
+
+// Structs (user-029)
+
+#[test]
+#[ignore = "requires nasm"]
+fn struct_fields_are_read_and_written() {
+    let out = output_of(r#"
+struct Point { x: int, y: int }
+struct Line { start: Point, end: Point }
+let p = Point { x: 1, y: 2 };
+p.x = 3;
+console.print(p);
+console.print(p.x + 1);
+let line = Line { start: Point { x: 0, y: 5 }, end: p };
+line.start.y = 0;
+console.print(line.start.y);
+exit(0);
+"#);
+    assert_eq!(out, "Point { x: 3, y: 2 }\n4\n0\n");
+}
+
+#[test]
+fn struct_errors_are_compile_errors() {
+    let stderr = compile_error("struct Point { x: int }\nstruct Point { y: int }\nexit(0);\n", &[]);
+    assert!(stderr.contains("Struct 'Point' already defined"), "{}", stderr);
+    let stderr = compile_error("struct Point { x: int, y: int }\nlet p = Point { x: 1 };\nexit(0);\n", &[]);
+    assert!(stderr.contains("Missing field 'y'"), "{}", stderr);
+    let stderr = compile_error("struct Point { x: int }\nlet p = Point { x: \"one\" };\nexit(0);\n", &[]);
+    assert!(stderr.contains("expects int, found string"), "{}", stderr);
+}
+
+// Enums and match (user-030)
+
+#[test]
+#[ignore = "requires nasm"]
+fn match_on_enum_variants() {
+    let out = output_of(r#"
+enum Color { Red, Green, Blue(int) }
+let c = Blue(7);
+match (c) {
+    Red => console.print("red");
+    Blue(n) => console.print(n);
+    _ => console.print("something else");
+}
+c = Green;
+match (c) {
+    Blue(_) => console.print("blue");
+    _ => console.print("not blue");
+}
+exit(0);
+"#);
+    assert_eq!(out, "7\nnot blue\n");
+}
+
+#[test]
+fn match_errors_are_compile_errors() {
+    let stderr = compile_error("enum Color { Red, Green }\nlet c = Red;\nmatch (c) {\n    Red => exit(1);\n}\nexit(0);\n", &[]);
+    assert!(stderr.contains("Non-exhaustive match: Green not covered at line 3, column 1"), "{}", stderr);
+    let stderr = compile_error("let n = 1;\nmatch (n) {\n    _ => exit(1);\n    1 => exit(2);\n}\n", &[]);
+    assert!(stderr.contains("Unreachable match arm 2: earlier arms already match every value at line 2, column 1"), "{}", stderr);
+    let stderr = compile_error("enum Color { Red }\nlet c = Purple;\nexit(0);\n", &[]);
+    assert!(stderr.contains("Purple"), "{}", stderr);
+    let stderr = compile_error("enum Color { Red }\nenum Light { Red }\nexit(0);\n", &[]);
+    assert!(stderr.contains("Variant 'Red' is already defined"), "{}", stderr);
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn match_gives_a_value() {
+    let out = output_of(r#"
+enum Shape { Circle(int), Square(int), Empty }
+let s = Square(4);
+let area = match (s) {
+    Circle(r) => r * r * 3,
+    Square(side) => side * side,
+    Empty => 0,
+};
+console.print(area);
+let code = 404;
+let text = match (code) { 200 => "ok", 404 => "not found", _ => "other" };
+console.print(text);
+text = match (code) { 200 => "fine", _ => "bad" };
+console.print(text);
+exit(0);
+"#);
+    assert_eq!(out, "16\nnot found\nbad\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn match_arms_end_with_commas() {
+    let out = output_of("let n = 2;\nmatch (n) {\n    1 => console.print(\"one\"),\n    2 => console.print(\"two\"),\n    _ => { console.print(\"many\"); },\n}\nexit(0);\n");
+    assert_eq!(out, "two\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn bindings_hide_variables_for_their_arm() {
+    let out = output_of(r#"
+enum Shape { Circle(int), Named(string) }
+let r = "outer";
+match (Circle(3)) {
+    Circle(r) => console.print(r * 2),
+    Named(r) => console.print(r),
+}
+console.print(r);
+match (Named("fresh")) {
+    Circle(size) => console.print(size),
+    Named(label) => console.print(label),
+}
+let label = "declared after";
+console.print(label);
+exit(0);
+"#);
+    assert_eq!(out, "6\nouter\nfresh\ndeclared after\n");
+}
+
+#[test]
+fn bindings_cannot_be_assigned() {
+    let stderr = compile_error("enum Box { Full(int) }\nmatch (Full(1)) {\n    Full(n) => { n = 2; },\n}\nexit(0);\n", &[]);
+    assert!(stderr.contains("Cannot assign to 'n', a match binding"), "{}", stderr);
+}
+
+#[test]
+fn match_value_arms_must_agree() {
+    let stderr = compile_error("let code = 1;\nlet t = match (code) { 1 => \"one\", _ => 2 };\nexit(0);\n", &[]);
+    assert!(stderr.contains("Match arm 2 gives int, the first arm gives string"), "{}", stderr);
+}
+
+// Else if chains (user-031)
+
+#[test]
+#[ignore = "requires nasm"]
+fn else_if_chains_take_the_first_true_branch() {
+    let source = "let h = console.readInt();\nif (h < 12) {\n    console.print(\"morning\");\n} else if (h < 18) {\n    console.print(\"afternoon\");\n} else if (h < 22) {\n    console.print(\"evening\");\n} else {\n    console.print(\"night\");\n}\nexit(0);\n";
+    for (hour, expected) in [("3", "morning"), ("15", "afternoon"), ("20", "evening"), ("23", "night")] {
+        let run = run_with(source, &[], &[], &format!("{}\n", hour));
+        assert_eq!(run.stdout, format!("{}\n", expected));
+    }
+    let out = output_of("let x = 5;\nif (x > 9) {\n    console.print(1);\n} else if (x > 7) {\n    console.print(2);\n}\nconsole.print(3);\nexit(0);\n");
+    assert_eq!(out, "3\n");
+}
+
+// Integer operators (user-032)
+
+#[test]
+#[ignore = "requires nasm"]
+fn integer_operators_fold_like_the_runtime() {
+    let out = output_of(r#"
+const big = 1 << 20;
+console.print(big * 1024 / 1024);
+console.print(-7 % 3);
+console.print(2 ** 10);
+console.print(2 ** -1);
+console.print(-16 >> 2);
+console.print(6 & 3 | 8 ^ 1);
+console.print(~5);
+let x = 40;
+console.print(1 << x);
+console.print(2 ** x);
+const k = 2 ** 40;
+console.print(k);
+console.print(1 << 40);
+exit(0);
+"#);
+    assert_eq!(out, "1048576\n-1\n1024\n0\n-4\n11\n-6\n1099511627776\n1099511627776\n1099511627776\n1099511627776\n");
+}
+
+#[test]
+fn folded_results_outside_64_bits_are_compile_errors() {
+    for source in ["const k = 2 ** 63;\nexit(0);\n", "const a = 1 << 62;\nlet b = a * 2;\nexit(0);\n",
+                   "let m = -(1 << 62) * 2 / -1;\nexit(0);\n"] {
+        let stderr = compile_error(source, &[]);
+        assert!(stderr.contains("Constant expression result does not fit in 64 bits at line "), "{}", stderr);
+    }
+    let stderr = compile_error("const m = 7 % 0;\nexit(0);\n", &[]);
+    assert!(stderr.contains("Modulo by zero"), "{}", stderr);
+}
+
+// Compound assignment (user-033)
+
+#[test]
+#[ignore = "requires nasm"]
+fn compound_assignment_and_increments() {
+    let source = r#"
+let n = 10;
+n += 5;
+n -= 3;
+n *= 4;
+n /= 6;
+n %= 5;
+n++;
+console.print(n);
+n--;
+n--;
+console.print(n);
+let arr = [1, 2, 3];
+arr[1] += 10;
+arr[2]++;
+console.print(arr);
+struct P { x: int, y: int }
+let p = P { x: 1, y: 2 };
+p.x *= 7;
+p.y--;
+console.print(p.x);
+console.print(p.y);
+let s = "ab";
+s += "cd";
+s += console.readLine();
+console.print(s);
+exit(0);
+"#;
+    let run = run_with(source, &[], &[], "hi\n");
+    assert_eq!(run.stdout, "4\n2\n[1, 12, 4]\n7\n1\nabcdhi\n");
+}
+
+// Runtime traps (user-034)
+
+#[test]
+#[ignore = "requires nasm"]
+fn division_by_zero_traps_with_its_location() {
+    let run = run_with("let zero = console.readInt();\nconsole.print(7 / zero);\nexit(0);\n", &[], &[], "0\n");
+    assert_eq!(run.code, 136);
+    assert!(run.stderr.starts_with("runtime error: division by zero at "), "{}", run.stderr);
+    assert!(run.stderr.trim_end().ends_with("main.spp:2:17"), "{}", run.stderr);
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn overflow_traps_only_with_overflow_checks() {
+    let source = "let n = console.readInt();\nlet m = n ** 40;\nconsole.print(m * m);\nexit(0);\n";
+    let run = run_with(source, &[], &[], "2\n");
+    assert_eq!((run.code, run.stdout.as_str()), (0, "0\n"));
+    let run = run_with(source, &["--overflow-checks"], &[], "2\n");
+    assert_eq!(run.code, 134);
+    assert!(run.stderr.starts_with("runtime error: integer overflow at "), "{}", run.stderr);
+    assert!(run.stderr.trim_end().ends_with("main.spp:3:17"), "{}", run.stderr);
+}
+
+// Console input (user-035)
+
+#[test]
+#[ignore = "requires nasm"]
+fn read_int_gives_numbers_and_null() {
+    let source = "let n = console.readInt();\nif (n == null) {\n    console.print(\"none\");\n    exit(0);\n}\nconsole.print(n + 1);\nexit(0);\n";
+    let run = run_with(source, &[], &[], "41\n");
+    assert_eq!(run.stdout, "42\n");
+    let run = run_with(source, &[], &[], "forty\n");
+    assert_eq!(run.stdout, "none\n");
+    let run = run_with(source, &[], &[], "9223372036854775808\n");
+    assert_eq!(run.stdout, "none\n");
+    let run = run_with("console.print(console.readInt());\nexit(0);\n", &[], &[], "-9223372036854775808\n");
+    assert_eq!(run.stdout, "-9223372036854775808\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn read_all_takes_input_of_any_size() {
+    let input = "x".repeat(99) + "\n";
+    let run = run_with("let a = console.readAll();\nconsole.print(a.len());\nexit(0);\n", &[], &[], &input.repeat(30000));
+    assert_eq!(run.code, 0, "stderr: {}", run.stderr);
+    assert_eq!(run.stdout, "3000000\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn arithmetic_on_null_input_is_a_runtime_error() {
+    let run = run_with("let n = console.readInt();\nconsole.print(n + 1);\nexit(0);\n", &[], &[], "");
+    assert_eq!(run.code, 1);
+    assert_eq!(run.stdout, "");
+    assert!(run.stderr.starts_with("runtime error: null operand at "), "{}", run.stderr);
+    assert!(run.stderr.trim_end().ends_with("main.spp:2:17"), "{}", run.stderr);
+}
+
+// Console output (user-036)
+
+#[test]
+#[ignore = "requires nasm"]
+fn write_error_and_printf() {
+    let source = "let x = 3;\nlet y = 2.5;\nconsole.write(\"a\");\nconsole.write(\"b\\n\");\nconsole.error(\"oops\");\nconsole.eprint(\"again\");\nconsole.printf(\"x = {}, y = {:.2} {{}}\\n\", x, y);\nexit(0);\n";
+    let run = run_with(source, &[], &[], "");
+    assert_eq!(run.stdout, "ab\nx = 3, y = 2.50 {}\n");
+    assert_eq!(run.stderr, "oops\nagain\n");
+}
+
+#[test]
+fn printf_formats_are_checked() {
+    let stderr = compile_error("console.printf(\"{} {}\\n\", 1);\nexit(0);\n", &[]);
+    assert!(stderr.contains("Format string at line 1, column 1 has 2 placeholder(s) but 1 argument(s) were given"), "{}", stderr);
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn runtime_format_strings_are_checked() {
+    let source = "let f = console.readLine();\nconsole.printf(f, 1);\nconsole.print(\"after\");\nexit(0);\n";
+    let run = run_with(source, &[], &[], "{} {}\n");
+    assert_eq!(run.code, 1);
+    assert_eq!(run.stdout, "");
+    assert_eq!(run.stderr, "runtime error: format string does not match its arguments\n");
+}
+
+// Program arguments and environment (user-037)
+
+#[test]
+#[ignore = "requires nasm"]
+fn process_args_and_env() {
+    let source = r#"
+let args = process.args();
+console.print(args.len());
+console.print(args[1]);
+console.print(args[2]);
+console.print(process.env("CARGO_PKG_NAME"));
+console.print(process.env("SPP_SURELY_NOT_SET") == null);
+exit(0);
+"#;
+    // Cargo sets CARGO_PKG_NAME for the tests, the program inherits it
+    let run = run_with(source, &[], &["one", "two words"], "");
+    assert_eq!(run.stdout, "3\none\ntwo words\nskibidipp\n1\n");
+}
+
+#[test]
+fn process_calls_check_their_arguments() {
+    let stderr = compile_error("let home = process.env();\nexit(0);\n", &[]);
+    assert!(stderr.contains("process.env takes 1 argument(s) but 0 were given at line 1, column 12"), "{}", stderr);
+    let stderr = compile_error("let args = process.args(1);\nexit(0);\n", &[]);
+    assert!(stderr.contains("process.args takes 0 argument(s) but 1 were given at line 1, column 12"), "{}", stderr);
+}
+
+// Files (user-038)
+
+#[test]
+#[ignore = "requires nasm"]
+fn file_read_write_append_and_remove() {
+    let source = r#"
+console.print(fs.exists("notes.txt"));
+fs.write("notes.txt", "one\n");
+fs.append("notes.txt", "two\n");
+console.write(fs.read("notes.txt"));
+console.print(fs.exists("notes.txt"));
+fs.remove("notes.txt");
+console.print(fs.exists("notes.txt"));
+exit(0);
+"#;
+    let run = run_with(source, &[], &[], "");
+    assert_eq!(run.stdout, "false\none\ntwo\ntrue\nfalse\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn large_files_are_read_whole() {
+    let source = "let a = console.readAll();\nfs.write(\"big.txt\", a);\nlet b = fs.read(\"big.txt\");\nconsole.print(b.len());\nconsole.print(b == a);\nfs.remove(\"big.txt\");\nexit(0);\n";
+    let run = run_with(source, &[], &[], &"0123456789".repeat(300000));
+    assert_eq!(run.code, 0, "stderr: {}", run.stderr);
+    assert_eq!(run.stdout, "3000000\n1\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn failed_file_calls_name_the_errno() {
+    let run = run_with("console.print(\"start\");\nconsole.print(fs.read(\"missing.txt\"));\nexit(0);\n", &[], &[], "");
+    assert_eq!(run.code, 1);
+    assert_eq!(run.stdout, "start\n");
+    assert!(run.stderr.starts_with("runtime error: fs.read(\"missing.txt\") failed with ENOENT at "), "{}", run.stderr);
+    assert!(run.stderr.trim_end().ends_with("main.spp:2:15"), "{}", run.stderr);
+}
+
+// Math floats computed at runtime (user-039)
+
+#[test]
+#[ignore = "requires nasm"]
+fn runtime_math_floats_compare_by_value() {
+    let out = output_of(r#"
+random.seed(1);
+let r = math.sqrt(random.float());
+console.print(r < 0.9);
+console.print(r > 0.9);
+console.print(r <= r);
+exit(0);
+"#);
+    assert_eq!(out, "1\n0\n1\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn runtime_math_floats_compare_with_input() {
+    let source = "let n = console.readInt();\nlet r = math.sqrt(n);\nconsole.print(r < 1.5);\nconsole.print(math.sqrt(n) >= 1.4);\nexit(0);\n";
+    let run = run_with(source, &[], &[], "2\n");
+    assert_eq!(run.stdout, "1\n1\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn runtime_floats_print_like_folded_ones() {
+    let source = r#"
+let n = console.readInt();
+console.print(math.pow(n, -20));
+console.print(math.pow(10, -20));
+console.print(math.sqrt(n - 8));
+console.print(math.sqrt(2));
+console.print(math.min(n, 0.1));
+console.print(math.max(n, 1e300));
+console.print(math.sqrt(n * 100) == math.sqrt(1000));
+exit(0);
+"#;
+    let run = run_with(source, &[], &[], "10\n");
+    let folded = format!("{}", 1e300);
+    assert_eq!(run.stdout, format!("0.00000000000000000001\n0.00000000000000000001\n1.4142135623730951\n1.4142135623730951\n0.1\n{}\n1\n", folded));
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn rounding_outside_64_bits_is_a_runtime_error() {
+    let run = run_with("let n = console.readInt();\nconsole.print(math.round(math.pow(n, 2)));\nconsole.print(math.floor(math.pow(n, 70)));\nexit(0);\n", &[], &[], "3\n");
+    assert_eq!(run.code, 1);
+    assert_eq!(run.stdout, "9\n");
+    assert!(run.stderr.starts_with("runtime error: rounded value does not fit in 64 bits at ") && run.stderr.trim_end().ends_with("main.spp:3:15"), "{}", run.stderr);
+}
+
+#[test]
+fn arithmetic_on_runtime_floats_is_rejected() {
+    let stderr = compile_error("let n = console.readInt();\nconsole.print(math.sqrt(n) * 2);\nexit(0);\n", &[]);
+    assert!(stderr.contains("cannot be used in arithmetic") && stderr.contains("at line 2, column 15"), "{}", stderr);
+    let stderr = compile_error("let n = console.readInt();\nlet x = (n + 1) * 2.5;\nexit(0);\n", &[]);
+    assert!(stderr.contains("Float arithmetic only works on constants and float variables, not on values computed at runtime at line 2, column 17"), "{}", stderr);
+    let stderr = compile_error("let n = console.readInt();\nconsole.print(n * 0.5 < 2);\nexit(0);\n", &[]);
+    assert!(stderr.contains("at line 2, column 17"), "{}", stderr);
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn arithmetic_on_random_floats_is_a_runtime_error() {
+    let run = run_with("let r = random.float();\nconsole.print(r * 2);\nexit(0);\n", &[], &[], "");
+    assert_eq!(run.code, 1);
+    assert!(run.stderr.starts_with("runtime error: arithmetic needs integers at "), "{}", run.stderr);
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn ordering_null_input_is_a_runtime_error() {
+    let run = run_with("let n = console.readInt();\nconsole.print(n < 1);\nexit(0);\n", &[], &[], "");
+    assert_eq!(run.code, 1);
+    assert!(run.stderr.starts_with("runtime error: null operand at "), "{}", run.stderr);
+}
+
+// Random floats (user-040)
+
+#[test]
+#[ignore = "requires nasm"]
+fn random_floats_compare_by_value() {
+    let out = output_of(r#"
+random.seed(1);
+let _first = random.float();
+let f = random.float();
+let x = 0.5;
+console.print(f < x);
+console.print(f < 1);
+console.print(f >= 0);
+console.print(random.float() < 1.0);
+exit(0);
+"#);
+    assert_eq!(out, "0\n1\n1\n1\n");
+}
+
+// Number literals (user-041)
+
+#[test]
+#[ignore = "requires nasm"]
+fn number_literals_in_every_base() {
+    let out = output_of(r#"
+console.print(0xFF + 0b1010 + 0o7 + 1_000);
+console.print(2.5e1);
+console.print(7i32);
+console.print(5_000_000_000i64 + 0xFFFFFFFF);
+console.print(-9223372036854775808);
+console.print(-2147483648i32);
+exit(0);
+"#);
+    assert_eq!(out, "1272\n25\n7\n9294967295\n-9223372036854775808\n-2147483648\n");
+}
+
+#[test]
+fn out_of_range_literals_are_compile_errors() {
+    let stderr = compile_error("let big = 99999999999999999999;\nexit(0);\n", &[]);
+    assert!(stderr.contains("Malformed number literal '99999999999999999999': the value does not fit in 64 bits at "), "{}", stderr);
+    assert!(stderr.trim_end().ends_with("main.spp:1:11"), "{}", stderr);
+    let stderr = compile_error("let mask = 0xFFFFFFFFi32;\nexit(0);\n", &[]);
+    assert!(stderr.contains("does not fit in 32 bits"), "{}", stderr);
+    // One past the maximum is only allowed negated
+    let stderr = compile_error("let big = 9223372036854775808;\nexit(0);\n", &[]);
+    assert!(stderr.contains("Integer literal 9223372036854775808 does not fit in 64 bits, only its negation does at line 1, column 11"), "{}", stderr);
+    let stderr = compile_error("let big = -2147483648i32 ** 1;\nexit(0);\n", &[]);
+    assert!(stderr.contains("does not fit in 32 bits"), "{}", stderr);
+}
+
+// Lexer errors (user-042)
+
+#[test]
+fn every_lexer_error_is_reported() {
+    let stderr = compile_error("let a = 1 @ 2;\nlet s = \"open;\n", &[]);
+    let errors: Vec<&str> = stderr.lines().collect();
+    assert_eq!(errors.len(), 2, "{}", stderr);
+    assert!(errors[0].starts_with("Error: Unexpected character '@' at ") && errors[0].ends_with("main.spp:1:11"), "{}", stderr);
+    assert!(errors[1].starts_with("Error: Unterminated string at ") && errors[1].ends_with("main.spp:2:9"), "{}", stderr);
+}
+
+// String escapes, raw and multi-line strings (user-043)
+
+#[test]
+#[ignore = "requires nasm"]
+fn string_escapes_raw_and_multi_line_strings() {
+    let out = output_of("console.print(\"caf\\u{e9} \\x41\\tB\");\nconsole.print(r\"C:\\new\");\nconsole.print(\"\"\"\n    one\n      two\n    \"\"\");\nexit(0);\n");
+    assert_eq!(out, "café A\tB\nC:\\new\none\n  two\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn strings_keep_nul_characters() {
+    let source = r#"
+let s = console.readLine() + "a\0b";
+console.print(s);
+console.print(s.len());
+console.print(s + "c\x00" == "a\0bc\u{0}");
+console.print(s.replace("\0", "-"));
+console.print("x\0y\0z".split("\0").len());
+console.print("\xe9\xFF".len());
+console.print(fs.exists(s));
+exit(0);
+"#;
+    let run = run_with(source, &[], &[], "\n");
+    assert_eq!(run.code, 0, "stderr: {}", run.stderr);
+    assert_eq!(run.stdout, "a\0b\n3\n1\na-b\n3\n2\nfalse\n");
+}
+
+// Template strings (user-044)
+
+#[test]
+#[ignore = "requires nasm"]
+fn template_strings_interpolate() {
+    let source = r#"
+const NAME = "Ada";
+let age = console.readInt();
+console.print(`Hello ${NAME}, you are ${age + 1}`);
+console.print(`${1 + 2} and ${"x"}`);
+console.print(`cost: \${5}`);
+exit(0);
+"#;
+    let run = run_with(source, &[], &[], "36\n");
+    assert_eq!(run.stdout, "Hello Ada, you are 37\n3 and x\ncost: ${5}\n");
+}
+
+#[test]
+fn malformed_templates_are_compile_errors() {
+    let stderr = compile_error("console.print(`a ${}`);\nexit(0);\n", &[]);
+    assert!(stderr.contains("Empty '${}' in template string at "), "{}", stderr);
+    let stderr = compile_error("console.print(`a ${1 + 2`);\nexit(0);\n", &[]);
+    assert!(stderr.contains("Unterminated '${' in template string at "), "{}", stderr);
+}
+
+// Keywords (user-045)
+
+#[test]
+fn reserved_words_are_not_names() {
+    let stderr = compile_error("let if = 3;\nexit(0);\n", &[]);
+    assert!(stderr.contains("'if' is a reserved word and cannot be used as a variable name at line 1, column 5"), "{}", stderr);
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn names_may_start_with_reserved_words() {
+    let out = output_of("let iffy = 3;\nif (iffy == 3) { console.print(iffy); }\nexit(0);\n");
+    assert_eq!(out, "3\n");
+}
+
+// Chars (user-046)
+
+#[test]
+#[ignore = "requires nasm"]
+fn chars_index_compare_and_convert() {
+    let source = "let s = \"héllo\";\nlet c = s[1];\nconsole.print(c);\nconsole.print(c.code());\nconsole.print(char.fromCode(65));\nconsole.print(s[0] >= 'a');\nconsole.print(s[9] == null);\nexit(0);\n";
+    let run = run_with(source, &["--edition=2026"], &[], "");
+    assert_eq!(run.stdout, "é\n233\nA\n1\n1\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn stored_index_results_can_be_null() {
+    let source = "let s = \"ab\";\nlet i = console.readInt();\nlet c = s[i];\nconsole.print(c == null);\nlet m = {\"a\": 1};\nlet v = m[\"zz\"];\nconsole.print(v);\nlet arr = [1, 2];\nlet e = arr[i];\nconsole.print(e);\nexit(0);\n";
+    let run = run_with(source, &["--edition=2026"], &[], "5\n");
+    assert_eq!(run.stdout, "1\nnull\nnull\n");
+    let run = run_with(source, &["--edition=2026"], &[], "1\n");
+    assert_eq!(run.stdout, "0\nnull\n2\n");
+}
+
+#[test]
+#[ignore = "requires nasm"]
+fn single_quotes_are_strings_before_2026() {
+    let out = output_of("console.print('text');\nexit(0);\n");
+    assert_eq!(out, "text\n");
+}
+
+#[test]
+fn single_quotes_are_chars_in_2026() {
+    let stderr = compile_error("console.print('text');\nexit(0);\n", &["--edition=2026"]);
+    assert!(stderr.contains("use double quotes for a string"), "{}", stderr);
+    let stderr = compile_error("console.print('a' + 1);\nexit(0);\n", &["--edition=2026"]);
+    assert!(stderr.contains("Cannot do arithmetic on a char at line 1, column 19"), "{}", stderr);
+}
+
+// Comments (user-047)
+
+#[test]
+#[ignore = "requires nasm"]
+fn nested_and_doc_comments_are_skipped() {
+    let out = output_of("/* outer /* inner */ still outer */\n/// The answer\nconst A = 42;\n/** A label */\nlet label = \"x\";\nconsole.print(A);\nconsole.print(label);\nexit(0);\n");
+    assert_eq!(out, "42\nx\n");
+}
+
+// Lossless tokens (user-048)
+
+#[test]
+fn tokens_option_prints_tokens_and_trivia() {
+    let dir = test_dir();
+    let path = dir.join("main.spp");
+    fs::write(&path, "let x = 1; // one\nexit(x);\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_skibidipp")).arg("--tokens").arg(&path).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 17, "{}", stdout);
+    assert_eq!(lines[..3], ["1:1 Let \"let\"", "1:4 Whitespace \" \"", "1:5 Identifier(\"x\") \"x\""]);
+    assert_eq!(lines[9..11], ["1:12 LineComment \"// one\"", "1:18 Whitespace \"\\n\""]);
+}
+
+// Formatter (user-049)
+
+fn fmt(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_skibidipp")).arg("fmt").args(args).output().unwrap()
+}
+
+#[test]
+fn fmt_rewrites_and_check_reports() {
+    let dir = test_dir();
+    let messy = dir.join("messy.spp");
+    let tidy = dir.join("tidy.spp");
+    fs::write(&messy, "let x=1;// one\nif(x>0){console.print(x);}\n").unwrap();
+    fs::write(&tidy, "let x = 1;\n").unwrap();
+
+    let output = fmt(&["--check", messy.to_str().unwrap(), tidy.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{} is not formatted\n", messy.display()));
+    assert_eq!(fs::read_to_string(&messy).unwrap(), "let x=1;// one\nif(x>0){console.print(x);}\n");
+
+    assert!(fmt(&[messy.to_str().unwrap()]).status.success());
+    assert_eq!(fs::read_to_string(&messy).unwrap(), "let x = 1;  // one\nif (x > 0) {\n    console.print(x);\n}\n");
+    assert!(fmt(&["--check", messy.to_str().unwrap(), tidy.to_str().unwrap()]).status.success());
+}
+
+#[test]
+fn fmt_reports_lexer_errors_and_editions() {
+    let dir = test_dir();
+    let path = dir.join("main.spp");
+    fs::write(&path, "let c = 'ab';\n").unwrap();
+    assert!(fmt(&["--check", path.to_str().unwrap()]).status.success());
+    let output = fmt(&["--check", "--edition=2026", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Char literal 'ab' holds more than one character"));
+}
+
+// Lints (user-050)
+
+#[test]
+fn lints_warn_and_deny() {
+    let source = "const NOT_FOUND = 404;\nlet unused = 1;\nmatch (3) {\n    NOT_FOUND => { exit(1); }\n    _ => { exit(0); }\n}\n";
+    let (output, _) = compile(source, &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Warning: Variable 'unused' is never used at "), "{}", stderr);
+    assert!(!stderr.contains("NOT_FOUND"), "{}", stderr);
+    let stderr = compile_error(source, &["--deny=unused_variable"]);
+    assert!(stderr.contains("Error: Variable 'unused' is never used at "), "{}", stderr);
+    assert!(stderr.trim_end().ends_with("main.spp:2:1 [unused_variable]"), "{}", stderr);
+}